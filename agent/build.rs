@@ -0,0 +1,8 @@
+//! Records compile-time settings the agent reports about itself.
+
+fn main() {
+    // Target triple, for the build description in `features`
+    let target = std::env::var("TARGET").unwrap_or_default();
+    println!("cargo:rustc-env=AUTOSTRIKE_TARGET={}", target);
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,224 @@
+//! Linux capability restriction for task commands, so a privileged agent
+//! runs each technique with only the capabilities it is allowed to keep.
+
+use anyhow::{bail, Result};
+
+/// Capability names indexed by number (`linux/capability.h`).
+const CAPABILITY_NAMES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+/// Capabilities a task command keeps, as a bit mask over capability numbers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CapabilitySet(u64);
+
+impl CapabilitySet {
+    /// Parses capability names, with or without the `CAP_` prefix and in any case.
+    pub fn parse(names: &[String]) -> Result<Self> {
+        let mut mask = 0u64;
+        for name in names {
+            let lower = name.to_ascii_lowercase();
+            let short = lower.strip_prefix("cap_").unwrap_or(&lower);
+            match CAPABILITY_NAMES.iter().position(|&known| known == short) {
+                Some(number) => mask |= 1 << number,
+                None => bail!("unknown capability {:?} in task_capabilities", name),
+            }
+        }
+        Ok(Self(mask))
+    }
+
+    /// Whether capability `number` is in the set.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn contains(self, number: u32) -> bool {
+        number < 64 && self.0 & (1 << number) != 0
+    }
+}
+
+/// Makes `cmd` start with only `retained` capabilities: the rest are dropped
+/// from the bounding set, so they do not come back when the command execs,
+/// and from the effective, permitted and inheritable sets; ambient
+/// capabilities are cleared.
+///
+/// Fails when the agent cannot do it (it lacks `CAP_SETPCAP`).
+#[cfg(target_os = "linux")]
+pub fn restrict(cmd: &mut tokio::process::Command, retained: CapabilitySet) -> Result<()> {
+    use nix::libc;
+
+    const CAP_SETPCAP: u32 = 8;
+    if !effective_capabilities()?.contains(CAP_SETPCAP) {
+        bail!("cannot drop capabilities: the agent lacks CAP_SETPCAP");
+    }
+    let last_cap: u32 = std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")?
+        .trim()
+        .parse()?;
+    let dropped: Vec<u32> = (0..=last_cap).filter(|&n| !retained.contains(n)).collect();
+    let mask = retained.0;
+
+    // SAFETY: the hook runs between fork and exec and only makes system
+    // calls on data prepared beforehand, without allocating.
+    unsafe {
+        cmd.pre_exec(move || {
+            for &cap in &dropped {
+                if libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            if libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+                0,
+                0,
+                0,
+            ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            let header = CapHeader {
+                version: LINUX_CAPABILITY_VERSION_3,
+                pid: 0,
+            };
+            let mut data = [CapData::default(); 2];
+            if libc::syscall(libc::SYS_capget, &header, data.as_mut_ptr()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            for (i, word) in data.iter_mut().enumerate() {
+                let keep = (mask >> (32 * i)) as u32;
+                word.effective &= keep;
+                word.permitted &= keep;
+                word.inheritable &= keep;
+            }
+            if libc::syscall(libc::SYS_capset, &header, data.as_ptr()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// `_LINUX_CAPABILITY_VERSION_3`: two 32-bit words per set.
+#[cfg(target_os = "linux")]
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// `struct __user_cap_header_struct`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: i32,
+}
+
+/// `struct __user_cap_data_struct`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// The agent's own effective capabilities, from `/proc/self/status`.
+#[cfg(target_os = "linux")]
+pub fn effective_capabilities() -> Result<CapabilitySet> {
+    let status = std::fs::read_to_string("/proc/self/status")?;
+    let hex = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .ok_or_else(|| anyhow::anyhow!("no CapEff line in /proc/self/status"))?;
+    Ok(CapabilitySet(u64::from_str_radix(hex.trim(), 16)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_capability_names() {
+        let set = CapabilitySet::parse(&["CAP_NET_RAW".to_string(), "chown".to_string()]).unwrap();
+
+        assert!(set.contains(13));
+        assert!(set.contains(0));
+        assert!(!set.contains(21));
+        assert_eq!(CapabilitySet::parse(&[]).unwrap(), CapabilitySet::default());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_capability() {
+        let err = CapabilitySet::parse(&["CAP_MAKE_COFFEE".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("CAP_MAKE_COFFEE"));
+    }
+
+    /// Runs a Python snippet opening a raw ICMP socket, keeping `retained`.
+    #[cfg(target_os = "linux")]
+    async fn open_raw_socket(retained: Option<CapabilitySet>) -> std::io::Result<bool> {
+        let mut cmd = tokio::process::Command::new("python3");
+        cmd.args([
+            "-c",
+            "import socket; socket.socket(socket.AF_INET, socket.SOCK_RAW, socket.IPPROTO_ICMP)",
+        ]);
+        cmd.stderr(std::process::Stdio::null());
+        if let Some(retained) = retained {
+            restrict(&mut cmd, retained).unwrap();
+        }
+        Ok(cmd.status().await?.success())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_dropped_net_raw_blocks_raw_socket() {
+        // Needs an agent allowed to open raw sockets and to drop capabilities
+        if !matches!(open_raw_socket(None).await, Ok(true))
+            || !effective_capabilities().unwrap().contains(8)
+        {
+            eprintln!("skipping: no raw socket or CAP_SETPCAP in this environment");
+            return;
+        }
+
+        let net_raw = CapabilitySet::parse(&["net_raw".to_string()]).unwrap();
+        assert!(open_raw_socket(Some(net_raw)).await.unwrap());
+        assert!(!open_raw_socket(Some(CapabilitySet::default()))
+            .await
+            .unwrap());
+    }
+}
@@ -1,66 +1,469 @@
 //! WebSocket client for agent-server communication.
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Local};
 use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
-use tokio::time::{interval, Duration};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Interval, MissedTickBehavior};
 use tokio_tungstenite::{
-    connect_async_with_config,
+    client_async_tls_with_config,
     tungstenite::{
         client::IntoClientRequest,
+        handshake::client::Request,
         http::header::{HeaderName, HeaderValue},
-        Message as WsMessage,
+        protocol::CloseFrame,
+        Error as WsError, Message as WsMessage,
     },
+    Connector,
 };
-use tracing::{debug, error, info, warn};
-
-use crate::config::AgentConfig;
-use crate::executor::CommandExecutor;
-use crate::system::SystemInfo;
-
-/// Message structure for agent-server WebSocket communication.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AgentMessage {
-    /// Message type (register, heartbeat, task, task_result, etc.)
-    #[serde(rename = "type")]
-    pub msg_type: String,
-    /// Message payload as JSON value.
-    pub payload: serde_json::Value,
-}
-
-/// Payload for agent registration with the server.
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RegisterPayload {
-    /// Unique agent identifier.
-    pub paw: String,
-    /// Agent hostname.
-    pub hostname: String,
-    /// Current username.
-    pub username: String,
-    /// Operating system platform (linux, windows, darwin).
-    pub platform: String,
-    /// Available command executors (sh, bash, powershell, etc.).
-    pub executors: Vec<String>,
-}
-
-/// Payload for task execution requests from the server.
-#[derive(Debug, Deserialize)]
-pub struct TaskPayload {
-    /// Unique task identifier.
-    pub id: String,
-    /// MITRE ATT&CK technique ID.
-    pub technique_id: String,
-    /// Command to execute.
-    pub command: String,
-    /// Executor type (sh, bash, powershell, etc.).
-    pub executor: String,
-    /// Execution timeout in seconds.
-    pub timeout: Option<u64>,
-    /// Optional cleanup command to run after execution.
-    pub cleanup: Option<String>,
+use tracing::{debug, error, field, info, info_span, trace, warn, Instrument, Span};
+
+use crate::capabilities::CapabilitySet;
+use crate::collect;
+use crate::config::{AgentConfig, PausePolicy};
+use crate::disk::DiskGuard;
+use crate::error::AgentError;
+use crate::eventlog;
+use crate::exec_context;
+use crate::executor::{self, CommandExecutor, ExecOptions, ExecutionResult};
+use crate::file_read;
+use crate::group_queue::GroupQueues;
+use crate::health::{HealthMonitor, Observations, Remediation};
+use crate::helper::{self, HelperRequest};
+use crate::history::{History, HistoryEntry};
+use crate::log_dedup::{self, LogDedup};
+use crate::outbox::{Outbox, OutboxReceiver};
+use crate::patterns;
+use crate::protocol::{
+    self, AgentMessage, CollectBundlePayload, CollectBundleResultPayload,
+    ConnectionDiagnosticsPayload, EmptyPayload, HeartbeatMetrics, HeartbeatPayload,
+    KillAllResultPayload, ProbePayload, ProbeResultPayload, ReadFilePayload, ReadFileResultPayload,
+    RegisterPayload, ResultAckPayload, ResultsExpiredPayload, ResyncRequestPayload, StepResult,
+    TaskPayload, TaskResultPayload, TimeSyncPayload, UpdateAvailablePayload, Verification,
+};
+use crate::registry;
+use crate::resolve;
+use crate::result_endpoint::{self, Route};
+use crate::secret::SecretString;
+use crate::syslog;
+use crate::system::{self, SystemInfo};
+use crate::task_dir::TaskDir;
+use crate::task_tracker::TaskTracker;
+use crate::technique;
+use crate::technique_lock::TechniqueLocks;
+use crate::telemetry;
+use crate::tls::{self, ChainRecorder, TlsDiagnostics};
+use crate::update;
+
+/// Where a task's sequence number falls relative to the previous one.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SeqCheck {
+    /// The next number, or the first one seen on the connection.
+    InOrder,
+    /// Numbers were skipped: tasks in between were lost.
+    Gap { expected: u64, received: u64 },
+    /// At or below the last number: a duplicate or a late, reordered task.
+    Stale { last: u64, received: u64 },
+}
+
+/// Last task sequence number seen on the current connection.
+#[derive(Debug, Default)]
+pub struct TaskSequence {
+    last: Option<u64>,
+}
+
+impl TaskSequence {
+    /// Checks `seq` against the last number seen, moving forward unless it is stale.
+    pub fn observe(&mut self, seq: u64) -> SeqCheck {
+        let check = match self.last {
+            None => SeqCheck::InOrder,
+            Some(last) if seq <= last => {
+                return SeqCheck::Stale {
+                    last,
+                    received: seq,
+                }
+            }
+            Some(last) if seq == last + 1 => SeqCheck::InOrder,
+            Some(last) => SeqCheck::Gap {
+                expected: last + 1,
+                received: seq,
+            },
+        };
+        self.last = Some(seq);
+        check
+    }
+}
+
+/// Bounded list of task IDs whose results the server has not acknowledged yet.
+///
+/// Only the most recent `limit` IDs are kept; a limit of 0 disables tracking.
+#[derive(Debug, Default)]
+pub struct PendingAcks {
+    limit: usize,
+    task_ids: VecDeque<String>,
+}
+
+impl PendingAcks {
+    /// Creates a tracker keeping at most `limit` task IDs.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            task_ids: VecDeque::new(),
+        }
+    }
+
+    /// Records a task result as awaiting acknowledgment.
+    pub fn record(&mut self, task_id: &str) {
+        if self.limit == 0 {
+            return;
+        }
+        self.task_ids.retain(|id| id != task_id);
+        if self.task_ids.len() == self.limit {
+            self.task_ids.pop_front();
+        }
+        self.task_ids.push_back(task_id.to_string());
+    }
+
+    /// Clears a task ID once the server has acknowledged its result.
+    pub fn ack(&mut self, task_id: &str) {
+        self.task_ids.retain(|id| id != task_id);
+    }
+
+    /// Returns the pending task IDs, oldest first.
+    pub fn snapshot(&self) -> Vec<String> {
+        self.task_ids.iter().cloned().collect()
+    }
+}
+
+/// Offset of the server's clock relative to the agent's, learned from the
+/// server's `register_ack` or `time_sync` messages.
+#[derive(Debug, Default)]
+pub struct ClockSkew {
+    offset_ms: Mutex<Option<i64>>,
+}
+
+impl ClockSkew {
+    /// Records the server time observed at local time `now` and returns the
+    /// offset in milliseconds (positive when the server is ahead).
+    pub fn record(&self, server_time: DateTime<FixedOffset>, now: DateTime<Local>) -> i64 {
+        let offset = server_time.signed_duration_since(now).num_milliseconds();
+        if let Ok(mut offset_ms) = self.offset_ms.lock() {
+            *offset_ms = Some(offset);
+        }
+        offset
+    }
+
+    /// Returns the last recorded offset in milliseconds, if any.
+    pub fn offset_ms(&self) -> Option<i64> {
+        self.offset_ms.lock().ok().and_then(|offset| *offset)
+    }
+}
+
+/// Decides whether a read error ends the connection.
+///
+/// Transient I/O errors (timeout, reset, interruption) are tolerated up to
+/// `tolerance` times in a row; any successful read resets the count. Every
+/// other error (TLS, protocol, closed connection) is fatal.
+#[derive(Debug)]
+pub struct ReadErrorPolicy {
+    tolerance: u32,
+    consecutive: u32,
+}
+
+impl ReadErrorPolicy {
+    /// Tolerates `tolerance` consecutive transient errors.
+    pub fn new(tolerance: u32) -> Self {
+        Self {
+            tolerance,
+            consecutive: 0,
+        }
+    }
+
+    /// Records a successful read.
+    pub fn on_success(&mut self) {
+        self.consecutive = 0;
+    }
+
+    /// Records a read error; returns whether to reconnect.
+    pub fn on_error(&mut self, error: &WsError) -> bool {
+        if !is_transient(error) {
+            return true;
+        }
+        self.consecutive += 1;
+        self.consecutive > self.tolerance
+    }
+}
+
+/// Whether a read error may clear up on its own, without a new connection.
+fn is_transient(error: &WsError) -> bool {
+    use std::io::ErrorKind;
+
+    match error {
+        WsError::Io(e) => matches!(
+            e.kind(),
+            ErrorKind::TimedOut
+                | ErrorKind::WouldBlock
+                | ErrorKind::Interrupted
+                | ErrorKind::ConnectionReset
+        ),
+        _ => false,
+    }
+}
+
+/// Delay before reconnecting after a failed connection: doubles after each
+/// failure up to `max`, and starts over once a connection succeeds.
+///
+/// Delays are plain durations slept on tokio's monotonic clock, so a wall
+/// clock step (NTP, manual change) neither shortens nor stretches them.
+#[derive(Debug)]
+pub struct ReconnectBackoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl ReconnectBackoff {
+    /// Starts at `initial`, never exceeding `max`.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// Returns the delay to wait now and doubles the next one.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Starts over from the initial delay after a successful connection.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// What the agent does after the server closed the connection, decided by
+/// the close code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseAction {
+    /// Reconnect on the usual schedule.
+    Reconnect,
+    /// The server is going away for maintenance: wait longer, randomized so
+    /// agents do not all come back at the same moment.
+    Maintenance,
+    /// The server refused the agent (policy violation, authentication):
+    /// stop, as when the handshake is refused.
+    Refused,
+}
+
+/// Close codes of a server going away (1001), restarting (1012) or asking
+/// to try again later (1013).
+const MAINTENANCE_CLOSE_CODES: &[u16] = &[1001, 1012, 1013];
+
+/// Close codes of a server refusing the agent: policy violation (1008) and
+/// the registered Unauthorized (3000) and Forbidden (3003).
+const REFUSED_CLOSE_CODES: &[u16] = &[1008, 3000, 3003];
+
+/// Shortest wait before reconnecting to a server closed for maintenance.
+const MAINTENANCE_DELAY: Duration = Duration::from_secs(30);
+
+/// Action for a close frame with `code`; a close without a code reconnects.
+pub fn close_action(code: Option<u16>) -> CloseAction {
+    match code {
+        Some(code) if MAINTENANCE_CLOSE_CODES.contains(&code) => CloseAction::Maintenance,
+        Some(code) if REFUSED_CLOSE_CODES.contains(&code) => CloseAction::Refused,
+        _ => CloseAction::Reconnect,
+    }
+}
+
+/// Wait before reconnecting after a maintenance close: [`MAINTENANCE_DELAY`]
+/// plus up to as much again, picked from `random`.
+pub fn maintenance_delay(random: u64) -> Duration {
+    MAINTENANCE_DELAY + Duration::from_millis(random % MAINTENANCE_DELAY.as_millis() as u64)
+}
+
+/// Classifies a failed WebSocket handshake: refused credentials (401, 403)
+/// stop the agent; everything else (server errors, TLS, resets, a 404 from
+/// a proxy during a deploy) is retried.
+fn handshake_error(error: WsError) -> AgentError {
+    let status = match &error {
+        WsError::Http(response) => Some(response.status().as_u16()),
+        _ => None,
+    };
+    let source = anyhow::Error::new(error).context("Failed to connect to server");
+    match status {
+        Some(401 | 403) => AgentError::Auth(source),
+        _ => AgentError::network(source),
+    }
+}
+
+/// Whether the server answered the handshake with a client error other
+/// than 408 and 429, which is unlikely to clear soon: a wrong server path,
+/// or a proxy in front of a server being deployed.
+fn is_client_error(error: &AgentError) -> bool {
+    let AgentError::Network { error, .. } = error else {
+        return false;
+    };
+    match error.downcast_ref::<WsError>() {
+        Some(WsError::Http(response)) => {
+            let status = response.status().as_u16();
+            (400..500).contains(&status) && !matches!(status, 408 | 429)
+        }
+        _ => false,
+    }
+}
+
+/// Heartbeat interval state machine.
+///
+/// In adaptive mode the interval drops to `min` after a reconnect, to quickly
+/// re-establish liveness, then doubles after each stable heartbeat until it is
+/// back at the configured interval. Otherwise it stays at the configured value.
+#[derive(Debug, Clone)]
+pub struct AdaptiveHeartbeat {
+    min: Duration,
+    target: Duration,
+    current: Duration,
+}
+
+impl AdaptiveHeartbeat {
+    /// Builds the schedule from the configured interval and adaptive bounds.
+    pub fn from_config(config: &AgentConfig) -> Self {
+        let interval = config.heartbeat_interval;
+        let max = config.heartbeat_max.unwrap_or(interval);
+        let min = config.heartbeat_min.unwrap_or(interval).min(max);
+        let target = Duration::from_secs(interval.clamp(min, max));
+
+        Self {
+            min: Duration::from_secs(min),
+            target,
+            current: target,
+        }
+    }
+
+    /// Shortens the interval to the lower bound after a reconnect.
+    pub fn on_reconnect(&mut self) {
+        self.current = self.min;
+    }
+
+    /// Records a stable heartbeat and returns the delay before the next one.
+    pub fn on_stable(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.target).max(self.min);
+        delay
+    }
+
+    /// Returns the delay the next stable heartbeat will wait.
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+}
+
+/// Heartbeat intervals `adaptive_heartbeat` may stay silent for while other
+/// messages flow, before a heartbeat is sent anyway.
+const MAX_SILENT_INTERVALS: u32 = 3;
+
+/// Decides whether a heartbeat goes out. With `adaptive_heartbeat`, it is
+/// skipped when another message was sent within the interval, since that
+/// already shows the agent is alive, unless the last heartbeat is
+/// [`MAX_SILENT_INTERVALS`] intervals old.
+#[derive(Debug, Clone)]
+pub struct HeartbeatGate {
+    enabled: bool,
+    interval: Duration,
+    last_beat: Option<Instant>,
+}
+
+impl HeartbeatGate {
+    /// Builds the gate from the `adaptive_heartbeat` setting and interval.
+    pub fn from_config(config: &AgentConfig) -> Self {
+        Self {
+            enabled: config.adaptive_heartbeat,
+            interval: Duration::from_secs(config.heartbeat_interval),
+            last_beat: None,
+        }
+    }
+
+    /// Whether to send a heartbeat at `now`, given when other traffic last
+    /// went out; records the heartbeat if so.
+    pub fn due(&mut self, now: Instant, last_activity: Option<Instant>) -> bool {
+        let recent_traffic =
+            last_activity.is_some_and(|at| now.saturating_duration_since(at) < self.interval);
+        let overdue = self.last_beat.is_none_or(|at| {
+            now.saturating_duration_since(at) >= self.interval * MAX_SILENT_INTERVALS
+        });
+        if self.enabled && recent_traffic && !overdue {
+            return false;
+        }
+        self.last_beat = Some(now);
+        true
+    }
+}
+
+/// Log target of raw WebSocket frames, enabled at TRACE by `--trace-frames`.
+pub const FRAME_LOG_TARGET: &str = "autostrike_agent::frames";
+
+/// Bytes of a frame kept in its log entry.
+const FRAME_LOG_MAX_BYTES: usize = 4096;
+
+/// Logs raw WebSocket frames at TRACE level, capped in size and with the
+/// agent secret redacted.
+pub struct FrameLogger {
+    enabled: bool,
+    secret: Option<SecretString>,
+}
+
+impl FrameLogger {
+    /// Builds a logger enabled by the `log_frames` setting.
+    pub fn from_config(config: &AgentConfig) -> Self {
+        Self {
+            enabled: config.log_frames,
+            secret: config.agent_secret.clone(),
+        }
+    }
+
+    /// Logs a frame sent (`out`) or received (`in`).
+    pub fn log(&self, direction: &str, frame: &WsMessage) {
+        if self.enabled {
+            trace!(target: FRAME_LOG_TARGET, direction, bytes = frame.len(), "{}", self.render(frame));
+        }
+    }
+
+    /// Renders a frame for the log: text as-is, binary payloads as hex.
+    fn render(&self, frame: &WsMessage) -> String {
+        let hex_prefix =
+            |data: &[u8]| hex::encode(&data[..data.len().min(FRAME_LOG_MAX_BYTES / 2)]);
+        let mut text = match frame {
+            WsMessage::Text(text) => text.clone(),
+            WsMessage::Binary(data) => format!("binary {}", hex_prefix(data)),
+            WsMessage::Ping(data) => format!("ping {}", hex_prefix(data)),
+            WsMessage::Pong(data) => format!("pong {}", hex_prefix(data)),
+            WsMessage::Close(close) => format!("close {:?}", close),
+            WsMessage::Frame(frame) => format!("frame {}", frame),
+        };
+        // Redact before capping so a secret straddling the cut never leaks in part
+        if let Some(secret) = self.secret.as_ref().filter(|s| !s.expose().is_empty()) {
+            text = text.replace(secret.expose(), "[REDACTED]");
+        }
+        if text.len() > FRAME_LOG_MAX_BYTES {
+            text.truncate(executor::find_char_boundary(&text, FRAME_LOG_MAX_BYTES));
+            text.push_str(&format!("... [{} bytes]", frame.len()));
+        }
+        text
+    }
 }
 
 /// WebSocket client for communicating with the AutoStrike server.
+///
+/// Clones share the task, acknowledgment and lock state, so each task can
+/// run on its own tokio task.
+#[derive(Clone)]
 pub struct AgentClient {
     /// Agent configuration.
     pub config: AgentConfig,
@@ -68,45 +471,154 @@ pub struct AgentClient {
     pub sys_info: SystemInfo,
     /// Command executor instance.
     pub executor: CommandExecutor,
+    /// TLS failure analysis to report once a connection succeeds.
+    pub pending_diagnostics: Option<TlsDiagnostics>,
+    /// Task results awaiting server acknowledgment, shared with the heartbeat task.
+    pub pending_acks: Arc<Mutex<PendingAcks>>,
+    /// Free-space guard for the agent's workspace volume.
+    pub disk: DiskGuard,
+    /// Clock offset against the server, shared with the heartbeat task.
+    pub clock_skew: Arc<ClockSkew>,
+    /// Whether a connection has been established before (next one is a reconnect).
+    pub has_connected: bool,
+    /// Techniques with a task in progress, to serialize runs of the same one.
+    pub technique_locks: Arc<TechniqueLocks>,
+    /// Tasks accepted and not finished yet, for `kill_all` and the concurrency limit.
+    pub tasks: Arc<TaskTracker>,
+    /// Task groups with tasks in progress, to run each group's tasks in order.
+    pub groups: Arc<GroupQueues>,
+    /// Sequence numbers of the tasks received on the current connection.
+    pub task_seq: Arc<Mutex<TaskSequence>>,
+    /// Local record of executed tasks, when `history.path` is set.
+    pub history: Option<Arc<History>>,
+    /// Recently logged warnings, so a repeated bad frame is logged once per window.
+    pub log_dedup: Arc<LogDedup>,
+    /// Close frames received from the server, counted by close code.
+    pub close_codes: Arc<Mutex<BTreeMap<u16, u64>>>,
+    /// Environment variables task commands do not inherit.
+    pub env_denylist: Vec<String>,
+    /// Self-checks, whose failures the heartbeat reports.
+    pub health: Arc<HealthMonitor>,
+    /// Messages waiting to be sent, kept across reconnections so results of
+    /// tasks finishing in between are not lost.
+    pub outbox: Outbox,
+    /// Receiving half of `outbox`, drained by the current connection.
+    pub outbox_rx: Arc<tokio::sync::Mutex<OutboxReceiver>>,
+    /// Set while a self-update is installing; new tasks are turned away.
+    pub updating: Arc<AtomicBool>,
 }
 
 impl AgentClient {
     /// Creates a new agent client with the given configuration and system info.
     pub fn new(config: AgentConfig, sys_info: SystemInfo) -> Result<Self> {
-        let executor = CommandExecutor::new();
+        // A 32-bit agent on 64-bit Windows reaches the 64-bit shells through Sysnative
+        let native_shell_dir = (config.prefer_native_shell && sys_info.is_wow64)
+            .then(|| executor::sysnative_dir(|name| std::env::var(name).ok()));
+        let capabilities = config
+            .task_capabilities
+            .as_deref()
+            .map(CapabilitySet::parse)
+            .transpose()?;
+        if capabilities.is_some() && !cfg!(target_os = "linux") {
+            warn!("task_capabilities only applies on Linux, ignoring it");
+        }
+        if let Some(fields) = &config.result_fields {
+            protocol::check_result_fields(fields)?;
+        }
+        let executor = CommandExecutor::new()
+            .with_output_order(config.output_order)
+            .with_native_shell_dir(native_shell_dir)
+            .with_task_capabilities(capabilities)
+            .with_output_budget(config.output_memory_budget_bytes);
+        let mut sys_info = sys_info;
+        if config.helper_path.is_some() {
+            sys_info.executors.push(helper::HELPER_EXECUTOR.to_string());
+        }
+        let pending_acks = Arc::new(Mutex::new(PendingAcks::new(config.heartbeat_pending_acks)));
+        let disk = DiskGuard::new(config.disk_reserve_bytes);
+        let tasks = Arc::new(TaskTracker::new(config.max_concurrent_tasks));
+        let history = History::from_config(&config.history).map(Arc::new);
+        let env_denylist = config.denied_env(std::env::vars_os());
+        let health = Arc::new(HealthMonitor::new(config.health.clone()));
+        let (outbox, outbox_rx) = Outbox::channel(32);
 
         Ok(Self {
             config,
             sys_info,
             executor,
+            pending_diagnostics: None,
+            pending_acks,
+            disk,
+            clock_skew: Arc::new(ClockSkew::default()),
+            has_connected: false,
+            technique_locks: Arc::new(TechniqueLocks::default()),
+            tasks,
+            groups: Arc::default(),
+            close_codes: Arc::default(),
+            task_seq: Arc::default(),
+            history,
+            log_dedup: Arc::new(LogDedup::new(log_dedup::WINDOW)),
+            env_denylist,
+            health,
+            outbox,
+            outbox_rx: Arc::new(tokio::sync::Mutex::new(outbox_rx)),
+            updating: Arc::default(),
         })
     }
 
     /// Runs the agent client with automatic reconnection on failure.
-    pub async fn run(&mut self) -> Result<()> {
-        let mut retry_delay = Duration::from_secs(1);
-        let max_delay = Duration::from_secs(60);
+    /// Connects and reconnects until an error retrying cannot fix, such as
+    /// refused credentials.
+    pub async fn run(&mut self) -> Result<(), AgentError> {
+        let mut backoff = ReconnectBackoff::new(Duration::from_secs(1), Duration::from_secs(60));
+        // HTTP client errors get a slower schedule, not to hammer a
+        // misrouted proxy
+        let mut long_backoff =
+            ReconnectBackoff::new(Duration::from_secs(60), Duration::from_secs(30 * 60));
 
         loop {
             match self.connect_and_run().await {
-                Ok(_) => {
-                    retry_delay = Duration::from_secs(1);
+                Ok(CloseAction::Reconnect) => {
+                    backoff.reset();
+                    long_backoff.reset();
                     info!("Connection closed, reconnecting...");
                 }
+                Ok(CloseAction::Maintenance) => {
+                    backoff.reset();
+                    long_backoff.reset();
+                    let delay = maintenance_delay(uuid::Uuid::new_v4().as_u128() as u64);
+                    info!("Server closed for maintenance, reconnecting in {:?}", delay);
+                    tokio::time::sleep(delay).await;
+                }
+                Ok(CloseAction::Refused) => {
+                    // Revoked credentials would be refused again on every reconnect
+                    return Err(AgentError::Auth(anyhow::anyhow!(
+                        "Server closed the connection refusing the agent"
+                    )));
+                }
+                Err(e) if !e.is_retryable() => return Err(e),
+                Err(e) if is_client_error(&e) => {
+                    let retry_delay = long_backoff.next_delay();
+                    error!(
+                        "Connection error: {}, reconnecting in {:?}...",
+                        e, retry_delay
+                    );
+                    tokio::time::sleep(retry_delay).await;
+                }
                 Err(e) => {
+                    let retry_delay = backoff.next_delay();
                     error!(
                         "Connection error: {}, reconnecting in {:?}...",
                         e, retry_delay
                     );
                     tokio::time::sleep(retry_delay).await;
-
-                    retry_delay = std::cmp::min(retry_delay * 2, max_delay);
                 }
             }
         }
     }
 
-    async fn connect_and_run(&mut self) -> Result<()> {
+    /// Builds the WebSocket upgrade request with the optional X-Agent-Key header.
+    fn build_request(&self) -> Result<Request> {
         let ws_url = self
             .config
             .server_url
@@ -116,73 +628,155 @@ impl AgentClient {
 
         info!("Connecting to {}", ws_url);
 
-        // Build request with optional X-Agent-Key header
         let mut request = ws_url.into_client_request()?;
         if let Some(ref secret) = self.config.agent_secret {
-            request.headers_mut().insert(
-                HeaderName::from_static("x-agent-key"),
-                HeaderValue::from_str(secret).context("Invalid agent secret value")?,
-            );
+            let mut value =
+                HeaderValue::from_str(secret.expose()).context("Invalid agent secret value")?;
+            value.set_sensitive(true);
+            request
+                .headers_mut()
+                .insert(HeaderName::from_static("x-agent-key"), value);
             debug!("Added X-Agent-Key header for authentication");
         }
 
-        let (ws_stream, _) = connect_async_with_config(request, None)
+        Ok(request)
+    }
+
+    /// Connects, registers and serves the connection until it ends. Returns
+    /// what to do next when it ends without an error.
+    #[tracing::instrument(skip_all, fields(server = %self.config.server_url))]
+    async fn connect_and_run(&mut self) -> Result<CloseAction, AgentError> {
+        let request = self.build_request().map_err(AgentError::Config)?;
+
+        let recorder = Arc::new(ChainRecorder::default());
+        let connector = Connector::Rustls(tls::client_config(recorder.clone()));
+
+        // Resolved here so host_overrides apply; TLS still uses the URL's host
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .context("Server URL has no host")
+            .map_err(AgentError::Config)?
+            .to_string();
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("wss") {
+                443
+            } else {
+                80
+            });
+        let stream = resolve::connect(&host, port, &self.config.network)
             .await
-            .context("Failed to connect to server")?;
+            .with_context(|| format!("Failed to connect to server {}:{}", host, port))
+            .map_err(AgentError::network)?;
+
+        let (ws_stream, _) =
+            match client_async_tls_with_config(request, stream, None, Some(connector)).await {
+                Ok(connected) => connected,
+                Err(e) => {
+                    if let Some(diagnostics) = recorder.take_diagnostics() {
+                        diagnostics.log();
+                        self.pending_diagnostics = Some(diagnostics);
+                    }
+                    return Err(handshake_error(e));
+                }
+            };
 
         let (mut write, mut read) = ws_stream.split();
 
-        let register_msg = AgentMessage {
-            msg_type: "register".to_string(),
-            payload: serde_json::to_value(RegisterPayload {
-                paw: self.config.paw.clone(),
-                hostname: self.sys_info.hostname.clone(),
-                username: self.sys_info.username.clone(),
-                platform: self.sys_info.platform.clone(),
-                executors: self.sys_info.executors.clone(),
-            })?,
-        };
-
-        write
-            .send(WsMessage::Text(serde_json::to_string(&register_msg)?))
-            .await?;
-        info!("Registered with server");
+        let limits = &self.config.message_limits;
+        let register_msg = AgentMessage::new(
+            "register",
+            &RegisterPayload::new(&self.config.paw, &self.sys_info, limits),
+        )
+        .and_then(|msg| encode_message(msg, limits.max_message_bytes))
+        .map_err(AgentError::Protocol)?;
 
-        let heartbeat_interval = self.config.heartbeat_interval;
-        let paw = self.config.paw.clone();
+        let frames = FrameLogger::from_config(&self.config);
+        let frame = WsMessage::Text(register_msg);
+        frames.log("out", &frame);
+        write.send(frame).await.map_err(AgentError::network)?;
+        info!("Registered with server");
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(32);
+        if let Some(diagnostics) = self.pending_diagnostics.take() {
+            let message = connection_diagnostics_message(&self.config.paw, &diagnostics)
+                .and_then(|msg| encode_message(msg, limits.max_message_bytes))
+                .map_err(AgentError::Protocol)?;
+            let frame = WsMessage::Text(message);
+            frames.log("out", &frame);
+            write.send(frame).await.map_err(AgentError::network)?;
+            debug!("Reported previous TLS verification failure");
+        }
 
-        let tx_heartbeat = tx.clone();
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(heartbeat_interval));
-            loop {
-                interval.tick().await;
-                let msg = AgentMessage {
-                    msg_type: "heartbeat".to_string(),
-                    payload: serde_json::json!({ "paw": paw }),
-                };
-                match serde_json::to_string(&msg) {
-                    Ok(json_str) => {
-                        if tx_heartbeat.send(json_str).await.is_err() {
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to serialize heartbeat: {}", e);
-                        break;
-                    }
+        let mut heartbeat = AdaptiveHeartbeat::from_config(&self.config);
+        if self.has_connected {
+            heartbeat.on_reconnect();
+            debug!(
+                "Heartbeat interval after reconnect: {:?}",
+                heartbeat.current()
+            );
+        }
+        self.has_connected = true;
+        // The server numbers tasks per connection
+        *self.task_seq.lock().unwrap_or_else(|e| e.into_inner()) = TaskSequence::default();
+        // Results left from the previous connection go out after registering
+        let tx = self.outbox.clone();
+        let mut rx = self.outbox_rx.clone().lock_owned().await;
+        let stale = rx.drop_control();
+        if stale > 0 {
+            debug!(
+                "Dropped {} control message(s) meant for the previous connection",
+                stale
+            );
+        }
+        if self.config.result_ttl > 0 {
+            let expired = rx.expire_bulk(Duration::from_secs(self.config.result_ttl));
+            if !expired.is_empty() {
+                let task_ids: Vec<String> = expired
+                    .iter()
+                    .filter_map(|msg| serde_json::from_str::<serde_json::Value>(msg).ok())
+                    .filter_map(|msg| msg["payload"]["task_id"].as_str().map(str::to_string))
+                    .collect();
+                warn!(
+                    "Dropped {} queued result(s) older than {}s",
+                    expired.len(),
+                    self.config.result_ttl
+                );
+                if let Ok(mut pending) = self.pending_acks.lock() {
+                    task_ids.iter().for_each(|task_id| pending.ack(task_id));
                 }
+                let message =
+                    AgentMessage::new("results_expired", &ResultsExpiredPayload { task_ids })
+                        .and_then(|msg| encode_message(msg, limits.max_message_bytes))
+                        .map_err(AgentError::Protocol)?;
+                tx.control(message).await.map_err(AgentError::network)?;
             }
+        }
+        let mut read_errors = ReadErrorPolicy::new(self.config.connection_error_tolerance);
+
+        // The outbox outlives the connection, so the heartbeat is stopped here
+        let mut heartbeat_task = AbortOnDrop(self.spawn_heartbeat(&tx, heartbeat));
+        let mut health_checks = self.health.interval().map(|period| {
+            let mut checks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            checks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            checks
         });
+        let mut last_frame = Instant::now();
 
         loop {
             tokio::select! {
                 Some(msg) = rx.recv() => {
-                    write.send(WsMessage::Text(msg)).await?;
+                    let frame = WsMessage::Text(msg);
+                    frames.log("out", &frame);
+                    write.send(frame).await.map_err(AgentError::network)?;
                 }
 
                 msg = read.next() => {
+                    if let Some(Ok(frame)) = &msg {
+                        frames.log("in", frame);
+                        read_errors.on_success();
+                        last_frame = Instant::now();
+                    }
                     match msg {
                         Some(Ok(WsMessage::Text(text))) => {
                             match serde_json::from_str::<AgentMessage>(&text) {
@@ -190,105 +784,1511 @@ impl AgentClient {
                                     self.handle_message(agent_msg, &tx).await?;
                                 }
                                 Err(e) => {
-                                    warn!("Failed to parse message: {} - content: {}", e, text);
+                                    warn_deduped(
+                                        &self.log_dedup,
+                                        "Failed to parse message",
+                                        &text,
+                                        format_args!(
+                                            "Failed to parse message: {} - content: {}",
+                                            e, text
+                                        ),
+                                    );
                                 }
                             }
                         }
                         Some(Ok(WsMessage::Ping(data))) => {
-                            write.send(WsMessage::Pong(data)).await?;
+                            let frame = WsMessage::Pong(data);
+                            frames.log("out", &frame);
+                            write.send(frame).await.map_err(AgentError::network)?;
                         }
-                        Some(Ok(WsMessage::Close(_))) => {
-                            info!("Server closed connection");
-                            break;
+                        Some(Ok(WsMessage::Close(frame))) => {
+                            return Ok(self.on_server_close(frame.as_ref()));
                         }
                         Some(Err(e)) => {
-                            error!("WebSocket error: {}", e);
-                            break;
+                            if read_errors.on_error(&e) {
+                                error!("WebSocket error: {}", e);
+                                break;
+                            }
+                            warn!("Transient WebSocket error, still connected: {}", e);
                         }
                         None => break,
                         _ => {}
                     }
                 }
+
+                _ = next_check(&mut health_checks) => {
+                    if self.check_health(&mut heartbeat_task.0, &tx, last_frame) {
+                        warn!("Reconnecting to recover from a failed health check");
+                        break;
+                    }
+                }
             }
         }
 
-        Ok(())
+        Ok(CloseAction::Reconnect)
     }
 
-    /// Handles incoming messages from the server.
-    pub async fn handle_message(
+    /// Starts the task sending heartbeats on `tx`, which ends with the
+    /// connection.
+    fn spawn_heartbeat(&self, tx: &Outbox, mut heartbeat: AdaptiveHeartbeat) -> JoinHandle<()> {
+        let paw = self.config.paw.clone();
+        let report_pending_acks = self.config.heartbeat_pending_acks > 0;
+        let pending_acks = self.pending_acks.clone();
+        let client = self.clone();
+        let max_message_bytes = self.config.message_limits.max_message_bytes;
+        let log_dedup = self.log_dedup.clone();
+
+        let tx_heartbeat = tx.clone();
+        let mut gate = HeartbeatGate::from_config(&self.config);
+        tokio::spawn(async move {
+            loop {
+                // Summarize the repeats that stopped since the last beat
+                for summary in log_dedup.flush(Instant::now()) {
+                    warn!("{}", summary);
+                }
+                if !gate.due(Instant::now(), tx_heartbeat.last_activity()) {
+                    debug!("Skipping heartbeat, recent messages show the agent is alive");
+                    tokio::time::sleep(heartbeat.on_stable()).await;
+                    continue;
+                }
+                let pending = report_pending_acks.then(|| {
+                    pending_acks
+                        .lock()
+                        .map(|p| p.snapshot())
+                        .unwrap_or_default()
+                });
+                let metrics = client.metrics();
+                match heartbeat_message(&paw, pending, &metrics, client.tasks.is_paused())
+                    .and_then(|msg| encode_message(msg, max_message_bytes))
+                {
+                    Ok(json_str) => {
+                        if tx_heartbeat.heartbeat(json_str).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to serialize heartbeat: {}", e);
+                        break;
+                    }
+                }
+                tokio::time::sleep(heartbeat.on_stable()).await;
+            }
+        })
+    }
+
+    /// Current runtime metrics, as reported in the heartbeat.
+    pub fn metrics(&self) -> HeartbeatMetrics {
+        // Commands and their output files live in the system temp directory
+        let mut metrics = HeartbeatMetrics::collect(&self.disk, &std::env::temp_dir());
+        let reaper = self.executor.reaper();
+        metrics.clock_skew_ms = self.clock_skew.offset_ms();
+        metrics.unreaped_children = reaper.pending();
+        metrics.reap_failures = reaper.failures();
+        metrics.executor_fallbacks = self.executor.fallbacks();
+        metrics.output_bytes_in_use = self.executor.output_bytes_in_use();
+        metrics.group_queue_depths = self.groups.depths();
+        metrics.server_close_codes = self
+            .close_codes
+            .lock()
+            .map(|c| c.clone())
+            .unwrap_or_default();
+        metrics.health = self.health.issues();
+        metrics
+    }
+
+    /// Runs the self-checks and applies their remediations. Returns whether
+    /// to reconnect.
+    fn check_health(
         &self,
-        msg: AgentMessage,
-        tx: &tokio::sync::mpsc::Sender<String>,
-    ) -> Result<()> {
+        heartbeat_task: &mut JoinHandle<()>,
+        tx: &Outbox,
+        last_frame: Instant,
+    ) -> bool {
+        let seen = Observations {
+            heartbeat_alive: !heartbeat_task.is_finished(),
+            oldest_result: tx.oldest_bulk_age(),
+            disk_error: self
+                .disk
+                .ensure_space(&std::env::temp_dir(), 0)
+                .err()
+                .map(|e| e.to_string()),
+            silence: last_frame.elapsed(),
+        };
+        let mut reconnect = false;
+        for remediation in self.health.check(&seen, Instant::now(), SystemTime::now()) {
+            match remediation {
+                Remediation::RestartHeartbeat => {
+                    heartbeat_task.abort();
+                    *heartbeat_task =
+                        self.spawn_heartbeat(tx, AdaptiveHeartbeat::from_config(&self.config));
+                }
+                Remediation::Reconnect => reconnect = true,
+            }
+        }
+        reconnect
+    }
+
+    /// Logs and counts a close frame from the server, and decides what to do.
+    fn on_server_close(&self, frame: Option<&CloseFrame>) -> CloseAction {
+        let code = frame.map(|frame| u16::from(frame.code));
+        let action = close_action(code);
+        match frame {
+            Some(frame) => info!(
+                "Server closed connection with code {} ({:?}): {:?}",
+                u16::from(frame.code),
+                action,
+                frame.reason
+            ),
+            None => info!("Server closed connection"),
+        }
+        if let Some(code) = code {
+            *self
+                .close_codes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(code)
+                .or_default() += 1;
+        }
+        action
+    }
+
+    /// Handles incoming messages from the server.
+    pub async fn handle_message(&self, msg: AgentMessage, tx: &Outbox) -> Result<(), AgentError> {
         debug!("Received message: {:?}", msg.msg_type);
+        let correlation_id = msg.correlation_id.as_deref();
 
         match msg.msg_type.as_str() {
             "task" => {
-                let task: TaskPayload = serde_json::from_value(msg.payload)?;
-                self.execute_task(task, tx).await?;
+                let task: TaskPayload = match serde_json::from_value(msg.payload.clone()) {
+                    Ok(task) => task,
+                    Err(e) => return self.send_rejected_invalid(&msg.payload, &e, tx).await,
+                };
+                if let Some(seq) = task.seq {
+                    self.check_task_seq(seq, &task.id, tx).await?;
+                }
+                let route = result_endpoint::route(
+                    task.result_endpoint.as_deref(),
+                    self.config.allow_insecure_result_endpoints,
+                );
+                let unmet = task
+                    .requires
+                    .as_ref()
+                    .and_then(|requires| self.sys_info.unmet(requires));
+                if let Err(e) = route {
+                    self.send_rejected_endpoint(&task, &e, tx).await?;
+                } else if let Some(reason) = unmet {
+                    self.send_skipped(&task, &reason, tx).await?;
+                } else if self.updating.load(Ordering::Relaxed)
+                    || (self.tasks.is_paused() && self.config.pause_policy == PausePolicy::Reject)
+                {
+                    self.send_rejected_paused(&task, tx).await?;
+                } else if self.is_overloaded(tx) {
+                    self.send_rejected_overloaded(&task, tx).await?;
+                } else {
+                    self.spawn_task(task, tx);
+                }
+            }
+            "pause" => {
+                if !self.tasks.set_paused(true) {
+                    warn!("Task processing paused by the server");
+                }
+            }
+            "resume" => {
+                if self.tasks.set_paused(false) {
+                    info!("Task processing resumed by the server");
+                }
+            }
+            "kill_all" => {
+                let task_ids = self.tasks.cancel_all();
+                warn!("kill_all: cancelling {} task(s)", task_ids.len());
+                let summary = KillAllResultPayload {
+                    cancelled: task_ids.len(),
+                    task_ids,
+                };
+                let message = AgentMessage::reply("kill_all_result", &summary, correlation_id)
+                    .and_then(|msg| {
+                        encode_message(msg, self.config.message_limits.max_message_bytes)
+                    })
+                    .map_err(AgentError::Protocol)?;
+                tx.control(message).await.map_err(AgentError::network)?;
+            }
+            "result_ack" => match serde_json::from_value::<ResultAckPayload>(msg.payload) {
+                Ok(ack) => {
+                    debug!("Server acknowledged result for task {}", ack.task_id);
+                    if let Ok(mut pending) = self.pending_acks.lock() {
+                        pending.ack(&ack.task_id);
+                    }
+                }
+                Err(e) => warn!("Invalid result_ack payload: {}", e),
+            },
+            "register_ack" | "time_sync" => {
+                let sync: TimeSyncPayload = serde_json::from_value(msg.payload).unwrap_or_default();
+                if let Some(server_time) = sync.server_time {
+                    self.sync_clock(&server_time);
+                }
             }
             "ping" => {
-                let pong = AgentMessage {
-                    msg_type: "pong".to_string(),
-                    payload: serde_json::json!({}),
+                let pong = AgentMessage::reply("pong", &EmptyPayload::default(), correlation_id)
+                    .and_then(|msg| {
+                        encode_message(msg, self.config.message_limits.max_message_bytes)
+                    })
+                    .map_err(AgentError::Protocol)?;
+                tx.control(pong).await.map_err(AgentError::network)?;
+            }
+            "probe" => {
+                let result = match serde_json::from_value::<ProbePayload>(msg.payload.clone()) {
+                    Ok(probe) => probe_binary(probe),
+                    Err(e) => {
+                        self.warn_invalid_payload(&msg.msg_type, &e);
+                        ProbeResultPayload {
+                            id: request_id(&msg.payload),
+                            binary: string_field(&msg.payload, "binary").unwrap_or_default(),
+                            found: false,
+                            path: None,
+                            error: Some(format!("invalid probe: {}", e)),
+                        }
+                    }
                 };
-                tx.send(serde_json::to_string(&pong)?).await?;
+                let message = AgentMessage::reply("probe_result", &result, correlation_id)
+                    .and_then(|msg| {
+                        encode_message(msg, self.config.message_limits.max_message_bytes)
+                    })
+                    .map_err(AgentError::Protocol)?;
+                tx.control(message).await.map_err(AgentError::network)?;
+            }
+            "read_file" => match serde_json::from_value::<ReadFilePayload>(msg.payload.clone()) {
+                Ok(request) => self.spawn_read_file(request, msg.correlation_id, tx),
+                Err(e) => {
+                    self.warn_invalid_payload(&msg.msg_type, &e);
+                    let result = ReadFileResultPayload {
+                        id: request_id(&msg.payload),
+                        path: string_field(&msg.payload, "path").unwrap_or_default(),
+                        success: false,
+                        content: None,
+                        encoding: None,
+                        size: None,
+                        error: Some(format!("invalid read_file: {}", e)),
+                    };
+                    let message = encode_read_file_result(
+                        result,
+                        correlation_id,
+                        self.config.message_limits.max_message_bytes,
+                    )
+                    .map_err(AgentError::Protocol)?;
+                    tx.bulk_nowait(message).map_err(AgentError::network)?;
+                }
+            },
+            "collect_bundle" => {
+                match serde_json::from_value::<CollectBundlePayload>(msg.payload.clone()) {
+                    Ok(request) => self.spawn_collect_bundle(request, msg.correlation_id, tx),
+                    Err(e) => {
+                        self.warn_invalid_payload(&msg.msg_type, &e);
+                        let bundle = CollectBundleResultPayload {
+                            id: request_id(&msg.payload),
+                            platform: std::env::consts::OS.to_string(),
+                            collected_at: Local::now().to_rfc3339(),
+                            collectors: BTreeMap::new(),
+                            error: Some(format!("invalid collect_bundle: {}", e)),
+                        };
+                        let message = encode_collect_bundle_result(
+                            bundle,
+                            correlation_id,
+                            self.config.message_limits.max_message_bytes,
+                        )
+                        .map_err(AgentError::Protocol)?;
+                        tx.bulk_nowait(message).map_err(AgentError::network)?;
+                    }
+                }
+            }
+            "update_available" => {
+                match serde_json::from_value::<UpdateAvailablePayload>(msg.payload) {
+                    Ok(update) => {
+                        self.offer_update(update);
+                    }
+                    Err(e) => self.warn_invalid_payload(&msg.msg_type, &e),
+                }
             }
             _ => {
-                warn!("Unknown message type: {}", msg.msg_type);
+                warn_deduped(
+                    &self.log_dedup,
+                    "Unknown message type",
+                    &msg.msg_type,
+                    format_args!("Unknown message type: {}", msg.msg_type),
+                );
             }
         }
 
         Ok(())
     }
 
-    /// Executes a task and sends the result back to the server.
-    pub async fn execute_task(
+    /// Reads a file off the message loop, so a slow file system does not hold
+    /// up pings and other frames, and queues the result.
+    fn spawn_read_file(
         &self,
-        task: TaskPayload,
-        tx: &tokio::sync::mpsc::Sender<String>,
-    ) -> Result<()> {
-        info!(
-            "Executing task {} (technique: {})",
-            task.id, task.technique_id
-        );
-
-        let timeout = task.timeout.unwrap_or(300);
-        let result = self
-            .executor
-            .execute(&task.executor, &task.command, Duration::from_secs(timeout))
-            .await;
+        request: ReadFilePayload,
+        correlation_id: Option<String>,
+        tx: &Outbox,
+    ) {
+        let config = self.config.file_read.clone();
+        let max_bytes = self.config.message_limits.max_message_bytes;
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let path = request.path.clone();
+            let sent = async {
+                let result =
+                    tokio::task::spawn_blocking(move || file_read::read_file(request, &config))
+                        .await?;
+                tx.bulk(encode_read_file_result(
+                    result,
+                    correlation_id.as_deref(),
+                    max_bytes,
+                )?)
+                .await?;
+                Ok::<_, anyhow::Error>(())
+            };
+            if let Err(e) = sent.await {
+                warn!("Failed to answer read_file for {}: {}", path, e);
+            }
+        });
+    }
 
-        let response = AgentMessage {
-            msg_type: "task_result".to_string(),
-            payload: serde_json::json!({
-                "task_id": task.id,
-                "technique_id": task.technique_id,
-                "success": result.success,
-                "output": result.output,
-                "exit_code": result.exit_code,
-            }),
-        };
+    /// Collects the artifact bundle off the message loop, since its tools
+    /// can take minutes, and queues the result.
+    fn spawn_collect_bundle(
+        &self,
+        request: CollectBundlePayload,
+        correlation_id: Option<String>,
+        tx: &Outbox,
+    ) {
+        let max_bytes = self.config.message_limits.max_message_bytes;
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let sent = async {
+                let bundle = collect::collect_bundle(request).await;
+                tx.bulk(encode_collect_bundle_result(
+                    bundle,
+                    correlation_id.as_deref(),
+                    max_bytes,
+                )?)
+                .await?;
+                Ok::<_, anyhow::Error>(())
+            };
+            if let Err(e) = sent.await {
+                warn!("Failed to answer collect_bundle: {}", e);
+            }
+        });
+    }
 
-        tx.send(serde_json::to_string(&response)?).await?;
+    /// Logs a server request whose payload could not be parsed; the
+    /// connection carries on.
+    fn warn_invalid_payload(&self, msg_type: &str, error: &serde_json::Error) {
+        warn_deduped(
+            &self.log_dedup,
+            "Invalid payload",
+            msg_type,
+            format_args!("Invalid {} payload: {}", msg_type, error),
+        );
+    }
 
-        if let Some(cleanup) = task.cleanup {
-            debug!("Executing cleanup command");
-            let _ = self
-                .executor
-                .execute(&task.executor, &cleanup, Duration::from_secs(30))
-                .await;
+    /// Warns when a task's sequence number shows tasks were lost or
+    /// reordered, asking the server to resync after a gap if configured.
+    async fn check_task_seq(&self, seq: u64, task_id: &str, tx: &Outbox) -> Result<(), AgentError> {
+        let check = self
+            .task_seq
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .observe(seq);
+        match check {
+            SeqCheck::InOrder => {}
+            SeqCheck::Gap { expected, received } => {
+                warn!(
+                    "Task {} has seq {} but {} was expected: {} task(s) lost",
+                    task_id,
+                    received,
+                    expected,
+                    received - expected
+                );
+                if self.config.resync_on_seq_gap {
+                    let request = ResyncRequestPayload {
+                        expected_seq: expected,
+                        received_seq: received,
+                    };
+                    let message = AgentMessage::new("resync_request", &request)
+                        .and_then(|msg| {
+                            encode_message(msg, self.config.message_limits.max_message_bytes)
+                        })
+                        .map_err(AgentError::Protocol)?;
+                    tx.control(message).await.map_err(AgentError::network)?;
+                }
+            }
+            SeqCheck::Stale { last, received } => warn!(
+                "Task {} has seq {}, not after the last seen {}: duplicate or out of order",
+                task_id, received, last
+            ),
         }
-
         Ok(())
     }
-}
 
-#[cfg(test)]
+    /// Logs a newer version announced by the server and, with `auto_update`,
+    /// installs it in the background once no task is in flight.
+    fn offer_update(&self, update: UpdateAvailablePayload) -> bool {
+        match update::is_newer(update::CURRENT_VERSION, &update.version) {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!(
+                    "Server offers agent {}, running {}",
+                    update.version,
+                    update::CURRENT_VERSION
+                );
+                return false;
+            }
+            Err(e) => {
+                warn!("Ignoring update_available: {}", e);
+                return false;
+            }
+        }
+        info!(
+            "Agent {} is available (running {})",
+            update.version,
+            update::CURRENT_VERSION
+        );
+        if !self.config.auto_update {
+            return false;
+        }
+        if !self.tasks.is_empty() {
+            warn!(
+                "Not updating to {} while tasks are in flight",
+                update.version
+            );
+            return false;
+        }
+        // No new task starts from here; the binary is only swapped once
+        // the running ones are done
+        self.updating.store(true, Ordering::Relaxed);
+        let was_paused = self.tasks.set_paused(true);
+        let tls = self.config.tls.clone();
+        let tasks = self.tasks.clone();
+        let updating = self.updating.clone();
+        tokio::spawn(async move {
+            let version = update.version.clone();
+            let idle = async {
+                while !tasks.is_empty() {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+            };
+            if let Err(e) = update::install(update, &tls, idle).await {
+                error!("Failed to update the agent to {}: {:#}", version, e);
+                if !was_paused {
+                    tasks.set_paused(false);
+                }
+                updating.store(false, Ordering::Relaxed);
+            }
+        });
+        true
+    }
+
+    /// Records the clock offset against the server's RFC 3339 `server_time`,
+    /// warning when it exceeds the configured threshold. Returns whether it did.
+    fn sync_clock(&self, server_time: &str) -> bool {
+        let server_time = match DateTime::parse_from_rfc3339(server_time) {
+            Ok(server_time) => server_time,
+            Err(e) => {
+                warn!("Invalid server time {:?}: {}", server_time, e);
+                return false;
+            }
+        };
+
+        let offset = self.clock_skew.record(server_time, Local::now());
+        let threshold_ms = self.config.clock_skew_warn_secs.saturating_mul(1000);
+        let excessive = offset.unsigned_abs() > threshold_ms;
+        if excessive {
+            warn!(
+                "Agent clock is {} ms {} the server; result timestamps will not line up",
+                offset.unsigned_abs(),
+                if offset > 0 { "behind" } else { "ahead of" }
+            );
+        } else {
+            debug!("Clock skew against server: {} ms", offset);
+        }
+        excessive
+    }
+
+    /// Runs a task on its own tokio task once a slot is free, so the read
+    /// loop keeps serving messages, and tracks it until it finishes.
+    ///
+    /// A cancelled task's run is dropped, which kills its process group; a
+    /// `cancelled` result is sent in its place unless it already reported.
+    fn spawn_task(&self, task: TaskPayload, tx: &Outbox) {
+        let client = self.clone();
+        let tx = tx.clone();
+        let (key, cancelled) = self.tasks.track(&task.id);
+
+        tokio::spawn(async move {
+            let accepted_at = Local::now();
+            let task_id = task.id.clone();
+            let technique_id = task.technique_id.clone();
+            let group = task.group_id.clone();
+            let run = async {
+                // A group waits for a slot with one task at a time, so slots
+                // go round-robin between groups
+                let _turn = match &group {
+                    Some(group) => Some(client.groups.enter(group).await),
+                    None => None,
+                };
+                let _slot = client.tasks.slot().await;
+                client.execute_task(task, &tx).await
+            };
+
+            tokio::select! {
+                result = run => {
+                    if let Err(e) = result {
+                        error!("Task {} failed: {}", task_id, e);
+                    }
+                }
+                Ok(cancelled) = cancelled => {
+                    warn!("Task {} cancelled", task_id);
+                    if !cancelled.reported {
+                        let result = client.send_cancelled(&task_id, &technique_id, accepted_at, &tx);
+                        if let Err(e) = result.await {
+                            error!("Failed to report cancelled task {}: {}", task_id, e);
+                        }
+                    }
+                }
+            }
+            client.tasks.finish(key);
+        });
+    }
+
+    /// Sends the result of a task stopped before it could report.
+    async fn send_cancelled(
+        &self,
+        task_id: &str,
+        technique_id: &str,
+        accepted_at: DateTime<Local>,
+        tx: &Outbox,
+    ) -> Result<(), AgentError> {
+        let result = TaskResultPayload {
+            cancelled: true,
+            ..unrun_result(task_id, technique_id, accepted_at, "Task cancelled")
+        };
+        self.send_unrun_result(&result, tx).await?;
+        self.record_history(HistoryEntry {
+            timestamp: Local::now().to_rfc3339(),
+            task_id: task_id.to_string(),
+            technique_id: technique_id.to_string(),
+            status: "cancelled".to_string(),
+            duration_ms: None,
+            exit_code: None,
+            command: None,
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Answers a task received while paused under the `reject` pause policy.
+    async fn send_rejected_paused(
+        &self,
+        task: &TaskPayload,
+        tx: &Outbox,
+    ) -> Result<(), AgentError> {
+        warn!("Task {} rejected: task processing is paused", task.id);
+        let result = unrun_result(
+            &task.id,
+            &task.technique_id,
+            Local::now(),
+            "Task rejected: task processing is paused",
+        );
+        self.send_unrun_result(&result, tx).await
+    }
+
+    /// Whether every task slot is busy and results are backing up past
+    /// `overload_result_backlog`, so a new task would only add to the backlog.
+    fn is_overloaded(&self, tx: &Outbox) -> bool {
+        self.config
+            .overload_result_backlog
+            .is_some_and(|backlog| self.tasks.is_saturated() && tx.bulk_queued() >= backlog)
+    }
+
+    /// Answers a task whose `result_endpoint` is refused, without running it.
+    async fn send_rejected_endpoint(
+        &self,
+        task: &TaskPayload,
+        error: &anyhow::Error,
+        tx: &Outbox,
+    ) -> Result<(), AgentError> {
+        warn!("Task {} rejected: {}", task.id, error);
+        let result = unrun_result(
+            &task.id,
+            &task.technique_id,
+            Local::now(),
+            &format!("Task rejected: {}", error),
+        );
+        self.send_unrun_result(&result, tx).await
+    }
+
+    /// Answers a task whose `requires` this host does not meet, without
+    /// running it.
+    async fn send_skipped(
+        &self,
+        task: &TaskPayload,
+        reason: &str,
+        tx: &Outbox,
+    ) -> Result<(), AgentError> {
+        info!("Task {} skipped: {}", task.id, reason);
+        let result = TaskResultPayload {
+            skipped: true,
+            ..unrun_result(
+                &task.id,
+                &task.technique_id,
+                Local::now(),
+                &format!("Task skipped: {}", reason),
+            )
+        };
+        self.send_unrun_result(&result, tx).await
+    }
+
+    /// Answers a task received while the agent is overloaded.
+    async fn send_rejected_overloaded(
+        &self,
+        task: &TaskPayload,
+        tx: &Outbox,
+    ) -> Result<(), AgentError> {
+        warn!(
+            "Task {} rejected: all task slots busy and {} result(s) waiting to be sent",
+            task.id,
+            tx.bulk_queued()
+        );
+        let result = unrun_result(
+            &task.id,
+            &task.technique_id,
+            Local::now(),
+            "agent overloaded, retry later",
+        );
+        self.send_unrun_result(&result, tx).await
+    }
+
+    /// Answers a task whose payload could not be parsed, so the server sees
+    /// why it did not run. Without a task ID, there is nothing to answer.
+    async fn send_rejected_invalid(
+        &self,
+        payload: &serde_json::Value,
+        error: &serde_json::Error,
+        tx: &Outbox,
+    ) -> Result<(), AgentError> {
+        let field = |name: &str| payload.get(name).and_then(|v| v.as_str());
+        let Some(task_id) = field("id") else {
+            warn!("Ignoring task without an id: {}", error);
+            return Ok(());
+        };
+        warn!("Task {} rejected: {}", task_id, error);
+        let result = unrun_result(
+            task_id,
+            field("technique_id").unwrap_or_default(),
+            Local::now(),
+            &format!("Task rejected: {}", error),
+        );
+        self.send_unrun_result(&result, tx).await
+    }
+
+    /// Sends the result of a task whose command did not run. Called from the
+    /// read loop, so it does not wait for room in the queue.
+    async fn send_unrun_result(
+        &self,
+        result: &TaskResultPayload,
+        tx: &Outbox,
+    ) -> Result<(), AgentError> {
+        let response = AgentMessage::new("task_result", result)
+            .and_then(|msg| encode_message(msg, self.config.message_limits.max_message_bytes))
+            .map_err(AgentError::Protocol)?;
+        tx.bulk_nowait(response).map_err(AgentError::network)?;
+        if let Ok(mut pending) = self.pending_acks.lock() {
+            pending.record(&result.task_id);
+        }
+        Ok(())
+    }
+
+    /// Sends a task's encoded result to its `result_endpoint`, or to the
+    /// server when it has none. A result the endpoint refuses goes to the
+    /// server, so it is not lost.
+    async fn deliver_result(
+        &self,
+        task: &TaskPayload,
+        response: String,
+        tx: &Outbox,
+    ) -> Result<(), AgentError> {
+        // Invalid endpoints are rejected on arrival
+        let route = result_endpoint::route(
+            task.result_endpoint.as_deref(),
+            self.config.allow_insecure_result_endpoints,
+        )
+        .unwrap_or(Route::WebSocket);
+        if let Route::Http(url) = &route {
+            // Only the server itself is trusted with the agent's credential
+            let secret = self
+                .config
+                .agent_secret
+                .as_ref()
+                .filter(|_| result_endpoint::same_origin(url, &self.config.server_url));
+            match result_endpoint::post(url, response.clone(), &self.config.tls, secret).await {
+                Ok(()) => {
+                    info!("Result of task {} delivered to {}", task.id, url);
+                    return Ok(());
+                }
+                Err(e) => warn!(
+                    "Failed to deliver the result of task {} to {}, sending it to the server: {:#}",
+                    task.id, url, e
+                ),
+            }
+        }
+        tx.bulk(response).await.map_err(AgentError::network)?;
+        if let Ok(mut pending) = self.pending_acks.lock() {
+            pending.record(&task.id);
+        }
+        Ok(())
+    }
+
+    /// Adds a finished task to the local history, if one is kept, and to
+    /// the system log with `syslog`. The command is dropped unless
+    /// `history.record_commands` is set.
+    async fn record_history(&self, mut entry: HistoryEntry) {
+        if self.config.syslog {
+            syslog::report(&syslog::TaskRecord {
+                task_id: &entry.task_id,
+                technique_id: &entry.technique_id,
+                success: entry.status == "success",
+                exit_code: entry.exit_code,
+            });
+        }
+        let Some(history) = self.history.clone() else {
+            return;
+        };
+        if !history.records_commands() {
+            entry.command = None;
+        }
+        let task_id = entry.task_id.clone();
+        match tokio::task::spawn_blocking(move || history.record(entry)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to record task {} in the history: {:#}", task_id, e),
+            Err(e) => warn!("Failed to record task {} in the history: {}", task_id, e),
+        }
+    }
+
+    /// Executes a task and sends the result back to the server.
+    pub async fn execute_task(&self, task: TaskPayload, tx: &Outbox) -> Result<(), AgentError> {
+        let span = info_span!(
+            "task",
+            task_id = %task.id,
+            technique_id = %task.technique_id,
+            executor = %task.executor,
+            exit_code = field::Empty,
+            status = field::Empty,
+        );
+        telemetry::set_parent(&span, task.traceparent.as_deref());
+
+        self.run_task(task, tx).instrument(span).await
+    }
+
+    async fn run_task(&self, task: TaskPayload, tx: &Outbox) -> Result<(), AgentError> {
+        info!(
+            "Executing task {} (technique: {})",
+            task.id, task.technique_id
+        );
+
+        // Held until cleanup is done, so the next run of the technique starts clean
+        let wait_started = std::time::Instant::now();
+        let technique_guard = if self.config.serialize_same_technique && !task.allow_concurrent {
+            Some(self.technique_locks.acquire(&task.technique_id).await)
+        } else {
+            None
+        };
+        let technique_wait_ms = technique_guard
+            .as_ref()
+            .map(|_| wait_started.elapsed().as_millis() as u64);
+        if let Some(wait) = technique_wait_ms.filter(|&wait| wait > 0) {
+            debug!(
+                "Task {} waited {} ms for technique {}",
+                task.id, wait, task.technique_id
+            );
+        }
+
+        // Clamped into duration_bounds, or rejecting the task with strict_durations
+        let task_timeout = task
+            .timeout
+            .map(|timeout| self.config.task_timeout(timeout).map_err(|e| e.to_string()))
+            .transpose();
+        // A deadline in the server's time caps the timeout, or stands in for it
+        let deadline = task_timeout.clone().and_then(|_| {
+            task.deadline
+                .as_deref()
+                .map(|deadline| seconds_until(deadline, Local::now(), self.clock_skew.offset_ms()))
+                .transpose()
+        });
+        let task_timeout = task_timeout.ok().flatten();
+        let requested = match &deadline {
+            Ok(Some(remaining)) => task_timeout.map_or(*remaining, |t| t.min(*remaining)),
+            _ => task_timeout.unwrap_or(300),
+        };
+        let timeout = self.config.effective_timeout(&task.executor, requested);
+        let started_at = Local::now();
+        let started = std::time::Instant::now();
+        let time_limit = Duration::from_secs(timeout);
+        // Removed on drop, so a cancelled task does not leave it behind either
+        let task_dir = self
+            .config
+            .task_dirs
+            .then(|| TaskDir::create(&std::env::temp_dir(), &task.id, task.keep_task_dir))
+            .transpose();
+        let options = ExecOptions {
+            force_utf8_console: task
+                .force_utf8_console
+                .unwrap_or(self.config.force_utf8_console),
+            task_dir: task_dir
+                .as_ref()
+                .ok()
+                .and_then(Option::as_ref)
+                .map(|dir| dir.path().to_path_buf()),
+            netns: task.netns.clone(),
+            env_denylist: self.env_denylist.clone(),
+            login_shell: task.login_shell.unwrap_or(self.config.login_shell),
+        };
+        // Children inherit the agent's identity, so capturing it here matches the command's
+        let context = exec_context::capture();
+        debug!("Task {} runs under {:?}", task.id, context);
+        let mut steps = Vec::new();
+        let mut result = if let Err(e) = &deadline {
+            warn!("Task {} rejected: {}", task.id, e);
+            ExecutionResult {
+                success: false,
+                output: format!("Task rejected: {}", e),
+                exit_code: None,
+                program: None,
+                spawn_latency_ms: None,
+                output_budget_limited: false,
+                spawned_argv: None,
+            }
+        } else if let Err(e) = &task_dir {
+            error!("Failed to create the directory of task {}: {}", task.id, e);
+            ExecutionResult {
+                success: false,
+                output: format!("Execution error: cannot create task directory: {}", e),
+                exit_code: None,
+                program: None,
+                spawn_latency_ms: None,
+                output_budget_limited: false,
+                spawned_argv: None,
+            }
+        } else if let Some(argv) = &task.argv {
+            self.executor.execute_argv(argv, time_limit, &options).await
+        } else if !task.steps.is_empty() {
+            let (result, results) = self.run_steps(&task, time_limit, &options).await;
+            steps = results;
+            result
+        } else {
+            self.run_command(&task, &task.executor, &task.command, time_limit, &options)
+                .await
+        };
+        let finished_at = Local::now();
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let (executor_requested, executor_used) = match task.argv {
+            Some(_) => (None, None),
+            None if task.executor == helper::HELPER_EXECUTOR => {
+                (Some(task.executor.clone()), Some(task.executor.clone()))
+            }
+            None => (
+                Some(task.executor.clone()),
+                Some(executor::resolve_executor(&task.executor).0.to_string()),
+            ),
+        };
+        // Helpers and argv commands run without a shell; rejected tasks
+        // run nothing
+        let login_shell = options.login_shell
+            && result.program.is_some()
+            && task.argv.is_none()
+            && task.executor != helper::HELPER_EXECUTOR;
+        let effective_path = match login_shell {
+            true => self.executor.probe_path(&task.executor, &options).await,
+            false => None,
+        };
+        let output_match = match task.expect_output_regex.as_deref() {
+            Some(pattern) if !steps.is_empty() => {
+                let outputs: Vec<String> = steps
+                    .iter()
+                    .map(|step| format!("[{}]\n{}", step.name, step.output))
+                    .collect();
+                Some(match_expected_output(pattern, &outputs.join("\n")).await)
+            }
+            Some(pattern) => Some(match_expected_output(pattern, &result.output).await),
+            None => None,
+        };
+        if let Some(Err(e)) = &output_match {
+            warn!("Task {}: {}", task.id, e);
+        }
+        let verification = (!task.verify_registry.is_empty() || task.capture_events.is_some())
+            .then(|| Verification {
+                registry: registry::read_values(&task.verify_registry),
+                events: task
+                    .capture_events
+                    .as_ref()
+                    .map(|capture| eventlog::capture(capture, started.elapsed())),
+            });
+        // Sampled last, so output_matched saw the whole output
+        if let Some(sample) = &task.output_sample {
+            let (head, tail) = (sample.head_lines, sample.tail_lines);
+            result.output = executor::sample_lines(&result.output, head, tail);
+            for step in &mut steps {
+                step.output = executor::sample_lines(&step.output, head, tail);
+            }
+        }
+
+        let span = Span::current();
+        span.record("exit_code", result.exit_code);
+        span.record("status", if result.success { "success" } else { "failed" });
+        self.record_history(HistoryEntry {
+            timestamp: finished_at.to_rfc3339(),
+            task_id: task.id.clone(),
+            technique_id: task.technique_id.clone(),
+            status: if result.success { "success" } else { "failed" }.to_string(),
+            duration_ms: Some(duration_ms),
+            exit_code: result.exit_code,
+            command: Some(match &task.argv {
+                Some(argv) => argv.join(" "),
+                None if !task.steps.is_empty() => task
+                    .steps
+                    .iter()
+                    .map(|step| step.command.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                None => task.command.clone(),
+            }),
+        })
+        .await;
+
+        let mut payload = TaskResultPayload {
+            task_id: task.id.clone(),
+            technique_id: task.technique_id.clone(),
+            parent_technique: technique::parent(&task.technique_id).map(str::to_string),
+            success: result.success,
+            output: result.output,
+            exit_code: result.exit_code,
+            started_at: started_at.to_rfc3339(),
+            finished_at: finished_at.to_rfc3339(),
+            duration_ms: Some(duration_ms),
+            spawn_latency_ms: result.spawn_latency_ms,
+            shell_path: result.program,
+            spawned_argv: result
+                .spawned_argv
+                .filter(|_| self.config.report_spawned_argv)
+                .map(|argv| redact_argv(argv, self.config.agent_secret.as_ref())),
+            executor_requested,
+            executor_used,
+            login_shell,
+            effective_path,
+            technique_wait_ms,
+            verification,
+            output_matched: output_match.as_ref().and_then(|m| m.as_ref().ok().copied()),
+            output_match_error: output_match.and_then(Result::err),
+            steps,
+            truncated_fields: Vec::new(),
+            output_budget_limited: result.output_budget_limited,
+            cancelled: false,
+            skipped: false,
+            context: Some(context),
+        };
+        if let Some(fields) = &self.config.result_fields {
+            payload.retain_fields(fields);
+        }
+        let max_message_bytes = self.config.message_limits.max_message_bytes;
+        let response = match AgentMessage::new("task_result", &payload)
+            .and_then(|msg| encode_message(msg, max_message_bytes))
+        {
+            Ok(response) => response,
+            Err(e) => {
+                // Still report the outcome, without what does not fit
+                warn!(
+                    "Task {}: sending its result without details: {}",
+                    task.id, e
+                );
+                let summary = TaskResultPayload {
+                    success: payload.success,
+                    exit_code: payload.exit_code,
+                    duration_ms: payload.duration_ms,
+                    truncated_fields: vec!["output".to_string(), "steps".to_string()],
+                    ..unrun_result(
+                        &task.id,
+                        &task.technique_id,
+                        started_at,
+                        &format!("Result too large: {}", e),
+                    )
+                };
+                AgentMessage::new("task_result", &summary)
+                    .and_then(|msg| encode_message(msg, max_message_bytes))
+                    .map_err(AgentError::Protocol)?
+            }
+        };
+
+        self.deliver_result(&task, response, tx).await?;
+        self.tasks.mark_reported(&task.id);
+
+        if let (Some(cleanup), Ok(_), Ok(_)) = (&task.cleanup, &task_dir, &deadline) {
+            debug!("Executing cleanup command");
+            let timeout = Duration::from_secs(self.config.effective_timeout(&task.executor, 30));
+            let cleanup_run = async {
+                if task.executor == helper::HELPER_EXECUTOR {
+                    self.run_helper(&task, cleanup, timeout, &options).await
+                } else {
+                    self.executor
+                        .execute(&task.executor, cleanup, timeout, &options)
+                        .await
+                }
+            };
+            let _ = cleanup_run.instrument(info_span!("cleanup")).await;
+        }
+        drop(task_dir);
+        drop(technique_guard);
+
+        Ok(())
+    }
+
+    /// Runs `command` of `task` with `executor`: through the helper, in a
+    /// pseudo-terminal with `use_pty`, or in a shell.
+    async fn run_command(
+        &self,
+        task: &TaskPayload,
+        executor: &str,
+        command: &str,
+        time_limit: Duration,
+        options: &ExecOptions,
+    ) -> ExecutionResult {
+        if executor == helper::HELPER_EXECUTOR {
+            self.run_helper(task, command, time_limit, options).await
+        } else if task.use_pty {
+            self.executor
+                .execute_in_pty(executor, command, time_limit, options)
+                .await
+        } else {
+            self.executor
+                .execute(executor, command, time_limit, options)
+                .await
+        }
+    }
+
+    /// Runs the `steps` of `task` in order, each within its own timeout and
+    /// what is left of `time_limit`, until one fails without
+    /// `continue_on_error`. The task succeeds if no other step failed; its
+    /// exit code, program and command line are those of the last step run.
+    /// Outputs are only in the step results, so the task's own is empty.
+    async fn run_steps(
+        &self,
+        task: &TaskPayload,
+        time_limit: Duration,
+        options: &ExecOptions,
+    ) -> (ExecutionResult, Vec<StepResult>) {
+        let started = std::time::Instant::now();
+        let mut combined = ExecutionResult {
+            success: true,
+            output: String::new(),
+            exit_code: None,
+            program: None,
+            spawn_latency_ms: None,
+            output_budget_limited: false,
+            spawned_argv: None,
+        };
+        let mut results = Vec::with_capacity(task.steps.len());
+        for step in &task.steps {
+            if !combined.success {
+                results.push(StepResult {
+                    name: step.name.clone(),
+                    success: false,
+                    skipped: true,
+                    exit_code: None,
+                    output: String::new(),
+                    duration_ms: None,
+                });
+                continue;
+            }
+            let executor = step.executor.as_deref().unwrap_or(&task.executor);
+            let step_started = std::time::Instant::now();
+            let result = match step
+                .timeout
+                .map(|t| self.config.task_timeout(t))
+                .transpose()
+            {
+                Ok(timeout) => {
+                    let remaining = time_limit.saturating_sub(started.elapsed());
+                    let timeout = timeout.map_or(remaining, |t| {
+                        Duration::from_secs(self.config.effective_timeout(executor, t))
+                            .min(remaining)
+                    });
+                    self.run_command(task, executor, &step.command, timeout, options)
+                        .await
+                }
+                Err(e) => ExecutionResult {
+                    success: false,
+                    output: format!("Step rejected: {}", e),
+                    exit_code: None,
+                    program: None,
+                    spawn_latency_ms: None,
+                    output_budget_limited: false,
+                    spawned_argv: None,
+                },
+            };
+            if !result.success {
+                warn!("Task {}: step {:?} failed", task.id, step.name);
+            }
+
+            combined.success &= result.success || step.continue_on_error;
+            combined.exit_code = result.exit_code;
+            combined.program = result.program;
+            combined.spawn_latency_ms = combined.spawn_latency_ms.or(result.spawn_latency_ms);
+            combined.output_budget_limited |= result.output_budget_limited;
+            combined.spawned_argv = result.spawned_argv;
+            results.push(StepResult {
+                name: step.name.clone(),
+                success: result.success,
+                skipped: false,
+                exit_code: result.exit_code,
+                output: result.output,
+                duration_ms: Some(step_started.elapsed().as_millis() as u64),
+            });
+        }
+        (combined, results)
+    }
+
+    /// Runs `command` of `task` through the configured helper binary.
+    async fn run_helper(
+        &self,
+        task: &TaskPayload,
+        command: &str,
+        time_limit: Duration,
+        options: &ExecOptions,
+    ) -> ExecutionResult {
+        let request = HelperRequest {
+            task_id: task.id.clone(),
+            technique_id: task.technique_id.clone(),
+            command: command.to_string(),
+            timeout: time_limit.as_secs(),
+        };
+        helper::run(
+            self.config.helper_path.as_deref(),
+            &request,
+            time_limit,
+            options,
+        )
+        .await
+    }
+}
+
+/// Serializes an outbound message, trimming a task's `output` and its
+/// steps' outputs, longest first, when the message would exceed
+/// `max_bytes`. Fails if it cannot be made to fit.
+fn encode_message(mut msg: AgentMessage, max_bytes: usize) -> Result<String> {
+    let mut encoded = serde_json::to_string(&msg)?;
+    while encoded.len() > max_bytes {
+        let pointers = output_pointers(&msg.payload);
+        if pointers.is_empty() {
+            anyhow::bail!(
+                "{} message is {} bytes, over the {} byte limit",
+                msg.msg_type,
+                encoded.len(),
+                max_bytes
+            );
+        }
+        let longest = pointers
+            .into_iter()
+            .filter_map(|pointer| {
+                let output = msg.payload.pointer(pointer.as_str())?.as_str()?;
+                let (base, total) = executor::strip_truncation_notice(output);
+                let total = total.unwrap_or(base.len());
+                (!base.is_empty()).then(|| (base.to_string(), total, pointer))
+            })
+            .max_by_key(|(base, _, _)| base.len());
+        let Some((base, total, pointer)) = longest else {
+            anyhow::bail!(
+                "{} message is {} bytes even without output, over the {} byte limit",
+                msg.msg_type,
+                encoded.len(),
+                max_bytes
+            );
+        };
+
+        // Every output byte takes at least one byte once escaped, so dropping
+        // the excess converges within a few passes.
+        let excess = encoded.len() - max_bytes;
+        let notice_len = executor::truncation_notice(base.len(), total).len();
+        let keep =
+            executor::find_char_boundary(&base, base.len().saturating_sub(excess + notice_len));
+        let output = format!(
+            "{}{}",
+            &base[..keep],
+            executor::truncation_notice(keep, total)
+        );
+        if let Some(field) = msg.payload.pointer_mut(&pointer) {
+            *field = output.into();
+        }
+        let field = if pointer == "/output" {
+            "output"
+        } else {
+            "steps"
+        };
+        let truncated = &mut msg.payload["truncated_fields"];
+        if !truncated.is_array() {
+            *truncated = serde_json::json!([]);
+        }
+        if let Some(fields) = truncated.as_array_mut() {
+            if !fields.iter().any(|f| f == field) {
+                fields.push(field.into());
+            }
+        }
+        encoded = serde_json::to_string(&msg)?;
+    }
+    Ok(encoded)
+}
+
+/// JSON pointers of the outputs in `payload` that may be trimmed: its own
+/// and its steps'.
+fn output_pointers(payload: &serde_json::Value) -> Vec<String> {
+    let mut pointers = Vec::new();
+    if payload.get("output").is_some_and(|v| v.is_string()) {
+        pointers.push("/output".to_string());
+    }
+    if let Some(steps) = payload.get("steps").and_then(|v| v.as_array()) {
+        for (i, step) in steps.iter().enumerate() {
+            if step.get("output").is_some_and(|v| v.is_string()) {
+                pointers.push(format!("/steps/{}/output", i));
+            }
+        }
+    }
+    pointers
+}
+
+/// Logs `message` as a warning unless `template` about `detail` was logged
+/// within the de-duplication window, with the summaries of closed windows.
+fn warn_deduped(
+    dedup: &LogDedup,
+    template: &'static str,
+    detail: &str,
+    message: std::fmt::Arguments,
+) {
+    let report = dedup.record(template, detail, Instant::now());
+    for summary in report.summaries {
+        warn!("{}", summary);
+    }
+    if report.log {
+        warn!("{}", message);
+    }
+}
+
+/// Replaces the agent secret in `argv` by `[REDACTED]`.
+fn redact_argv(argv: Vec<String>, secret: Option<&SecretString>) -> Vec<String> {
+    match secret.filter(|s| !s.expose().is_empty()) {
+        Some(secret) => argv
+            .into_iter()
+            .map(|arg| arg.replace(secret.expose(), "[REDACTED]"))
+            .collect(),
+        None => argv,
+    }
+}
+
+/// Result of a task that reports `output` without having run its command.
+fn unrun_result(
+    task_id: &str,
+    technique_id: &str,
+    accepted_at: DateTime<Local>,
+    output: &str,
+) -> TaskResultPayload {
+    TaskResultPayload {
+        task_id: task_id.to_string(),
+        technique_id: technique_id.to_string(),
+        parent_technique: technique::parent(technique_id).map(str::to_string),
+        success: false,
+        output: output.to_string(),
+        exit_code: None,
+        started_at: accepted_at.to_rfc3339(),
+        finished_at: Local::now().to_rfc3339(),
+        duration_ms: None,
+        spawn_latency_ms: None,
+        shell_path: None,
+        spawned_argv: None,
+        executor_requested: None,
+        executor_used: None,
+        login_shell: false,
+        effective_path: None,
+        technique_wait_ms: None,
+        verification: None,
+        output_matched: None,
+        output_match_error: None,
+        steps: Vec::new(),
+        truncated_fields: Vec::new(),
+        output_budget_limited: false,
+        cancelled: false,
+        skipped: false,
+        context: None,
+    }
+}
+
+/// Whole seconds left at local time `now` until `deadline`, an RFC 3339 time
+/// on the server's clock, which is `clock_skew_ms` ahead of the agent's.
+/// Fails when the deadline is invalid or less than a second away.
+fn seconds_until(
+    deadline: &str,
+    now: DateTime<Local>,
+    clock_skew_ms: Option<i64>,
+) -> std::result::Result<u64, String> {
+    let parsed = DateTime::parse_from_rfc3339(deadline)
+        .map_err(|e| format!("invalid deadline {:?}: {}", deadline, e))?;
+    let server_now = now + chrono::Duration::milliseconds(clock_skew_ms.unwrap_or(0));
+    let remaining = parsed.signed_duration_since(server_now).num_seconds();
+    if remaining < 1 {
+        return Err(format!("deadline {} has passed", deadline));
+    }
+    Ok(remaining as u64)
+}
+
+/// Evaluates a task's expected-output regex against its output, returning
+/// whether it matched or why the pattern was rejected. The regex engine runs
+/// in linear time, so a hostile pattern can only cost its bounded compile size.
+async fn match_expected_output(pattern: &str, output: &str) -> std::result::Result<bool, String> {
+    patterns::regex(pattern)
+        .await
+        .map(|regex| regex.is_match(output))
+        .map_err(|e| format!("invalid expect_output_regex: {}", e))
+}
+
+/// Encodes a `read_file_result`, replacing content that does not fit in a
+/// message with an error.
+fn encode_read_file_result(
+    mut result: ReadFileResultPayload,
+    correlation_id: Option<&str>,
+    max_bytes: usize,
+) -> Result<String> {
+    let reply = |result: &ReadFileResultPayload| {
+        AgentMessage::reply("read_file_result", result, correlation_id)
+    };
+    let encoded = encode_message(reply(&result)?, max_bytes);
+    if encoded.is_ok() || result.content.is_none() {
+        return encoded;
+    }
+    result.success = false;
+    result.content = None;
+    result.encoding = None;
+    result.error = Some(format!(
+        "file content does not fit in a {} byte message",
+        max_bytes
+    ));
+    encode_message(reply(&result)?, max_bytes)
+}
+
+/// Encodes a `collect_bundle_result`, replacing the collected entries with
+/// an error when they do not fit in a message.
+fn encode_collect_bundle_result(
+    mut bundle: CollectBundleResultPayload,
+    correlation_id: Option<&str>,
+    max_bytes: usize,
+) -> Result<String> {
+    let reply = |bundle: &CollectBundleResultPayload| {
+        AgentMessage::reply("collect_bundle_result", bundle, correlation_id)
+    };
+    let encoded = encode_message(reply(&bundle)?, max_bytes);
+    if encoded.is_ok() {
+        return encoded;
+    }
+    for output in bundle.collectors.values_mut() {
+        output.items.clear();
+    }
+    bundle.error = Some(format!(
+        "bundle does not fit in a {} byte message",
+        max_bytes
+    ));
+    encode_message(reply(&bundle)?, max_bytes)
+}
+
+/// String field `name` of a request payload, if present.
+fn string_field(payload: &serde_json::Value, name: &str) -> Option<String> {
+    payload
+        .get(name)
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+}
+
+/// Identifier of a request whose payload is invalid, to echo in the error.
+fn request_id(payload: &serde_json::Value) -> Option<String> {
+    string_field(payload, "id")
+}
+
+/// Answers a probe by looking the binary up on the `PATH`; nothing is executed.
+fn probe_binary(probe: ProbePayload) -> ProbeResultPayload {
+    let path = system::find_binary(&probe.binary);
+    debug!("Probe for {}: {:?}", probe.binary, path);
+    ProbeResultPayload {
+        id: probe.id,
+        binary: probe.binary,
+        found: path.is_some(),
+        path,
+        error: None,
+    }
+}
+
+/// Builds a heartbeat message with runtime metrics, optionally carrying the
+/// unacknowledged task IDs.
+fn heartbeat_message(
+    paw: &str,
+    pending_acks: Option<Vec<String>>,
+    metrics: &HeartbeatMetrics,
+    paused: bool,
+) -> Result<AgentMessage> {
+    AgentMessage::new(
+        "heartbeat",
+        &HeartbeatPayload {
+            paw: paw.to_string(),
+            metrics: metrics.clone(),
+            pending_acks,
+            paused,
+        },
+    )
+}
+
+/// Aborts the task when dropped.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Waits for the next health check run; never completes when the checks are
+/// disabled.
+async fn next_check(checks: &mut Option<Interval>) {
+    match checks {
+        Some(checks) => {
+            checks.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Builds the `connection_diagnostics` message reporting an earlier TLS failure.
+fn connection_diagnostics_message(paw: &str, diagnostics: &TlsDiagnostics) -> Result<AgentMessage> {
+    AgentMessage::new(
+        "connection_diagnostics",
+        &ConnectionDiagnosticsPayload {
+            paw: paw.to_string(),
+            tls_error: diagnostics.clone(),
+        },
+    )
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::TlsConfig;
+    use crate::config::{MessageLimits, TlsConfig};
+    use crate::health::HealthCheck;
+    use crate::protocol::{OutputSample, TaskStep};
+    use crate::secret::SecretString;
+    use crate::system::FsInfo;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     fn create_test_config() -> AgentConfig {
         AgentConfig {
@@ -297,6 +2297,7 @@ mod tests {
             heartbeat_interval: 30,
             tls: TlsConfig::default(),
             agent_secret: None,
+            ..AgentConfig::default()
         }
     }
 
@@ -306,7 +2307,8 @@ mod tests {
             paw: "test-paw-123".to_string(),
             heartbeat_interval: 30,
             tls: TlsConfig::default(),
-            agent_secret: Some("test-secret".to_string()),
+            agent_secret: Some(SecretString::from("test-secret")),
+            ..AgentConfig::default()
         }
     }
 
@@ -318,6 +2320,14 @@ mod tests {
             executors: vec!["sh".to_string(), "bash".to_string()],
             os_version: "5.0".to_string(),
             architecture: "x86_64".to_string(),
+            filesystems: Vec::new(),
+            process_bits: 64,
+            os_bits: 64,
+            is_wow64: false,
+            agent_pid: 4242,
+            agent_ppid: Some(1),
+            agent_exe: Some("/opt/autostrike/autostrike-agent".to_string()),
+            launch_context: "systemd".to_string(),
         }
     }
 
@@ -325,6 +2335,7 @@ mod tests {
     fn test_agent_message_serialization() {
         let msg = AgentMessage {
             msg_type: "register".to_string(),
+            correlation_id: None,
             payload: serde_json::json!({"key": "value"}),
         };
 
@@ -346,6 +2357,7 @@ mod tests {
     fn test_agent_message_debug() {
         let msg = AgentMessage {
             msg_type: "test".to_string(),
+            correlation_id: None,
             payload: serde_json::json!({}),
         };
 
@@ -368,7 +2380,35 @@ mod tests {
         let sys_info = create_test_sys_info();
 
         let client = AgentClient::new(config, sys_info).unwrap();
-        assert_eq!(client.config.agent_secret, Some("test-secret".to_string()));
+        assert_eq!(
+            client
+                .config
+                .agent_secret
+                .as_ref()
+                .map(SecretString::expose),
+            Some("test-secret")
+        );
+    }
+
+    #[test]
+    fn test_build_request_carries_real_secret() {
+        let config = create_test_config_with_secret();
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+
+        let request = client.build_request().unwrap();
+        let header = request.headers().get("x-agent-key").unwrap();
+        assert_eq!(header.to_str().unwrap(), "test-secret");
+        assert!(header.is_sensitive());
+        assert!(!format!("{:?}", client.config).contains("test-secret"));
+    }
+
+    #[test]
+    fn test_build_request_without_secret() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+
+        let request = client.build_request().unwrap();
+        assert_eq!(request.uri().to_string(), "wss://test.server:8443/ws/agent");
+        assert!(request.headers().get("x-agent-key").is_none());
     }
 
     #[tokio::test]
@@ -377,10 +2417,11 @@ mod tests {
         let sys_info = create_test_sys_info();
         let client = AgentClient::new(config, sys_info).unwrap();
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(32);
+        let (tx, mut rx) = Outbox::channel(32);
 
         let msg = AgentMessage {
             msg_type: "ping".to_string(),
+            correlation_id: None,
             payload: serde_json::json!({}),
         };
 
@@ -389,51 +2430,2171 @@ mod tests {
 
         let response = rx.recv().await.unwrap();
         assert!(response.contains("pong"));
+        // Without a correlation id, the reply has none either
+        assert!(!response.contains("correlation_id"));
     }
 
     #[tokio::test]
-    async fn test_handle_message_unknown_type() {
-        let config = create_test_config();
-        let sys_info = create_test_sys_info();
-        let client = AgentClient::new(config, sys_info).unwrap();
+    async fn test_handle_message_echoes_correlation_id() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let request = |msg_type: &str, id: &str, payload| AgentMessage {
+            msg_type: msg_type.to_string(),
+            correlation_id: Some(id.to_string()),
+            payload,
+        };
 
-        let (tx, _rx) = tokio::sync::mpsc::channel::<String>(32);
+        let requests = [
+            request("ping", "corr-1", serde_json::json!({})),
+            request("probe", "corr-2", serde_json::json!({"binary": "sh"})),
+            request("kill_all", "corr-3", serde_json::json!({})),
+        ];
+        for msg in requests {
+            client.handle_message(msg, &tx).await.unwrap();
+        }
 
-        let msg = AgentMessage {
-            msg_type: "unknown_type".to_string(),
-            payload: serde_json::json!({}),
-        };
+        for (msg_type, id) in [
+            ("pong", "corr-1"),
+            ("probe_result", "corr-2"),
+            ("kill_all_result", "corr-3"),
+        ] {
+            let reply: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+            assert_eq!(reply["type"], msg_type);
+            assert_eq!(reply["correlation_id"], id);
+        }
 
-        let result = client.handle_message(msg, &tx).await;
-        assert!(result.is_ok());
+        // Unsolicited messages carry none
+        let heartbeat =
+            heartbeat_message("test-paw-123", None, &HeartbeatMetrics::default(), false).unwrap();
+        assert!(heartbeat.correlation_id.is_none());
+        let encoded = encode_message(heartbeat, 4096).unwrap();
+        assert!(!encoded.contains("correlation_id"));
     }
 
+    #[cfg(unix)]
     #[tokio::test]
-    async fn test_handle_message_task() {
-        let config = create_test_config();
-        let sys_info = create_test_sys_info();
-        let client = AgentClient::new(config, sys_info).unwrap();
-
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(32);
+    async fn test_pong_overtakes_queued_task_results() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
 
-        let msg = AgentMessage {
-            msg_type: "task".to_string(),
-            payload: serde_json::json!({
-                "id": "task-test",
-                "technique_id": "T1082",
-                "command": "echo hello",
-                "executor": "sh"
-            }),
+        for id in ["task-1", "task-2"] {
+            let task = TaskPayload {
+                id: id.to_string(),
+                command: "true".to_string(),
+                ..sleep_task(id, true)
+            };
+            client.execute_task(task, &tx).await.unwrap();
+        }
+        let ping = AgentMessage {
+            msg_type: "ping".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({}),
         };
+        client.handle_message(ping, &tx).await.unwrap();
 
-        let result = client.handle_message(msg, &tx).await;
-        assert!(result.is_ok());
+        let mut types = Vec::new();
+        for _ in 0..3 {
+            let msg: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+            types.push(msg["type"].as_str().unwrap().to_string());
+        }
+        assert_eq!(types, ["pong", "task_result", "task_result"]);
+    }
 
-        let response = rx.recv().await.unwrap();
+    /// Runs `f` with a subscriber writing TRACE-level logs into the returned buffer.
+    fn capture_logs(f: impl FnOnce()) -> String {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn frame_config(secret: &str) -> AgentConfig {
+        AgentConfig {
+            log_frames: true,
+            agent_secret: Some(SecretString::from(secret)),
+            ..create_test_config()
+        }
+    }
+
+    #[test]
+    fn test_frame_logger_logs_content_and_redacts_secret() {
+        let frames = FrameLogger::from_config(&frame_config("s3cr3t-key"));
+
+        let logs = capture_logs(|| {
+            frames.log(
+                "in",
+                &WsMessage::Text(r#"{"type":"ping","payload":{"echo":"s3cr3t-key"}}"#.to_string()),
+            );
+            frames.log("out", &WsMessage::Pong(vec![0xde, 0xad]));
+        });
+
+        assert!(logs.contains(FRAME_LOG_TARGET));
+        assert!(logs.contains(r#"{"type":"ping","payload":{"echo":"[REDACTED]"}}"#));
+        assert!(logs.contains("direction=\"in\""));
+        assert!(logs.contains("pong dead"));
+        assert!(!logs.contains("s3cr3t-key"));
+    }
+
+    #[test]
+    fn test_frame_logger_disabled_by_default() {
+        let frames = FrameLogger::from_config(&create_test_config());
+
+        let logs = capture_logs(|| frames.log("in", &WsMessage::Text("hello".to_string())));
+        assert!(logs.is_empty());
+    }
+
+    #[test]
+    fn test_frame_logger_caps_size() {
+        let frames = FrameLogger::from_config(&frame_config("s3cr3t-key"));
+        let text = format!("{}s3cr3t-key", "x".repeat(FRAME_LOG_MAX_BYTES - 4));
+
+        let rendered = frames.render(&WsMessage::Text(text.clone()));
+        assert!(rendered.len() < FRAME_LOG_MAX_BYTES + 32);
+        assert!(rendered.ends_with(&format!("... [{} bytes]", text.len())));
+        assert!(!rendered.contains("s3cr"));
+    }
+
+    #[cfg(unix)]
+    async fn run_expect_task(pattern: &str) -> serde_json::Value {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "expect-task",
+                "technique_id": "T1082",
+                "argv": ["echo", "uid=0(root)"],
+                "executor": "sh",
+                "expect_output_regex": pattern,
+            }),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        response["payload"].clone()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_expect_output_regex_match() {
+        let payload = run_expect_task(r"uid=0\(\w+\)").await;
+
+        assert_eq!(payload["success"], true);
+        assert_eq!(payload["output_matched"], true);
+        assert!(payload.get("output_match_error").is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_expect_output_regex_no_match() {
+        let payload = run_expect_task(r"^gid=").await;
+
+        // A missed expectation does not turn a successful command into a failure
+        assert_eq!(payload["success"], true);
+        assert_eq!(payload["output_matched"], false);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_expect_output_regex_invalid() {
+        let payload = run_expect_task(r"uid=(").await;
+
+        assert_eq!(payload["success"], true);
+        assert!(payload.get("output_matched").is_none());
+        assert!(payload["output_match_error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid expect_output_regex"));
+    }
+
+    #[cfg(unix)]
+    async fn run_result_fields_task(result_fields: Option<Vec<String>>) -> serde_json::Value {
+        let mut config = create_test_config();
+        config.result_fields = result_fields;
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "fields-task",
+                "technique_id": "T1082",
+                "command": "echo fields",
+                "executor": "sh",
+                "expect_output_regex": "fields",
+            }),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        response["payload"].clone()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_result_fields_minimal_vs_full() {
+        let full = run_result_fields_task(None).await;
+        let minimal = run_result_fields_task(Some(Vec::new())).await;
+        let keys = |payload: &serde_json::Value| {
+            let mut keys: Vec<String> = payload.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(
+            keys(&minimal),
+            [
+                "exit_code",
+                "finished_at",
+                "output",
+                "started_at",
+                "success",
+                "task_id",
+                "technique_id"
+            ]
+        );
+        assert_eq!(minimal["output"], full["output"]);
+        assert_eq!(minimal["success"], full["success"]);
+        for field in [
+            "duration_ms",
+            "spawn_latency_ms",
+            "shell_path",
+            "executor_requested",
+            "executor_used",
+            "output_matched",
+            "context",
+        ] {
+            assert!(full.get(field).is_some(), "{} missing", field);
+        }
+        assert!(full["spawn_latency_ms"].as_u64() <= full["duration_ms"].as_u64());
+
+        let some = run_result_fields_task(Some(vec!["duration_ms".into()])).await;
+        assert!(some.get("duration_ms").is_some());
+        assert!(some.get("context").is_none());
+    }
+
+    #[test]
+    fn test_result_fields_rejects_unknown_field() {
+        let mut config = create_test_config();
+        config.result_fields = Some(vec!["output".into()]);
+
+        let err = AgentClient::new(config, create_test_sys_info())
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().contains("unknown field \"output\""),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_match_expected_output_rejects_oversized_pattern() {
+        let err = match_expected_output(r"\w{1000}{1000}", "x")
+            .await
+            .unwrap_err();
+        assert!(err.contains("invalid expect_output_regex"));
+    }
+
+    #[test]
+    fn test_seconds_until_future_deadline() {
+        let now = Local::now();
+        let deadline = (now + chrono::Duration::seconds(90)).to_rfc3339();
+
+        assert_eq!(seconds_until(&deadline, now, None), Ok(90));
+        // The server's clock being 30 s ahead leaves 30 s less
+        assert_eq!(seconds_until(&deadline, now, Some(30_000)), Ok(60));
+        let utc = (now + chrono::Duration::seconds(90))
+            .with_timezone(&chrono::Utc)
+            .to_rfc3339();
+        assert_eq!(seconds_until(&utc, now, None), Ok(90));
+    }
+
+    #[test]
+    fn test_seconds_until_past_deadline() {
+        let now = Local::now();
+        let past = (now - chrono::Duration::seconds(5)).to_rfc3339();
+
+        let err = seconds_until(&past, now, None).unwrap_err();
+        assert!(err.contains("has passed"), "{}", err);
+        // Still ahead locally, but already past on the server's clock
+        let soon = (now + chrono::Duration::seconds(5)).to_rfc3339();
+        assert!(seconds_until(&soon, now, Some(10_000)).is_err());
+        assert!(seconds_until("14:00", now, None)
+            .unwrap_err()
+            .starts_with("invalid deadline"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_task_past_deadline_rejected() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let marker =
+            std::env::temp_dir().join(format!("autostrike_deadline_{}", std::process::id()));
+        let task = TaskPayload {
+            command: format!("touch {}", marker.display()),
+            cleanup: Some(format!("touch {}", marker.display())),
+            deadline: Some((Local::now() - chrono::Duration::minutes(1)).to_rfc3339()),
+            ..sleep_task("late-task", true)
+        };
+
+        client.execute_task(task, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["success"], false);
+        let output = response["payload"]["output"].as_str().unwrap();
+        assert!(output.starts_with("Task rejected: deadline"), "{}", output);
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_task_timeout_out_of_bounds() {
+        let (tx, mut rx) = Outbox::channel(32);
+        let task = || TaskPayload {
+            command: "echo ran".to_string(),
+            timeout: Some(1_000_000),
+            ..sleep_task("long-task", true)
+        };
+
+        // Clamped by default
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        client.execute_task(task(), &tx).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["success"], true);
+
+        // Rejected with strict_durations
+        let config = AgentConfig {
+            strict_durations: true,
+            ..create_test_config()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        client.execute_task(task(), &tx).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["success"], false);
+        let output = response["payload"]["output"].as_str().unwrap();
+        assert_eq!(
+            output,
+            "Task rejected: timeout of 1000000 s is outside the maximum of 86400 s"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_task_steps_with_failing_middle_step() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let steps = |continue_on_error| {
+            vec![
+                TaskStep {
+                    name: "create".to_string(),
+                    command: "echo created".to_string(),
+                    executor: None,
+                    timeout: None,
+                    continue_on_error: false,
+                },
+                TaskStep {
+                    name: "schedule".to_string(),
+                    command: "echo denied; exit 3".to_string(),
+                    executor: Some("bash".to_string()),
+                    timeout: Some(5),
+                    continue_on_error,
+                },
+                TaskStep {
+                    name: "trigger".to_string(),
+                    command: "echo triggered".to_string(),
+                    executor: None,
+                    timeout: None,
+                    continue_on_error: false,
+                },
+            ]
+        };
+
+        // Stops at the failing step
+        let task = TaskPayload {
+            steps: steps(false),
+            ..sleep_task("steps-stop", true)
+        };
+        client.execute_task(task, &tx).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        let payload = &response["payload"];
+        assert_eq!(payload["success"], false);
+        assert_eq!(payload["exit_code"], 3);
+        assert_eq!(payload["output"], "");
+        let steps_result = payload["steps"].as_array().unwrap();
+        let summary: Vec<_> = steps_result
+            .iter()
+            .map(|step| {
+                (
+                    step["name"].clone(),
+                    step["success"].clone(),
+                    step["exit_code"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    serde_json::json!("create"),
+                    serde_json::json!(true),
+                    serde_json::json!(0)
+                ),
+                (
+                    serde_json::json!("schedule"),
+                    serde_json::json!(false),
+                    serde_json::json!(3)
+                ),
+                (
+                    serde_json::json!("trigger"),
+                    serde_json::json!(false),
+                    serde_json::json!(null)
+                ),
+            ]
+        );
+        assert_eq!(steps_result[1]["output"], "denied");
+        assert_eq!(steps_result[2]["skipped"], true);
+        assert!(steps_result[0].get("skipped").is_none());
+
+        // Carries on past it, without failing the task
+        let task = TaskPayload {
+            steps: steps(true),
+            ..sleep_task("steps-continue", true)
+        };
+        client.execute_task(task, &tx).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        let payload = &response["payload"];
+        assert_eq!(payload["success"], true);
+        assert_eq!(payload["exit_code"], 0);
+        let steps_result = payload["steps"].as_array().unwrap();
+        let summary: Vec<_> = steps_result
+            .iter()
+            .map(|step| (step["success"].clone(), step["exit_code"].clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (serde_json::json!(true), serde_json::json!(0)),
+                (serde_json::json!(false), serde_json::json!(3)),
+                (serde_json::json!(true), serde_json::json!(0)),
+            ]
+        );
+        assert_eq!(steps_result[2]["output"], "triggered");
+    }
+
+    fn control_message(msg_type: &str) -> AgentMessage {
+        AgentMessage {
+            msg_type: msg_type.to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({}),
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_tasks_wait_while_paused() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        client
+            .handle_message(control_message("pause"), &tx)
+            .await
+            .unwrap();
+        assert!(client.tasks.is_paused());
+        for id in ["paused-1", "paused-2"] {
+            let task = serde_json::to_value(TaskPayload {
+                command: "true".to_string(),
+                ..sleep_task(id, true)
+            })
+            .unwrap();
+            let msg = AgentMessage {
+                msg_type: "task".to_string(),
+                correlation_id: None,
+                payload: task,
+            };
+            client.handle_message(msg, &tx).await.unwrap();
+        }
+
+        let early = tokio::time::timeout(Duration::from_millis(300), rx.recv()).await;
+        assert!(early.is_err(), "a task ran while paused");
+        assert_eq!(client.tasks.len(), 2);
+
+        client
+            .handle_message(control_message("resume"), &tx)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let msg: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+            assert_eq!(msg["payload"]["success"], true);
+            ids.push(msg["payload"]["task_id"].as_str().unwrap().to_string());
+        }
+        assert_eq!(ids, ["paused-1", "paused-2"]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_task_payload_is_answered() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "bad-timeout",
+                "technique_id": "T1082",
+                "command": "echo never",
+                "executor": "sh",
+                "timeout": -5,
+            }),
+        };
+
+        // The connection carries on
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["task_id"], "bad-timeout");
+        assert_eq!(response["payload"]["success"], false);
+        let output = response["payload"]["output"].as_str().unwrap();
+        assert!(
+            output.starts_with("Task rejected: invalid timeout: -5 is negative"),
+            "{}",
+            output
+        );
+        assert!(client.tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_task_without_command_is_rejected() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "no-command",
+                "technique_id": "T1082",
+                "executor": "sh",
+            }),
+        };
+
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["task_id"], "no-command");
+        assert_eq!(response["payload"]["success"], false);
+        assert_eq!(
+            response["payload"]["output"],
+            "Task rejected: task has no command, steps or argv"
+        );
+        assert!(client.tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tasks_rejected_while_paused() {
+        let mut config = create_test_config();
+        config.pause_policy = PausePolicy::Reject;
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        client
+            .handle_message(control_message("pause"), &tx)
+            .await
+            .unwrap();
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "rejected-task",
+                "technique_id": "T1082",
+                "command": "echo never",
+                "executor": "sh",
+            }),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["task_id"], "rejected-task");
+        assert_eq!(response["payload"]["success"], false);
+        assert_eq!(
+            response["payload"]["output"],
+            "Task rejected: task processing is paused"
+        );
+        assert!(client.tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tasks_rejected_while_overloaded() {
+        let mut config = create_test_config();
+        config.max_concurrent_tasks = 1;
+        config.overload_result_backlog = Some(2);
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let task = |id: &str| AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": id,
+                "technique_id": "T1082",
+                "command": "echo never",
+                "executor": "sh",
+            }),
+        };
+
+        // Every slot busy and two results waiting for the write loop
+        let slot = client.tasks.slot().await;
+        assert!(client.tasks.is_saturated());
+        for i in 0..2 {
+            tx.bulk(format!("result-{}", i)).await.unwrap();
+        }
+        client
+            .handle_message(task("overloaded"), &tx)
+            .await
+            .unwrap();
+
+        assert_eq!(rx.recv().await.unwrap(), "result-0");
+        assert_eq!(rx.recv().await.unwrap(), "result-1");
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["task_id"], "overloaded");
+        assert_eq!(response["payload"]["success"], false);
+        assert_eq!(
+            response["payload"]["output"],
+            "agent overloaded, retry later"
+        );
+        assert!(client.tasks.is_empty());
+
+        // With the backlog drained, the task is queued for the busy slot
+        client.handle_message(task("queued"), &tx).await.unwrap();
+        assert_eq!(client.tasks.len(), 1);
+        assert!(rx.try_recv().is_err());
+        drop(slot);
+        client.tasks.cancel_all();
+    }
+
+    #[tokio::test]
+    async fn test_rejection_with_full_queue_does_not_block() {
+        let mut config = create_test_config();
+        config.max_concurrent_tasks = 1;
+        config.overload_result_backlog = Some(4);
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(4);
+        let task = |id: &str| AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": id,
+                "technique_id": "T1082",
+                "command": "echo never",
+                "executor": "sh",
+            }),
+        };
+
+        let slot = client.tasks.slot().await;
+        for i in 0..4 {
+            tx.bulk(format!("result-{}", i)).await.unwrap();
+        }
+        // Nothing drains the queue while the read loop handles messages
+        for (id, msg) in [
+            ("overloaded", task("overloaded")),
+            (
+                "invalid",
+                AgentMessage {
+                    payload: serde_json::json!({"id": "invalid", "timeout": -1}),
+                    ..task("invalid")
+                },
+            ),
+        ] {
+            tokio::time::timeout(Duration::from_secs(1), client.handle_message(msg, &tx))
+                .await
+                .unwrap_or_else(|_| panic!("{} blocked the read loop", id))
+                .unwrap();
+        }
+        let read_file = AgentMessage {
+            msg_type: "read_file".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({"id": "read-1"}),
+        };
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            client.handle_message(read_file, &tx),
+        )
+        .await
+        .expect("read_file blocked the read loop")
+        .unwrap();
+
+        // The rejections go out once there is room
+        let mut answered = Vec::new();
+        for _ in 0..7 {
+            let msg = rx.recv().await.unwrap();
+            if let Ok(msg) = serde_json::from_str::<serde_json::Value>(&msg) {
+                let payload = &msg["payload"];
+                answered.push(
+                    payload["task_id"]
+                        .as_str()
+                        .or(payload["id"].as_str())
+                        .unwrap()
+                        .to_string(),
+                );
+            }
+        }
+        answered.sort();
+        assert_eq!(answered, ["invalid", "overloaded", "read-1"]);
+        drop(slot);
+    }
+
+    #[test]
+    fn test_heartbeat_reports_pause() {
+        let metrics = HeartbeatMetrics::default();
+
+        let msg = heartbeat_message("test-paw", None, &metrics, true).unwrap();
+        assert_eq!(msg.payload["paused"], true);
+        let msg = heartbeat_message("test-paw", None, &metrics, false).unwrap();
+        assert!(msg.payload.get("paused").is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_executed_tasks_recorded_in_history() {
+        let dir =
+            std::env::temp_dir().join(format!("autostrike_client_history_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.jsonl");
+        let mut config = create_test_config();
+        config.history.path = Some(path.clone());
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        for (id, command) in [("ok-task", "true"), ("failed-task", "exit 3")] {
+            let task = TaskPayload {
+                command: command.to_string(),
+                ..sleep_task(id, true)
+            };
+            client.execute_task(task, &tx).await.unwrap();
+            rx.recv().await.unwrap();
+        }
+
+        let entries = crate::history::read(&path).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].task_id, "ok-task");
+        assert_eq!(entries[0].technique_id, "T1082");
+        assert_eq!(entries[0].status, "success");
+        assert_eq!(entries[1].status, "failed");
+        assert_eq!(entries[1].exit_code, Some(3));
+        assert!(entries[1].duration_ms.is_some());
+        // Commands stay out of the history unless record_commands is set
+        assert!(entries.iter().all(|entry| entry.command.is_none()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_task_result_reports_parent_technique() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        for technique_id in ["T1059.004", "T1082"] {
+            let task = TaskPayload {
+                technique_id: technique_id.to_string(),
+                command: "true".to_string(),
+                ..sleep_task(technique_id, true)
+            };
+            client.execute_task(task, &tx).await.unwrap();
+        }
+
+        let sub: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(sub["payload"]["parent_technique"], "T1059");
+        let base: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert!(base["payload"].get("parent_technique").is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_task_deadline_caps_timeout() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let task = TaskPayload {
+            command: "sleep 10".to_string(),
+            timeout: Some(300),
+            deadline: Some((Local::now() + chrono::Duration::seconds(2)).to_rfc3339()),
+            ..sleep_task("deadline-task", true)
+        };
+
+        let started = std::time::Instant::now();
+        client.execute_task(task, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(response["payload"]["output"]
+            .as_str()
+            .unwrap()
+            .contains("Command timed out"));
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn test_verify_registry_unsupported() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "reg-task",
+                "technique_id": "T1547.001",
+                "command": "true",
+                "executor": "sh",
+                "verify_registry": [{
+                    "hive": "HKCU",
+                    "key": "Software\\Microsoft\\Windows\\CurrentVersion\\Run",
+                    "value_name": "Updater"
+                }]
+            }),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        let reading = &response["payload"]["verification"]["registry"][0];
+        assert_eq!(response["payload"]["success"], true);
+        assert_eq!(reading["status"], "unsupported");
+        assert_eq!(reading["value_name"], "Updater");
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn test_capture_events_unsupported() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "events-task",
+                "technique_id": "T1033",
+                "command": "true",
+                "executor": "sh",
+                "capture_events": { "log": "Security", "count": 10 }
+            }),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        let verification = &response["payload"]["verification"];
+        assert_eq!(response["payload"]["success"], true);
+        assert_eq!(verification["events"]["log"], "Security");
+        assert_eq!(verification["events"]["status"], "unsupported");
+        assert!(verification.get("registry").is_none());
+    }
+
+    #[cfg(unix)]
+    fn sleep_task(id: &str, allow_concurrent: bool) -> TaskPayload {
+        TaskPayload {
+            id: id.to_string(),
+            technique_id: "T1082".to_string(),
+            command: "sleep 0.3".to_string(),
+            executor: "sh".to_string(),
+            timeout: Some(10),
+            cleanup: None,
+            traceparent: None,
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir: false,
+            netns: None,
+            seq: None,
+            deadline: None,
+        }
+    }
+
+    /// Runs two tasks at once and returns their results, ordered by start time.
+    #[cfg(unix)]
+    async fn run_pair(
+        first: TaskPayload,
+        second: TaskPayload,
+    ) -> (AgentClient, Vec<serde_json::Value>) {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let (a, b) = tokio::join!(
+            client.execute_task(first, &tx),
+            client.execute_task(second, &tx)
+        );
+        a.unwrap();
+        b.unwrap();
+
+        let mut results: Vec<serde_json::Value> = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            results
+                .push(serde_json::from_str::<serde_json::Value>(&msg).unwrap()["payload"].clone());
+        }
+        let started = |r: &serde_json::Value| {
+            DateTime::parse_from_rfc3339(r["started_at"].as_str().unwrap()).unwrap()
+        };
+        results.sort_by_key(started);
+        (client, results)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_same_technique_tasks_are_serialized() {
+        let (client, results) = run_pair(sleep_task("one", false), sleep_task("two", false)).await;
+
+        let parse =
+            |v: &serde_json::Value| DateTime::parse_from_rfc3339(v.as_str().unwrap()).unwrap();
+        assert!(parse(&results[1]["started_at"]) >= parse(&results[0]["finished_at"]));
+        assert!(results[1]["technique_wait_ms"].as_u64().unwrap() >= 200);
+        assert_eq!(client.technique_locks.len(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_task_groups_ordered_and_fair() {
+        let config = AgentConfig {
+            max_concurrent_tasks: 2,
+            ..create_test_config()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        // Group a arrives first and alone could fill both slots
+        for (id, group) in [
+            ("a1", "a"),
+            ("a2", "a"),
+            ("a3", "a"),
+            ("b1", "b"),
+            ("b2", "b"),
+        ] {
+            let task = TaskPayload {
+                command: "sleep 0.2".to_string(),
+                group_id: Some(group.to_string()),
+                ..sleep_task(id, true)
+            };
+            client.spawn_task(task, &tx);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            client.groups.depths(),
+            std::collections::BTreeMap::from([("a".to_string(), 3), ("b".to_string(), 2)])
+        );
+
+        let mut order = Vec::new();
+        for _ in 0..5 {
+            let msg: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+            assert_eq!(msg["payload"]["success"], true);
+            order.push(msg["payload"]["task_id"].as_str().unwrap().to_string());
+        }
+        let position = |id: &str| order.iter().position(|done| done == id).unwrap();
+        assert!(position("a1") < position("a2") && position("a2") < position("a3"));
+        assert!(position("b1") < position("b2"));
+        // Group b shares the slots instead of waiting for all of group a
+        assert!(position("b2") < position("a3"), "{:?}", order);
+        assert!(client.groups.depths().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_allow_concurrent_bypasses_technique_lock() {
+        let (_, results) = run_pair(sleep_task("one", false), sleep_task("two", true)).await;
+
+        let parse =
+            |v: &serde_json::Value| DateTime::parse_from_rfc3339(v.as_str().unwrap()).unwrap();
+        assert!(parse(&results[1]["started_at"]) < parse(&results[0]["finished_at"]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_kill_all_cancels_running_and_queued_tasks() {
+        let config = AgentConfig {
+            max_concurrent_tasks: 2,
+            ..create_test_config()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        for id in ["long-1", "long-2", "long-3"] {
+            let msg = AgentMessage {
+                msg_type: "task".to_string(),
+                correlation_id: None,
+                payload: serde_json::json!({
+                    "id": id,
+                    "technique_id": "T1059",
+                    "command": "sleep 30",
+                    "executor": "sh",
+                    "allow_concurrent": true
+                }),
+            };
+            client.handle_message(msg, &tx).await.unwrap();
+        }
+        // Two tasks start, the third waits for a slot
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(client.tasks.len(), 3);
+
+        let started = std::time::Instant::now();
+        let kill_all = AgentMessage {
+            msg_type: "kill_all".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({}),
+        };
+        client.handle_message(kill_all, &tx).await.unwrap();
+
+        let mut summary = None;
+        let mut cancelled = Vec::new();
+        while summary.is_none() || cancelled.len() < 3 {
+            let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+            match msg["type"].as_str().unwrap() {
+                "kill_all_result" => summary = Some(msg["payload"].clone()),
+                "task_result" => {
+                    assert_eq!(msg["payload"]["cancelled"], true);
+                    assert_eq!(msg["payload"]["success"], false);
+                    cancelled.push(msg["payload"]["task_id"].as_str().unwrap().to_string());
+                }
+                other => panic!("unexpected message: {}", other),
+            }
+        }
+
+        let summary = summary.unwrap();
+        assert_eq!(summary["cancelled"], 3);
+        assert_eq!(
+            summary["task_ids"],
+            serde_json::json!(["long-1", "long-2", "long-3"])
+        );
+        cancelled.sort();
+        assert_eq!(cancelled, ["long-1", "long-2", "long-3"]);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(client.tasks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_kill_all_without_tasks() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let kill_all = AgentMessage {
+            msg_type: "kill_all".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({}),
+        };
+        client.handle_message(kill_all, &tx).await.unwrap();
+
+        let msg: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(msg["type"], "kill_all_result");
+        assert_eq!(msg["payload"]["cancelled"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_probe_present() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        #[cfg(unix)]
+        let binary = "sh";
+        #[cfg(windows)]
+        let binary = "cmd";
+        let msg = AgentMessage {
+            msg_type: "probe".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({"id": "probe-1", "binary": binary}),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["type"], "probe_result");
+        assert_eq!(response["payload"]["id"], "probe-1");
+        assert_eq!(response["payload"]["binary"], binary);
+        assert_eq!(response["payload"]["found"], true);
+        assert!(response["payload"]["path"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_probe_absent() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "probe".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({"binary": "autostrike-no-such-binary"}),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["found"], false);
+        assert!(response["payload"]["path"].is_null());
+        assert!(response["payload"].get("id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_invalid_probe_is_answered() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "probe".to_string(),
+            correlation_id: Some("corr-1".to_string()),
+            payload: serde_json::json!({"id": "probe-1", "binary": 42}),
+        };
+        // The connection carries on
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["type"], "probe_result");
+        assert_eq!(response["correlation_id"], "corr-1");
+        assert_eq!(response["payload"]["id"], "probe-1");
+        assert_eq!(response["payload"]["found"], false);
+        assert!(response["payload"]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid probe: invalid type: integer `42`"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_read_file() {
+        let dir =
+            std::env::temp_dir().join(format!("autostrike_client_read_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("passwd");
+        std::fs::write(&file, "root:x:0:0::/root:/bin/sh\n").unwrap();
+        let mut config = create_test_config();
+        config.file_read.allowed_paths = vec![dir.clone()];
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "read_file".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({"id": "read-1", "path": file}),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["type"], "read_file_result");
+        assert_eq!(response["payload"]["id"], "read-1");
+        assert_eq!(response["payload"]["success"], true);
+        assert_eq!(
+            response["payload"]["content"],
+            "root:x:0:0::/root:/bin/sh\n"
+        );
+        assert_eq!(response["payload"]["encoding"], "utf8");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_invalid_read_file_is_answered() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "read_file".to_string(),
+            correlation_id: Some("corr-2".to_string()),
+            payload: serde_json::json!({"id": "read-2"}),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["type"], "read_file_result");
+        assert_eq!(response["correlation_id"], "corr-2");
+        assert_eq!(response["payload"]["id"], "read-2");
+        assert_eq!(response["payload"]["success"], false);
+        assert_eq!(
+            response["payload"]["error"],
+            "invalid read_file: missing field `path`"
+        );
+    }
+
+    #[test]
+    fn test_read_file_result_too_large_for_message() {
+        let result = ReadFileResultPayload {
+            id: None,
+            path: "/var/log/big".to_string(),
+            success: true,
+            content: Some("a".repeat(4096)),
+            encoding: Some(crate::protocol::ContentEncoding::Utf8),
+            size: Some(4096),
+            error: None,
+        };
+
+        let encoded = encode_read_file_result(result, Some("read-7"), 1024).unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(response["correlation_id"], "read-7");
+        assert_eq!(response["payload"]["success"], false);
+        assert!(response["payload"].get("content").is_none());
+        assert!(response["payload"]["error"]
+            .as_str()
+            .unwrap()
+            .contains("1024 byte message"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_collect_bundle_is_answered() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "collect_bundle".to_string(),
+            correlation_id: Some("corr-10".to_string()),
+            payload: serde_json::json!({"id": 7}),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["type"], "collect_bundle_result");
+        assert_eq!(response["correlation_id"], "corr-10");
+        let payload = &response["payload"];
+        assert!(payload["collectors"].as_object().unwrap().is_empty());
+        assert!(payload["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid collect_bundle: invalid type: integer `7`"));
+    }
+
+    #[tokio::test]
+    async fn test_collect_bundle_message() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "collect_bundle".to_string(),
+            correlation_id: Some("corr-9".to_string()),
+            payload: serde_json::json!({"id": "bundle-1"}),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["type"], "collect_bundle_result");
+        assert_eq!(response["correlation_id"], "corr-9");
+        let payload = &response["payload"];
+        assert_eq!(payload["id"], "bundle-1");
+        assert_eq!(payload["platform"], std::env::consts::OS);
+        for collector in collect::COLLECTORS {
+            assert!(
+                payload["collectors"][collector]["success"].is_boolean(),
+                "{} missing",
+                collector
+            );
+        }
+    }
+
+    #[test]
+    fn test_collect_bundle_result_too_large_for_message() {
+        let bundle = CollectBundleResultPayload {
+            id: None,
+            platform: "linux".to_string(),
+            collected_at: "2024-05-02T14:03:11+02:00".to_string(),
+            collectors: [(
+                "processes".to_string(),
+                crate::protocol::CollectorOutput {
+                    success: true,
+                    items: vec![serde_json::json!("a".repeat(4096))],
+                    error: None,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            error: None,
+        };
+
+        let encoded = encode_collect_bundle_result(bundle, None, 1024).unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(
+            response["payload"]["collectors"]["processes"]["items"],
+            serde_json::json!([])
+        );
+        assert!(response["payload"]["error"]
+            .as_str()
+            .unwrap()
+            .contains("1024 byte message"));
+    }
+
+    #[tokio::test]
+    async fn test_offer_update_only_reports_without_auto_update() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let update = |version: &str| UpdateAvailablePayload {
+            version: version.to_string(),
+            download_url: Some("https://test.server:8443/agent".to_string()),
+            sha256: Some("ab".repeat(32)),
+        };
+
+        assert!(!client.offer_update(update("999.0.0")));
+        assert!(!client.offer_update(update("0.0.1")));
+        assert!(!client.offer_update(update("not-a-version")));
+    }
+
+    #[tokio::test]
+    async fn test_update_pauses_intake_until_it_fails() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/agent", listener.local_addr().unwrap());
+        let mut config = create_test_config();
+        config.auto_update = true;
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        assert!(client.offer_update(UpdateAvailablePayload {
+            version: "999.0.0".to_string(),
+            download_url: Some(url),
+            sha256: Some("ab".repeat(32)),
+        }));
+        assert!(client.tasks.is_paused());
+
+        // A task arriving during the download is turned away
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::to_value(sleep_task("task-1", false)).unwrap(),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["task_id"], "task-1");
+        assert_eq!(response["payload"]["success"], false);
+        assert!(client.tasks.is_empty());
+
+        // The download fails: intake reopens
+        accept_http(listener, 404).await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while client.tasks.is_paused() || client.updating.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_update_available_keeps_connection() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "update_available".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({"download_url": "https://test.server:8443/agent"}),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_offer_update_waits_for_tasks_in_flight() {
+        let mut config = create_test_config();
+        config.auto_update = true;
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (_key, _cancelled) = client.tasks.track("task-1");
+
+        assert!(!client.offer_update(UpdateAvailablePayload {
+            version: "999.0.0".to_string(),
+            download_url: Some("https://test.server:8443/agent".to_string()),
+            sha256: Some("ab".repeat(32)),
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_unknown_type() {
+        let config = create_test_config();
+        let sys_info = create_test_sys_info();
+        let client = AgentClient::new(config, sys_info).unwrap();
+
+        let (tx, _rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "unknown_type".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({}),
+        };
+
+        let result = client.handle_message(msg, &tx).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_errors_by_cause() {
+        let mut config = create_test_config();
+        config.message_limits.max_message_bytes = 64;
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let message = |msg_type: &str, payload| AgentMessage {
+            msg_type: msg_type.to_string(),
+            correlation_id: None,
+            payload,
+        };
+
+        // A probe_result over the size limit cannot be built
+        let (tx, rx) = Outbox::channel(4);
+        let probe = message("probe", serde_json::json!({"binary": "x".repeat(100)}));
+        let result = client.handle_message(probe, &tx).await;
+        assert!(matches!(result, Err(AgentError::Protocol(_))));
+
+        drop(rx);
+        let ping = message("ping", serde_json::json!({}));
+        let result = client.handle_message(ping, &tx).await;
+        assert!(matches!(
+            result,
+            Err(AgentError::Network {
+                retryable: true,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_task() {
+        let config = create_test_config();
+        let sys_info = create_test_sys_info();
+        let client = AgentClient::new(config, sys_info).unwrap();
+
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "task-test",
+                "technique_id": "T1082",
+                "command": "echo hello",
+                "executor": "sh"
+            }),
+        };
+
+        let result = client.handle_message(msg, &tx).await;
+        assert!(result.is_ok());
+
+        let response = rx.recv().await.unwrap();
         assert!(response.contains("task_result"));
         assert!(response.contains("task-test"));
     }
 
+    #[test]
+    fn test_task_sequence_detects_gaps_and_reordering() {
+        let mut seq = TaskSequence::default();
+
+        assert_eq!(seq.observe(5), SeqCheck::InOrder);
+        assert_eq!(seq.observe(6), SeqCheck::InOrder);
+        assert_eq!(
+            seq.observe(9),
+            SeqCheck::Gap {
+                expected: 7,
+                received: 9
+            }
+        );
+        // A late task does not move the sequence back
+        assert_eq!(
+            seq.observe(8),
+            SeqCheck::Stale {
+                last: 9,
+                received: 8
+            }
+        );
+        assert_eq!(
+            seq.observe(9),
+            SeqCheck::Stale {
+                last: 9,
+                received: 9
+            }
+        );
+        assert_eq!(seq.observe(10), SeqCheck::InOrder);
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_task_seq_gap_requests_resync() {
+        let config = AgentConfig {
+            resync_on_seq_gap: true,
+            ..create_test_config()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let task = |id: &str, seq: u64| AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": id,
+                "technique_id": "T1082",
+                "command": "true",
+                "executor": "sh",
+                "seq": seq,
+            }),
+        };
+
+        client.handle_message(task("task-1", 1), &tx).await.unwrap();
+        client.handle_message(task("task-4", 4), &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["type"], "resync_request");
+        assert_eq!(response["payload"]["expected_seq"], 2);
+        assert_eq!(response["payload"]["received_seq"], 4);
+        // Both tasks still run
+        for _ in 0..2 {
+            assert!(rx.recv().await.unwrap().contains("task_result"));
+        }
+    }
+
+    #[test]
+    fn test_pending_acks_bounded() {
+        let mut pending = PendingAcks::new(2);
+        pending.record("task-1");
+        pending.record("task-2");
+        pending.record("task-3");
+
+        assert_eq!(pending.snapshot(), vec!["task-2", "task-3"]);
+    }
+
+    #[test]
+    fn test_pending_acks_disabled() {
+        let mut pending = PendingAcks::new(0);
+        pending.record("task-1");
+
+        assert!(pending.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_heartbeat_message_without_pending_acks() {
+        let msg =
+            heartbeat_message("test-paw-123", None, &HeartbeatMetrics::default(), false).unwrap();
+
+        assert_eq!(msg.msg_type, "heartbeat");
+        assert_eq!(msg.payload["paw"], "test-paw-123");
+        assert!(msg.payload.get("pending_acks").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pending_acks_in_heartbeat_until_acked() {
+        let config = AgentConfig {
+            heartbeat_pending_acks: 10,
+            ..create_test_config()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let task = TaskPayload {
+            id: "unacked-task".to_string(),
+            technique_id: "T1082".to_string(),
+            command: "echo ack".to_string(),
+            executor: "sh".to_string(),
+            timeout: Some(5),
+            cleanup: None,
+            traceparent: None,
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent: false,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir: false,
+            netns: None,
+            seq: None,
+            deadline: None,
+        };
+        client.execute_task(task, &tx).await.unwrap();
+        rx.recv().await.unwrap();
+
+        let pending = client.pending_acks.lock().unwrap().snapshot();
+        let heartbeat = heartbeat_message(
+            "test-paw-123",
+            Some(pending),
+            &HeartbeatMetrics::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(heartbeat.payload["pending_acks"][0], "unacked-task");
+
+        let ack = AgentMessage {
+            msg_type: "result_ack".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({ "task_id": "unacked-task" }),
+        };
+        client.handle_message(ack, &tx).await.unwrap();
+
+        let pending = client.pending_acks.lock().unwrap().snapshot();
+        let heartbeat = heartbeat_message(
+            "test-paw-123",
+            Some(pending),
+            &HeartbeatMetrics::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(heartbeat.payload["pending_acks"], serde_json::json!([]));
+    }
+
+    struct FixedProbe(u64);
+
+    impl crate::disk::SpaceProbe for FixedProbe {
+        fn available_bytes(&self, _path: &std::path::Path) -> std::io::Result<u64> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn test_heartbeat_metrics_disk_free() {
+        let disk = DiskGuard::with_probe(Arc::new(FixedProbe(5_000)), 1_000);
+        let metrics = HeartbeatMetrics::collect(&disk, std::path::Path::new("/tmp"));
+
+        let msg = heartbeat_message("test-paw-123", None, &metrics, false).unwrap();
+        assert_eq!(msg.payload["metrics"]["disk_free_bytes"], 5_000);
+        assert_eq!(msg.payload["metrics"]["disk_low"], false);
+    }
+
+    #[tokio::test]
+    async fn test_time_sync_records_clock_skew() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, _rx) = Outbox::channel(32);
+        assert!(client.clock_skew.offset_ms().is_none());
+
+        let server_time = Local::now() + chrono::Duration::minutes(10);
+        let msg = AgentMessage {
+            msg_type: "time_sync".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({ "server_time": server_time.to_rfc3339() }),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let offset = client.clock_skew.offset_ms().unwrap();
+        assert!((599_000..=600_000).contains(&offset), "{}", offset);
+    }
+
+    #[tokio::test]
+    async fn test_register_ack_invalid_server_time_ignored() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, _rx) = Outbox::channel(32);
+
+        let msg = AgentMessage {
+            msg_type: "register_ack".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({ "server_time": "yesterday" }),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        assert!(client.clock_skew.offset_ms().is_none());
+    }
+
+    #[test]
+    fn test_sync_clock_warns_above_threshold() {
+        let config = AgentConfig {
+            clock_skew_warn_secs: 60,
+            ..create_test_config()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+
+        let skewed = Local::now() - chrono::Duration::minutes(5);
+        assert!(client.sync_clock(&skewed.to_rfc3339()));
+
+        let close = Local::now() + chrono::Duration::seconds(5);
+        assert!(!client.sync_clock(&close.to_rfc3339()));
+    }
+
+    #[test]
+    fn test_clock_skew_record() {
+        let skew = ClockSkew::default();
+        let now = Local::now();
+        let server_time = (now - chrono::Duration::seconds(90)).fixed_offset();
+
+        assert_eq!(skew.record(server_time, now), -90_000);
+        assert_eq!(skew.offset_ms(), Some(-90_000));
+    }
+
+    #[test]
+    fn test_heartbeat_metrics_clock_skew() {
+        let mut metrics = HeartbeatMetrics::default();
+        let msg = heartbeat_message("test-paw-123", None, &metrics, false).unwrap();
+        assert!(msg.payload["metrics"].get("clock_skew_ms").is_none());
+
+        metrics.clock_skew_ms = Some(-1_500);
+        let msg = heartbeat_message("test-paw-123", None, &metrics, false).unwrap();
+        assert_eq!(msg.payload["metrics"]["clock_skew_ms"], -1_500);
+    }
+
+    #[test]
+    fn test_heartbeat_metrics_unreaped_children() {
+        let metrics = HeartbeatMetrics {
+            unreaped_children: 2,
+            reap_failures: 5,
+            ..HeartbeatMetrics::default()
+        };
+
+        let msg = heartbeat_message("test-paw", None, &metrics, false).unwrap();
+        assert_eq!(msg.payload["metrics"]["unreaped_children"], 2);
+        assert_eq!(msg.payload["metrics"]["reap_failures"], 5);
+        let quiet =
+            heartbeat_message("test-paw", None, &HeartbeatMetrics::default(), false).unwrap();
+        assert_eq!(quiet.payload["metrics"]["unreaped_children"], 0);
+    }
+
+    #[test]
+    fn test_register_payload_caps_executors() {
+        let sys_info = SystemInfo {
+            executors: (0..1000).map(|i| format!("executor-{}", i)).collect(),
+            ..create_test_sys_info()
+        };
+        let limits = MessageLimits {
+            max_executors: 16,
+            ..MessageLimits::default()
+        };
+
+        let payload = RegisterPayload::new("test-paw", &sys_info, &limits);
+        assert_eq!(payload.executors.len(), 16);
+        assert_eq!(payload.truncated_fields, vec!["executors"]);
+
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["truncated_fields"][0], "executors");
+    }
+
+    #[test]
+    fn test_register_payload_fits_size_cap() {
+        let sys_info = SystemInfo {
+            executors: (0..64).map(|i| format!("executor-{:0>200}", i)).collect(),
+            filesystems: (0..64)
+                .map(|i| FsInfo {
+                    mount_point: format!("/mnt/{:0>500}", i),
+                    fs_type: "ext4".to_string(),
+                    total_bytes: 1024,
+                    free_bytes: 512,
+                })
+                .collect(),
+            ..create_test_sys_info()
+        };
+        let limits = MessageLimits {
+            max_register_bytes: 8 * 1024,
+            ..MessageLimits::default()
+        };
+
+        let payload = RegisterPayload::new("test-paw", &sys_info, &limits);
+        assert!(serde_json::to_vec(&payload).unwrap().len() <= 8 * 1024);
+        assert!(payload.registration_truncated);
+        assert!(payload.filesystems.len() < 64);
+        assert_eq!(payload.truncated_fields, vec!["filesystems", "executors"]);
+
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["registration_truncated"], true);
+
+        // Small payloads are left alone and do not carry the flag
+        let payload = RegisterPayload::new("test-paw", &create_test_sys_info(), &limits);
+        assert!(!payload.registration_truncated);
+        let json = serde_json::to_value(&payload).unwrap();
+        assert!(json.get("registration_truncated").is_none());
+    }
+
+    #[test]
+    fn test_register_payload_caps_filesystems() {
+        let sys_info = SystemInfo {
+            filesystems: (0..100)
+                .map(|i| FsInfo {
+                    mount_point: format!("/mnt/{}", i),
+                    fs_type: "ext4".to_string(),
+                    total_bytes: 1024,
+                    free_bytes: 512,
+                })
+                .collect(),
+            ..create_test_sys_info()
+        };
+        let limits = MessageLimits {
+            max_filesystems: 4,
+            ..MessageLimits::default()
+        };
+
+        let payload = RegisterPayload::new("test-paw", &sys_info, &limits);
+        assert_eq!(payload.filesystems.len(), 4);
+        assert_eq!(payload.filesystems[0].mount_point, "/mnt/0");
+        assert_eq!(payload.truncated_fields, vec!["filesystems"]);
+    }
+
+    #[test]
+    fn test_register_payload_within_caps() {
+        let payload = RegisterPayload::new(
+            "test-paw",
+            &create_test_sys_info(),
+            &MessageLimits::default(),
+        );
+
+        let json = serde_json::to_value(&payload).unwrap();
+        assert!(json.get("truncated_fields").is_none());
+    }
+
+    #[test]
+    fn test_encode_message_within_limit() {
+        let msg = heartbeat_message("test-paw", None, &HeartbeatMetrics::default(), false).unwrap();
+        let encoded = encode_message(msg, 4096).unwrap();
+        assert!(encoded.contains("heartbeat"));
+    }
+
+    #[test]
+    fn test_encode_message_trims_task_output() {
+        let msg = AgentMessage {
+            msg_type: "task_result".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "task_id": "big-task",
+                // Control characters expand sixfold when escaped
+                "output": "\u{1}é".repeat(50_000),
+            }),
+        };
+
+        let encoded = encode_message(msg, 16 * 1024).unwrap();
+        assert!(encoded.len() <= 16 * 1024);
+
+        let decoded: serde_json::Value = serde_json::from_str(&encoded).unwrap();
+        let output = decoded["payload"]["output"].as_str().unwrap();
+        let (base, total) = executor::strip_truncation_notice(output);
+        assert_eq!(total, Some(150_000));
+        assert!(output.ends_with(&format!("showing {} of 150000 bytes]", base.len())));
+        assert_eq!(decoded["payload"]["truncated_fields"][0], "output");
+        assert_eq!(decoded["payload"]["task_id"], "big-task");
+    }
+
+    #[tokio::test]
+    async fn test_task_steps_large_output_fits_message_limit() {
+        let mut config = create_test_config();
+        config.message_limits.max_message_bytes = 64 * 1024;
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let task = TaskPayload {
+            steps: (0..4)
+                .map(|i| TaskStep {
+                    name: format!("dump-{}", i),
+                    command: "head -c 100000 /dev/zero | tr '\\0' a".to_string(),
+                    executor: None,
+                    timeout: None,
+                    continue_on_error: false,
+                })
+                .collect(),
+            ..sleep_task("steps-large", true)
+        };
+
+        client.execute_task(task, &tx).await.unwrap();
+        let response = rx.recv().await.unwrap();
+        assert!(response.len() <= 64 * 1024, "{} bytes", response.len());
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        let payload = &response["payload"];
+        assert_eq!(payload["success"], true);
+        // Step outputs are not repeated in the task's
+        assert_eq!(payload["output"], "");
+        assert_eq!(payload["truncated_fields"], serde_json::json!(["steps"]));
+        for step in payload["steps"].as_array().unwrap() {
+            let output = step["output"].as_str().unwrap();
+            let (base, total) = executor::strip_truncation_notice(output);
+            assert_eq!(total, Some(100_000));
+            assert!(base.len() < 100_000 && base.bytes().all(|b| b == b'a'));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_task_result_too_large_is_still_reported() {
+        let mut config = create_test_config();
+        config.message_limits.max_message_bytes = 16 * 1024;
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        // Step names cannot be trimmed; the failed first step skips the rest
+        let task = TaskPayload {
+            steps: (0..100)
+                .map(|i| TaskStep {
+                    name: format!("{}-{}", "step".repeat(100), i),
+                    command: "exit 2".to_string(),
+                    executor: None,
+                    timeout: None,
+                    continue_on_error: false,
+                })
+                .collect(),
+            ..sleep_task("steps-huge", true)
+        };
+
+        client.execute_task(task, &tx).await.unwrap();
+        let response = rx.recv().await.unwrap();
+        assert!(response.len() <= 16 * 1024);
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        let payload = &response["payload"];
+        assert_eq!(payload["task_id"], "steps-huge");
+        assert_eq!(payload["success"], false);
+        assert_eq!(payload["exit_code"], 2);
+        assert!(payload["output"]
+            .as_str()
+            .unwrap()
+            .starts_with("Result too large: task_result message is"));
+        assert!(payload.get("steps").is_none());
+    }
+
+    #[test]
+    fn test_encode_message_rejects_oversized_without_output() {
+        let sys_info = SystemInfo {
+            hostname: "h".repeat(10_000),
+            ..create_test_sys_info()
+        };
+        let msg = AgentMessage {
+            msg_type: "register".to_string(),
+            correlation_id: None,
+            payload: serde_json::to_value(RegisterPayload::new(
+                "test-paw",
+                &sys_info,
+                &MessageLimits::default(),
+            ))
+            .unwrap(),
+        };
+
+        let err = encode_message(msg, 1024).unwrap_err();
+        assert!(err.to_string().contains("register message"));
+    }
+
+    fn io_error(kind: std::io::ErrorKind) -> WsError {
+        WsError::Io(std::io::Error::new(kind, "test"))
+    }
+
+    #[test]
+    fn test_read_error_policy_tolerates_transient_errors() {
+        let mut policy = ReadErrorPolicy::new(2);
+
+        assert!(!policy.on_error(&io_error(std::io::ErrorKind::TimedOut)));
+        assert!(!policy.on_error(&io_error(std::io::ErrorKind::ConnectionReset)));
+        assert!(policy.on_error(&io_error(std::io::ErrorKind::TimedOut)));
+    }
+
+    #[test]
+    fn test_read_error_policy_success_resets_count() {
+        let mut policy = ReadErrorPolicy::new(1);
+
+        assert!(!policy.on_error(&io_error(std::io::ErrorKind::TimedOut)));
+        policy.on_success();
+        assert!(!policy.on_error(&io_error(std::io::ErrorKind::TimedOut)));
+    }
+
+    #[test]
+    fn test_read_error_policy_fatal_errors_reconnect() {
+        let mut policy = ReadErrorPolicy::new(5);
+
+        assert!(!policy.on_error(&io_error(std::io::ErrorKind::TimedOut)));
+        assert!(policy.on_error(&WsError::ConnectionClosed));
+        assert!(policy.on_error(&WsError::Tls(
+            tokio_tungstenite::tungstenite::error::TlsError::InvalidDnsName
+        )));
+        assert!(policy.on_error(&io_error(std::io::ErrorKind::PermissionDenied)));
+        assert!(ReadErrorPolicy::new(0).on_error(&io_error(std::io::ErrorKind::TimedOut)));
+    }
+
+    #[test]
+    fn test_reconnect_backoff_doubles_and_resets() {
+        let mut backoff = ReconnectBackoff::new(Duration::from_secs(1), Duration::from_secs(60));
+
+        let delays: Vec<u64> = (0..8).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_backoff_unaffected_by_wall_clock_step() {
+        // The only wall clock the agent adjusts is its view of the server's;
+        // stepping it by hours must leave reconnect timing alone.
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let mut backoff =
+            ReconnectBackoff::new(Duration::from_millis(50), Duration::from_millis(100));
+        let first = backoff.next_delay();
+
+        let stepped = Local::now() + chrono::Duration::hours(6);
+        assert!(client.sync_clock(&stepped.to_rfc3339()));
+
+        let started = tokio::time::Instant::now();
+        tokio::time::sleep(backoff.next_delay()).await;
+        let slept = started.elapsed();
+        assert_eq!(first, Duration::from_millis(50));
+        assert!(slept >= Duration::from_millis(100));
+        assert!(slept < Duration::from_secs(2), "{:?}", slept);
+    }
+
+    #[tokio::test]
+    async fn test_health_check_restarts_dead_heartbeat() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let mut heartbeat_task =
+            client.spawn_heartbeat(&tx, AdaptiveHeartbeat::from_config(&client.config));
+        let beat = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(beat.contains(r#""type":"heartbeat""#));
+
+        // Kill the heartbeat task behind the agent's back
+        heartbeat_task.abort();
+        assert!((&mut heartbeat_task).await.unwrap_err().is_cancelled());
+
+        assert!(!client.check_health(&mut heartbeat_task, &tx, Instant::now()));
+        assert!(!heartbeat_task.is_finished());
+        let beat = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let beat: serde_json::Value = serde_json::from_str(&beat).unwrap();
+        let issue = beat["payload"]["metrics"]["health"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|issue| issue["check"] == "heartbeat")
+            .cloned()
+            .unwrap();
+        assert_eq!(issue["remediation"], "restart_heartbeat");
+        assert_eq!(issue["remediations"], 1);
+
+        // Healthy again: nothing left to report
+        assert!(!client.check_health(&mut heartbeat_task, &tx, Instant::now()));
+        assert!(client
+            .health
+            .issues()
+            .iter()
+            .all(|issue| issue.check != HealthCheck::Heartbeat));
+        heartbeat_task.abort();
+    }
+
+    #[test]
+    fn test_adaptive_heartbeat_fixed_by_default() {
+        let mut heartbeat = AdaptiveHeartbeat::from_config(&create_test_config());
+
+        heartbeat.on_reconnect();
+        assert_eq!(heartbeat.on_stable(), Duration::from_secs(30));
+        assert_eq!(heartbeat.on_stable(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_adaptive_heartbeat_shortens_after_reconnect_and_recovers() {
+        let config = AgentConfig {
+            heartbeat_interval: 30,
+            heartbeat_min: Some(5),
+            heartbeat_max: Some(60),
+            ..create_test_config()
+        };
+        let mut heartbeat = AdaptiveHeartbeat::from_config(&config);
+        assert_eq!(heartbeat.current(), Duration::from_secs(30));
+
+        heartbeat.on_reconnect();
+        let delays: Vec<u64> = (0..5).map(|_| heartbeat.on_stable().as_secs()).collect();
+        assert_eq!(delays, vec![5, 10, 20, 30, 30]);
+    }
+
+    #[test]
+    fn test_adaptive_heartbeat_interval_clamped_to_bounds() {
+        let config = AgentConfig {
+            heartbeat_interval: 600,
+            heartbeat_min: Some(5),
+            heartbeat_max: Some(120),
+            ..create_test_config()
+        };
+        let mut heartbeat = AdaptiveHeartbeat::from_config(&config);
+        assert_eq!(heartbeat.current(), Duration::from_secs(120));
+
+        let config = AgentConfig {
+            heartbeat_interval: 1,
+            heartbeat_min: Some(5),
+            ..create_test_config()
+        };
+        heartbeat = AdaptiveHeartbeat::from_config(&config);
+        assert_eq!(heartbeat.current(), Duration::from_secs(1));
+        heartbeat.on_reconnect();
+        assert_eq!(heartbeat.on_stable(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_heartbeat_gate_skips_during_traffic() {
+        let config = AgentConfig {
+            adaptive_heartbeat: true,
+            ..create_test_config()
+        };
+        let mut gate = HeartbeatGate::from_config(&config);
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+
+        // Nothing else sent yet: the first heartbeat goes out
+        assert!(gate.due(t0, None));
+        // A task result 10s before each tick stands in for the heartbeat
+        assert!(!gate.due(at(30), Some(at(20))));
+        assert!(!gate.due(at(60), Some(at(50))));
+        // Three intervals without one: forced despite the traffic
+        assert!(gate.due(at(90), Some(at(80))));
+        assert!(!gate.due(at(120), Some(at(110))));
+        // Traffic stopped: back to regular heartbeats
+        assert!(gate.due(at(150), Some(at(110))));
+        assert!(gate.due(at(180), Some(at(110))));
+    }
+
+    #[test]
+    fn test_heartbeat_gate_disabled_always_sends() {
+        let mut gate = HeartbeatGate::from_config(&create_test_config());
+        let t0 = Instant::now();
+
+        assert!(gate.due(t0, None));
+        assert!(gate.due(
+            t0 + Duration::from_secs(30),
+            Some(t0 + Duration::from_secs(29))
+        ));
+    }
+
+    #[test]
+    fn test_heartbeat_metrics_disk_low() {
+        let disk = DiskGuard::with_probe(Arc::new(FixedProbe(500)), 1_000);
+        let metrics = HeartbeatMetrics::collect(&disk, std::path::Path::new("/tmp"));
+
+        assert_eq!(metrics.disk_free_bytes, Some(500));
+        assert!(metrics.disk_low);
+    }
+
+    #[test]
+    fn test_connection_diagnostics_message() {
+        let diagnostics = TlsDiagnostics::analyze(
+            "UnknownIssuer".to_string(),
+            vec![crate::tls::CertSummary {
+                subject: "CN=test.server".to_string(),
+                issuer: "CN=Zscaler Root CA".to_string(),
+                spki_sha256: "ab".repeat(32),
+                not_before: "Jan  1 00:00:00 2024 +00:00".to_string(),
+                not_after: "Jan  1 00:00:00 2034 +00:00".to_string(),
+            }],
+        );
+
+        let msg = connection_diagnostics_message("test-paw-123", &diagnostics).unwrap();
+        assert_eq!(msg.msg_type, "connection_diagnostics");
+        assert_eq!(msg.payload["paw"], "test-paw-123");
+        assert_eq!(msg.payload["tls_error"]["inspection_product"], "Zscaler");
+        assert_eq!(msg.payload["tls_error"]["error"], "UnknownIssuer");
+    }
+
     #[test]
     fn test_url_conversion_https_to_wss() {
         let url = "https://server:8443".replace("https://", "wss://");
@@ -479,6 +4640,7 @@ mod tests {
 
         let task: TaskPayload = serde_json::from_str(json).unwrap();
         assert!(task.timeout.is_none());
+        assert!(task.deadline.is_none());
         assert!(task.cleanup.is_none());
     }
 
@@ -488,7 +4650,7 @@ mod tests {
         let sys_info = create_test_sys_info();
         let client = AgentClient::new(config, sys_info).unwrap();
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(32);
+        let (tx, mut rx) = Outbox::channel(32);
 
         let task = TaskPayload {
             id: "cleanup-task".to_string(),
@@ -497,6 +4659,24 @@ mod tests {
             executor: "sh".to_string(),
             timeout: Some(5),
             cleanup: Some("echo cleanup".to_string()),
+            traceparent: None,
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent: false,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir: false,
+            netns: None,
+            seq: None,
+            deadline: None,
         };
 
         let result = client.execute_task(task, &tx).await;
@@ -506,13 +4686,642 @@ mod tests {
         assert!(response.contains("cleanup-task"));
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_with_task_dir() {
+        let mut config = create_test_config();
+        config.task_dirs = true;
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let run = |keep_task_dir: bool| TaskPayload {
+            id: "dir-task".to_string(),
+            technique_id: "T1074".to_string(),
+            command: "test -d \"$AUTOSTRIKE_TASK_DIR\" && printf %s \"$AUTOSTRIKE_TASK_DIR\""
+                .to_string(),
+            executor: "sh".to_string(),
+            timeout: Some(5),
+            cleanup: Some("touch \"$AUTOSTRIKE_TASK_DIR/cleaned\"".to_string()),
+            traceparent: None,
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent: false,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir,
+            netns: None,
+            seq: None,
+            deadline: None,
+        };
+
+        client.execute_task(run(false), &tx).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["success"], true);
+        let dir = PathBuf::from(response["payload"]["output"].as_str().unwrap());
+        assert!(dir.starts_with(std::env::temp_dir()));
+        assert!(!dir.exists());
+
+        client.execute_task(run(true), &tx).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        let kept = PathBuf::from(response["payload"]["output"].as_str().unwrap());
+        assert_ne!(kept, dir);
+        assert!(kept.join("cleaned").exists());
+        std::fs::remove_dir_all(kept).unwrap();
+    }
+
+    #[test]
+    fn test_task_payload_with_traceparent() {
+        let json = r#"{
+            "id": "task-123",
+            "technique_id": "T1082",
+            "command": "whoami",
+            "executor": "sh",
+            "traceparent": "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        }"#;
+
+        let task: TaskPayload = serde_json::from_str(json).unwrap();
+        assert!(!task.use_pty);
+        assert!(task.force_utf8_console.is_none());
+        assert_eq!(
+            task.traceparent.as_deref(),
+            Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        );
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn test_execute_task_exports_spans() {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let task = TaskPayload {
+            id: "traced-task".to_string(),
+            technique_id: "T1082".to_string(),
+            command: "echo traced".to_string(),
+            executor: "sh".to_string(),
+            timeout: Some(5),
+            cleanup: Some("echo cleanup".to_string()),
+            traceparent: Some(
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+            ),
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent: false,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir: false,
+            netns: None,
+            seq: None,
+            deadline: None,
+        };
+        client.execute_task(task, &tx).await.unwrap();
+        rx.recv().await.unwrap();
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let task_span = spans.iter().find(|s| s.name == "task").unwrap();
+        let cleanup_span = spans.iter().find(|s| s.name == "cleanup").unwrap();
+
+        assert_eq!(
+            task_span.span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(task_span.parent_span_id.to_string(), "00f067aa0ba902b7");
+        assert_eq!(
+            cleanup_span.parent_span_id,
+            task_span.span_context.span_id()
+        );
+
+        let attribute = |key: &str| {
+            task_span
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.to_string())
+        };
+        assert_eq!(attribute("task_id").as_deref(), Some("traced-task"));
+        assert_eq!(attribute("technique_id").as_deref(), Some("T1082"));
+        assert_eq!(attribute("executor").as_deref(), Some("sh"));
+        assert_eq!(attribute("exit_code").as_deref(), Some("0"));
+        assert_eq!(attribute("status").as_deref(), Some("success"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_with_pty() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let task: TaskPayload = serde_json::from_value(serde_json::json!({
+            "id": "pty-task",
+            "technique_id": "T1059",
+            "command": "test -t 1 && echo attached",
+            "executor": "sh",
+            "timeout": 5,
+            "use_pty": true
+        }))
+        .unwrap();
+        client.execute_task(task, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["success"], true);
+        assert_eq!(response["payload"]["output"], "attached");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_with_argv() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let task: TaskPayload = serde_json::from_value(serde_json::json!({
+            "id": "argv-task",
+            "technique_id": "T1059",
+            "executor": "sh",
+            "timeout": 5,
+            "argv": ["echo", "$HOME", "`id`"]
+        }))
+        .unwrap();
+        assert!(task.command.is_empty());
+        client.execute_task(task, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["success"], true);
+        assert_eq!(response["payload"]["output"], "$HOME `id`");
+    }
+
+    #[tokio::test]
+    async fn test_execute_task_reports_timestamps() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let task = TaskPayload {
+            id: "timed-task".to_string(),
+            technique_id: "T1082".to_string(),
+            command: "sleep 0.1".to_string(),
+            executor: "sh".to_string(),
+            timeout: Some(5),
+            cleanup: None,
+            traceparent: None,
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent: false,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir: false,
+            netns: None,
+            seq: None,
+            deadline: None,
+        };
+        client.execute_task(task, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        let started_at = chrono::DateTime::parse_from_rfc3339(
+            response["payload"]["started_at"].as_str().unwrap(),
+        )
+        .unwrap();
+        let finished_at = chrono::DateTime::parse_from_rfc3339(
+            response["payload"]["finished_at"].as_str().unwrap(),
+        )
+        .unwrap();
+        assert!(finished_at >= started_at);
+        let duration_ms = response["payload"]["duration_ms"].as_u64().unwrap();
+        assert!((100..5000).contains(&duration_ms), "{}", duration_ms);
+        #[cfg(unix)]
+        assert_eq!(
+            response["payload"]["context"]["euid"],
+            nix::unistd::geteuid().as_raw()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_through_helper() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("autostrike_client_helper_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let helper_path = dir.join("helper.sh");
+        std::fs::write(
+            &helper_path,
+            "#!/bin/sh\ncat >/dev/null\necho '{\"success\": false, \"output\": \"denied\", \"exit_code\": 1}'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&helper_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let config = AgentConfig {
+            helper_path: Some(helper_path),
+            ..create_test_config()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        assert!(client.sys_info.executors.contains(&"helper".to_string()));
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let task = TaskPayload {
+            executor: "helper".to_string(),
+            command: "ptrace-attach 1".to_string(),
+            ..sleep_task("helper-task", false)
+        };
+        client.execute_task(task, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["success"], false);
+        assert_eq!(response["payload"]["output"], "denied");
+        assert_eq!(response["payload"]["exit_code"], 1);
+        assert_eq!(response["payload"]["executor_used"], "helper");
+        assert_eq!(client.executor.fallbacks(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_task_reports_executor_fallback() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let task = TaskPayload {
+            id: "typo-task".to_string(),
+            technique_id: "T1082".to_string(),
+            command: "echo ran".to_string(),
+            executor: "powershel".to_string(),
+            timeout: Some(5),
+            cleanup: None,
+            traceparent: None,
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent: false,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir: false,
+            netns: None,
+            seq: None,
+            deadline: None,
+        };
+        client.execute_task(task, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["executor_requested"], "powershel");
+        let used = if cfg!(target_os = "windows") {
+            "powershell"
+        } else {
+            "sh"
+        };
+        assert_eq!(response["payload"]["executor_used"], used);
+        assert_eq!(client.executor.fallbacks(), 1);
+    }
+
+    /// Accepts one HTTP request on `listener`, answers it with `status` and
+    /// returns it.
+    async fn accept_http(listener: tokio::net::TcpListener, status: u16) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            request.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_ascii_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(str::to_string)
+                    })
+                    .and_then(|length| length.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    break;
+                }
+            }
+        }
+        let response = format!(
+            "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            status
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&request).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_result_endpoint_receives_result() {
+        for (status, delivered) in [(200, true), (503, false)] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            // An endpoint on the server's own origin gets the agent key
+            let config = AgentConfig {
+                server_url: format!("ws://{}", addr),
+                allow_insecure_result_endpoints: true,
+                ..create_test_config_with_secret()
+            };
+            let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+            let (tx, mut rx) = Outbox::channel(32);
+            let url = format!("http://{}/results", addr);
+            let task = TaskPayload {
+                command: "echo routed".to_string(),
+                result_endpoint: Some(url),
+                ..sleep_task("routed-task", true)
+            };
+
+            let (request, result) = tokio::join!(
+                accept_http(listener, status),
+                client.execute_task(task, &tx)
+            );
+            result.unwrap();
+
+            assert!(request.starts_with("POST /results "), "{}", request);
+            assert!(request.contains("x-agent-key: test-secret"));
+            let body = request.split_once("\r\n\r\n").unwrap().1;
+            let message: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(message["type"], "task_result");
+            assert_eq!(message["payload"]["task_id"], "routed-task");
+            // A refused result falls back to the server
+            match delivered {
+                true => assert!(rx.try_recv().is_err()),
+                false => assert!(rx.try_recv().unwrap().contains("routed-task")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_foreign_result_endpoint_gets_no_agent_key() {
+        let config = AgentConfig {
+            allow_insecure_result_endpoints: true,
+            ..create_test_config_with_secret()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, _rx) = Outbox::channel(32);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let task = TaskPayload {
+            command: "echo routed".to_string(),
+            result_endpoint: Some(format!("http://{}/results", listener.local_addr().unwrap())),
+            ..sleep_task("foreign-sink", true)
+        };
+
+        let (request, result) =
+            tokio::join!(accept_http(listener, 200), client.execute_task(task, &tx));
+        result.unwrap();
+
+        assert!(request.starts_with("POST /results "), "{}", request);
+        assert!(!request.to_ascii_lowercase().contains("x-agent-key"));
+        assert!(!request.contains("test-secret"));
+    }
+
+    #[tokio::test]
+    async fn test_insecure_result_endpoint_rejected() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let msg = AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": "plain-http",
+                "technique_id": "T1082",
+                "command": "echo never",
+                "executor": "sh",
+                "result_endpoint": "http://sink.lab/results",
+            }),
+        };
+        client.handle_message(msg, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["task_id"], "plain-http");
+        assert_eq!(response["payload"]["success"], false);
+        let output = response["payload"]["output"].as_str().unwrap();
+        assert!(
+            output.starts_with("Task rejected: result_endpoint"),
+            "{}",
+            output
+        );
+        assert!(client.tasks.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_task_requirements() {
+        // The test host is linux 5.0 with sh and bash
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let task = |id: &str, requires: serde_json::Value| AgentMessage {
+            msg_type: "task".to_string(),
+            correlation_id: None,
+            payload: serde_json::json!({
+                "id": id,
+                "technique_id": "T1082",
+                "command": "echo ran",
+                "executor": "sh",
+                "cleanup": null,
+                "requires": requires,
+            }),
+        };
+
+        let unmet = serde_json::json!({ "platform": "linux", "min_os_version": "6.1" });
+        client
+            .handle_message(task("unmet", unmet), &tx)
+            .await
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["task_id"], "unmet");
+        assert_eq!(response["payload"]["success"], false);
+        assert_eq!(response["payload"]["skipped"], true);
+        assert_eq!(
+            response["payload"]["output"],
+            "Task skipped: requires OS version 6.1 or later, host has 5.0"
+        );
+        assert!(client.tasks.is_empty());
+
+        let met =
+            serde_json::json!({ "platform": "linux", "min_os_version": "5.0", "executor": "bash" });
+        client.handle_message(task("met", met), &tx).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["task_id"], "met");
+        assert_eq!(response["payload"]["success"], true);
+        assert_eq!(response["payload"]["output"], "ran");
+        assert!(response["payload"].get("skipped").is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_task_output_sample() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+        let task = TaskPayload {
+            command: "seq 1 3000".to_string(),
+            output_sample: Some(OutputSample {
+                head_lines: 2,
+                tail_lines: 3,
+            }),
+            expect_output_regex: Some("(?m)^1500$".to_string()),
+            ..sleep_task("chatty-task", true)
+        };
+
+        client.execute_task(task, &tx).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(
+            response["payload"]["output"],
+            "1\n2\n[... 2995 lines omitted ...]\n2998\n2999\n3000"
+        );
+        // Matched against the whole output
+        assert_eq!(response["payload"]["output_matched"], true);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_reports_spawned_argv() {
+        for report_spawned_argv in [false, true] {
+            let config = AgentConfig {
+                report_spawned_argv,
+                ..create_test_config_with_secret()
+            };
+            let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+            let (tx, mut rx) = Outbox::channel(32);
+            let task = TaskPayload {
+                command: "echo test-secret >/dev/null".to_string(),
+                ..sleep_task("argv-task", false)
+            };
+            client.execute_task(task, &tx).await.unwrap();
+
+            let response: serde_json::Value =
+                serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+            let argv = &response["payload"]["spawned_argv"];
+            if report_spawned_argv {
+                assert_eq!(
+                    *argv,
+                    serde_json::json!(["/bin/sh", "-c", "echo [REDACTED] >/dev/null"])
+                );
+            } else {
+                assert!(argv.is_null());
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_task_reports_login_shell() {
+        let client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        for login_shell in [None, Some(true)] {
+            let task = TaskPayload {
+                command: "true".to_string(),
+                login_shell,
+                ..sleep_task("login-task", false)
+            };
+            client.execute_task(task, &tx).await.unwrap();
+
+            let response: serde_json::Value =
+                serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+            let payload = &response["payload"];
+            assert_eq!(payload["success"], true);
+            if login_shell.is_some() {
+                assert_eq!(payload["login_shell"], true);
+                let path = payload["effective_path"].as_str().unwrap();
+                assert!(path.split(':').any(|dir| dir.ends_with("bin")), "{}", path);
+            } else {
+                assert!(payload.get("login_shell").is_none());
+                assert!(payload.get("effective_path").is_none());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_task_clamped_by_executor_ceiling() {
+        let config = AgentConfig {
+            executor_timeout_ceilings: HashMap::from([("sh".to_string(), 1)]),
+            ..create_test_config()
+        };
+        let client = AgentClient::new(config, create_test_sys_info()).unwrap();
+        let (tx, mut rx) = Outbox::channel(32);
+
+        let task = TaskPayload {
+            id: "clamped-task".to_string(),
+            technique_id: "T1059".to_string(),
+            command: "sleep 10".to_string(),
+            executor: "sh".to_string(),
+            timeout: Some(3600),
+            cleanup: None,
+            traceparent: None,
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent: false,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir: false,
+            netns: None,
+            seq: None,
+            deadline: None,
+        };
+
+        let start = std::time::Instant::now();
+        client.execute_task(task, &tx).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let response: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["payload"]["success"], false);
+        assert_eq!(response["payload"]["output"], "Command timed out");
+    }
+
     #[tokio::test]
     async fn test_execute_task_default_timeout() {
         let config = create_test_config();
         let sys_info = create_test_sys_info();
         let client = AgentClient::new(config, sys_info).unwrap();
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(32);
+        let (tx, mut rx) = Outbox::channel(32);
 
         let task = TaskPayload {
             id: "timeout-task".to_string(),
@@ -521,6 +5330,24 @@ mod tests {
             executor: "sh".to_string(),
             timeout: None,
             cleanup: None,
+            traceparent: None,
+            use_pty: false,
+            argv: None,
+            steps: Vec::new(),
+            requires: None,
+            output_sample: None,
+            force_utf8_console: None,
+            login_shell: None,
+            group_id: None,
+            result_endpoint: None,
+            expect_output_regex: None,
+            allow_concurrent: false,
+            verify_registry: Vec::new(),
+            capture_events: None,
+            keep_task_dir: false,
+            netns: None,
+            seq: None,
+            deadline: None,
         };
 
         let result = client.execute_task(task, &tx).await;
@@ -529,4 +5356,319 @@ mod tests {
         let response = rx.recv().await.unwrap();
         assert!(response.contains("timeout-task"));
     }
+
+    #[tokio::test]
+    async fn test_connects_through_host_override() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let register = ws.next().await.unwrap().unwrap();
+            ws.close(None).await.unwrap();
+            register.into_text().unwrap()
+        });
+
+        let mut config = create_test_config();
+        // The name has no DNS record; only the override makes it reachable
+        config.server_url = format!("http://autostrike.lab:{}", port);
+        config.network.resolver = crate::config::Resolver::StaticOnly;
+        config
+            .network
+            .host_overrides
+            .insert("autostrike.lab".to_string(), [127, 0, 0, 1].into());
+        let mut client = AgentClient::new(config, create_test_sys_info()).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), client.connect_and_run())
+            .await
+            .unwrap()
+            .unwrap();
+        let register: serde_json::Value = serde_json::from_str(&server.await.unwrap()).unwrap();
+        assert_eq!(register["type"], "register");
+        assert_eq!(register["payload"]["paw"], "test-paw-123");
+    }
+
+    #[tokio::test]
+    async fn test_queued_results_survive_reconnect_remediation() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = create_test_config();
+        config.server_url = format!("http://{}", listener.local_addr().unwrap());
+        config.health.max_result_age_secs = 0;
+        let mut client = AgentClient::new(config, create_test_sys_info()).unwrap();
+
+        // Results stuck in the queue trip the check, which asks to reconnect
+        for i in 0..3 {
+            client.outbox.bulk(format!("result-{}", i)).await.unwrap();
+        }
+        client
+            .outbox
+            .control("stale pong".to_string())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let mut heartbeat = tokio::spawn(async {});
+        assert!(client.check_health(&mut heartbeat, &client.outbox, Instant::now()));
+        assert_eq!(
+            client.health.issues()[0].remediation,
+            Some(crate::health::Remediation::Reconnect)
+        );
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut frames = Vec::new();
+            while frames.len() < 4 {
+                let frame = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                if !frame.contains(r#""type":"heartbeat""#) {
+                    frames.push(frame);
+                }
+            }
+            ws.close(None).await.unwrap();
+            frames
+        });
+        tokio::time::timeout(Duration::from_secs(10), client.connect_and_run())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let frames = server.await.unwrap();
+        assert!(frames[0].contains(r#""type":"register""#));
+        assert_eq!(frames[1..], ["result-0", "result-1", "result-2"]);
+        assert!(client.outbox.oldest_bulk_age().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_results_past_ttl_expire_at_reconnect() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = create_test_config();
+        config.server_url = format!("http://{}", listener.local_addr().unwrap());
+        config.result_ttl = 60;
+        let mut client = AgentClient::new(config, create_test_sys_info()).unwrap();
+
+        let result = |task_id: &str| {
+            serde_json::json!({"type": "task_result", "payload": {"task_id": task_id}}).to_string()
+        };
+        client
+            .outbox
+            .bulk_aged(result("task-old"), Duration::from_secs(120))
+            .await;
+        client.outbox.bulk(result("task-new")).await.unwrap();
+        client.pending_acks.lock().unwrap().record("task-old");
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut frames = Vec::new();
+            while frames.len() < 3 {
+                let frame = ws.next().await.unwrap().unwrap().into_text().unwrap();
+                if !frame.contains(r#""type":"heartbeat""#) {
+                    frames.push(serde_json::from_str::<serde_json::Value>(&frame).unwrap());
+                }
+            }
+            ws.close(None).await.unwrap();
+            frames
+        });
+        tokio::time::timeout(Duration::from_secs(10), client.connect_and_run())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let frames = server.await.unwrap();
+        assert_eq!(frames[0]["type"], "register");
+        assert_eq!(frames[1]["type"], "results_expired");
+        assert_eq!(
+            frames[1]["payload"]["task_ids"],
+            serde_json::json!(["task-old"])
+        );
+        assert_eq!(frames[2]["payload"]["task_id"], "task-new");
+        assert!(client.pending_acks.lock().unwrap().snapshot().is_empty());
+        assert!(client.outbox.oldest_bulk_age().is_none());
+    }
+
+    #[test]
+    fn test_close_action_by_code() {
+        assert_eq!(close_action(None), CloseAction::Reconnect);
+        assert_eq!(close_action(Some(1000)), CloseAction::Reconnect);
+        assert_eq!(close_action(Some(1011)), CloseAction::Reconnect);
+        for code in [1001, 1012, 1013] {
+            assert_eq!(close_action(Some(code)), CloseAction::Maintenance);
+        }
+        for code in [1008, 3000, 3003] {
+            assert_eq!(close_action(Some(code)), CloseAction::Refused);
+        }
+
+        assert_eq!(maintenance_delay(0), Duration::from_secs(30));
+        assert!(maintenance_delay(u64::MAX) < Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_server_close_codes_decide_reconnect() {
+        use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+        let mut client = AgentClient::new(create_test_config(), create_test_sys_info()).unwrap();
+        let cases = [
+            (Some(CloseCode::Away), CloseAction::Maintenance),
+            (Some(CloseCode::Policy), CloseAction::Refused),
+            (Some(CloseCode::Normal), CloseAction::Reconnect),
+            (None, CloseAction::Reconnect),
+        ];
+        for (code, expected) in cases {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            client.config.server_url = format!("http://{}", listener.local_addr().unwrap());
+            let server = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                ws.next().await.unwrap().unwrap();
+                let frame = code.map(|code| CloseFrame {
+                    code,
+                    reason: "scheduled maintenance".into(),
+                });
+                ws.close(frame).await.unwrap();
+            });
+
+            let action = tokio::time::timeout(Duration::from_secs(10), client.connect_and_run())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(action, expected, "{:?}", code);
+            server.await.unwrap();
+        }
+        assert_eq!(
+            *client.close_codes.lock().unwrap(),
+            BTreeMap::from([(1000, 1), (1001, 1), (1008, 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refused_close_stops_reconnecting() {
+        use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counted = attempts.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counted.fetch_add(1, Ordering::SeqCst);
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                ws.next().await.unwrap().unwrap();
+                let frame = CloseFrame {
+                    code: CloseCode::from(3000),
+                    reason: "agent key revoked".into(),
+                };
+                let _ = ws.close(Some(frame)).await;
+            }
+        });
+        let mut client = AgentClient::new(
+            AgentConfig {
+                server_url: url,
+                ..create_test_config()
+            },
+            create_test_sys_info(),
+        )
+        .unwrap();
+
+        let err = tokio::time::timeout(Duration::from_secs(10), client.run())
+            .await
+            .expect("run kept reconnecting")
+            .unwrap_err();
+        assert!(matches!(err, AgentError::Auth(_)), "{}", err);
+        assert_eq!(err.exit_code(), 77);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    /// Server answering every WebSocket upgrade with HTTP `status`, counting
+    /// the attempts.
+    async fn refusing_server(status: &'static str) -> (String, Arc<AtomicUsize>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counted = attempts.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counted.fetch_add(1, Ordering::SeqCst);
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request).await;
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, attempts)
+    }
+
+    #[tokio::test]
+    async fn test_auth_error_stops_reconnecting() {
+        let (url, attempts) = refusing_server("401 Unauthorized").await;
+        let mut client = AgentClient::new(
+            AgentConfig {
+                server_url: url,
+                ..create_test_config()
+            },
+            create_test_sys_info(),
+        )
+        .unwrap();
+
+        let err = tokio::time::timeout(Duration::from_secs(10), client.run())
+            .await
+            .expect("run kept reconnecting")
+            .unwrap_err();
+        assert!(matches!(err, AgentError::Auth(_)), "{}", err);
+        assert_eq!(err.exit_code(), 77);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_errors_classified() {
+        for (status, retryable) in [
+            ("403 Forbidden", false),
+            ("404 Not Found", true),
+            ("429 Too Many Requests", true),
+            ("503 Service Unavailable", true),
+        ] {
+            let (url, _) = refusing_server(status).await;
+            let mut client = AgentClient::new(
+                AgentConfig {
+                    server_url: url,
+                    ..create_test_config()
+                },
+                create_test_sys_info(),
+            )
+            .unwrap();
+
+            let err = client.connect_and_run().await.unwrap_err();
+            assert_eq!(err.is_retryable(), retryable, "{}: {}", status, err);
+            assert_eq!(is_client_error(&err), status.starts_with("404"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_error_retried_on_long_backoff() {
+        let (url, attempts) = refusing_server("404 Not Found").await;
+        let mut client = AgentClient::new(
+            AgentConfig {
+                server_url: url,
+                ..create_test_config()
+            },
+            create_test_sys_info(),
+        )
+        .unwrap();
+
+        // Still retrying, but not after the usual one second
+        assert!(tokio::time::timeout(Duration::from_secs(2), client.run())
+            .await
+            .is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_static_only_resolver_refuses_unknown_host() {
+        let mut config = create_test_config();
+        config.network.resolver = crate::config::Resolver::StaticOnly;
+        let mut client = AgentClient::new(config, create_test_sys_info()).unwrap();
+
+        let err = client.connect_and_run().await.unwrap_err();
+        assert!(format!("{:#}", err).contains("static-only"), "{:#}", err);
+    }
 }
@@ -0,0 +1,314 @@
+//! Built-in `collect_bundle` action: one-shot collection of standard host
+//! artifacts (processes, network connections, kernel modules, scheduled
+//! tasks), each collector failing on its own.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::Local;
+use serde_json::{json, Value};
+use sysinfo::{PidExt, ProcessExt, System, SystemExt};
+
+use crate::executor::{CommandExecutor, ExecOptions};
+use crate::protocol::{CollectBundlePayload, CollectBundleResultPayload, CollectorOutput};
+
+/// Collectors run for every bundle, in the order of the result's keys.
+pub const COLLECTORS: &[&str] = &[
+    "kernel_modules",
+    "network_connections",
+    "processes",
+    "scheduled_tasks",
+];
+
+/// How long a collector running a system tool may take.
+const TOOL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs every collector for `request`.
+pub async fn collect_bundle(request: CollectBundlePayload) -> CollectBundleResultPayload {
+    let executor = CommandExecutor::new();
+    let mut collectors = BTreeMap::new();
+    for &name in COLLECTORS {
+        let items = match name {
+            "processes" => Ok(processes()),
+            "network_connections" => network_connections(&executor).await,
+            "kernel_modules" => kernel_modules(&executor).await,
+            "scheduled_tasks" => scheduled_tasks(&executor).await,
+            _ => Err(format!("unknown collector {}", name)),
+        };
+        let output = match items {
+            Ok(items) => CollectorOutput {
+                success: true,
+                items,
+                error: None,
+            },
+            Err(e) => CollectorOutput {
+                success: false,
+                items: Vec::new(),
+                error: Some(e),
+            },
+        };
+        collectors.insert(name.to_string(), output);
+    }
+    CollectBundleResultPayload {
+        id: request.id,
+        platform: std::env::consts::OS.to_string(),
+        collected_at: Local::now().to_rfc3339(),
+        collectors,
+        error: None,
+    }
+}
+
+/// Running processes.
+fn processes() -> Vec<Value> {
+    let mut sys = System::new();
+    sys.refresh_processes();
+    let mut processes: Vec<_> = sys.processes().values().collect();
+    processes.sort_by_key(|process| process.pid().as_u32());
+    processes
+        .into_iter()
+        .map(|process| {
+            json!({
+                "pid": process.pid().as_u32(),
+                "ppid": process.parent().map(|pid| pid.as_u32()),
+                "name": process.name(),
+                "exe": process.exe().to_string_lossy(),
+            })
+        })
+        .collect()
+}
+
+/// Output lines of `argv`, run without a shell.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+async fn tool_lines(executor: &CommandExecutor, argv: &[&str]) -> Result<Vec<Value>, String> {
+    let argv: Vec<String> = argv.iter().map(|arg| arg.to_string()).collect();
+    let result = executor
+        .execute_argv(&argv, TOOL_TIMEOUT, &ExecOptions::default())
+        .await;
+    if !result.success {
+        return Err(format!("{} failed: {}", argv[0], result.output.trim()));
+    }
+    Ok(result
+        .output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| Value::String(line.to_string()))
+        .collect())
+}
+
+#[cfg(target_os = "linux")]
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))
+}
+
+#[cfg(target_os = "linux")]
+async fn network_connections(_executor: &CommandExecutor) -> Result<Vec<Value>, String> {
+    let mut connections = Vec::new();
+    for protocol in ["tcp", "tcp6", "udp", "udp6"] {
+        // IPv6 tables are missing when IPv6 is disabled
+        let Ok(table) = std::fs::read_to_string(format!("/proc/net/{}", protocol)) else {
+            continue;
+        };
+        connections.extend(parse_proc_net(protocol, &table));
+    }
+    if connections.is_empty() {
+        read("/proc/net/tcp")?;
+    }
+    Ok(connections)
+}
+
+#[cfg(windows)]
+async fn network_connections(executor: &CommandExecutor) -> Result<Vec<Value>, String> {
+    tool_lines(executor, &["netstat", "-ano"]).await
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+async fn network_connections(executor: &CommandExecutor) -> Result<Vec<Value>, String> {
+    tool_lines(executor, &["netstat", "-an"]).await
+}
+
+#[cfg(target_os = "linux")]
+async fn kernel_modules(_executor: &CommandExecutor) -> Result<Vec<Value>, String> {
+    Ok(read("/proc/modules")?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let size: u64 = fields.next()?.parse().ok()?;
+            let _instances = fields.next();
+            let used_by: Vec<&str> = fields
+                .next()
+                .unwrap_or("-")
+                .split(',')
+                .filter(|module| !module.is_empty() && *module != "-")
+                .collect();
+            Some(json!({ "name": name, "size": size, "used_by": used_by }))
+        })
+        .collect())
+}
+
+#[cfg(windows)]
+async fn kernel_modules(executor: &CommandExecutor) -> Result<Vec<Value>, String> {
+    tool_lines(executor, &["driverquery", "/fo", "csv", "/nh"]).await
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+async fn kernel_modules(executor: &CommandExecutor) -> Result<Vec<Value>, String> {
+    tool_lines(executor, &["kextstat", "-l"]).await
+}
+
+#[cfg(unix)]
+async fn scheduled_tasks(_executor: &CommandExecutor) -> Result<Vec<Value>, String> {
+    let mut sources = vec![std::path::PathBuf::from("/etc/crontab")];
+    if let Ok(entries) = std::fs::read_dir("/etc/cron.d") {
+        let mut files: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+        files.sort();
+        sources.extend(files);
+    }
+    let mut tasks = Vec::new();
+    for source in sources {
+        let Ok(text) = std::fs::read_to_string(&source) else {
+            continue;
+        };
+        tasks.extend(
+            cron_entries(&text)
+                .map(|entry| json!({ "source": source.to_string_lossy(), "entry": entry })),
+        );
+    }
+    Ok(tasks)
+}
+
+#[cfg(windows)]
+async fn scheduled_tasks(executor: &CommandExecutor) -> Result<Vec<Value>, String> {
+    tool_lines(executor, &["schtasks", "/query", "/fo", "csv", "/nh"]).await
+}
+
+/// Connections of a `/proc/net` table, with addresses decoded.
+#[cfg(target_os = "linux")]
+fn parse_proc_net(protocol: &str, table: &str) -> Vec<Value> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            Some(json!({
+                "protocol": protocol,
+                "local": decode_address(fields.get(1)?)?,
+                "remote": decode_address(fields.get(2)?)?,
+                "state": tcp_state(fields.get(3)?),
+                "uid": fields.get(7)?.parse::<u32>().ok()?,
+                "inode": fields.get(9)?.parse::<u64>().ok()?,
+            }))
+        })
+        .collect()
+}
+
+/// Decodes a `/proc/net` address (`0100007F:0016`, the IP in host byte
+/// order by 32-bit word) as `127.0.0.1:22`.
+#[cfg(target_os = "linux")]
+fn decode_address(field: &str) -> Option<String> {
+    let (ip, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let words: Vec<u32> = (0..ip.len())
+        .step_by(8)
+        .map(|i| u32::from_str_radix(ip.get(i..i + 8)?, 16).ok())
+        .collect::<Option<_>>()?;
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_ne_bytes()).collect();
+    let ip: std::net::IpAddr = match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok()?.into(),
+        16 => <[u8; 16]>::try_from(bytes).ok()?.into(),
+        _ => return None,
+    };
+    Some(std::net::SocketAddr::new(ip, port).to_string())
+}
+
+/// Name of a `/proc/net` TCP state code (UDP sockets report `07`, `CLOSE`).
+#[cfg(target_os = "linux")]
+fn tcp_state(code: &str) -> &'static str {
+    match code {
+        "01" => "ESTABLISHED",
+        "02" => "SYN_SENT",
+        "03" => "SYN_RECV",
+        "04" => "FIN_WAIT1",
+        "05" => "FIN_WAIT2",
+        "06" => "TIME_WAIT",
+        "07" => "CLOSE",
+        "08" => "CLOSE_WAIT",
+        "09" => "LAST_ACK",
+        "0A" => "LISTEN",
+        "0B" => "CLOSING",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Schedule lines of a crontab, without comments, blank lines and variables.
+#[cfg(unix)]
+fn cron_entries(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|line| {
+        !line.is_empty()
+            && !line.starts_with('#')
+            && !line
+                .split_whitespace()
+                .next()
+                .is_some_and(|first| first.contains('='))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bundle_has_every_collector() {
+        let bundle = collect_bundle(CollectBundlePayload {
+            id: Some("bundle-1".to_string()),
+        })
+        .await;
+
+        assert_eq!(bundle.id.as_deref(), Some("bundle-1"));
+        assert_eq!(bundle.platform, std::env::consts::OS);
+        let keys: Vec<&str> = bundle.collectors.keys().map(String::as_str).collect();
+        assert_eq!(keys, COLLECTORS);
+        let processes = &bundle.collectors["processes"];
+        assert!(processes.success);
+        let own = std::process::id();
+        assert!(processes.items.iter().any(|process| process["pid"] == own));
+        for output in bundle.collectors.values() {
+            assert_eq!(output.success, output.error.is_none());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_proc_net() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   \
+            0: 0100007F:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 12345 1 0000000000000000 100 0 0 10 0\n";
+
+        let connections = parse_proc_net("tcp", table);
+        assert_eq!(
+            connections,
+            [json!({
+                "protocol": "tcp",
+                "local": "127.0.0.1:22",
+                "remote": "0.0.0.0:0",
+                "state": "LISTEN",
+                "uid": 0,
+                "inode": 12345,
+            })]
+        );
+        assert_eq!(
+            decode_address("00000000000000000000000001000000:0050").as_deref(),
+            Some("[::1]:80")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cron_entries() {
+        let crontab = "# m h dom mon dow user command\nSHELL=/bin/sh\n\n17 * * * * root cd / && run-parts /etc/cron.hourly\n";
+        assert_eq!(
+            cron_entries(crontab).collect::<Vec<_>>(),
+            ["17 * * * * root cd / && run-parts /etc/cron.hourly"]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Free disk space and write access checks for agent writes.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tracing::warn;
+
+/// Source of free-space information for a filesystem path.
+pub trait SpaceProbe: Send + Sync {
+    /// Returns the bytes available to the agent on the filesystem holding `path`.
+    fn available_bytes(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// Queries the operating system (statvfs / GetDiskFreeSpaceEx).
+pub struct SystemSpaceProbe;
+
+impl SpaceProbe for SystemSpaceProbe {
+    #[cfg(unix)]
+    fn available_bytes(&self, path: &Path) -> io::Result<u64> {
+        let stat = nix::sys::statvfs::statvfs(path).map_err(io::Error::from)?;
+        #[allow(clippy::useless_conversion)] // field widths differ between platforms
+        Ok(u64::from(stat.blocks_available()) * u64::from(stat.fragment_size()))
+    }
+
+    #[cfg(windows)]
+    fn available_bytes(&self, path: &Path) -> io::Result<u64> {
+        use std::os::windows::ffi::OsStrExt;
+        use winapi::um::fileapi::GetDiskFreeSpaceExW;
+        use winapi::um::winnt::ULARGE_INTEGER;
+
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut available: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+        // SAFETY: `wide` is NUL-terminated and outlives the call; the output
+        // pointers reference a live ULARGE_INTEGER or are null as allowed.
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                wide.as_ptr(),
+                &mut available,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { *available.QuadPart() })
+    }
+}
+
+/// Reason a write was refused for lack of disk space.
+#[derive(Debug)]
+pub enum DiskSpaceError {
+    /// Free space minus the reserve is below the required size.
+    InsufficientDisk {
+        path: PathBuf,
+        available: u64,
+        required: u64,
+        reserve: u64,
+    },
+    /// Free space could not be determined.
+    Probe(io::Error),
+}
+
+impl fmt::Display for DiskSpaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientDisk {
+                path,
+                available,
+                required,
+                reserve,
+            } => write!(
+                f,
+                "insufficient disk space on {}: {} bytes available, {} required plus {} reserved",
+                path.display(),
+                available,
+                required,
+                reserve
+            ),
+            Self::Probe(e) => write!(f, "failed to query free disk space: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DiskSpaceError {}
+
+/// Refuses writes that would eat into the configured free-space reserve.
+#[derive(Clone)]
+pub struct DiskGuard {
+    probe: Arc<dyn SpaceProbe>,
+    reserve_bytes: u64,
+}
+
+impl DiskGuard {
+    /// Creates a guard backed by the operating system probe.
+    pub fn new(reserve_bytes: u64) -> Self {
+        Self::with_probe(Arc::new(SystemSpaceProbe), reserve_bytes)
+    }
+
+    /// Creates a guard backed by a custom probe.
+    pub fn with_probe(probe: Arc<dyn SpaceProbe>, reserve_bytes: u64) -> Self {
+        Self {
+            probe,
+            reserve_bytes,
+        }
+    }
+
+    /// Checks that `required` bytes can be written to `path` while keeping the reserve free.
+    /// Returns the currently available bytes on success.
+    pub fn ensure_space(&self, path: &Path, required: u64) -> Result<u64, DiskSpaceError> {
+        let available = self
+            .probe
+            .available_bytes(path)
+            .map_err(DiskSpaceError::Probe)?;
+        if available < required.saturating_add(self.reserve_bytes) {
+            return Err(DiskSpaceError::InsufficientDisk {
+                path: path.to_path_buf(),
+                available,
+                required,
+                reserve: self.reserve_bytes,
+            });
+        }
+        Ok(available)
+    }
+}
+
+/// Checks that files can be created in the scratch directory `dir` by
+/// writing and removing a small probe file, failing when `required` and
+/// logging a warning otherwise.
+pub fn check_writable(dir: &Path, required: bool) -> anyhow::Result<()> {
+    let Err(e) = probe_write(dir) else {
+        return Ok(());
+    };
+    if required {
+        anyhow::bail!(
+            "refusing to start, cannot write to the scratch directory {}: {}",
+            dir.display(),
+            e
+        );
+    }
+    warn!(
+        "Cannot write to the scratch directory {}: {}. Tasks that write files there will fail",
+        dir.display(),
+        e
+    );
+    Ok(())
+}
+
+/// Writes, syncs and removes a probe file in `dir`.
+fn probe_write(dir: &Path) -> io::Result<()> {
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    let path = dir.join(format!(".autostrike-probe-{}", &suffix[..8]));
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    let written = file.write_all(b"autostrike").and_then(|()| file.sync_all());
+    drop(file);
+    written.and(std::fs::remove_file(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedProbe(u64);
+
+    impl SpaceProbe for FixedProbe {
+        fn available_bytes(&self, _path: &Path) -> io::Result<u64> {
+            Ok(self.0)
+        }
+    }
+
+    struct FailingProbe;
+
+    impl SpaceProbe for FailingProbe {
+        fn available_bytes(&self, _path: &Path) -> io::Result<u64> {
+            Err(io::Error::new(io::ErrorKind::NotFound, "no such volume"))
+        }
+    }
+
+    #[test]
+    fn test_ensure_space_sufficient() {
+        let guard = DiskGuard::with_probe(Arc::new(FixedProbe(1_000)), 100);
+
+        let available = guard.ensure_space(Path::new("/scratch"), 900).unwrap();
+        assert_eq!(available, 1_000);
+    }
+
+    #[test]
+    fn test_ensure_space_insufficient() {
+        let guard = DiskGuard::with_probe(Arc::new(FixedProbe(1_000)), 100);
+
+        let err = guard.ensure_space(Path::new("/scratch"), 901).unwrap_err();
+        assert!(matches!(err, DiskSpaceError::InsufficientDisk { .. }));
+        assert!(err.to_string().contains("1000 bytes available"));
+    }
+
+    #[test]
+    fn test_ensure_space_reserve_only() {
+        let guard = DiskGuard::with_probe(Arc::new(FixedProbe(50)), 100);
+
+        assert!(guard.ensure_space(Path::new("/scratch"), 0).is_err());
+    }
+
+    #[test]
+    fn test_ensure_space_probe_failure() {
+        let guard = DiskGuard::with_probe(Arc::new(FailingProbe), 0);
+
+        let err = guard.ensure_space(Path::new("/scratch"), 1).unwrap_err();
+        assert!(matches!(err, DiskSpaceError::Probe(_)));
+    }
+
+    #[test]
+    fn test_ensure_space_saturates_large_request() {
+        let guard = DiskGuard::with_probe(Arc::new(FixedProbe(u64::MAX - 1)), 100);
+
+        assert!(guard.ensure_space(Path::new("/scratch"), u64::MAX).is_err());
+    }
+
+    #[test]
+    fn test_check_writable_temp_dir() {
+        let dir = std::env::temp_dir().join(format!("autostrike_probe_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        check_writable(&dir, true).unwrap();
+        // The probe file is gone
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_writable_unwritable_dir() {
+        // Missing, so unwritable even for root
+        let dir = std::env::temp_dir().join("autostrike_missing_scratch/nested");
+
+        let err = check_writable(&dir, true).unwrap_err();
+        assert!(err.to_string().contains("refusing to start"), "{}", err);
+        assert!(check_writable(&dir, false).is_ok());
+    }
+
+    #[test]
+    fn test_system_probe_temp_dir() {
+        let available = SystemSpaceProbe
+            .available_bytes(&std::env::temp_dir())
+            .unwrap();
+        assert!(available > 0);
+    }
+}
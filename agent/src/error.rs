@@ -0,0 +1,110 @@
+//! Errors the agent tells apart to decide whether to retry or stop, and
+//! with which process exit code. Lower layers keep `anyhow` context chains,
+//! carried in each variant and shown in its message.
+
+use thiserror::Error;
+
+/// A failure that ends a connection attempt or the agent itself.
+#[derive(Debug, Error)]
+pub enum AgentError {
+    /// Unusable configuration; retrying cannot help until it is fixed.
+    #[error("configuration error: {0:#}")]
+    Config(anyhow::Error),
+    /// The server could not be reached, or the connection failed.
+    #[error("network error: {error:#}")]
+    Network {
+        /// Whether trying again later can succeed.
+        retryable: bool,
+        error: anyhow::Error,
+    },
+    /// A message could not be built, or the server broke the protocol.
+    #[error("protocol error: {0:#}")]
+    Protocol(anyhow::Error),
+    /// The server refused the agent's credentials.
+    #[error("authentication error: {0:#}")]
+    Auth(anyhow::Error),
+    /// A local file or directory the agent needs is unusable.
+    #[error("I/O error: {0:#}")]
+    Io(anyhow::Error),
+}
+
+impl AgentError {
+    /// A network failure worth retrying.
+    pub fn network(source: impl Into<anyhow::Error>) -> Self {
+        Self::Network {
+            retryable: true,
+            error: source.into(),
+        }
+    }
+
+    /// Whether the reconnect loop should try again after this error.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Network { retryable, .. } => *retryable,
+            Self::Protocol(_) | Self::Io(_) => true,
+            Self::Config(_) | Self::Auth(_) => false,
+        }
+    }
+
+    /// Process exit code, from the BSD `sysexits.h` conventions service
+    /// managers and scripts recognize.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Config(_) => 78,      // EX_CONFIG
+            Self::Network { .. } => 69, // EX_UNAVAILABLE
+            Self::Protocol(_) => 76,    // EX_PROTOCOL
+            Self::Auth(_) => 77,        // EX_NOPERM
+            Self::Io(_) => 74,          // EX_IOERR
+        }
+    }
+}
+
+/// Exit code for an error ending the agent: its [`AgentError`]'s, or 1.
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    error
+        .downcast_ref::<AgentError>()
+        .map_or(1, AgentError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let cases = [
+            (AgentError::Config(anyhow::anyhow!("bad gzip_level")), 78),
+            (AgentError::network(anyhow::anyhow!("refused")), 69),
+            (AgentError::Protocol(anyhow::anyhow!("too large")), 76),
+            (AgentError::Auth(anyhow::anyhow!("HTTP 401")), 77),
+            (AgentError::Io(anyhow::anyhow!("read-only")), 74),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.exit_code(), code, "{}", error);
+            assert_eq!(exit_code(&anyhow::Error::new(error)), code);
+        }
+        assert_eq!(exit_code(&anyhow::anyhow!("untyped")), 1);
+    }
+
+    #[test]
+    fn test_retryable() {
+        assert!(AgentError::network(anyhow::anyhow!("reset")).is_retryable());
+        assert!(!AgentError::Network {
+            retryable: false,
+            error: anyhow::anyhow!("HTTP 404"),
+        }
+        .is_retryable());
+        assert!(AgentError::Protocol(anyhow::anyhow!("too large")).is_retryable());
+        assert!(!AgentError::Auth(anyhow::anyhow!("HTTP 401")).is_retryable());
+        assert!(!AgentError::Config(anyhow::anyhow!("no host")).is_retryable());
+    }
+
+    #[test]
+    fn test_display_keeps_context() {
+        let source = anyhow::anyhow!("connection refused").context("Failed to connect to server");
+        assert_eq!(
+            AgentError::network(source).to_string(),
+            "network error: Failed to connect to server: connection refused"
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! Windows Event Log read-back, reporting the events (Security, Sysmon, ...)
+//! logged while a technique ran.
+
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Most events returned for one task.
+pub const MAX_EVENTS: usize = 200;
+
+/// Events of a log to read back after a task's command ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EventCapture {
+    /// Event log channel, e.g. `Security` or `Microsoft-Windows-Sysmon/Operational`.
+    pub log: String,
+    /// Also include events logged up to this many milliseconds before the
+    /// task started.
+    #[serde(default)]
+    pub since_ms: u64,
+    /// Most events returned, newest first (capped at 200).
+    #[serde(default = "default_count")]
+    pub count: usize,
+}
+
+fn default_count() -> usize {
+    20
+}
+
+/// Outcome of reading an event log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventLogStatus {
+    /// The log was read; `events` holds what it recorded in the window.
+    Captured,
+    /// The log could not be read; `error` says why.
+    Error,
+    /// Event log capture is only available on Windows.
+    Unsupported,
+}
+
+/// Summary of one logged event.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EventSummary {
+    /// Event identifier (4688 for a Security process creation).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<u32>,
+    /// Provider that logged the event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// UTC time the event was logged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_created: Option<String>,
+    /// Position of the event in its log.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_id: Option<u64>,
+    /// Severity: 1 critical, 2 error, 3 warning, 4 information.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+}
+
+/// Events of a log as read back by the agent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EventLogReading {
+    /// Log as requested.
+    pub log: String,
+    /// Whether the log was read, or why it could not be.
+    pub status: EventLogStatus,
+    /// Events logged in the window, newest first.
+    #[serde(default)]
+    pub events: Vec<EventSummary>,
+    /// Read failure, when `status` is `error`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Reads the events of `capture.log` logged within `elapsed` (the time since
+/// the task started) plus `capture.since_ms`. Best effort: failures are
+/// reported in the reading.
+pub fn capture(capture: &EventCapture, elapsed: Duration) -> EventLogReading {
+    let window = elapsed + Duration::from_millis(capture.since_ms);
+    let count = capture.count.min(MAX_EVENTS);
+    let reading = |status, events, error| EventLogReading {
+        log: capture.log.clone(),
+        status,
+        events,
+        error,
+    };
+    if !cfg!(windows) {
+        return reading(EventLogStatus::Unsupported, Vec::new(), None);
+    }
+    match query(&capture.log, window, count) {
+        Ok(events) => reading(EventLogStatus::Captured, events, None),
+        Err(e) => reading(EventLogStatus::Error, Vec::new(), Some(e)),
+    }
+}
+
+#[cfg(not(windows))]
+fn query(_log: &str, _window: Duration, _count: usize) -> Result<Vec<EventSummary>, String> {
+    Err("event log capture is only available on Windows".to_string())
+}
+
+/// Newest `count` events of `log` logged within `window`.
+#[cfg(windows)]
+fn query(log: &str, window: Duration, count: usize) -> Result<Vec<EventSummary>, String> {
+    use std::ptr::null_mut;
+    use winapi::shared::winerror::ERROR_NO_MORE_ITEMS;
+    use winapi::um::winevt::{EvtNext, EvtQuery, EvtQueryChannelPath, EvtQueryReverseDirection};
+
+    /// How long one event may take to come back.
+    const NEXT_TIMEOUT_MS: u32 = 5000;
+
+    let wide = |s: &str| -> Vec<u16> { s.encode_utf16().chain(Some(0)).collect() };
+    let path = wide(log);
+    let filter = wide(&format!(
+        "*[System[TimeCreated[timediff(@SystemTime) <= {}]]]",
+        window.as_millis()
+    ));
+    // SAFETY: the strings are NUL-terminated; a null session is the local one.
+    let results = Handle(unsafe {
+        EvtQuery(
+            null_mut(),
+            path.as_ptr(),
+            filter.as_ptr(),
+            EvtQueryChannelPath | EvtQueryReverseDirection,
+        )
+    });
+    if results.0.is_null() {
+        return Err(last_error());
+    }
+
+    let mut events = Vec::new();
+    while events.len() < count {
+        let mut event = null_mut();
+        let mut returned = 0;
+        // SAFETY: room for one handle, which we own once returned
+        let ok = unsafe { EvtNext(results.0, 1, &mut event, NEXT_TIMEOUT_MS, 0, &mut returned) };
+        if ok == 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(ERROR_NO_MORE_ITEMS as i32) {
+                break;
+            }
+            return Err(error.to_string());
+        }
+        let event = Handle(event);
+        events.push(summarize(&render_xml(&event)?));
+    }
+    Ok(events)
+}
+
+/// Event log handle, closed on drop.
+#[cfg(windows)]
+struct Handle(winapi::um::winevt::EVT_HANDLE);
+
+#[cfg(windows)]
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // SAFETY: the handle came from EvtQuery or EvtNext and is closed once
+            unsafe { winapi::um::winevt::EvtClose(self.0) };
+        }
+    }
+}
+
+#[cfg(windows)]
+fn last_error() -> String {
+    std::io::Error::last_os_error().to_string()
+}
+
+/// XML rendering of `event`.
+#[cfg(windows)]
+fn render_xml(event: &Handle) -> Result<String, String> {
+    use std::ptr::null_mut;
+    use winapi::shared::winerror::ERROR_INSUFFICIENT_BUFFER;
+    use winapi::um::winevt::{EvtRender, EvtRenderEventXml};
+
+    let mut buffer: Vec<u16> = Vec::new();
+    loop {
+        let mut used = 0;
+        let mut properties = 0;
+        // SAFETY: the size passed is the capacity of `buffer` in bytes (a null
+        // buffer when empty, which only queries the size).
+        let ok = unsafe {
+            EvtRender(
+                null_mut(),
+                event.0,
+                EvtRenderEventXml,
+                (buffer.len() * 2) as u32,
+                if buffer.is_empty() {
+                    null_mut()
+                } else {
+                    buffer.as_mut_ptr().cast()
+                },
+                &mut used,
+                &mut properties,
+            )
+        };
+        if ok != 0 {
+            buffer.truncate(used as usize / 2);
+            return Ok(String::from_utf16_lossy(&buffer)
+                .trim_end_matches('\0')
+                .to_string());
+        }
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() != Some(ERROR_INSUFFICIENT_BUFFER as i32) {
+            return Err(error.to_string());
+        }
+        buffer.resize((used as usize).div_ceil(2), 0);
+    }
+}
+
+/// Summary of an event rendered as XML.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn summarize(xml: &str) -> EventSummary {
+    EventSummary {
+        event_id: element_text(xml, "EventID").and_then(|id| id.parse().ok()),
+        provider: attribute(xml, "Provider", "Name").map(str::to_string),
+        time_created: attribute(xml, "TimeCreated", "SystemTime").map(str::to_string),
+        record_id: element_text(xml, "EventRecordID").and_then(|id| id.parse().ok()),
+        level: element_text(xml, "Level").and_then(|level| level.parse().ok()),
+    }
+}
+
+/// Start of the first `<tag>` element of `xml`, past its name.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}", tag);
+    let mut rest = xml;
+    loop {
+        let start = rest.find(&open)? + open.len();
+        rest = &rest[start..];
+        // `<Level` must not match `<LevelName`
+        if rest.starts_with(['>', ' ', '/']) {
+            return Some(rest);
+        }
+    }
+}
+
+/// Text content of the first `<tag>` element of `xml`.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn element_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let rest = element(xml, tag)?;
+    let text = &rest[rest.find('>')? + 1..];
+    Some(text[..text.find('<')?].trim())
+}
+
+/// Value of attribute `name` of the first `<tag>` element of `xml`.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn attribute<'a>(xml: &'a str, tag: &str, name: &str) -> Option<&'a str> {
+    let rest = element(xml, tag)?;
+    let attributes = &rest[..rest.find('>')?];
+    let value = &attributes[attributes.find(&format!(" {}=", name))? + name.len() + 2..];
+    let quote = value.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let value = &value[1..];
+    Some(&value[..value.find(quote)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECURITY_EVENT: &str = "<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'>\
+        <System><Provider Name='Microsoft-Windows-Security-Auditing' \
+        Guid='{54849625-5478-4994-a5ba-3e3b0328c30d}'/><EventID>4688</EventID>\
+        <Version>2</Version><Level>0</Level><Task>13312</Task>\
+        <TimeCreated SystemTime='2024-05-02T12:03:11.4820000Z'/>\
+        <EventRecordID>91823</EventRecordID><Channel>Security</Channel></System>\
+        <EventData><Data Name='NewProcessName'>C:\\Windows\\System32\\whoami.exe</Data></EventData></Event>";
+
+    #[test]
+    fn test_summarize_security_event() {
+        assert_eq!(
+            summarize(SECURITY_EVENT),
+            EventSummary {
+                event_id: Some(4688),
+                provider: Some("Microsoft-Windows-Security-Auditing".to_string()),
+                time_created: Some("2024-05-02T12:03:11.4820000Z".to_string()),
+                record_id: Some(91823),
+                level: Some(0),
+            }
+        );
+        assert_eq!(summarize("<Event/>"), EventSummary::default());
+    }
+
+    #[test]
+    fn test_capture_defaults() {
+        let capture: EventCapture = serde_json::from_str(r#"{"log": "Security"}"#).unwrap();
+        assert_eq!(capture.since_ms, 0);
+        assert_eq!(capture.count, 20);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_capture_unsupported() {
+        let capture = EventCapture {
+            log: "Security".to_string(),
+            since_ms: 0,
+            count: 5,
+        };
+        let reading = super::capture(&capture, Duration::from_secs(1));
+        assert_eq!(reading.status, EventLogStatus::Unsupported);
+        assert!(reading.events.is_empty());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_capture_system_log() {
+        // The System log always has entries from the Service Control Manager
+        // or the kernel within the last month
+        let capture = EventCapture {
+            log: "System".to_string(),
+            since_ms: 30 * 24 * 3600 * 1000,
+            count: 5,
+        };
+        let reading = super::capture(&capture, Duration::from_secs(1));
+        assert_eq!(
+            reading.status,
+            EventLogStatus::Captured,
+            "{:?}",
+            reading.error
+        );
+        assert!(!reading.events.is_empty() && reading.events.len() <= 5);
+        assert!(reading.events.iter().all(|event| event.event_id.is_some()));
+
+        let missing = super::capture(
+            &EventCapture {
+                log: "AutoStrike-No-Such-Log".to_string(),
+                ..capture
+            },
+            Duration::from_secs(1),
+        );
+        assert_eq!(missing.status, EventLogStatus::Error);
+        assert!(missing.error.is_some());
+    }
+}
@@ -0,0 +1,172 @@
+//! Identity commands run under, captured when they are spawned so reports
+//! can state the exact execution context of each technique.
+
+use crate::protocol::ExecutionContext;
+
+/// Captures the context a command spawned now runs under: the agent's own,
+/// which children inherit.
+#[cfg(unix)]
+pub fn capture() -> ExecutionContext {
+    use nix::unistd::{getegid, geteuid, getgroups, User};
+
+    let euid = geteuid();
+    ExecutionContext {
+        user: User::from_uid(euid).ok().flatten().map(|user| user.name),
+        euid: Some(euid.as_raw()),
+        egid: Some(getegid().as_raw()),
+        groups: getgroups()
+            .map(|groups| groups.iter().map(|gid| gid.as_raw()).collect())
+            .unwrap_or_default(),
+        umask: Some(format!("{:04o}", umask())),
+        elevated: None,
+    }
+}
+
+/// The process umask, read from `/proc` where available, since setting it
+/// to read it back briefly changes it for every thread.
+#[cfg(unix)]
+fn umask() -> u32 {
+    use nix::sys::stat::{umask, Mode};
+
+    let from_proc = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let value = status
+                .lines()
+                .find_map(|line| line.strip_prefix("Umask:"))?;
+            u32::from_str_radix(value.trim(), 8).ok()
+        });
+    from_proc.unwrap_or_else(|| {
+        // Most restrictive while it is swapped out
+        let old = umask(Mode::from_bits_truncate(0o077));
+        umask(old);
+        old.bits() as u32
+    })
+}
+
+/// Captures the context a command spawned now runs under: the agent's
+/// token, which children inherit.
+#[cfg(windows)]
+pub fn capture() -> ExecutionContext {
+    let (user, elevated) = token_info().unwrap_or_else(|e| {
+        tracing::debug!("Cannot query the agent's token: {}", e);
+        (None, None)
+    });
+    ExecutionContext {
+        user,
+        elevated,
+        ..ExecutionContext::default()
+    }
+}
+
+/// `DOMAIN\user` and elevation of the agent's process token.
+#[cfg(windows)]
+fn token_info() -> std::io::Result<(Option<String>, Option<bool>)> {
+    use std::io::Error;
+    use winapi::shared::minwindef::DWORD;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+    use winapi::um::securitybaseapi::GetTokenInformation;
+    use winapi::um::winbase::LookupAccountSidW;
+    use winapi::um::winnt::{
+        TokenElevation, TokenUser, HANDLE, TOKEN_ELEVATION, TOKEN_QUERY, TOKEN_USER,
+    };
+
+    let mut token: HANDLE = std::ptr::null_mut();
+    // SAFETY: the pseudo-handle of the current process is always valid.
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+    let mut len: DWORD = 0;
+    // SAFETY: the buffer is a live TOKEN_ELEVATION of the size passed.
+    let elevated = unsafe {
+        GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut _ as *mut _,
+            std::mem::size_of::<TOKEN_ELEVATION>() as DWORD,
+            &mut len,
+        )
+    } != 0;
+    let elevated = elevated.then_some(elevation.TokenIsElevated != 0);
+
+    // The SID follows the TOKEN_USER header in the same buffer
+    let mut buf = vec![0u64; 64];
+    // SAFETY: the buffer is live, 8-byte aligned and of the size passed.
+    let has_user = unsafe {
+        GetTokenInformation(
+            token,
+            TokenUser,
+            buf.as_mut_ptr() as *mut _,
+            (buf.len() * 8) as DWORD,
+            &mut len,
+        )
+    } != 0;
+    let user = has_user.then(|| {
+        // SAFETY: GetTokenInformation filled the buffer with a TOKEN_USER.
+        let sid = unsafe { (*(buf.as_ptr() as *const TOKEN_USER)).User.Sid };
+        let mut name = [0u16; 256];
+        let mut domain = [0u16; 256];
+        let (mut name_len, mut domain_len) = (name.len() as DWORD, domain.len() as DWORD);
+        let mut kind = 0;
+        // SAFETY: the SID points into `buf`, and the name buffers are live
+        // with their lengths passed.
+        let found = unsafe {
+            LookupAccountSidW(
+                std::ptr::null(),
+                sid,
+                name.as_mut_ptr(),
+                &mut name_len,
+                domain.as_mut_ptr(),
+                &mut domain_len,
+                &mut kind,
+            )
+        } != 0;
+        found.then(|| {
+            format!(
+                "{}\\{}",
+                String::from_utf16_lossy(&domain[..domain_len as usize]),
+                String::from_utf16_lossy(&name[..name_len as usize])
+            )
+        })
+    });
+
+    // SAFETY: the token was opened above and is not used afterwards.
+    unsafe { CloseHandle(token) };
+    Ok((user.flatten(), elevated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_capture_reports_runner_identity() {
+        let context = capture();
+
+        assert_eq!(context.euid, Some(nix::unistd::geteuid().as_raw()));
+        assert_eq!(context.egid, Some(nix::unistd::getegid().as_raw()));
+        let umask = context.umask.unwrap();
+        assert_eq!(umask.len(), 4);
+        assert!(u32::from_str_radix(&umask, 8).unwrap() <= 0o777);
+        assert!(context.elevated.is_none());
+
+        // Matches what a command spawned by the agent reports
+        let id = std::process::Command::new("id").arg("-u").output().unwrap();
+        let uid: u32 = String::from_utf8_lossy(&id.stdout).trim().parse().unwrap();
+        assert_eq!(context.euid, Some(uid));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_capture_reports_token_user() {
+        let context = capture();
+
+        assert!(context.user.unwrap().contains('\\'));
+        assert!(context.elevated.is_some());
+        assert!(context.euid.is_none());
+    }
+}
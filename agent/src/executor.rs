@@ -1,12 +1,19 @@
 //! Command execution with timeout support.
 
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::io::AsyncReadExt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::process::Command;
-use tracing::{debug, error};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+use crate::capabilities::CapabilitySet;
+use crate::reaper::Reaper;
 
 /// Result of a command execution.
 pub struct ExecutionResult {
@@ -16,34 +23,347 @@ pub struct ExecutionResult {
     pub output: String,
     /// Process exit code, if available.
     pub exit_code: Option<i32>,
+    /// Program launched, resolved to a full path when found on the `PATH`.
+    pub program: Option<String>,
+    /// Time the operating system took to start the process, in milliseconds.
+    pub spawn_latency_ms: Option<u64>,
+    /// Whether output was cut short because the agent-wide output budget was
+    /// in use by other commands.
+    pub output_budget_limited: bool,
+    /// Program and arguments handed to the operating system, the program as
+    /// given (not resolved like `program`).
+    pub spawned_argv: Option<Vec<String>>,
 }
 
 /// Maximum output size in bytes (1 MB) to prevent memory exhaustion.
-const MAX_OUTPUT_SIZE: usize = 1_048_576;
+pub const MAX_OUTPUT_SIZE: usize = 1_048_576;
+
+/// Default agent-wide output budget (16 MB), shared by concurrent commands.
+pub const DEFAULT_OUTPUT_BUDGET: usize = 16 * 1_048_576;
+
+/// Starts the notice appended to output cut short by a size limit.
+const TRUNCATION_PREFIX: &str = "\n... [output truncated: showing ";
+
+/// Notice appended to output cut short by a size limit, showing `shown` of
+/// the `total` bytes the command produced.
+pub fn truncation_notice(shown: usize, total: usize) -> String {
+    format!("{}{} of {} bytes]", TRUNCATION_PREFIX, shown, total)
+}
+
+/// Splits a trailing truncation notice off `output`, returning the output
+/// before it and the total byte count it reported.
+pub fn strip_truncation_notice(output: &str) -> (&str, Option<usize>) {
+    let Some(start) = output.rfind(TRUNCATION_PREFIX) else {
+        return (output, None);
+    };
+    let total = output[start + TRUNCATION_PREFIX.len()..]
+        .strip_suffix(" bytes]")
+        .and_then(|counts| counts.split_once(" of "))
+        .and_then(|(shown, total)| {
+            shown.parse::<usize>().ok()?;
+            total.parse().ok()
+        });
+    match total {
+        Some(total) => (&output[..start], Some(total)),
+        None => (output, None),
+    }
+}
+
+/// Order in which stdout and stderr are combined into the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputOrder {
+    /// All stdout followed by all stderr.
+    #[default]
+    StdoutFirst,
+    /// All stderr followed by all stdout.
+    StderrFirst,
+    /// Both streams share one pipe, preserving emission order (`2>&1`).
+    Merged,
+}
+
+/// Executor names accepted on Windows, with the executor each runs as.
+const WINDOWS_EXECUTORS: &[(&str, &str)] = &[
+    ("powershell", "powershell"),
+    ("ps", "powershell"),
+    ("pwsh", "pwsh"),
+    ("powershell7", "pwsh"),
+    ("cmd", "cmd"),
+];
+
+/// Executor Windows runs commands with when the requested one is unknown.
+const WINDOWS_FALLBACK: &str = "powershell";
+
+/// Executor names accepted on Unix, with the executor each runs as.
+const UNIX_EXECUTORS: &[(&str, &str)] = &[("sh", "sh"), ("bash", "bash"), ("zsh", "zsh")];
+
+/// Executor Unix runs commands with when the requested one is unknown.
+const UNIX_FALLBACK: &str = "sh";
+
+/// Looks `requested` up in `table`, returning the executor it runs as and
+/// whether that is `fallback`, substituted for an unknown name.
+fn map_executor(
+    table: &[(&str, &'static str)],
+    fallback: &'static str,
+    requested: &str,
+) -> (&'static str, bool) {
+    table
+        .iter()
+        .find(|(name, _)| *name == requested)
+        .map_or((fallback, true), |&(_, used)| (used, false))
+}
+
+/// Executor a requested name runs as on this platform, and whether it was
+/// substituted for an unknown name.
+pub fn resolve_executor(requested: &str) -> (&'static str, bool) {
+    if cfg!(target_os = "windows") {
+        map_executor(WINDOWS_EXECUTORS, WINDOWS_FALLBACK, requested)
+    } else {
+        map_executor(UNIX_EXECUTORS, UNIX_FALLBACK, requested)
+    }
+}
+
+/// Environment variable giving a command its task directory.
+pub const TASK_DIR_ENV: &str = "AUTOSTRIKE_TASK_DIR";
+
+/// Per-task options that change how a command is launched.
+#[derive(Debug, Clone, Default)]
+pub struct ExecOptions {
+    /// Force a UTF-8 console and en-US culture for Windows shells (no-op elsewhere).
+    pub force_utf8_console: bool,
+    /// Task directory exposed to the command as [`TASK_DIR_ENV`].
+    pub task_dir: Option<PathBuf>,
+    /// Linux network namespace (as named by `ip netns`) to run the command in.
+    pub netns: Option<String>,
+    /// Environment variables the command does not inherit.
+    pub env_denylist: Vec<String>,
+    /// Run the shell as a login shell (profile sourced), as in an operator's
+    /// terminal; for PowerShell, load the profile.
+    pub login_shell: bool,
+}
 
 /// Executes commands using platform-specific shells.
-pub struct CommandExecutor;
+#[derive(Clone)]
+pub struct CommandExecutor {
+    output_order: OutputOrder,
+    /// Where to take the Windows shells from instead of the `PATH`.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    native_shell_dir: Option<PathBuf>,
+    /// Retries children whose exit status could not be collected.
+    reaper: Arc<Reaper>,
+    /// Linux capabilities commands keep; `None` leaves them unchanged.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    capabilities: Option<CapabilitySet>,
+    /// Commands run with a fallback executor, shared by clones of this executor.
+    fallbacks: Arc<AtomicU64>,
+    /// Output memory shared by every command running at once.
+    output_budget: Arc<OutputBudget>,
+}
 
 impl CommandExecutor {
     /// Creates a new command executor instance.
     pub fn new() -> Self {
-        Self
+        Self {
+            output_order: OutputOrder::default(),
+            native_shell_dir: None,
+            reaper: Arc::new(Reaper::default()),
+            capabilities: None,
+            fallbacks: Arc::new(AtomicU64::new(0)),
+            output_budget: Arc::new(OutputBudget::new(DEFAULT_OUTPUT_BUDGET)),
+        }
+    }
+
+    /// Caps the output held in memory by all running commands together.
+    pub fn with_output_budget(mut self, bytes: usize) -> Self {
+        self.output_budget = Arc::new(OutputBudget::new(bytes));
+        self
+    }
+
+    /// Bytes of command output currently held against the output budget.
+    pub fn output_bytes_in_use(&self) -> usize {
+        self.output_budget.in_use()
+    }
+
+    /// Sets how stdout and stderr are combined into the output.
+    pub fn with_output_order(mut self, output_order: OutputOrder) -> Self {
+        self.output_order = output_order;
+        self
+    }
+
+    /// Launches `cmd.exe` and `powershell.exe` from `dir` (see [`sysnative_dir`]).
+    pub fn with_native_shell_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.native_shell_dir = dir;
+        self
     }
 
-    /// Executes a command with the specified executor and timeout.
-    /// On timeout, the child process is actively killed.
+    /// Drops every Linux capability but `capabilities` from commands
+    /// (ignored on other platforms).
+    pub fn with_task_capabilities(mut self, capabilities: Option<CapabilitySet>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Children left for the reaper, shared by clones of this executor.
+    pub fn reaper(&self) -> &Arc<Reaper> {
+        &self.reaper
+    }
+
+    /// Commands run so far with a fallback executor.
+    pub fn fallbacks(&self) -> u64 {
+        self.fallbacks.load(Ordering::Relaxed)
+    }
+
+    /// Executor `requested` runs as, warning and counting when it is unknown.
+    fn select_executor(&self, requested: &str) -> &'static str {
+        let (used, fallback) = resolve_executor(requested);
+        if fallback {
+            warn!(
+                "Unknown executor {:?}, running the command with {} instead",
+                requested, used
+            );
+            self.fallbacks.fetch_add(1, Ordering::Relaxed);
+        }
+        used
+    }
+
+    /// Executes a command with the specified executor, timeout and per-task options.
     pub async fn execute(
         &self,
         executor_type: &str,
         command: &str,
         time_limit: Duration,
+        options: &ExecOptions,
+    ) -> ExecutionResult {
+        let executor = self.select_executor(executor_type);
+        self.execute_with(executor, command, time_limit, options)
+            .await
+    }
+
+    /// Executes a command with an executor already resolved by [`Self::select_executor`].
+    async fn execute_with(
+        &self,
+        executor: &'static str,
+        command: &str,
+        time_limit: Duration,
+        options: &ExecOptions,
+    ) -> ExecutionResult {
+        debug!("Executing command with {}: {}", executor, command);
+
+        let mut cmd = self.build_command(executor, command, options);
+        if let Some(dir) = &options.task_dir {
+            cmd.env(TASK_DIR_ENV, dir);
+        }
+        for name in &options.env_denylist {
+            cmd.env_remove(name);
+        }
+        self.run(cmd, time_limit, options.netns.as_deref()).await
+    }
+
+    /// Executes a program with an explicit argument vector, without a shell,
+    /// so nothing in the arguments is interpreted.
+    pub async fn execute_argv(
+        &self,
+        argv: &[String],
+        time_limit: Duration,
+        options: &ExecOptions,
+    ) -> ExecutionResult {
+        let Some((program, args)) = argv.split_first() else {
+            return ExecutionResult {
+                success: false,
+                output: "Execution error: empty argv".to_string(),
+                exit_code: None,
+                program: None,
+                spawn_latency_ms: None,
+                output_budget_limited: false,
+                spawned_argv: None,
+            };
+        };
+        debug!("Executing argv: {:?}", argv);
+
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+        if let Some(dir) = &options.task_dir {
+            cmd.env(TASK_DIR_ENV, dir);
+        }
+        for name in &options.env_denylist {
+            cmd.env_remove(name);
+        }
+        self.run(cmd, time_limit, options.netns.as_deref()).await
+    }
+
+    /// Spawns a prepared command, in network namespace `netns` when set, and
+    /// collects its output. On timeout, the child process is actively killed.
+    async fn run(
+        &self,
+        mut cmd: Command,
+        time_limit: Duration,
+        netns: Option<&str>,
     ) -> ExecutionResult {
-        debug!("Executing command with {}: {}", executor_type, command);
+        let merged_reader = if self.output_order == OutputOrder::Merged {
+            match merged_pipe() {
+                Ok((reader, stdout, stderr)) => {
+                    cmd.stdout(stdout).stderr(stderr);
+                    Some(reader)
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to create merged output pipe, using separate pipes: {}",
+                        e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if merged_reader.is_none() {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        // Own process group, so a cancelled task takes its descendants with it
+        #[cfg(unix)]
+        cmd.process_group(0);
+        cmd.kill_on_drop(true);
 
-        let mut cmd = self.build_command(executor_type, command);
-        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let program = resolve_program(cmd.as_std().get_program());
+        let argv = command_argv(cmd.as_std());
+        // Entered before capabilities are dropped, as it needs CAP_SYS_ADMIN
+        if let Some(name) = netns {
+            if let Err(e) = crate::netns::enter(&mut cmd, name) {
+                error!("Failed to set up network namespace: {}", e);
+                return ExecutionResult {
+                    success: false,
+                    output: format!("Execution error: {}", e),
+                    exit_code: None,
+                    program: Some(program),
+                    spawn_latency_ms: None,
+                    output_budget_limited: false,
+                    spawned_argv: Some(argv),
+                };
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(capabilities) = self.capabilities {
+            if let Err(e) = crate::capabilities::restrict(&mut cmd, capabilities) {
+                error!("Failed to restrict capabilities: {}", e);
+                return ExecutionResult {
+                    success: false,
+                    output: format!("Execution error: {}", e),
+                    exit_code: None,
+                    program: Some(program),
+                    spawn_latency_ms: None,
+                    output_budget_limited: false,
+                    spawned_argv: Some(argv),
+                };
+            }
+        }
+        let spawn_started = Instant::now();
+        let spawned = cmd.spawn();
+        let spawn_latency_ms = spawn_started.elapsed().as_millis() as u64;
+        // The command holds the parent's copies of the merged pipe's write end;
+        // drop it so the reader sees EOF once the child exits.
+        drop(cmd);
 
-        let mut child = match cmd.spawn() {
+        let mut child = match spawned {
             Ok(child) => child,
             Err(e) => {
                 error!("Failed to spawn command: {}", e);
@@ -51,118 +371,426 @@ impl CommandExecutor {
                     success: false,
                     output: format!("Execution error: {}", e),
                     exit_code: None,
+                    program: Some(program),
+                    spawn_latency_ms: None,
+                    output_budget_limited: false,
+                    spawned_argv: Some(argv),
                 };
             }
         };
 
-        // Take ownership of stdout/stderr for concurrent reads
-        let stdout = child.stdout.take().expect("stdout piped");
-        let stderr = child.stderr.take().expect("stderr piped");
+        let group = GroupKiller::new(child.id());
+
+        // Take ownership of stdout/stderr for concurrent reads. In merged mode
+        // both streams arrive on the single pipe read as stdout.
+        let (stdout, stderr): (PipeReader, _) = match merged_reader {
+            Some(reader) => (reader, None),
+            None => (
+                Box::new(child.stdout.take().expect("stdout piped")),
+                Some(child.stderr.take().expect("stderr piped")),
+            ),
+        };
 
-        // Shared byte budget to cap total output across both streams
+        // Shared byte budget to cap total output across both streams, and
+        // count of every byte produced, kept or not
         let budget = Arc::new(AtomicUsize::new(MAX_OUTPUT_SIZE));
+        let produced = Arc::new(AtomicUsize::new(0));
+        let reservation = Arc::new(Reservation::new(self.output_budget.clone()));
 
-        // Drain stdout concurrently
-        let stdout_fut = {
-            let budget = budget.clone();
-            async move { drain_stream(stdout, &budget).await }
-        };
+        // Each stream is drained by its own task into a buffer that outlives
+        // the timeout, preventing pipe deadlocks and keeping pre-timeout bytes
+        let mut drains = vec![Drain::spawn(
+            stdout,
+            budget.clone(),
+            produced.clone(),
+            reservation.clone(),
+        )];
+        if let Some(stderr) = stderr {
+            drains.push(Drain::spawn(
+                Box::new(stderr),
+                budget.clone(),
+                produced.clone(),
+                reservation.clone(),
+            ));
+        }
 
-        // Drain stderr concurrently
-        let stderr_fut = {
-            let budget = budget.clone();
-            async move { drain_stream(stderr, &budget).await }
-        };
+        // The timeout only decides when to kill the child
+        let finished = tokio::time::timeout(time_limit, async {
+            finish_drains(&mut drains).await;
+            child.wait().await
+        })
+        .await;
 
-        // Both streams are polled concurrently via join!, preventing pipe deadlocks
-        let read_output = async {
-            let (stdout_buf, stderr_buf) = tokio::join!(stdout_fut, stderr_fut);
-            let truncated = budget.load(Ordering::Relaxed) == 0;
-            let stdout_str = String::from_utf8_lossy(&stdout_buf);
-            let stderr_str = String::from_utf8_lossy(&stderr_buf);
-            let combined = format!("{}{}", stdout_str, stderr_str);
-            let mut output = combined.trim().to_string();
-            if truncated {
-                // Safe UTF-8 truncation
-                let safe_boundary = find_char_boundary(&output, MAX_OUTPUT_SIZE);
-                output.truncate(safe_boundary);
-                output.push_str("\n... [output truncated]");
+        let (success, exit_code, timed_out) = match finished {
+            Ok(Ok(status)) => (status.success(), status.code(), false),
+            Ok(Err(e)) => {
+                error!("Failed to wait for child: {}", e);
+                self.reaper.adopt(Box::new(child));
+                (false, None, false)
+            }
+            Err(_) => {
+                // Timeout: kill the child process and reap the zombie
+                if let Err(e) = child.kill().await {
+                    warn!("Failed to kill timed-out child: {}", e);
+                    group.kill();
+                }
+                if let Err(e) = child.wait().await {
+                    warn!("Failed to wait for killed child: {}", e);
+                    self.reaper.adopt(Box::new(child));
+                }
+                // Let the drainers pull what the pipes still buffer
+                let _ = tokio::time::timeout(DRAIN_GRACE, finish_drains(&mut drains)).await;
+                (false, None, true)
             }
-            output
         };
 
-        // Race output collection against timeout, kill child on timeout
-        tokio::select! {
-            output = read_output => {
-                // Output collected, now wait for child to exit
-                match child.wait().await {
-                    Ok(status) => ExecutionResult {
-                        success: status.success(),
-                        output,
-                        exit_code: status.code(),
-                    },
-                    Err(e) => {
-                        error!("Failed to wait for child: {}", e);
-                        ExecutionResult {
-                            success: false,
-                            output,
-                            exit_code: None,
-                        }
-                    }
-                }
+        group.disarm();
+        let truncated = budget.load(Ordering::Relaxed) == 0;
+        let stdout_buf = drains[0].take();
+        let stderr_buf = drains.get(1).map(Drain::take).unwrap_or_default();
+        let stdout_str = String::from_utf8_lossy(&stdout_buf);
+        let stderr_str = String::from_utf8_lossy(&stderr_buf);
+        let combined = match self.output_order {
+            OutputOrder::StderrFirst => format!("{}{}", stderr_str, stdout_str),
+            OutputOrder::StdoutFirst | OutputOrder::Merged => {
+                format!("{}{}", stdout_str, stderr_str)
             }
-            _ = tokio::time::sleep(time_limit) => {
-                // Timeout: kill the child process
-                let _ = child.kill().await;
-                let _ = child.wait().await; // Reap the zombie
-                ExecutionResult {
-                    success: false,
-                    output: "Command timed out".to_string(),
-                    exit_code: None,
+        };
+        let output = finish_output(&combined, truncated, produced.load(Ordering::Relaxed));
+
+        ExecutionResult {
+            success,
+            output: if timed_out {
+                timed_out_output(output)
+            } else {
+                output
+            },
+            exit_code,
+            program: Some(program),
+            spawn_latency_ms: Some(spawn_latency_ms),
+            output_budget_limited: reservation.limited(),
+            spawned_argv: Some(argv),
+        }
+    }
+
+    /// Executes a command attached to a pseudo-terminal, capturing the
+    /// terminal's combined output. Falls back to pipes when no pseudo-terminal
+    /// can be allocated or capabilities must be dropped, and always on Windows.
+    pub async fn execute_in_pty(
+        &self,
+        executor_type: &str,
+        command: &str,
+        time_limit: Duration,
+        options: &ExecOptions,
+    ) -> ExecutionResult {
+        let executor = self.select_executor(executor_type);
+
+        #[cfg(unix)]
+        if self.capabilities.is_some() || options.netns.is_some() {
+            debug!(
+                "Capabilities and network namespaces are set up through pipe execution, \
+                 not using a pseudo-terminal"
+            );
+        } else {
+            let shell = unix_shell(executor);
+            let spawn_started = Instant::now();
+            match spawn_in_pty(shell, command, options) {
+                Ok((child, reader)) => {
+                    let spawn_latency_ms = spawn_started.elapsed().as_millis() as u64;
+                    debug!("Executing command in pty with {}: {}", executor, command);
+                    let mut result = run_in_pty(
+                        child,
+                        reader,
+                        time_limit,
+                        shell,
+                        &self.reaper,
+                        &self.output_budget,
+                    )
+                    .await;
+                    result.spawn_latency_ms = Some(spawn_latency_ms);
+                    let args = unix_shell_args(command, options);
+                    result.spawned_argv =
+                        Some([shell, args[0], args[1]].map(str::to_string).to_vec());
+                    return result;
                 }
+                Err(e) => warn!("Failed to allocate a pseudo-terminal, using pipes: {}", e),
             }
         }
+
+        #[cfg(not(unix))]
+        debug!("Pseudo-terminals are only supported on Unix, using pipes");
+
+        self.execute_with(executor, command, time_limit, options)
+            .await
     }
 
     #[cfg(target_os = "windows")]
-    fn build_command(&self, executor_type: &str, command: &str) -> Command {
-        let cmd = match executor_type {
-            "powershell" | "ps" => {
-                let mut c = Command::new("powershell.exe");
-                c.args(["-NoProfile", "-NonInteractive", "-Command", command]);
-                c
-            }
-            "pwsh" | "powershell7" => {
-                let mut c = Command::new("pwsh.exe");
-                c.args(["-NoProfile", "-NonInteractive", "-Command", command]);
-                c
-            }
-            "cmd" => {
-                let mut c = Command::new("cmd.exe");
-                c.args(["/C", command]);
-                c
-            }
-            _ => {
-                let mut c = Command::new("powershell.exe");
-                c.args(["-NoProfile", "-NonInteractive", "-Command", command]);
-                c
-            }
-        };
+    fn build_command(&self, executor: &str, command: &str, options: &ExecOptions) -> Command {
+        let (program, args) = windows_argv(executor, command, options);
+        let mut cmd = Command::new(windows_program(program, self.native_shell_dir.as_deref()));
+        cmd.args(args);
         cmd
     }
 
     #[cfg(not(target_os = "windows"))]
-    fn build_command(&self, executor_type: &str, command: &str) -> Command {
-        let shell = match executor_type {
-            "bash" => "/bin/bash",
-            "zsh" => "/bin/zsh",
-            "sh" => "/bin/sh",
-            _ => "/bin/sh",
+    fn build_command(&self, executor: &str, command: &str, options: &ExecOptions) -> Command {
+        let mut cmd = Command::new(unix_shell(executor));
+        cmd.args(unix_shell_args(command, options));
+        cmd
+    }
+
+    /// `PATH` a command run by `executor_type` with `options` starts with,
+    /// read by running the shell once more. `None` if the probe failed.
+    pub async fn probe_path(&self, executor_type: &str, options: &ExecOptions) -> Option<String> {
+        let (executor, _) = resolve_executor(executor_type);
+        let probe = match executor {
+            "cmd" => "echo %PATH%",
+            "powershell" | "pwsh" => "$env:Path",
+            _ => "printf '%s' \"$PATH\"",
         };
+        let result = self
+            .execute_with(executor, probe, PATH_PROBE_TIMEOUT, options)
+            .await;
+        let path = result.output.trim();
+        (result.success && !path.is_empty()).then(|| path.to_string())
+    }
+}
 
-        let mut cmd = Command::new(shell);
-        cmd.args(["-c", command]);
-        cmd
+/// How long the `PATH` probe of [`CommandExecutor::probe_path`] may take.
+const PATH_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Arguments making a Unix shell run `command`, as a login shell when asked.
+#[cfg(not(target_os = "windows"))]
+fn unix_shell_args<'a>(command: &'a str, options: &ExecOptions) -> [&'a str; 2] {
+    [if options.login_shell { "-lc" } else { "-c" }, command]
+}
+
+/// Switches the console to UTF-8 before a cmd.exe command runs.
+const CMD_UTF8_PRELUDE: &str = "chcp 65001 >nul & ";
+
+/// Switches output encoding and culture to UTF-8 / en-US before a PowerShell
+/// command runs, so native tool output decodes and parses predictably.
+const POWERSHELL_UTF8_PRELUDE: &str = "[Console]::OutputEncoding = [System.Text.Encoding]::UTF8; \
+    [System.Threading.Thread]::CurrentThread.CurrentCulture = 'en-US'; \
+    [System.Threading.Thread]::CurrentThread.CurrentUICulture = 'en-US'; ";
+
+/// Directory through which a 32-bit process on 64-bit Windows reaches the
+/// real `System32`, which it otherwise sees redirected to `SysWOW64`.
+/// `env` reads environment variables.
+pub fn sysnative_dir(env: impl Fn(&str) -> Option<String>) -> PathBuf {
+    let system_root = env("SystemRoot").unwrap_or_else(|| r"C:\Windows".to_string());
+    Path::new(&system_root).join("Sysnative")
+}
+
+/// Path of the Windows shell `program`, taken from `native_dir` when set.
+/// `pwsh.exe` is installed outside `System32` and always comes from the `PATH`.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn windows_program(program: &str, native_dir: Option<&Path>) -> PathBuf {
+    match (native_dir, program) {
+        (Some(dir), "cmd.exe") => dir.join("cmd.exe"),
+        (Some(dir), "powershell.exe") => dir
+            .join("WindowsPowerShell")
+            .join("v1.0")
+            .join("powershell.exe"),
+        _ => PathBuf::from(program),
+    }
+}
+
+/// Program and arguments `cmd` launches.
+fn command_argv(cmd: &std::process::Command) -> Vec<String> {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Resolves the program a command launches to a full path, as the `PATH`
+/// lookup finds it; unresolvable programs are reported as given.
+fn resolve_program(program: &OsStr) -> String {
+    which::which(program)
+        .unwrap_or_else(|_| PathBuf::from(program))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Maps a Windows executor (see [`resolve_executor`]) to the program and
+/// arguments that run `command`.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn windows_argv(
+    executor: &str,
+    command: &str,
+    options: &ExecOptions,
+) -> (&'static str, Vec<String>) {
+    let powershell_args = |command: &str| {
+        let command = if options.force_utf8_console {
+            format!("{}{}", POWERSHELL_UTF8_PRELUDE, command)
+        } else {
+            command.to_string()
+        };
+        let mut args = vec![
+            "-NoProfile".to_string(),
+            "-NonInteractive".to_string(),
+            "-Command".to_string(),
+            command,
+        ];
+        if options.login_shell {
+            args.remove(0);
+        }
+        args
+    };
+
+    match executor {
+        "pwsh" | "powershell7" => ("pwsh.exe", powershell_args(command)),
+        "cmd" => {
+            let command = if options.force_utf8_console {
+                format!("{}{}", CMD_UTF8_PRELUDE, command)
+            } else {
+                command.to_string()
+            };
+            ("cmd.exe", vec!["/C".to_string(), command])
+        }
+        // "powershell", "ps" and anything not resolved first
+        _ => ("powershell.exe", powershell_args(command)),
+    }
+}
+
+/// Maps a Unix executor (see [`resolve_executor`]) to the shell that runs it.
+#[cfg(not(target_os = "windows"))]
+fn unix_shell(executor: &str) -> &'static str {
+    match executor {
+        "bash" => "/bin/bash",
+        "zsh" => "/bin/zsh",
+        _ => "/bin/sh",
+    }
+}
+
+/// Child process running attached to a pseudo-terminal.
+#[cfg(unix)]
+type PtyChild = Box<dyn portable_pty::Child + Send + Sync>;
+
+/// Spawns `shell -c command` attached to a new pseudo-terminal, with the
+/// task directory exposed as [`TASK_DIR_ENV`] and the denied variables removed.
+/// Returns the child and an async reader over the terminal's output.
+#[cfg(unix)]
+fn spawn_in_pty(
+    shell: &str,
+    command: &str,
+    options: &ExecOptions,
+) -> anyhow::Result<(PtyChild, PipeReader)> {
+    use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+    use std::os::fd::BorrowedFd;
+
+    let pair = native_pty_system().openpty(PtySize::default())?;
+    let mut cmd = CommandBuilder::new(shell);
+    cmd.args(unix_shell_args(command, options));
+    // portable-pty starts in the home directory by default; match pipe execution.
+    cmd.cwd(std::env::current_dir()?);
+    if let Some(dir) = &options.task_dir {
+        cmd.env(TASK_DIR_ENV, dir);
+    }
+    for name in &options.env_denylist {
+        cmd.env_remove(name);
+    }
+    let child = pair.slave.spawn_command(cmd)?;
+    // Close our copy of the slave so reads end once the child side closes.
+    drop(pair.slave);
+
+    let fd = pair
+        .master
+        .as_raw_fd()
+        .ok_or_else(|| anyhow::anyhow!("pseudo-terminal has no file descriptor"))?;
+    // SAFETY: `fd` belongs to `pair.master`, which is alive for this borrow.
+    let master = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+    // Registered with the reactor like the merged pipe, so a terminal kept
+    // open by an orphaned grandchild never pins a thread.
+    let reader: PipeReader = Box::new(tokio::net::unix::pipe::Receiver::from_file_unchecked(
+        std::fs::File::from(master),
+    )?);
+
+    Ok((child, reader))
+}
+
+/// Collects the output of a pseudo-terminal child running `shell`, killing
+/// it on timeout.
+#[cfg(unix)]
+async fn run_in_pty(
+    mut child: PtyChild,
+    reader: PipeReader,
+    time_limit: Duration,
+    shell: &str,
+    reaper: &Arc<Reaper>,
+    output_budget: &Arc<OutputBudget>,
+) -> ExecutionResult {
+    let mut killer = child.clone_killer();
+    // The terminal makes the child a session (and process group) leader
+    let group = GroupKiller::new(child.process_id());
+    let budget = Arc::new(AtomicUsize::new(MAX_OUTPUT_SIZE));
+    let produced = Arc::new(AtomicUsize::new(0));
+    let reservation = Arc::new(Reservation::new(output_budget.clone()));
+    // The terminal reports EIO rather than EOF once the child side closes,
+    // which drain_stream treats as the end of the stream.
+    let mut drains = [Drain::spawn(
+        reader,
+        budget.clone(),
+        produced.clone(),
+        reservation.clone(),
+    )];
+    // The child comes back with its status, for the reaper if waiting failed
+    let mut wait = tokio::task::spawn_blocking(move || {
+        let status = child.wait();
+        (child, status)
+    });
+
+    let finished = tokio::time::timeout(time_limit, async {
+        finish_drains(&mut drains).await;
+        (&mut wait).await
+    })
+    .await;
+
+    let (success, exit_code, timed_out) = match finished {
+        Ok(Ok((_, Ok(status)))) => (status.success(), Some(status.exit_code() as i32), false),
+        Ok(Ok((child, Err(e)))) => {
+            error!("Failed to wait for child: {}", e);
+            reaper.adopt(Box::new(child));
+            (false, None, false)
+        }
+        Ok(Err(e)) => {
+            error!("Failed to wait for child: {}", e);
+            (false, None, false)
+        }
+        Err(_) => {
+            // Timeout: kill the child process and reap it
+            if let Err(e) = killer.kill() {
+                warn!("Failed to kill timed-out child: {}", e);
+                group.kill();
+            }
+            if let Ok((child, Err(e))) = wait.await {
+                warn!("Failed to wait for killed child: {}", e);
+                reaper.adopt(Box::new(child));
+            }
+            let _ = tokio::time::timeout(DRAIN_GRACE, finish_drains(&mut drains)).await;
+            (false, None, true)
+        }
+    };
+
+    group.disarm();
+    let truncated = budget.load(Ordering::Relaxed) == 0;
+    // Terminals translate newlines to CRLF
+    let output = String::from_utf8_lossy(&drains[0].take()).replace("\r\n", "\n");
+    let output = finish_output(&output, truncated, produced.load(Ordering::Relaxed));
+
+    ExecutionResult {
+        success,
+        output: if timed_out {
+            timed_out_output(output)
+        } else {
+            output
+        },
+        exit_code,
+        program: Some(shell.to_string()),
+        spawn_latency_ms: None,
+        output_budget_limited: reservation.limited(),
+        spawned_argv: None,
     }
 }
 
@@ -172,33 +800,179 @@ impl Default for CommandExecutor {
     }
 }
 
-/// Drains an async reader into a Vec, claiming bytes from a shared atomic budget.
-/// Returns the collected bytes. Stops when the stream is exhausted or the budget is depleted.
+/// Async reader over the read end of a merged output pipe.
+type PipeReader = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Creates a pipe whose write end serves as both stdout and stderr of a child.
+/// Returns the async read end plus the two `Stdio` handles for the child.
+fn merged_pipe() -> std::io::Result<(PipeReader, Stdio, Stdio)> {
+    let (reader, writer) = os_pipe::pipe()?;
+    let writer_clone = writer.try_clone()?;
+
+    // Unix pipes are registered with the reactor so an abandoned read never
+    // pins a thread; Windows anonymous pipes fall back to blocking file reads.
+    #[cfg(unix)]
+    let reader: PipeReader = Box::new(tokio::net::unix::pipe::Receiver::from_owned_fd(
+        std::os::fd::OwnedFd::from(reader),
+    )?);
+    #[cfg(windows)]
+    let reader: PipeReader = Box::new(tokio::fs::File::from_std(std::fs::File::from(
+        std::os::windows::io::OwnedHandle::from(reader),
+    )));
+
+    Ok((reader, writer.into(), writer_clone.into()))
+}
+
+/// How long drainers may keep reading buffered output after a timed-out
+/// child is killed, before the result is assembled without them.
+const DRAIN_GRACE: Duration = Duration::from_millis(500);
+
+/// An output stream drained by a spawned task into a buffer that stays
+/// readable when the task is abandoned.
+struct Drain {
+    buf: Arc<Mutex<Vec<u8>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Drain {
+    /// Starts draining `stream`, claiming bytes from the shared `budget`
+    /// and from the agent-wide output budget through `reservation`, and
+    /// counting every byte read into `produced`.
+    fn spawn(
+        stream: PipeReader,
+        budget: Arc<AtomicUsize>,
+        produced: Arc<AtomicUsize>,
+        reservation: Arc<Reservation>,
+    ) -> Self {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let task = tokio::spawn({
+            let buf = buf.clone();
+            async move { drain_stream(stream, &budget, &produced, &reservation, &buf).await }
+        });
+        Self {
+            buf,
+            task: Some(task),
+        }
+    }
+
+    /// Waits for the stream to be exhausted. Cancel-safe.
+    async fn finish(&mut self) {
+        if let Some(task) = self.task.as_mut() {
+            let _ = task.await;
+            self.task = None;
+        }
+    }
+
+    /// Takes the bytes collected so far.
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.buf.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl Drop for Drain {
+    fn drop(&mut self) {
+        // A pipe held open by an orphaned grandchild would otherwise keep
+        // the task reading after the result was sent.
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Kills a command's process group if its run is abandoned before the
+/// command finished, e.g. when the task is cancelled.
+///
+/// Only Unix commands get their own group; on Windows the child alone is
+/// killed, through `kill_on_drop`.
+struct GroupKiller {
+    pgid: Option<u32>,
+}
+
+impl GroupKiller {
+    /// Watches the group led by `pid`.
+    fn new(pid: Option<u32>) -> Self {
+        Self {
+            pgid: pid.filter(|_| cfg!(unix)),
+        }
+    }
+
+    /// Kills every process in the group, e.g. when killing its leader failed.
+    fn kill(&self) {
+        #[cfg(unix)]
+        if let Some(pgid) = self.pgid {
+            use nix::sys::signal::{killpg, Signal};
+            let _ = killpg(nix::unistd::Pid::from_raw(pgid as i32), Signal::SIGKILL);
+        }
+    }
+
+    /// The run completed; leave the group alone.
+    fn disarm(mut self) {
+        self.pgid = None;
+    }
+}
+
+impl Drop for GroupKiller {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.pgid {
+            debug!("Killing abandoned process group {}", pgid);
+            self.kill();
+        }
+    }
+}
+
+/// Waits for every drain to finish. The drains run concurrently in their own
+/// tasks, so awaiting them in turn does not serialize the reads.
+async fn finish_drains(drains: &mut [Drain]) {
+    for drain in drains {
+        drain.finish().await;
+    }
+}
+
+/// Output reported for a command killed on timeout, keeping what it printed first.
+fn timed_out_output(partial: String) -> String {
+    if partial.is_empty() {
+        "Command timed out".to_string()
+    } else {
+        format!("{}\nCommand timed out", partial)
+    }
+}
+
+/// Drains an async reader into a shared buffer, claiming bytes from a shared
+/// atomic budget and from the agent-wide output budget. Once either is
+/// depleted, bytes are only counted into `produced` and discarded, until the
+/// stream is exhausted.
 async fn drain_stream<R: tokio::io::AsyncRead + Unpin>(
     mut stream: R,
     budget: &AtomicUsize,
-) -> Vec<u8> {
-    let mut buf = Vec::new();
+    produced: &AtomicUsize,
+    reservation: &Reservation,
+    buf: &Mutex<Vec<u8>>,
+) {
     let mut chunk = [0u8; 8192];
     loop {
-        if budget.load(Ordering::Relaxed) == 0 {
-            break;
-        }
         match stream.read(&mut chunk).await {
             Ok(0) => break,
             Ok(n) => {
-                let claimed = claim_budget(budget, n);
+                produced.fetch_add(n, Ordering::Relaxed);
+                let mut claimed = claim_budget(budget, n);
                 if claimed > 0 {
-                    buf.extend_from_slice(&chunk[..claimed]);
+                    let granted = reservation.claim(claimed);
+                    if granted < claimed {
+                        // Truncate this command now rather than keep competing
+                        // with the others for what is left
+                        budget.store(0, Ordering::Relaxed);
+                        claimed = granted;
+                    }
                 }
-                if claimed < n {
-                    break; // Budget exhausted
+                if claimed > 0 {
+                    buf.lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .extend_from_slice(&chunk[..claimed]);
                 }
             }
             Err(_) => break,
         }
     }
-    buf
 }
 
 /// Atomically claims up to `want` bytes from the shared budget.
@@ -222,9 +996,104 @@ fn claim_budget(budget: &AtomicUsize, want: usize) -> usize {
     }
 }
 
+/// Output memory shared by every command running at once, so concurrent
+/// tasks cannot each hold [`MAX_OUTPUT_SIZE`] bytes past what the host
+/// tolerates.
+pub struct OutputBudget {
+    limit: usize,
+    available: AtomicUsize,
+}
+
+impl OutputBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            available: AtomicUsize::new(limit),
+        }
+    }
+
+    /// Bytes currently held by running commands.
+    pub fn in_use(&self) -> usize {
+        self.limit - self.available.load(Ordering::Relaxed)
+    }
+}
+
+/// Bytes one command holds from the [`OutputBudget`], given back when its
+/// run ends.
+struct Reservation {
+    pool: Arc<OutputBudget>,
+    held: AtomicUsize,
+    limited: AtomicBool,
+}
+
+impl Reservation {
+    fn new(pool: Arc<OutputBudget>) -> Self {
+        Self {
+            pool,
+            held: AtomicUsize::new(0),
+            limited: AtomicBool::new(false),
+        }
+    }
+
+    /// Claims up to `want` bytes from the pool, noting when it had less.
+    fn claim(&self, want: usize) -> usize {
+        let granted = claim_budget(&self.pool.available, want);
+        self.held.fetch_add(granted, Ordering::Relaxed);
+        if granted < want {
+            self.limited.store(true, Ordering::Relaxed);
+        }
+        granted
+    }
+
+    /// Whether the pool ran short for this command.
+    fn limited(&self) -> bool {
+        self.limited.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.pool
+            .available
+            .fetch_add(*self.held.get_mut(), Ordering::Relaxed);
+    }
+}
+
+/// Trims collected output and marks it when the byte budget was exhausted,
+/// out of the `produced` bytes the command wrote.
+fn finish_output(combined: &str, truncated: bool, produced: usize) -> String {
+    let mut output = combined.trim().to_string();
+    if truncated {
+        // Safe UTF-8 truncation
+        let safe_boundary = find_char_boundary(&output, MAX_OUTPUT_SIZE);
+        output.truncate(safe_boundary);
+        let notice = truncation_notice(output.len(), produced);
+        output.push_str(&notice);
+    }
+    output
+}
+
+/// Keeps the first `head` and last `tail` lines of `output`, with a marker
+/// counting the lines left out between them; shorter output is unchanged.
+pub fn sample_lines(output: &str, head: usize, tail: usize) -> String {
+    let lines: Vec<&str> = output.lines().collect();
+    if lines.len() <= head.saturating_add(tail) {
+        return output.to_string();
+    }
+    let omitted = lines.len() - head - tail;
+    let marker = format!("[... {} lines omitted ...]", omitted);
+    lines[..head]
+        .iter()
+        .copied()
+        .chain(std::iter::once(marker.as_str()))
+        .chain(lines[lines.len() - tail..].iter().copied())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 /// Finds the largest valid UTF-8 char boundary at or before `max` bytes.
 /// Prevents panics when slicing multi-byte characters.
-fn find_char_boundary(s: &str, max: usize) -> usize {
+pub fn find_char_boundary(s: &str, max: usize) -> usize {
     if max >= s.len() {
         return s.len();
     }
@@ -239,9 +1108,144 @@ fn find_char_boundary(s: &str, max: usize) -> usize {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sample_lines() {
+        let output: String = (1..=5000).map(|i| format!("line {}\n", i)).collect();
+        let sampled = sample_lines(output.trim(), 3, 2);
+        assert_eq!(
+            sampled,
+            "line 1\nline 2\nline 3\n[... 4995 lines omitted ...]\nline 4999\nline 5000"
+        );
+
+        // Head or tail alone
+        assert_eq!(
+            sample_lines(output.trim(), 0, 1),
+            "[... 4999 lines omitted ...]\nline 5000"
+        );
+        assert_eq!(
+            sample_lines(output.trim(), 1, 0),
+            "line 1\n[... 4999 lines omitted ...]"
+        );
+
+        // Nothing to omit
+        assert_eq!(sample_lines("a\nb\nc", 2, 1), "a\nb\nc");
+        assert_eq!(sample_lines("a\nb", usize::MAX, usize::MAX), "a\nb");
+    }
+
     #[test]
     fn test_executor_default() {
-        let _executor = CommandExecutor;
+        let executor = CommandExecutor::default();
+        assert_eq!(executor.output_order, OutputOrder::StdoutFirst);
+    }
+
+    #[test]
+    fn test_output_order_deserialization() {
+        let order: OutputOrder = serde_json::from_str("\"stderr_first\"").unwrap();
+        assert_eq!(order, OutputOrder::StderrFirst);
+
+        let order: OutputOrder = serde_json::from_str("\"merged\"").unwrap();
+        assert_eq!(order, OutputOrder::Merged);
+
+        assert!(serde_json::from_str::<OutputOrder>("\"random\"").is_err());
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    const ALTERNATING_COMMAND: &str = "echo out1; echo err1 >&2; echo out2; echo err2 >&2";
+
+    #[cfg(target_os = "windows")]
+    const ALTERNATING_COMMAND: &str = "echo out1& echo err1 1>&2& echo out2& echo err2 1>&2";
+
+    #[cfg(not(target_os = "windows"))]
+    const TEST_SHELL: &str = "sh";
+
+    #[cfg(target_os = "windows")]
+    const TEST_SHELL: &str = "cmd";
+
+    fn output_lines(output: &str) -> Vec<&str> {
+        output.lines().map(str::trim).collect()
+    }
+
+    #[tokio::test]
+    async fn test_output_order_stdout_first() {
+        let executor = CommandExecutor::new().with_output_order(OutputOrder::StdoutFirst);
+        let result = executor
+            .execute(
+                TEST_SHELL,
+                ALTERNATING_COMMAND,
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert_eq!(
+            output_lines(&result.output),
+            vec!["out1", "out2", "err1", "err2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_order_stderr_first() {
+        let executor = CommandExecutor::new().with_output_order(OutputOrder::StderrFirst);
+        let result = executor
+            .execute(
+                TEST_SHELL,
+                ALTERNATING_COMMAND,
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert_eq!(
+            output_lines(&result.output),
+            vec!["err1", "err2", "out1", "out2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_order_merged() {
+        let executor = CommandExecutor::new().with_output_order(OutputOrder::Merged);
+        let result = executor
+            .execute(
+                TEST_SHELL,
+                ALTERNATING_COMMAND,
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert!(result.success);
+        assert_eq!(
+            output_lines(&result.output),
+            vec!["out1", "err1", "out2", "err2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_order_merged_timeout() {
+        let executor = CommandExecutor::new().with_output_order(OutputOrder::Merged);
+
+        #[cfg(not(target_os = "windows"))]
+        let result = executor
+            .execute(
+                "sh",
+                "sleep 10",
+                Duration::from_millis(100),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        #[cfg(target_os = "windows")]
+        let result = executor
+            .execute(
+                "cmd",
+                "ping -n 10 127.0.0.1",
+                Duration::from_millis(100),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert!(!result.success);
+        assert!(result.output.contains("timed out"));
     }
 
     #[test]
@@ -256,6 +1260,10 @@ mod tests {
             success: true,
             output: "test output".to_string(),
             exit_code: Some(0),
+            program: None,
+            spawn_latency_ms: None,
+            output_budget_limited: false,
+            spawned_argv: None,
         };
         assert!(result.success);
         assert_eq!(result.output, "test output");
@@ -268,6 +1276,10 @@ mod tests {
             success: false,
             output: "error message".to_string(),
             exit_code: Some(1),
+            program: None,
+            spawn_latency_ms: None,
+            output_budget_limited: false,
+            spawned_argv: None,
         };
         assert!(!result.success);
         assert_eq!(result.exit_code, Some(1));
@@ -279,6 +1291,10 @@ mod tests {
             success: false,
             output: "timed out".to_string(),
             exit_code: None,
+            program: None,
+            spawn_latency_ms: None,
+            output_budget_limited: false,
+            spawned_argv: None,
         };
         assert!(!result.success);
         assert!(result.exit_code.is_none());
@@ -290,30 +1306,79 @@ mod tests {
 
         #[cfg(not(target_os = "windows"))]
         let result = executor
-            .execute("sh", "echo hello", Duration::from_secs(5))
+            .execute(
+                "sh",
+                "echo hello",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         #[cfg(target_os = "windows")]
         let result = executor
-            .execute("cmd", "echo hello", Duration::from_secs(5))
+            .execute(
+                "cmd",
+                "echo hello",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         assert!(result.success);
         assert!(result.output.contains("hello"));
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_spawn_latency_captured() {
+        let executor = CommandExecutor::new();
+        let started = Instant::now();
+        let result = executor
+            .execute_argv(
+                &["sleep".to_string(), "0.2".to_string()],
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+        let total_ms = started.elapsed().as_millis() as u64;
+
+        let spawn_latency_ms = result.spawn_latency_ms.expect("spawn latency measured");
+        // The spawn is only part of the run, which lasts at least the sleep
+        assert!(spawn_latency_ms <= total_ms);
+        assert!(total_ms >= 200);
+
+        let missing = executor
+            .execute_argv(
+                &["/nonexistent/autostrike".to_string()],
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+        assert!(missing.spawn_latency_ms.is_none());
+    }
+
     #[tokio::test]
     async fn test_command_with_exit_code() {
         let executor = CommandExecutor::new();
 
         #[cfg(not(target_os = "windows"))]
         let result = executor
-            .execute("sh", "exit 0", Duration::from_secs(5))
+            .execute(
+                "sh",
+                "exit 0",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         #[cfg(target_os = "windows")]
         let result = executor
-            .execute("cmd", "exit /b 0", Duration::from_secs(5))
+            .execute(
+                "cmd",
+                "exit /b 0",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         assert!(result.success);
@@ -326,12 +1391,22 @@ mod tests {
 
         #[cfg(not(target_os = "windows"))]
         let result = executor
-            .execute("sh", "exit 1", Duration::from_secs(5))
+            .execute(
+                "sh",
+                "exit 1",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         #[cfg(target_os = "windows")]
         let result = executor
-            .execute("cmd", "exit /b 1", Duration::from_secs(5))
+            .execute(
+                "cmd",
+                "exit /b 1",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         assert!(!result.success);
@@ -344,12 +1419,22 @@ mod tests {
 
         #[cfg(not(target_os = "windows"))]
         let result = executor
-            .execute("sh", "sleep 10", Duration::from_millis(100))
+            .execute(
+                "sh",
+                "sleep 10",
+                Duration::from_millis(100),
+                &ExecOptions::default(),
+            )
             .await;
 
         #[cfg(target_os = "windows")]
         let result = executor
-            .execute("cmd", "ping -n 10 127.0.0.1", Duration::from_millis(100))
+            .execute(
+                "cmd",
+                "ping -n 10 127.0.0.1",
+                Duration::from_millis(100),
+                &ExecOptions::default(),
+            )
             .await;
 
         assert!(!result.success);
@@ -364,7 +1449,12 @@ mod tests {
         #[cfg(not(target_os = "windows"))]
         {
             let result = executor
-                .execute("bash", "echo $SHELL", Duration::from_secs(5))
+                .execute(
+                    "bash",
+                    "echo $SHELL",
+                    Duration::from_secs(5),
+                    &ExecOptions::default(),
+                )
                 .await;
             assert!(result.success);
         }
@@ -377,11 +1467,57 @@ mod tests {
         #[cfg(not(target_os = "windows"))]
         {
             let result = executor
-                .execute("unknown_executor", "echo fallback", Duration::from_secs(5))
+                .execute(
+                    "unknown_executor",
+                    "echo fallback",
+                    Duration::from_secs(5),
+                    &ExecOptions::default(),
+                )
                 .await;
             assert!(result.success);
             assert!(result.output.contains("fallback"));
         }
+        assert_eq!(executor.fallbacks(), 1);
+
+        // Clones share the count; known executors leave it alone
+        let clone = executor.clone();
+        let result = clone
+            .execute(
+                if cfg!(target_os = "windows") {
+                    "cmd"
+                } else {
+                    "sh"
+                },
+                "echo known",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+        assert!(result.success);
+        assert_eq!(executor.fallbacks(), 1);
+    }
+
+    #[test]
+    fn test_map_executor_windows_typos() {
+        let map = |name| map_executor(WINDOWS_EXECUTORS, WINDOWS_FALLBACK, name);
+        assert_eq!(map("powershell"), ("powershell", false));
+        assert_eq!(map("ps"), ("powershell", false));
+        assert_eq!(map("powershell7"), ("pwsh", false));
+        assert_eq!(map("cmd"), ("cmd", false));
+        assert_eq!(map("powershel"), ("powershell", true));
+        assert_eq!(map("cmd.exe"), ("powershell", true));
+        assert_eq!(map("bash"), ("powershell", true));
+    }
+
+    #[test]
+    fn test_map_executor_unix_typos() {
+        let map = |name| map_executor(UNIX_EXECUTORS, UNIX_FALLBACK, name);
+        assert_eq!(map("bash"), ("bash", false));
+        assert_eq!(map("zsh"), ("zsh", false));
+        assert_eq!(map("sh"), ("sh", false));
+        assert_eq!(map("bsah"), ("sh", true));
+        assert_eq!(map("Bash"), ("sh", true));
+        assert_eq!(map("powershell"), ("sh", true));
     }
 
     #[tokio::test]
@@ -390,12 +1526,22 @@ mod tests {
 
         #[cfg(not(target_os = "windows"))]
         let result = executor
-            .execute("sh", "echo error >&2", Duration::from_secs(5))
+            .execute(
+                "sh",
+                "echo error >&2",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         #[cfg(target_os = "windows")]
         let result = executor
-            .execute("cmd", "echo error 1>&2", Duration::from_secs(5))
+            .execute(
+                "cmd",
+                "echo error 1>&2",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         assert!(result.output.contains("error"));
@@ -407,12 +1553,22 @@ mod tests {
 
         #[cfg(not(target_os = "windows"))]
         let result = executor
-            .execute("sh", "echo line1; echo line2", Duration::from_secs(5))
+            .execute(
+                "sh",
+                "echo line1; echo line2",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         #[cfg(target_os = "windows")]
         let result = executor
-            .execute("cmd", "echo line1 & echo line2", Duration::from_secs(5))
+            .execute(
+                "cmd",
+                "echo line1 & echo line2",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
             .await;
 
         assert!(result.success);
@@ -420,6 +1576,39 @@ mod tests {
         assert!(result.output.contains("line2"));
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_truncation_notice_reports_byte_counts() {
+        let executor = CommandExecutor::new();
+        let result = executor
+            .execute(
+                "sh",
+                "head -c 3000000 /dev/zero | tr '\\0' a; head -c 2000 /dev/zero | tr '\\0' b >&2",
+                Duration::from_secs(10),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        // The command ran to completion despite its output overflowing
+        assert!(result.success);
+        assert!(result
+            .output
+            .ends_with("\n... [output truncated: showing 1048576 of 3002000 bytes]"));
+        let (base, total) = strip_truncation_notice(&result.output);
+        assert_eq!(base.len(), MAX_OUTPUT_SIZE);
+        assert_eq!(total, Some(3_002_000));
+    }
+
+    #[test]
+    fn test_strip_truncation_notice() {
+        let output = format!("partial{}", truncation_notice(7, 900));
+        assert_eq!(strip_truncation_notice(&output), ("partial", Some(900)));
+        assert_eq!(strip_truncation_notice("complete"), ("complete", None));
+        // Only a well-formed notice is recognized
+        let odd = "text\n... [output truncated: showing many of 900 bytes]";
+        assert_eq!(strip_truncation_notice(odd), (odd, None));
+    }
+
     #[tokio::test]
     async fn test_zsh_executor() {
         let executor = CommandExecutor::new();
@@ -427,10 +1616,539 @@ mod tests {
         #[cfg(not(target_os = "windows"))]
         {
             let result = executor
-                .execute("zsh", "echo zsh_test", Duration::from_secs(5))
+                .execute(
+                    "zsh",
+                    "echo zsh_test",
+                    Duration::from_secs(5),
+                    &ExecOptions::default(),
+                )
                 .await;
             // This may succeed or fail depending on if zsh is installed
             let _ = result;
         }
     }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_in_pty_reports_terminal() {
+        let executor = CommandExecutor::new();
+        let result = executor
+            .execute_in_pty("sh", "tty", Duration::from_secs(5), &ExecOptions::default())
+            .await;
+
+        assert!(result.success);
+        assert!(result.output.starts_with("/dev/"), "{}", result.output);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_in_pty_exit_code_and_crlf() {
+        let executor = CommandExecutor::new();
+        let result = executor
+            .execute_in_pty(
+                "sh",
+                "printf 'a\\nb\\n'; exit 3",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert!(!result.success);
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.output, "a\nb");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_timeout_keeps_output_before_timeout() {
+        let executor = CommandExecutor::new();
+        let result = executor
+            .execute(
+                "sh",
+                "echo before; echo err-before >&2; sleep 10",
+                Duration::from_millis(500),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert!(!result.success);
+        assert!(result.exit_code.is_none());
+        assert!(result.output.contains("before"));
+        assert!(result.output.contains("err-before"));
+        assert!(result.output.ends_with("Command timed out"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_timeout_keeps_merged_output() {
+        let executor = CommandExecutor::new().with_output_order(OutputOrder::Merged);
+        let result = executor
+            .execute(
+                "sh",
+                "echo first; echo second >&2; sleep 10",
+                Duration::from_millis(500),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert_eq!(result.output, "first\nsecond\nCommand timed out");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_timeout_with_pipe_held_by_grandchild() {
+        let executor = CommandExecutor::new();
+        let start = std::time::Instant::now();
+        // The shell exits at once but the background sleep keeps stdout open
+        let result = executor
+            .execute(
+                "sh",
+                "echo started; sleep 10 &",
+                Duration::from_millis(300),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert!(!result.success);
+        assert_eq!(result.output, "started\nCommand timed out");
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    /// Whether a process has exited (gone, or a zombie awaiting its reaper).
+    #[cfg(target_os = "linux")]
+    fn exited(pid: &str) -> bool {
+        match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => stat.rsplit(") ").next().is_some_and(|s| s.starts_with('Z')),
+            Err(_) => true,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_abandoned_run_kills_process_group() {
+        let executor = CommandExecutor::new();
+        let pid_file =
+            std::env::temp_dir().join(format!("autostrike_group_{}", std::process::id()));
+        let command = format!("sleep 30 & echo $! > {}; wait", pid_file.display());
+
+        // Dropping the run, as cancelling its task does
+        let abandoned = tokio::time::timeout(
+            Duration::from_millis(500),
+            executor.execute(
+                "sh",
+                &command,
+                Duration::from_secs(30),
+                &ExecOptions::default(),
+            ),
+        )
+        .await;
+        assert!(abandoned.is_err());
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        std::fs::remove_file(&pid_file).unwrap();
+        let pid = pid.trim();
+        for _ in 0..20 {
+            if exited(pid) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("background sleep {} survived the abandoned run", pid);
+    }
+
+    #[test]
+    fn test_timed_out_output() {
+        assert_eq!(timed_out_output(String::new()), "Command timed out");
+        assert_eq!(
+            timed_out_output("partial".to_string()),
+            "partial\nCommand timed out"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_in_pty_timeout_keeps_output() {
+        let executor = CommandExecutor::new();
+        let result = executor
+            .execute_in_pty(
+                "sh",
+                "echo before; sleep 10",
+                Duration::from_millis(500),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert!(!result.success);
+        assert_eq!(result.output, "before\nCommand timed out");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_in_pty_timeout() {
+        let executor = CommandExecutor::new();
+        let start = std::time::Instant::now();
+        let result = executor
+            .execute_in_pty(
+                "sh",
+                "sleep 10",
+                Duration::from_millis(500),
+                &ExecOptions::default(),
+            )
+            .await;
+
+        assert!(!result.success);
+        assert_eq!(result.output, "Command timed out");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_argv_no_shell_interpretation() {
+        let executor = CommandExecutor::new();
+        let argv = vec![
+            "echo".to_string(),
+            "$HOME".to_string(),
+            "; echo injected".to_string(),
+        ];
+        let result = executor
+            .execute_argv(&argv, Duration::from_secs(5), &ExecOptions::default())
+            .await;
+
+        assert!(result.success);
+        assert_eq!(result.output, "$HOME ; echo injected");
+    }
+
+    #[tokio::test]
+    async fn test_execute_argv_empty() {
+        let executor = CommandExecutor::new();
+        let result = executor
+            .execute_argv(&[], Duration::from_secs(5), &ExecOptions::default())
+            .await;
+
+        assert!(!result.success);
+        assert!(result.output.contains("empty argv"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_argv_missing_program() {
+        let executor = CommandExecutor::new();
+        let argv = vec!["/nonexistent/program".to_string()];
+        let result = executor
+            .execute_argv(&argv, Duration::from_secs(5), &ExecOptions::default())
+            .await;
+
+        assert!(!result.success);
+        assert!(result.output.contains("Execution error"));
+    }
+
+    #[test]
+    fn test_windows_argv_cmd_utf8_prelude() {
+        let plain = windows_argv("cmd", "systeminfo", &ExecOptions::default());
+        assert_eq!(
+            plain,
+            ("cmd.exe", vec!["/C".to_string(), "systeminfo".to_string()])
+        );
+
+        let options = ExecOptions {
+            force_utf8_console: true,
+            ..ExecOptions::default()
+        };
+        let (program, args) = windows_argv("cmd", "systeminfo", &options);
+        assert_eq!(program, "cmd.exe");
+        assert_eq!(args[1], "chcp 65001 >nul & systeminfo");
+    }
+
+    #[test]
+    fn test_windows_argv_powershell_utf8_prelude() {
+        let (program, args) = windows_argv("powershell", "Get-Date", &ExecOptions::default());
+        assert_eq!(program, "powershell.exe");
+        assert_eq!(
+            args,
+            ["-NoProfile", "-NonInteractive", "-Command", "Get-Date"]
+        );
+
+        let options = ExecOptions {
+            force_utf8_console: true,
+            ..ExecOptions::default()
+        };
+        for executor in ["powershell", "pwsh", "unknown"] {
+            let (_, args) = windows_argv(executor, "Get-Date", &options);
+            let command = args.last().unwrap();
+            assert!(command.starts_with("[Console]::OutputEncoding"));
+            assert!(command.contains("'en-US'"));
+            assert!(command.ends_with("Get-Date"));
+        }
+    }
+
+    #[test]
+    fn test_windows_argv_login_shell_loads_profile() {
+        let options = ExecOptions {
+            login_shell: true,
+            ..ExecOptions::default()
+        };
+        for executor in ["powershell", "pwsh"] {
+            let (_, args) = windows_argv(executor, "Get-Date", &options);
+            assert_eq!(args, ["-NonInteractive", "-Command", "Get-Date"]);
+        }
+        let (_, args) = windows_argv("cmd", "ver", &options);
+        assert_eq!(args, ["/C", "ver"]);
+    }
+
+    #[test]
+    fn test_sysnative_dir() {
+        let env = |name: &str| (name == "SystemRoot").then(|| r"D:\WINNT".to_string());
+        assert_eq!(sysnative_dir(env), Path::new(r"D:\WINNT").join("Sysnative"));
+        assert_eq!(
+            sysnative_dir(|_| None),
+            Path::new(r"C:\Windows").join("Sysnative")
+        );
+    }
+
+    #[test]
+    fn test_windows_program_prefers_native_dir() {
+        let native = Path::new(r"C:\Windows").join("Sysnative");
+
+        assert_eq!(windows_program("cmd.exe", None), PathBuf::from("cmd.exe"));
+        assert_eq!(
+            windows_program("cmd.exe", Some(&native)),
+            native.join("cmd.exe")
+        );
+        assert_eq!(
+            windows_program("powershell.exe", Some(&native)),
+            native
+                .join("WindowsPowerShell")
+                .join("v1.0")
+                .join("powershell.exe")
+        );
+        assert_eq!(
+            windows_program("pwsh.exe", Some(&native)),
+            PathBuf::from("pwsh.exe")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_reports_resolved_program() {
+        let executor = CommandExecutor::new();
+
+        let result = executor
+            .execute(
+                "sh",
+                "true",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+        assert_eq!(result.program.as_deref(), Some("/bin/sh"));
+
+        let argv = vec!["true".to_string()];
+        let result = executor
+            .execute_argv(&argv, Duration::from_secs(5), &ExecOptions::default())
+            .await;
+        assert!(Path::new(&result.program.unwrap()).is_absolute());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_task_capabilities_are_dropped() {
+        let executor =
+            CommandExecutor::new().with_task_capabilities(Some(CapabilitySet::default()));
+
+        let result = executor
+            .execute_in_pty(
+                "sh",
+                "grep -E '^Cap(Eff|Bnd)' /proc/self/status",
+                Duration::from_secs(5),
+                &ExecOptions::default(),
+            )
+            .await;
+        if result.output.contains("lacks CAP_SETPCAP") {
+            // Unprivileged test run: the task fails instead of running
+            assert!(!result.success);
+            assert_eq!(result.exit_code, None);
+            return;
+        }
+        assert!(result.success, "{}", result.output);
+        assert!(result.output.contains("CapEff:\t0000000000000000"));
+        assert!(result.output.contains("CapBnd:\t0000000000000000"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_netns_fails_without_running() {
+        let marker = std::env::temp_dir().join(format!("autostrike_netns_{}", std::process::id()));
+        let options = ExecOptions {
+            netns: Some("autostrike-no-such-namespace".to_string()),
+            ..ExecOptions::default()
+        };
+        let result = CommandExecutor::new()
+            .execute_in_pty(
+                "sh",
+                &format!("touch {}", marker.display()),
+                Duration::from_secs(5),
+                &options,
+            )
+            .await;
+
+        assert!(!result.success);
+        assert_eq!(result.exit_code, None);
+        assert!(result.output.starts_with("Execution error: "));
+        assert!(result.output.contains("autostrike-no-such-namespace"));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_windows_argv_pwsh_program() {
+        let (program, _) = windows_argv("pwsh", "Get-Date", &ExecOptions::default());
+        assert_eq!(program, "pwsh.exe");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_force_utf8_console_noop_on_unix() {
+        let executor = CommandExecutor::new();
+        let options = ExecOptions {
+            force_utf8_console: true,
+            ..ExecOptions::default()
+        };
+        let result = executor
+            .execute("sh", "echo plain", Duration::from_secs(5), &options)
+            .await;
+
+        assert!(result.success);
+        assert_eq!(result.output, "plain");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_env_denylist_removed_from_child() {
+        let executor = CommandExecutor::new();
+        let options = ExecOptions {
+            env_denylist: vec!["HOME".to_string()],
+            ..ExecOptions::default()
+        };
+        let script = "echo home=${HOME-unset} path=${PATH:+set}";
+
+        let piped = executor
+            .execute("sh", script, Duration::from_secs(5), &options)
+            .await;
+        let pty = executor
+            .execute_in_pty("sh", script, Duration::from_secs(5), &options)
+            .await;
+        let argv = executor
+            .execute_argv(
+                &["sh".to_string(), "-c".to_string(), script.to_string()],
+                Duration::from_secs(5),
+                &options,
+            )
+            .await;
+        for result in [piped, pty, argv] {
+            assert!(result.success, "{}", result.output);
+            assert_eq!(result.output.trim(), "home=unset path=set");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_login_shell_sources_profile() {
+        let home = std::env::temp_dir().join(format!("autostrike_login_{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::write(
+            home.join(".profile"),
+            "PATH=\"$PATH:/opt/autostrike-marker/bin\"\n",
+        )
+        .unwrap();
+        let executor = CommandExecutor::new();
+
+        for shell in ["sh", "bash"] {
+            for login_shell in [false, true] {
+                let options = ExecOptions {
+                    login_shell,
+                    ..ExecOptions::default()
+                };
+                let mut cmd = executor.build_command(shell, "echo \"$PATH\"", &options);
+                // Profiles come from the home directory, pointed at the scratch one
+                let output = cmd.env("HOME", &home).output().await.unwrap();
+                let path = String::from_utf8_lossy(&output.stdout);
+                assert_eq!(
+                    path.contains("/opt/autostrike-marker/bin"),
+                    login_shell,
+                    "{} (login: {}): {}",
+                    shell,
+                    login_shell,
+                    path
+                );
+            }
+        }
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_spawned_argv_reflects_shell_and_flags() {
+        let executor = CommandExecutor::new();
+        let login = ExecOptions {
+            login_shell: true,
+            ..ExecOptions::default()
+        };
+        let timeout = Duration::from_secs(5);
+
+        let result = executor
+            .execute("sh", "echo hi", timeout, &ExecOptions::default())
+            .await;
+        assert_eq!(result.spawned_argv.unwrap(), ["/bin/sh", "-c", "echo hi"]);
+        let result = executor.execute("bash", "echo hi", timeout, &login).await;
+        assert_eq!(
+            result.spawned_argv.unwrap(),
+            ["/bin/bash", "-lc", "echo hi"]
+        );
+        let result = executor
+            .execute_in_pty("sh", "echo hi", timeout, &login)
+            .await;
+        assert_eq!(result.spawned_argv.unwrap(), ["/bin/sh", "-lc", "echo hi"]);
+        let argv = ["echo".to_string(), "a b".to_string()];
+        let result = executor
+            .execute_argv(&argv, timeout, &ExecOptions::default())
+            .await;
+        assert_eq!(result.spawned_argv.unwrap(), argv);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_output_budget_shared_across_commands() {
+        const LIMIT: usize = 1_000_000;
+        let executor = CommandExecutor::new().with_output_budget(LIMIT);
+        // Each command alone fits MAX_OUTPUT_SIZE; together they do not fit
+        // the budget. The sleep keeps them all running at once.
+        let script = "head -c 600000 /dev/zero | tr '\\0' a; sleep 0.5";
+        let options = ExecOptions::default();
+        let run = || executor.execute("sh", script, Duration::from_secs(10), &options);
+
+        let watch = async {
+            let mut peak = 0;
+            for _ in 0..20 {
+                peak = peak.max(executor.output_bytes_in_use());
+                tokio::time::sleep(Duration::from_millis(25)).await;
+            }
+            peak
+        };
+        let (a, b, c, d, peak) = tokio::join!(run(), run(), run(), run(), watch);
+
+        assert!(peak > 0 && peak <= LIMIT, "peak {}", peak);
+        let results = [a, b, c, d];
+        let kept: usize = results
+            .iter()
+            .map(|result| strip_truncation_notice(&result.output).0.len())
+            .sum();
+        assert!(kept <= LIMIT, "kept {}", kept);
+        let limited: Vec<_> = results
+            .iter()
+            .filter(|result| result.output_budget_limited)
+            .collect();
+        assert!(!limited.is_empty());
+        for result in limited {
+            assert!(result.success);
+            assert_eq!(strip_truncation_notice(&result.output).1, Some(600_000));
+        }
+        assert_eq!(executor.output_bytes_in_use(), 0);
+    }
 }
@@ -0,0 +1,124 @@
+//! Description of how this binary was built: enabled cargo features and
+//! compile-time settings, reported at registration, in `--version --verbose`
+//! and in support bundles.
+
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Optional cargo features of the agent, and whether each is compiled in.
+const FEATURES: &[(&str, bool)] = &[("otel", cfg!(feature = "otel"))];
+
+/// TLS implementation used for the WebSocket and HTTP connections.
+const TLS_BACKEND: &str = "rustls";
+
+/// Build settings of the running agent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BuildInfo {
+    /// Agent version.
+    pub version: String,
+    /// Target triple the agent was compiled for.
+    pub target: String,
+    /// Optional cargo features compiled in (e.g. `otel`).
+    pub features: Vec<String>,
+    /// Whether the C runtime is statically linked.
+    pub static_linkage: bool,
+    /// TLS implementation (`rustls`).
+    pub tls_backend: String,
+}
+
+/// Build settings of this binary.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        target: env!("AUTOSTRIKE_TARGET").to_string(),
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        static_linkage: cfg!(target_feature = "crt-static"),
+        tls_backend: TLS_BACKEND.to_string(),
+    }
+}
+
+/// Whether the optional cargo feature `name` is compiled in.
+pub fn enabled(name: &str) -> bool {
+    FEATURES
+        .iter()
+        .any(|&(feature, enabled)| feature == name && enabled)
+}
+
+/// Fails when config `option` is set but needs `feature`, which this binary
+/// was built without.
+pub fn require(feature: &str, option: &str) -> Result<()> {
+    if !enabled(feature) {
+        bail!(
+            "{} is set, but this agent was built without the `{}` feature",
+            option,
+            feature
+        );
+    }
+    Ok(())
+}
+
+/// Multi-line description printed by `--version --verbose`.
+pub fn describe(info: &BuildInfo) -> String {
+    let features = if info.features.is_empty() {
+        "none".to_string()
+    } else {
+        info.features.join(", ")
+    };
+    format!(
+        "autostrike-agent {}\ntarget: {}\nfeatures: {}\nlinkage: {}\ntls: {}",
+        info.version,
+        info.target,
+        features,
+        if info.static_linkage {
+            "static"
+        } else {
+            "dynamic"
+        },
+        info.tls_backend
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features_match_cfg() {
+        let info = build_info();
+
+        assert_eq!(
+            info.features.contains(&"otel".to_string()),
+            cfg!(feature = "otel")
+        );
+        assert_eq!(enabled("otel"), cfg!(feature = "otel"));
+        assert!(!enabled("unknown"));
+        assert_eq!(
+            require("otel", "otel.endpoint").is_ok(),
+            cfg!(feature = "otel")
+        );
+        assert_eq!(info.static_linkage, cfg!(target_feature = "crt-static"));
+        assert!(!info.target.is_empty());
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_describe() {
+        let info = BuildInfo {
+            version: "1.2.3".to_string(),
+            target: "x86_64-unknown-linux-musl".to_string(),
+            features: Vec::new(),
+            static_linkage: true,
+            tls_backend: "rustls".to_string(),
+        };
+
+        let text = describe(&info);
+        assert!(text.starts_with("autostrike-agent 1.2.3\n"));
+        assert!(text.contains("features: none"));
+        assert!(text.contains("linkage: static"));
+    }
+}
@@ -0,0 +1,318 @@
+//! Built-in `read_file` action: returns the content of a host file under an
+//! allowlisted directory, without spawning a shell.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use tracing::debug;
+
+use crate::config::FileReadConfig;
+use crate::protocol::{ContentEncoding, ReadFilePayload, ReadFileResultPayload};
+
+/// Answers a `read_file` request, refusing paths outside the allowlist and
+/// files above the size cap. Blocking: run it off the async runtime.
+pub fn read_file(request: ReadFilePayload, config: &FileReadConfig) -> ReadFileResultPayload {
+    let mut result = ReadFileResultPayload {
+        id: request.id,
+        path: request.path,
+        success: false,
+        content: None,
+        encoding: None,
+        size: None,
+        error: None,
+    };
+    match read_allowed(Path::new(&result.path), config) {
+        Ok(bytes) => {
+            debug!("Read {} bytes from {}", bytes.len(), result.path);
+            let size = bytes.len() as u64;
+            let (content, encoding) = match String::from_utf8(bytes) {
+                Ok(text) => (text, ContentEncoding::Utf8),
+                Err(e) => (
+                    base64::engine::general_purpose::STANDARD.encode(e.as_bytes()),
+                    ContentEncoding::Base64,
+                ),
+            };
+            result.success = true;
+            result.size = Some(size);
+            result.content = Some(content);
+            result.encoding = Some(encoding);
+        }
+        Err(e) => {
+            debug!("Did not read {}: {:#}", result.path, e);
+            result.error = Some(format!("{:#}", e));
+        }
+    }
+    result
+}
+
+/// Reads `path` once it resolves, symlinks and `..` included, under one of
+/// the allowed directories. The file is opened without following a final
+/// symlink and its location is checked again from the open handle, so a path
+/// swapped for a symlink after the first check is refused.
+fn read_allowed(path: &Path, config: &FileReadConfig) -> Result<Vec<u8>> {
+    if config.allowed_paths.is_empty() {
+        bail!("file reading is disabled (file_read.allowed_paths is empty)");
+    }
+    if !path.is_absolute() {
+        bail!("path must be absolute");
+    }
+    let roots: Vec<PathBuf> = config
+        .allowed_paths
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .collect();
+    let resolved = path.canonicalize().context("cannot resolve path")?;
+    if !is_allowed(&resolved, &roots) {
+        bail!("path is outside file_read.allowed_paths");
+    }
+
+    let file = open_no_follow(&resolved).context("cannot open file")?;
+    if !file.metadata()?.is_file() {
+        bail!("not a regular file");
+    }
+    if let Some(opened) = handle_path(&file) {
+        if !is_allowed(&opened, &roots) {
+            bail!("path is outside file_read.allowed_paths");
+        }
+    }
+    // Sizes reported by pseudo-files (/proc) are unreliable: read one byte
+    // past the cap instead of trusting the metadata.
+    let mut bytes = Vec::new();
+    file.take(config.max_bytes.saturating_add(1))
+        .read_to_end(&mut bytes)
+        .context("cannot read file")?;
+    if bytes.len() as u64 > config.max_bytes {
+        bail!("file is larger than the {} byte limit", config.max_bytes);
+    }
+    Ok(bytes)
+}
+
+fn is_allowed(path: &Path, roots: &[PathBuf]) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+/// Opens `path` for reading, failing when its last component is a symlink.
+/// On Windows, a reparse point is opened itself, and then fails the regular
+/// file check.
+fn open_no_follow(path: &Path) -> std::io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, nix::libc::O_NOFOLLOW);
+    #[cfg(windows)]
+    std::os::windows::fs::OpenOptionsExt::custom_flags(
+        &mut options,
+        winapi::um::winbase::FILE_FLAG_OPEN_REPARSE_POINT,
+    );
+    options.open(path)
+}
+
+/// Path of the file behind `file`, where the platform reports it.
+#[cfg(target_os = "linux")]
+fn handle_path(file: &File) -> Option<PathBuf> {
+    use std::os::fd::AsRawFd;
+    std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).ok()
+}
+
+/// Path of the file behind `file`, where the platform reports it.
+#[cfg(windows)]
+fn handle_path(file: &File) -> Option<PathBuf> {
+    use std::os::windows::ffi::OsStringExt;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::GetFinalPathNameByHandleW;
+
+    let mut buffer = vec![0u16; 1024];
+    loop {
+        // SAFETY: the handle is open for the call and the length passed is
+        // the capacity of `buffer`.
+        let len = unsafe {
+            GetFinalPathNameByHandleW(
+                file.as_raw_handle().cast(),
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                0,
+            )
+        } as usize;
+        if len == 0 {
+            return None;
+        }
+        if len < buffer.len() {
+            buffer.truncate(len);
+            return Some(std::ffi::OsString::from_wide(&buffer).into());
+        }
+        buffer.resize(len + 1, 0);
+    }
+}
+
+/// Path of the file behind `file`, where the platform reports it.
+#[cfg(not(any(target_os = "linux", windows)))]
+fn handle_path(_file: &File) -> Option<PathBuf> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_for(allowed: &Path, max_bytes: u64) -> FileReadConfig {
+        FileReadConfig {
+            allowed_paths: vec![allowed.to_path_buf()],
+            max_bytes,
+        }
+    }
+
+    /// Fresh scratch directory for one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "autostrike_read_file_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn request(path: &Path) -> ReadFilePayload {
+        ReadFilePayload {
+            id: Some("read-1".to_string()),
+            path: path.to_string_lossy().into_owned(),
+        }
+    }
+
+    #[test]
+    fn test_read_allowed_text_file() {
+        let dir = scratch_dir("text");
+        let file = dir.join("hosts");
+        std::fs::write(&file, "127.0.0.1 localhost\n").unwrap();
+
+        let result = read_file(request(&file), &config_for(&dir, 1024));
+
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.id.as_deref(), Some("read-1"));
+        assert_eq!(result.content.as_deref(), Some("127.0.0.1 localhost\n"));
+        assert_eq!(result.encoding, Some(ContentEncoding::Utf8));
+        assert_eq!(result.size, Some(20));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_binary_file_as_base64() {
+        let dir = scratch_dir("binary");
+        let file = dir.join("blob");
+        std::fs::write(&file, [0xff, 0x00, 0xfe, 0x41]).unwrap();
+
+        let result = read_file(request(&file), &config_for(&dir, 1024));
+
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.content.as_deref(), Some("/wD+QQ=="));
+        assert_eq!(result.encoding, Some(ContentEncoding::Base64));
+        assert_eq!(result.size, Some(4));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_outside_allowlist_refused() {
+        let allowed = scratch_dir("allowed");
+        let other = scratch_dir("other");
+        let file = other.join("secret");
+        std::fs::write(&file, "secret").unwrap();
+
+        let result = read_file(request(&file), &config_for(&allowed, 1024));
+        assert!(!result.success);
+        assert!(result.content.is_none());
+        assert!(result.error.unwrap().contains("outside"));
+
+        // `..` resolves before the allowlist check
+        let escaping = allowed
+            .join("..")
+            .join(other.file_name().unwrap())
+            .join("secret");
+        let result = read_file(request(&escaping), &config_for(&allowed, 1024));
+        assert!(!result.success);
+
+        let result = read_file(request(Path::new("secret")), &config_for(&other, 1024));
+        assert!(result.error.unwrap().contains("absolute"));
+
+        std::fs::remove_dir_all(allowed).unwrap();
+        std::fs::remove_dir_all(other).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_symlink_out_of_allowlist_refused() {
+        let allowed = scratch_dir("link");
+        let other = scratch_dir("link_target");
+        std::fs::write(other.join("secret"), "secret").unwrap();
+        let link = allowed.join("innocent");
+        std::os::unix::fs::symlink(other.join("secret"), &link).unwrap();
+
+        let result = read_file(request(&link), &config_for(&allowed, 1024));
+        assert!(!result.success);
+
+        std::fs::remove_dir_all(allowed).unwrap();
+        std::fs::remove_dir_all(other).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_open_refuses_swapped_symlink() {
+        let allowed = scratch_dir("swap");
+        let other = scratch_dir("swap_target");
+        std::fs::write(other.join("shadow"), "secret").unwrap();
+        let file = allowed.join("report.txt");
+        std::fs::write(&file, "report").unwrap();
+        let roots = [allowed.canonicalize().unwrap()];
+        let resolved = file.canonicalize().unwrap();
+        assert!(is_allowed(&resolved, &roots));
+
+        // Swapped after the containment check, before the open
+        std::fs::remove_file(&file).unwrap();
+        std::os::unix::fs::symlink(other.join("shadow"), &file).unwrap();
+        assert!(open_no_follow(&resolved).is_err());
+
+        std::fs::remove_dir_all(allowed).unwrap();
+        std::fs::remove_dir_all(other).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_handle_path_reports_opened_file() {
+        let dir = scratch_dir("handle");
+        let file = dir.join("hosts");
+        std::fs::write(&file, "127.0.0.1 localhost\n").unwrap();
+
+        let opened = open_no_follow(&file).unwrap();
+        assert_eq!(handle_path(&opened), Some(file.canonicalize().unwrap()));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_oversized_file_refused() {
+        let dir = scratch_dir("oversized");
+        let file = dir.join("big");
+        std::fs::write(&file, vec![b'a'; 11]).unwrap();
+
+        let result = read_file(request(&file), &config_for(&dir, 10));
+        assert!(!result.success);
+        assert!(result.content.is_none());
+        assert!(result.error.unwrap().contains("10 byte limit"));
+
+        std::fs::write(&file, vec![b'a'; 10]).unwrap();
+        let result = read_file(request(&file), &config_for(&dir, 10));
+        assert!(result.success);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_disabled_without_allowlist() {
+        let result = read_file(request(&std::env::temp_dir()), &FileReadConfig::default());
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("disabled"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Per-group task queues: tasks sharing a `group_id` (one operator's
+//! scenario) run one at a time in arrival order, while different groups
+//! share the task slots.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// A group with tasks running or waiting.
+#[derive(Default)]
+struct Queue {
+    /// Taken by the group's running task; handed out in arrival order.
+    turn: Arc<AsyncMutex<()>>,
+    /// Tasks of the group running or waiting for their turn.
+    tasks: usize,
+}
+
+/// Queues of the groups with tasks in progress.
+///
+/// A group holds at most one task slot and waits for the next one behind the
+/// other groups, so slots go round-robin between groups and a large group
+/// cannot starve a small one.
+#[derive(Default)]
+pub struct GroupQueues {
+    queues: Mutex<HashMap<String, Queue>>,
+}
+
+impl GroupQueues {
+    /// Waits until every earlier task of `group` finished, then holds the
+    /// group until the returned turn is dropped.
+    pub async fn enter(self: &Arc<Self>, group: &str) -> GroupTurn {
+        let turn = {
+            let mut queues = self.map();
+            let queue = queues.entry(group.to_string()).or_default();
+            queue.tasks += 1;
+            queue.turn.clone()
+        };
+        // Counted until dropped, whether the turn came or the wait was cancelled
+        let mut entered = GroupTurn {
+            queues: self.clone(),
+            group: group.to_string(),
+            guard: None,
+        };
+        entered.guard = Some(turn.lock_owned().await);
+        entered
+    }
+
+    /// Tasks running or waiting in each group.
+    pub fn depths(&self) -> BTreeMap<String, usize> {
+        self.map()
+            .iter()
+            .map(|(group, queue)| (group.clone(), queue.tasks))
+            .collect()
+    }
+
+    fn map(&self) -> std::sync::MutexGuard<'_, HashMap<String, Queue>> {
+        self.queues.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A task's place in its group; lets the next task of the group start once
+/// dropped, and forgets the group when it was the last.
+pub struct GroupTurn {
+    queues: Arc<GroupQueues>,
+    group: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for GroupTurn {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        let mut queues = self.queues.map();
+        if let Some(queue) = queues.get_mut(&self.group) {
+            queue.tasks -= 1;
+            if queue.tasks == 0 {
+                queues.remove(&self.group);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_group_runs_in_arrival_order() {
+        let queues = Arc::new(GroupQueues::default());
+        let first = queues.enter("scenario-a").await;
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let mut waiters = Vec::new();
+        for i in 0..3 {
+            waiters.push(tokio::spawn({
+                let queues = queues.clone();
+                let order = order.clone();
+                async move {
+                    let _turn = queues.enter("scenario-a").await;
+                    order.lock().unwrap().push(i);
+                }
+            }));
+            // Let each waiter queue up before the next
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(queues.depths()["scenario-a"], 4);
+        assert!(order.lock().unwrap().is_empty());
+
+        drop(first);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2]);
+        assert!(queues.depths().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_groups_do_not_wait_for_each_other() {
+        let queues = Arc::new(GroupQueues::default());
+        let _a = queues.enter("scenario-a").await;
+
+        let b = tokio::time::timeout(Duration::from_millis(100), queues.enter("scenario-b")).await;
+        assert!(b.is_ok());
+        assert_eq!(
+            queues.depths(),
+            BTreeMap::from([("scenario-a".to_string(), 1), ("scenario-b".to_string(), 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_wait_leaves_queue() {
+        let queues = Arc::new(GroupQueues::default());
+        let first = queues.enter("scenario-a").await;
+
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(20), queues.enter("scenario-a")).await;
+        assert!(cancelled.is_err());
+        assert_eq!(queues.depths()["scenario-a"], 1);
+
+        drop(first);
+        assert!(queues.depths().is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! Periodic self-checks of a long-running agent, so degraded states that
+//! used to need a restart (dead heartbeat task, stuck results, silent
+//! connection, clock jump) are detected and, where possible, repaired.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::HealthConfig;
+
+/// A self-check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthCheck {
+    /// The heartbeat task is running.
+    Heartbeat,
+    /// Queued task results are being sent.
+    Results,
+    /// The workspace volume has room left.
+    Disk,
+    /// The server sent something recently.
+    Connection,
+    /// The wall clock moved in step with the monotonic clock.
+    Clock,
+}
+
+/// What the agent does about a failed check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Remediation {
+    /// Start a new heartbeat task.
+    RestartHeartbeat,
+    /// Drop the connection and connect again.
+    Reconnect,
+}
+
+impl HealthCheck {
+    /// Remediation of this check's failure, if there is one.
+    pub fn remediation(self) -> Option<Remediation> {
+        match self {
+            Self::Heartbeat => Some(Remediation::RestartHeartbeat),
+            // Queued results outlive the connection; the server re-syncs
+            // the clock skew on registration
+            Self::Results | Self::Connection | Self::Clock => Some(Remediation::Reconnect),
+            Self::Disk => None,
+        }
+    }
+}
+
+/// A check that failed at the last run, as reported in the heartbeat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct HealthIssue {
+    /// The failed check.
+    pub check: HealthCheck,
+    /// What was found.
+    pub detail: String,
+    /// Remediation applied at the last run; absent when there is none or
+    /// the last one is too recent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remediation: Option<Remediation>,
+    /// Remediations of this check since the agent started.
+    #[serde(default)]
+    pub remediations: u64,
+}
+
+/// State of the agent seen by a check run.
+#[derive(Debug, Clone)]
+pub struct Observations {
+    /// Whether the heartbeat task is still running.
+    pub heartbeat_alive: bool,
+    /// Age of the oldest task result waiting to be sent, if any.
+    pub oldest_result: Option<Duration>,
+    /// Why the workspace volume is unusable, if it is.
+    pub disk_error: Option<String>,
+    /// Time since the last frame from the server.
+    pub silence: Duration,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Last remediation of each check.
+    last_remediation: HashMap<HealthCheck, Instant>,
+    /// Remediations of each check since startup.
+    remediations: HashMap<HealthCheck, u64>,
+    /// Both clocks at the last run, to spot wall-clock jumps.
+    last_run: Option<(Instant, SystemTime)>,
+    /// Checks that failed at the last run.
+    issues: Vec<HealthIssue>,
+}
+
+/// Runs the checks and rate-limits their remediations, keeping the failures
+/// for the heartbeat.
+#[derive(Debug)]
+pub struct HealthMonitor {
+    config: HealthConfig,
+    state: Mutex<State>,
+}
+
+impl HealthMonitor {
+    /// Creates a monitor with no failed checks.
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Time between check runs, or `None` when the monitor is disabled.
+    pub fn interval(&self) -> Option<Duration> {
+        (self.config.interval_secs > 0).then(|| Duration::from_secs(self.config.interval_secs))
+    }
+
+    /// Runs the checks against `seen` and returns the remediations to apply
+    /// now. A check is remediated at most once per
+    /// `remediation_cooldown_secs`; it stays reported meanwhile.
+    pub fn check(&self, seen: &Observations, now: Instant, wall: SystemTime) -> Vec<Remediation> {
+        let mut state = self.state();
+        let mut failures = Vec::new();
+
+        if !seen.heartbeat_alive {
+            failures.push((HealthCheck::Heartbeat, "heartbeat task stopped".to_string()));
+        }
+        if let Some(age) = seen.oldest_result {
+            if age > Duration::from_secs(self.config.max_result_age_secs) {
+                failures.push((
+                    HealthCheck::Results,
+                    format!("oldest queued result is {} s old", age.as_secs()),
+                ));
+            }
+        }
+        if let Some(error) = &seen.disk_error {
+            failures.push((HealthCheck::Disk, error.clone()));
+        }
+        if self.config.max_silence_secs > 0
+            && seen.silence > Duration::from_secs(self.config.max_silence_secs)
+        {
+            failures.push((
+                HealthCheck::Connection,
+                format!("no frame from the server for {} s", seen.silence.as_secs()),
+            ));
+        }
+        if let Some((last_now, last_wall)) = state.last_run {
+            let elapsed = now.saturating_duration_since(last_now).as_secs_f64();
+            let wall_elapsed = match wall.duration_since(last_wall) {
+                Ok(forward) => forward.as_secs_f64(),
+                Err(backward) => -backward.duration().as_secs_f64(),
+            };
+            let jump = wall_elapsed - elapsed;
+            if jump.abs() > self.config.clock_jump_secs as f64 {
+                failures.push((
+                    HealthCheck::Clock,
+                    format!("wall clock jumped by {:+.0} s", jump),
+                ));
+            }
+        }
+        state.last_run = Some((now, wall));
+
+        let cooldown = Duration::from_secs(self.config.remediation_cooldown_secs);
+        let mut remediations = Vec::new();
+        let mut issues = Vec::new();
+        for (check, detail) in failures {
+            let due = state
+                .last_remediation
+                .get(&check)
+                .is_none_or(|last| now.saturating_duration_since(*last) >= cooldown);
+            let remediation = check.remediation().filter(|_| due);
+            if let Some(remediation) = remediation {
+                warn!(
+                    "Health check {:?} failed ({}), applying {:?}",
+                    check, detail, remediation
+                );
+                state.last_remediation.insert(check, now);
+                *state.remediations.entry(check).or_default() += 1;
+                if !remediations.contains(&remediation) {
+                    remediations.push(remediation);
+                }
+            } else {
+                warn!("Health check {:?} failed: {}", check, detail);
+            }
+            issues.push(HealthIssue {
+                check,
+                detail,
+                remediation,
+                remediations: state.remediations.get(&check).copied().unwrap_or(0),
+            });
+        }
+        state.issues = issues;
+        remediations
+    }
+
+    /// Checks that failed at the last run.
+    pub fn issues(&self) -> Vec<HealthIssue> {
+        self.state().issues.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy() -> Observations {
+        Observations {
+            heartbeat_alive: true,
+            oldest_result: None,
+            disk_error: None,
+            silence: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_healthy_agent_reports_nothing() {
+        let monitor = HealthMonitor::new(HealthConfig::default());
+        let now = Instant::now();
+        let wall = SystemTime::now();
+        assert!(monitor.check(&healthy(), now, wall).is_empty());
+        let later = Duration::from_secs(60);
+        assert!(monitor
+            .check(&healthy(), now + later, wall + later)
+            .is_empty());
+        assert!(monitor.issues().is_empty());
+    }
+
+    #[test]
+    fn test_remediations_are_rate_limited() {
+        let monitor = HealthMonitor::new(HealthConfig {
+            remediation_cooldown_secs: 600,
+            ..HealthConfig::default()
+        });
+        let dead = Observations {
+            heartbeat_alive: false,
+            ..healthy()
+        };
+        let now = Instant::now();
+        let wall = SystemTime::now();
+
+        assert_eq!(
+            monitor.check(&dead, now, wall),
+            [Remediation::RestartHeartbeat]
+        );
+        let issues = monitor.issues();
+        assert_eq!(issues[0].check, HealthCheck::Heartbeat);
+        assert_eq!(issues[0].remediation, Some(Remediation::RestartHeartbeat));
+        assert_eq!(issues[0].remediations, 1);
+
+        // Still failing within the cooldown: reported, not remediated again
+        let soon = Duration::from_secs(60);
+        assert!(monitor.check(&dead, now + soon, wall + soon).is_empty());
+        let issues = monitor.issues();
+        assert_eq!(issues[0].remediation, None);
+        assert_eq!(issues[0].remediations, 1);
+
+        let later = Duration::from_secs(600);
+        assert_eq!(
+            monitor.check(&dead, now + later, wall + later),
+            [Remediation::RestartHeartbeat]
+        );
+        assert_eq!(monitor.issues()[0].remediations, 2);
+
+        // Recovery clears the report
+        let after = Duration::from_secs(660);
+        monitor.check(&healthy(), now + after, wall + after);
+        assert!(monitor.issues().is_empty());
+    }
+
+    #[test]
+    fn test_checks_and_their_remediations() {
+        let monitor = HealthMonitor::new(HealthConfig {
+            max_result_age_secs: 300,
+            max_silence_secs: 900,
+            ..HealthConfig::default()
+        });
+        let degraded = Observations {
+            heartbeat_alive: true,
+            oldest_result: Some(Duration::from_secs(301)),
+            disk_error: Some("only 1024 bytes free".to_string()),
+            silence: Duration::from_secs(901),
+        };
+        let remediations = monitor.check(&degraded, Instant::now(), SystemTime::now());
+        assert_eq!(remediations, [Remediation::Reconnect]);
+
+        let issues = monitor.issues();
+        let checks: Vec<HealthCheck> = issues.iter().map(|i| i.check).collect();
+        assert_eq!(
+            checks,
+            [
+                HealthCheck::Results,
+                HealthCheck::Disk,
+                HealthCheck::Connection
+            ]
+        );
+        // Nothing to do about a full disk but report it
+        assert_eq!(issues[1].remediation, None);
+        assert_eq!(issues[1].detail, "only 1024 bytes free");
+    }
+
+    #[test]
+    fn test_silence_check_disabled_by_default() {
+        let monitor = HealthMonitor::new(HealthConfig::default());
+        let silent = Observations {
+            silence: Duration::from_secs(86400),
+            ..healthy()
+        };
+        assert!(monitor
+            .check(&silent, Instant::now(), SystemTime::now())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_clock_jump_detected() {
+        let monitor = HealthMonitor::new(HealthConfig {
+            clock_jump_secs: 60,
+            ..HealthConfig::default()
+        });
+        let now = Instant::now();
+        let wall = SystemTime::now();
+        monitor.check(&healthy(), now, wall);
+
+        // 30 s passed, but the wall clock went back an hour
+        let remediations = monitor.check(
+            &healthy(),
+            now + Duration::from_secs(30),
+            wall - Duration::from_secs(3600),
+        );
+        assert_eq!(remediations, [Remediation::Reconnect]);
+        let issues = monitor.issues();
+        assert_eq!(issues[0].check, HealthCheck::Clock);
+        assert_eq!(issues[0].detail, "wall clock jumped by -3630 s");
+    }
+}
@@ -0,0 +1,308 @@
+//! The `helper` executor: hands a task to an operator-supplied binary
+//! (`helper_path`), for techniques a shell cannot express.
+//!
+//! The helper gets a [`HelperRequest`] as JSON on stdin and must print a
+//! [`HelperResponse`] as JSON on stdout; stderr is only logged.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::executor::{self, ExecOptions, ExecutionResult, TASK_DIR_ENV};
+
+/// Executor name selecting the helper.
+pub const HELPER_EXECUTOR: &str = "helper";
+
+/// Largest response read from the helper's stdout, in bytes.
+const MAX_RESPONSE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Task handed to the helper on stdin.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HelperRequest {
+    /// Task identifier.
+    pub task_id: String,
+    /// MITRE ATT&CK technique ID.
+    pub technique_id: String,
+    /// Command from the task, for the helper to interpret.
+    pub command: String,
+    /// Seconds the helper has before it is killed.
+    pub timeout: u64,
+}
+
+/// Result the helper prints on stdout.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HelperResponse {
+    /// Whether the technique succeeded.
+    pub success: bool,
+    /// Output reported for the task.
+    #[serde(default)]
+    pub output: String,
+    /// Exit code reported for the task, if any.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+}
+
+/// Runs `request` through the helper at `helper_path`, killing it after
+/// `time_limit`. Fails the task when no helper is configured, it cannot
+/// start, or its stdout is not a [`HelperResponse`].
+pub async fn run(
+    helper_path: Option<&Path>,
+    request: &HelperRequest,
+    time_limit: Duration,
+    options: &ExecOptions,
+) -> ExecutionResult {
+    let Some(helper_path) = helper_path else {
+        return failure(
+            None,
+            "the helper executor needs helper_path in the configuration",
+        );
+    };
+    let program = Some(helper_path.display().to_string());
+    debug!(
+        "Running task {} through helper {}",
+        request.task_id,
+        helper_path.display()
+    );
+
+    let mut cmd = Command::new(helper_path);
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(dir) = &options.task_dir {
+        cmd.env(TASK_DIR_ENV, dir);
+    }
+    for name in &options.env_denylist {
+        cmd.env_remove(name);
+    }
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            return failure(
+                program,
+                &format!("cannot start helper {}: {}", helper_path.display(), e),
+            )
+        }
+    };
+
+    let input = serde_json::to_vec(request).expect("helper request serializes");
+    let mut stdin = child.stdin.take().expect("stdin piped");
+    let mut stdout = child.stdout.take().expect("stdout piped");
+    let mut stderr = child.stderr.take().expect("stderr piped");
+    let finished = tokio::time::timeout(time_limit, async {
+        // Written while the output is read, so neither side blocks on a full pipe
+        let write = async {
+            let _ = stdin.write_all(&input).await;
+            drop(stdin);
+        };
+        let mut response = Vec::new();
+        let mut errors = Vec::new();
+        let read = async {
+            let mut limited = (&mut stdout).take(MAX_RESPONSE_BYTES + 1);
+            limited.read_to_end(&mut response).await
+        };
+        let read_errors = async {
+            let mut limited = (&mut stderr).take(executor::MAX_OUTPUT_SIZE as u64);
+            limited.read_to_end(&mut errors).await
+        };
+        let (_, read, _) = tokio::join!(write, read, read_errors);
+        read?;
+        if response.len() as u64 > MAX_RESPONSE_BYTES {
+            return Ok((response, errors, None));
+        }
+        child
+            .wait()
+            .await
+            .map(|status| (response, errors, Some(status)))
+    })
+    .await;
+
+    let (response, errors, status) = match finished {
+        Ok(Ok(done)) => done,
+        Ok(Err(e)) => return failure(program, &format!("helper failed: {}", e)),
+        Err(_) => {
+            let _ = child.kill().await;
+            return ExecutionResult {
+                success: false,
+                output: "Command timed out".to_string(),
+                exit_code: None,
+                program,
+                spawn_latency_ms: None,
+                output_budget_limited: false,
+                spawned_argv: None,
+            };
+        }
+    };
+    if !errors.is_empty() {
+        warn!(
+            "Helper stderr for task {}: {}",
+            request.task_id,
+            String::from_utf8_lossy(&errors).trim()
+        );
+    }
+    if status.is_none() {
+        let _ = child.kill().await;
+        return failure(
+            program,
+            &format!("helper response exceeds {} bytes", MAX_RESPONSE_BYTES),
+        );
+    }
+
+    match serde_json::from_slice::<HelperResponse>(&response) {
+        Ok(response) => ExecutionResult {
+            success: response.success,
+            output: cap_output(response.output),
+            exit_code: response.exit_code,
+            program,
+            spawn_latency_ms: None,
+            output_budget_limited: false,
+            spawned_argv: None,
+        },
+        Err(e) => failure(
+            program,
+            &format!(
+                "helper printed invalid JSON ({}): {}",
+                e,
+                String::from_utf8_lossy(&response).trim()
+            ),
+        ),
+    }
+}
+
+/// Result of a helper that could not produce one.
+fn failure(program: Option<String>, error: &str) -> ExecutionResult {
+    ExecutionResult {
+        success: false,
+        output: cap_output(format!("Execution error: {}", error)),
+        exit_code: None,
+        program,
+        spawn_latency_ms: None,
+        output_budget_limited: false,
+        spawned_argv: None,
+    }
+}
+
+/// Cuts output to the size shell commands are held to.
+fn cap_output(mut output: String) -> String {
+    if output.len() <= executor::MAX_OUTPUT_SIZE {
+        return output;
+    }
+    let total = output.len();
+    output.truncate(executor::find_char_boundary(
+        &output,
+        executor::MAX_OUTPUT_SIZE,
+    ));
+    let notice = executor::truncation_notice(output.len(), total);
+    output.push_str(&notice);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> HelperRequest {
+        HelperRequest {
+            task_id: "task-1".to_string(),
+            technique_id: "T1055".to_string(),
+            command: "ptrace-attach 1234".to_string(),
+            timeout: 5,
+        }
+    }
+
+    /// Writes an executable helper script into a fresh directory.
+    #[cfg(unix)]
+    fn helper_script(name: &str, body: &str) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("autostrike_helper_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("helper.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_helper_json_contract() {
+        let helper = helper_script(
+            "contract",
+            r#"cat > "$(dirname "$0")/request.json"
+echo 'diagnostics' >&2
+echo '{"success": true, "output": "attached", "exit_code": 3}'"#,
+        );
+
+        let result = run(
+            Some(&helper),
+            &request(),
+            Duration::from_secs(5),
+            &ExecOptions::default(),
+        )
+        .await;
+
+        assert!(result.success, "{}", result.output);
+        assert_eq!(result.output, "attached");
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.program, Some(helper.display().to_string()));
+        let sent: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(helper.with_file_name("request.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            sent,
+            serde_json::json!({
+                "task_id": "task-1",
+                "technique_id": "T1055",
+                "command": "ptrace-attach 1234",
+                "timeout": 5,
+            })
+        );
+        std::fs::remove_dir_all(helper.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_helper_invalid_response() {
+        let helper = helper_script("invalid", "cat >/dev/null; echo not json");
+
+        let result = run(
+            Some(&helper),
+            &request(),
+            Duration::from_secs(5),
+            &ExecOptions::default(),
+        )
+        .await;
+
+        assert!(!result.success);
+        assert!(result.output.contains("invalid JSON"), "{}", result.output);
+        assert!(result.output.contains("not json"));
+        std::fs::remove_dir_all(helper.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_helper() {
+        let options = ExecOptions::default();
+        let result = run(None, &request(), Duration::from_secs(5), &options).await;
+        assert!(!result.success);
+        assert!(result.output.contains("needs helper_path"));
+
+        let missing = std::env::temp_dir().join("autostrike-no-such-helper");
+        let result = run(Some(&missing), &request(), Duration::from_secs(5), &options).await;
+        assert!(!result.success);
+        assert_eq!(result.exit_code, None);
+        assert!(
+            result
+                .output
+                .starts_with("Execution error: cannot start helper"),
+            "{}",
+            result.output
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! Local history of executed tasks, so operators on the host can see what
+//! the agent ran without access to the server (`autostrike-agent history`).
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::config::HistoryConfig;
+
+/// One executed task.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// RFC 3339 local time the task finished.
+    pub timestamp: String,
+    pub task_id: String,
+    pub technique_id: String,
+    /// `success`, `failed` or `cancelled`.
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Command run, only kept with `record_commands`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+/// History file with its retention limits, shared by all tasks.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    max_entries: usize,
+    max_age: Duration,
+    record_commands: bool,
+    /// Held while the file is rewritten, so concurrent tasks do not lose entries.
+    write_lock: Mutex<()>,
+}
+
+impl History {
+    /// History configured by `config`, if it has a path.
+    pub fn from_config(config: &HistoryConfig) -> Option<Self> {
+        Some(Self {
+            path: config.path.clone()?,
+            max_entries: config.max_entries,
+            max_age: Duration::from_secs(config.max_age_secs),
+            record_commands: config.record_commands,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    /// Whether entries carry the task's command.
+    pub fn records_commands(&self) -> bool {
+        self.record_commands
+    }
+
+    /// Adds `entry`, dropping the entries past the retention limits.
+    pub fn record(&self, entry: HistoryEntry) -> Result<()> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = read(&self.path)?;
+        entries.push(entry);
+        let entries = prune(entries, Local::now(), self.max_age, self.max_entries);
+        write(&self.path, &entries)
+    }
+}
+
+/// Entries of the history file at `path`, oldest first; none if it does not
+/// exist yet. Unreadable lines are skipped.
+pub fn read(path: &Path) -> Result<Vec<HistoryEntry>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+    };
+    let mut entries = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => debug!("Skipping history line {:?}: {}", line, e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Replaces the history file with `entries`, through a temporary file so a
+/// crash never leaves it half-written.
+fn write(path: &Path, entries: &[HistoryEntry]) -> Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let staged = path.with_file_name(name);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = std::io::BufWriter::new(
+        options
+            .open(&staged)
+            .with_context(|| format!("cannot write {}", staged.display()))?,
+    );
+    for entry in entries {
+        serde_json::to_writer(&mut file, entry)?;
+        file.write_all(b"\n")?;
+    }
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&staged, path).with_context(|| format!("cannot replace {}", path.display()))
+}
+
+/// Local time of `entry`, if its timestamp is valid.
+fn finished_at(entry: &HistoryEntry) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(&entry.timestamp)
+        .ok()
+        .map(|time| time.with_timezone(&Local))
+}
+
+/// Drops the entries older than `max_age` at `now`, then the oldest ones
+/// beyond `max_entries`.
+fn prune(
+    entries: Vec<HistoryEntry>,
+    now: DateTime<Local>,
+    max_age: Duration,
+    max_entries: usize,
+) -> Vec<HistoryEntry> {
+    let mut kept: Vec<HistoryEntry> = entries
+        .into_iter()
+        .filter(|entry| {
+            finished_at(entry).is_some_and(|time| {
+                now.signed_duration_since(time)
+                    .to_std()
+                    .map_or(true, |age| age <= max_age)
+            })
+        })
+        .collect();
+    let excess = kept.len().saturating_sub(max_entries);
+    kept.drain(..excess);
+    kept
+}
+
+/// Entries finished within `since` of `now` and, if given, of `technique`.
+pub fn query(
+    entries: Vec<HistoryEntry>,
+    now: DateTime<Local>,
+    since: Option<Duration>,
+    technique: Option<&str>,
+) -> Vec<HistoryEntry> {
+    entries
+        .into_iter()
+        .filter(|entry| technique.is_none_or(|technique| entry.technique_id == technique))
+        .filter(|entry| match since {
+            Some(since) => finished_at(entry).is_some_and(|time| {
+                now.signed_duration_since(time)
+                    .to_std()
+                    .map_or(true, |age| age <= since)
+            }),
+            None => true,
+        })
+        .collect()
+}
+
+/// Parses an age such as `90s`, `30m`, `24h` or `7d`.
+pub fn parse_age(age: &str) -> Result<Duration> {
+    let age = age.trim();
+    let unit = age.chars().last().unwrap_or_default();
+    let secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 24 * 3600,
+        _ => bail!("invalid age {:?}, expected e.g. 90s, 30m, 24h or 7d", age),
+    };
+    let number: u64 = age[..age.len() - unit.len_utf8()]
+        .parse()
+        .with_context(|| format!("invalid age {:?}", age))?;
+    Ok(Duration::from_secs(number.saturating_mul(secs)))
+}
+
+/// One line per entry, for the terminal.
+pub fn render(entries: &[HistoryEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| {
+            let mut line = format!(
+                "{}  {:<10} {:<9} {}",
+                entry.timestamp, entry.technique_id, entry.status, entry.task_id
+            );
+            if let Some(exit_code) = entry.exit_code {
+                line.push_str(&format!("  exit={}", exit_code));
+            }
+            if let Some(duration_ms) = entry.duration_ms {
+                line.push_str(&format!("  {}ms", duration_ms));
+            }
+            if let Some(command) = &entry.command {
+                line.push_str(&format!("  {}", command));
+            }
+            line + "\n"
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(task_id: &str, technique_id: &str, finished: DateTime<Local>) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: finished.to_rfc3339(),
+            task_id: task_id.to_string(),
+            technique_id: technique_id.to_string(),
+            status: "success".to_string(),
+            duration_ms: Some(12),
+            exit_code: Some(0),
+            command: None,
+        }
+    }
+
+    #[test]
+    fn test_query_filters_by_age_and_technique() {
+        let now = Local::now();
+        let entries = vec![
+            entry("old", "T1059", now - chrono::Duration::hours(30)),
+            entry("recent", "T1059", now - chrono::Duration::hours(2)),
+            entry("other", "T1082", now - chrono::Duration::minutes(5)),
+        ];
+
+        let ids = |entries: Vec<HistoryEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.task_id).collect()
+        };
+        let day = Some(Duration::from_secs(24 * 3600));
+        assert_eq!(
+            ids(query(entries.clone(), now, day, Some("T1059"))),
+            ["recent"]
+        );
+        assert_eq!(
+            ids(query(entries.clone(), now, day, None)),
+            ["recent", "other"]
+        );
+        assert_eq!(
+            ids(query(entries, now, None, Some("T1059"))),
+            ["old", "recent"]
+        );
+    }
+
+    #[test]
+    fn test_retention_pruning() {
+        let dir = std::env::temp_dir().join(format!("autostrike_history_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.jsonl");
+        let history = History::from_config(&HistoryConfig {
+            path: Some(path.clone()),
+            max_entries: 2,
+            max_age_secs: 3600,
+            record_commands: false,
+        })
+        .unwrap();
+        let now = Local::now();
+
+        history
+            .record(entry("expired", "T1059", now - chrono::Duration::hours(2)))
+            .unwrap();
+        assert!(read(&path).unwrap().is_empty());
+        for id in ["first", "second", "third"] {
+            history.record(entry(id, "T1059", now)).unwrap();
+        }
+        std::fs::write(
+            &path,
+            std::fs::read_to_string(&path).unwrap() + "not json\n",
+        )
+        .unwrap();
+
+        let ids: Vec<String> = read(&path)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.task_id)
+            .collect();
+        assert_eq!(ids, ["second", "third"]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("24h").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_age("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_age("7d").unwrap(), Duration::from_secs(7 * 86400));
+        assert_eq!(parse_age("90s").unwrap(), Duration::from_secs(90));
+        assert!(parse_age("24").is_err());
+        assert!(parse_age("h").is_err());
+        assert!(parse_age("").is_err());
+        assert!(parse_age("5é").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! De-duplication of repeated warnings, so a server sending the same bad
+//! frame every second does not flood the log and rotate away its history.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long repeats of a warning are suppressed after it is logged.
+pub const WINDOW: Duration = Duration::from_secs(60);
+
+/// Distinct warnings tracked at once; past it, warnings are logged as they come.
+const MAX_TRACKED: usize = 1024;
+
+/// A warning logged within the current window.
+struct Seen {
+    template: &'static str,
+    opened: Instant,
+    suppressed: u64,
+}
+
+/// What to log for one occurrence of a warning.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// Whether this occurrence is logged; repeats within the window are not.
+    pub log: bool,
+    /// Summaries of the windows closed since the last report.
+    pub summaries: Vec<String>,
+}
+
+/// Warnings logged recently, keyed by template and content. Time is passed
+/// in by the caller.
+pub struct LogDedup {
+    window: Duration,
+    seen: Mutex<HashMap<u64, Seen>>,
+}
+
+impl LogDedup {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records an occurrence at `now` of the warning `template` about
+    /// `detail` (its dynamic content), and tells whether to log it.
+    pub fn record(&self, template: &'static str, detail: &str, now: Instant) -> Report {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        let summaries = close_windows(&mut seen, now, self.window);
+        let key = key(template, detail);
+        let log = match seen.get_mut(&key) {
+            Some(entry) => {
+                entry.suppressed += 1;
+                false
+            }
+            None => {
+                if seen.len() < MAX_TRACKED {
+                    seen.insert(
+                        key,
+                        Seen {
+                            template,
+                            opened: now,
+                            suppressed: 0,
+                        },
+                    );
+                }
+                true
+            }
+        };
+        Report { log, summaries }
+    }
+
+    /// Summaries of the windows closed by `now`, for repeats that stopped.
+    pub fn flush(&self, now: Instant) -> Vec<String> {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        close_windows(&mut seen, now, self.window)
+    }
+}
+
+fn key(template: &str, detail: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    template.hash(&mut hasher);
+    detail.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Forgets the warnings whose window ended by `now`, summarizing those that
+/// were repeated.
+fn close_windows(seen: &mut HashMap<u64, Seen>, now: Instant, window: Duration) -> Vec<String> {
+    let mut summaries = Vec::new();
+    seen.retain(|_, entry| {
+        if now.saturating_duration_since(entry.opened) < window {
+            return true;
+        }
+        if entry.suppressed > 0 {
+            summaries.push(format!(
+                "{}: {} identical warning(s) suppressed in the last {}s",
+                entry.template,
+                entry.suppressed,
+                window.as_secs()
+            ));
+        }
+        false
+    });
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_warnings_logged_once_then_summarized() {
+        let dedup = LogDedup::new(WINDOW);
+        let start = Instant::now();
+
+        let logged = (0..10)
+            .map(|i| {
+                dedup.record(
+                    "Failed to parse message",
+                    "expected value at line 1",
+                    start + Duration::from_secs(i),
+                )
+            })
+            .filter(|report| report.log)
+            .count();
+        assert_eq!(logged, 1);
+        assert!(dedup.flush(start + Duration::from_secs(30)).is_empty());
+
+        let summaries = dedup.flush(start + WINDOW);
+        assert_eq!(
+            summaries,
+            ["Failed to parse message: 9 identical warning(s) suppressed in the last 60s"]
+        );
+        // The window closed, so the next occurrence is logged again
+        assert!(
+            dedup
+                .record(
+                    "Failed to parse message",
+                    "expected value at line 1",
+                    start + WINDOW
+                )
+                .log
+        );
+    }
+
+    #[test]
+    fn test_distinct_warnings_logged() {
+        let dedup = LogDedup::new(WINDOW);
+        let now = Instant::now();
+
+        assert!(dedup.record("Unknown message type", "foo", now).log);
+        assert!(dedup.record("Unknown message type", "bar", now).log);
+        assert!(dedup.record("Failed to parse message", "foo", now).log);
+        assert!(!dedup.record("Unknown message type", "foo", now).log);
+
+        // A warning logged once has nothing to summarize
+        let report = dedup.record("Unknown message type", "baz", now + WINDOW);
+        assert!(report.log);
+        assert_eq!(
+            report.summaries,
+            ["Unknown message type: 1 identical warning(s) suppressed in the last 60s"]
+        );
+    }
+}
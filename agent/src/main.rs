@@ -3,18 +3,56 @@
 //! This agent connects to the AutoStrike server via WebSocket and executes
 //! MITRE ATT&CK techniques for security testing purposes.
 
+mod capabilities;
 mod client;
+mod collect;
 mod config;
+mod disk;
+mod error;
+mod eventlog;
+mod exec_context;
 mod executor;
+mod features;
+mod file_read;
+mod group_queue;
+mod health;
+mod helper;
+mod history;
+mod log_dedup;
+mod netns;
+mod outbox;
+mod patterns;
+mod permissions;
+mod protocol;
+mod reaper;
+mod registry;
+mod resolve;
+mod result_endpoint;
+mod secret;
+#[cfg(unix)]
+mod signals;
+mod support;
+mod syslog;
 mod system;
+mod task_dir;
+mod task_tracker;
+mod technique;
+mod technique_lock;
+mod telemetry;
+mod tls;
+mod update;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 use anyhow::Result;
-use clap::Parser;
-use tracing::{error, info};
+use clap::{Parser, Subcommand, ValueEnum};
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use client::AgentClient;
 use config::AgentConfig;
+use error::AgentError;
 use system::SystemInfo;
 
 /// Command-line arguments for the AutoStrike agent.
@@ -41,24 +79,191 @@ struct Args {
     /// Agent authentication secret (X-Agent-Key header)
     #[arg(short = 'k', long)]
     agent_secret: Option<String>,
+
+    /// Log every raw WebSocket frame at TRACE level (agent secret redacted)
+    #[arg(long)]
+    trace_frames: bool,
+
+    /// Print version
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// With --version, also print the target, cargo features and TLS backend
+    #[arg(long, requires = "version")]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Commands run instead of connecting to the server.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the JSON Schemas of the protocol messages
+    Schema {
+        /// Only print the schema of this message type (e.g. task, task_result)
+        #[arg(long)]
+        message: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = SchemaFormat::Json)]
+        format: SchemaFormat,
+    },
+    /// List the tasks this agent ran, from its local history (`history.path`)
+    History {
+        /// Only tasks finished within this age (e.g. 90s, 30m, 24h, 7d)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Only tasks of this technique (e.g. T1059)
+        #[arg(long)]
+        technique: Option<String>,
+
+        /// Print the entries as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a support bundle (sanitized config, system and build info)
+    SupportBundle {
+        /// Output tarball path
+        #[arg(long, default_value = "support-bundle.tar.gz")]
+        output: PathBuf,
+    },
+}
+
+/// Output format of the `schema` command.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SchemaFormat {
+    Json,
+    Yaml,
+}
+
+/// Prints the requested protocol schemas to stdout.
+fn print_schema(message: Option<&str>, format: SchemaFormat) -> Result<()> {
+    let document = protocol::schema_document(message)?;
+    match format {
+        SchemaFormat::Json => println!("{}", serde_json::to_string_pretty(&document)?),
+        SchemaFormat::Yaml => print!("{}", serde_yaml::to_string(&document)?),
+    }
+    Ok(())
+}
+
+/// Prints the local task history, filtered by age and technique.
+fn print_history(
+    config: &AgentConfig,
+    since: Option<&str>,
+    technique: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let Some(path) = &config.history.path else {
+        anyhow::bail!("no task history is kept: history.path is not set");
+    };
+    let since = since.map(history::parse_age).transpose()?;
+    let entries = history::query(history::read(path)?, chrono::Local::now(), since, technique);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        print!("{}", history::render(&entries));
+    }
+    Ok(())
+}
+
+/// Log filter at `level`, keeping raw frames at TRACE when `log_frames` is set.
+fn log_filter(level: &str, log_frames: bool) -> String {
+    if log_frames {
+        format!("{},{}=trace", level, client::FRAME_LOG_TARGET)
+    } else {
+        level.to_string()
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+async fn main() -> ExitCode {
+    match run(Args::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(error::exit_code(&e))
+        }
+    }
+}
 
-    // Initialize logging
-    let log_level = if args.debug { "debug" } else { "info" };
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(log_level))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+/// Runs the command of `args`, the agent itself by default. Startup and
+/// connection failures come back as an [`AgentError`], for the exit code.
+async fn run(args: Args) -> Result<()> {
+    if args.version {
+        let info = features::build_info();
+        if args.verbose {
+            println!("{}", features::describe(&info));
+        } else {
+            println!("autostrike-agent {}", info.version);
+        }
+        return Ok(());
+    }
+    if let Some(Command::Schema { message, format }) = &args.command {
+        return print_schema(message.as_deref(), *format);
+    }
 
-    info!("AutoStrike Agent starting...");
+    // Load configuration (before logging, which depends on the trace export settings)
+    let mut config = AgentConfig::load(&args.config, &args.server, args.paw, args.agent_secret)?;
+    config.log_frames |= args.trace_frames;
+
+    if let Some(Command::History {
+        since,
+        technique,
+        json,
+    }) = &args.command
+    {
+        return print_history(&config, since.as_deref(), technique.as_deref(), *json);
+    }
 
-    // Load configuration
-    let config = AgentConfig::load(&args.config, &args.server, args.paw, args.agent_secret)?;
+    if let Some(Command::SupportBundle { output }) = &args.command {
+        support::write_bundle(output, &config, &SystemInfo::gather()).await?;
+        println!("Support bundle written to {}", output.display());
+        return Ok(());
+    }
+
+    // Initialize logging, with a filter SIGUSR2 can switch to debug level and back
+    let normal_filter = log_filter("info", config.log_frames);
+    let debug_filter = log_filter("debug", config.log_frames);
+    let (filter, filter_handle) =
+        tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::new(if args.debug {
+            &debug_filter
+        } else {
+            &normal_filter
+        }));
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer());
+    #[cfg(feature = "otel")]
+    let (otel_layer, _otel_guard) = telemetry::layer(&config.otel)?.unzip();
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel_layer);
+    registry.init();
+
+    info!("AutoStrike Agent starting...");
+    #[cfg(unix)]
+    signals::spawn_debug_toggle(signals::DebugToggle::new(
+        filter_handle,
+        normal_filter,
+        debug_filter,
+        args.debug,
+    ))?;
+    #[cfg(not(unix))]
+    drop((filter_handle, normal_filter, debug_filter));
     info!("Configuration loaded");
+    // Keep a typo from making the agent flood the server or look dead
+    config.check_durations()?;
+
+    // Refuse (or warn about) files other local users could tamper with
+    permissions::enforce(
+        &permissions::agent_paths(&args.config, &config),
+        config.strict_permissions,
+    )
+    .map_err(AgentError::Config)?;
+    // Commands, task directories and their files live in the system temp directory
+    disk::check_writable(&std::env::temp_dir(), config.require_scratch_writable)
+        .map_err(AgentError::Io)?;
 
     // Gather system information
     let sys_info = SystemInfo::gather();
@@ -67,13 +272,28 @@ async fn main() -> Result<()> {
         platform = %sys_info.platform,
         "System information gathered"
     );
+    if sys_info.is_wow64 {
+        if config.prefer_native_shell {
+            info!("32-bit agent on 64-bit Windows, launching the native shells via Sysnative");
+        } else {
+            warn!(
+                "32-bit agent on 64-bit Windows: shells run under WOW64 redirection \
+                 (set prefer_native_shell to launch the 64-bit ones)"
+            );
+        }
+    }
 
     // Create and run agent client
-    let mut client = AgentClient::new(config, sys_info)?;
+    let mut client = AgentClient::new(config, sys_info).map_err(AgentError::Config)?;
+    #[cfg(unix)]
+    signals::spawn_status_dump({
+        let client = client.clone();
+        move || client.metrics()
+    })?;
 
     if let Err(e) = client.run().await {
         error!("Agent error: {}", e);
-        return Err(e);
+        return Err(e.into());
     }
 
     Ok(())
@@ -92,6 +312,31 @@ mod tests {
         assert_eq!(args.config, "agent.yaml");
         assert!(!args.debug);
         assert!(args.agent_secret.is_none());
+        assert!(args.command.is_none());
+        assert!(!args.trace_frames);
+        assert!(!args.version);
+        assert!(!args.verbose);
+    }
+
+    #[test]
+    fn test_args_version_verbose() {
+        let args = Args::try_parse_from(["autostrike-agent", "--version", "--verbose"]).unwrap();
+        assert!(args.version);
+        assert!(args.verbose);
+
+        let args = Args::try_parse_from(["autostrike-agent", "-V"]).unwrap();
+        assert!(args.version);
+        assert!(!args.verbose);
+
+        // --verbose only describes the version
+        assert!(Args::try_parse_from(["autostrike-agent", "--verbose"]).is_err());
+    }
+
+    #[test]
+    fn test_args_with_trace_frames() {
+        let args = Args::try_parse_from(["autostrike-agent", "--trace-frames"]).unwrap();
+
+        assert!(args.trace_frames);
     }
 
     #[test]
@@ -202,6 +447,87 @@ mod tests {
         assert!(debug_str.contains("agent_secret"));
     }
 
+    #[test]
+    fn test_args_schema_subcommand() {
+        let args = Args::try_parse_from(["autostrike-agent", "schema"]).unwrap();
+        match args.command {
+            Some(Command::Schema { message, format }) => {
+                assert!(message.is_none());
+                assert_eq!(format, SchemaFormat::Json);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        let args = Args::try_parse_from([
+            "autostrike-agent",
+            "schema",
+            "--message",
+            "task",
+            "--format",
+            "yaml",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::Schema { message, format }) => {
+                assert_eq!(message.as_deref(), Some("task"));
+                assert_eq!(format, SchemaFormat::Yaml);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_args_support_bundle_subcommand() {
+        let args = Args::try_parse_from([
+            "autostrike-agent",
+            "-c",
+            "/etc/agent.yaml",
+            "support-bundle",
+            "--output",
+            "/tmp/bundle.tar.gz",
+        ])
+        .unwrap();
+
+        assert_eq!(args.config, "/etc/agent.yaml");
+        match args.command {
+            Some(Command::SupportBundle { output }) => {
+                assert_eq!(output, PathBuf::from("/tmp/bundle.tar.gz"));
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_args_history_subcommand() {
+        let args = Args::try_parse_from([
+            "autostrike-agent",
+            "history",
+            "--since",
+            "24h",
+            "--technique",
+            "T1059",
+            "--json",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::History {
+                since,
+                technique,
+                json,
+            }) => {
+                assert_eq!(since.as_deref(), Some("24h"));
+                assert_eq!(technique.as_deref(), Some("T1059"));
+                assert!(json);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_args_schema_rejects_unknown_format() {
+        assert!(Args::try_parse_from(["autostrike-agent", "schema", "--format", "xml"]).is_err());
+    }
+
     #[test]
     fn test_log_level_selection() {
         let debug_mode = true;
@@ -0,0 +1,118 @@
+//! Linux network namespaces for task commands, so a technique can run from
+//! the network position of a namespace created with `ip netns add`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+/// Directory where `ip netns` keeps its named namespaces.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const NETNS_DIR: &str = "/var/run/netns";
+
+/// `CAP_SYS_ADMIN`, required to switch namespaces.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const CAP_SYS_ADMIN: u32 = 21;
+
+/// Path of the namespace `ip netns` knows as `name`, rejecting names that
+/// would point outside its directory.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub fn netns_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        bail!("invalid network namespace name {:?}", name);
+    }
+    Ok(Path::new(NETNS_DIR).join(name))
+}
+
+/// Fails unless `effective` lets the agent enter namespace `name`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn require_privilege(effective: crate::capabilities::CapabilitySet, name: &str) -> Result<()> {
+    if !effective.contains(CAP_SYS_ADMIN) {
+        bail!(
+            "cannot enter network namespace {:?}: the agent lacks CAP_SYS_ADMIN",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Makes `cmd` start inside the network namespace `name`, as
+/// `ip netns exec` would, through `setns` between fork and exec. Unlike
+/// `ip netns exec`, `/etc/netns/<name>` is not bind-mounted over `/etc`.
+///
+/// Fails when the namespace does not exist or the agent lacks `CAP_SYS_ADMIN`.
+#[cfg(target_os = "linux")]
+pub fn enter(cmd: &mut tokio::process::Command, name: &str) -> Result<()> {
+    use anyhow::Context;
+    use nix::libc;
+    use std::os::fd::AsRawFd;
+
+    let path = netns_path(name)?;
+    if !path.exists() {
+        bail!("network namespace {:?} does not exist", name);
+    }
+    require_privilege(crate::capabilities::effective_capabilities()?, name)?;
+    // Opened close-on-exec here, so the hook only has to hand it to setns
+    let file = std::fs::File::open(&path)
+        .with_context(|| format!("cannot open network namespace {:?}", name))?;
+
+    // SAFETY: the hook runs between fork and exec and only makes a system
+    // call on a descriptor opened beforehand.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Network namespaces only exist on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn enter(_cmd: &mut tokio::process::Command, name: &str) -> Result<()> {
+    bail!(
+        "cannot enter network namespace {:?}: network namespaces are only supported on Linux",
+        name
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::CapabilitySet;
+
+    #[test]
+    fn test_netns_path() {
+        assert_eq!(
+            netns_path("red").unwrap(),
+            PathBuf::from("/var/run/netns/red")
+        );
+        for name in ["", ".", "..", "../../proc/1/ns/net", "a/b"] {
+            assert!(netns_path(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_entering_requires_sys_admin() {
+        let err = require_privilege(CapabilitySet::default(), "red").unwrap_err();
+        assert!(err.to_string().contains("lacks CAP_SYS_ADMIN"));
+
+        let admin = CapabilitySet::parse(&["CAP_SYS_ADMIN".to_string()]).unwrap();
+        assert!(require_privilege(admin, "red").is_ok());
+    }
+
+    #[test]
+    fn test_enter_missing_namespace() {
+        let mut cmd = tokio::process::Command::new("true");
+        let err = enter(&mut cmd, "autostrike-no-such-namespace").unwrap_err();
+        #[cfg(target_os = "linux")]
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+        #[cfg(not(target_os = "linux"))]
+        assert!(
+            err.to_string().contains("only supported on Linux"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Outbound message queues: control messages (heartbeats, pongs, replies to
+//! server requests) go out ahead of bulk task results.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TrySendError},
+};
+
+/// Control messages sent in a row while bulk ones wait, before one bulk
+/// message is let through.
+const CONTROL_BURST: usize = 8;
+
+/// Sending half, cloned by every producer of outbound messages.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    control: mpsc::Sender<String>,
+    /// Bulk messages with the time they were queued.
+    bulk: mpsc::Sender<(Instant, String)>,
+    /// When a message other than a heartbeat was last queued.
+    last_activity: Arc<Mutex<Option<Instant>>>,
+    /// When each bulk message still waiting was queued, oldest first.
+    bulk_queued_at: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+/// Receiving half, drained by the connection's write loop.
+#[derive(Debug)]
+pub struct OutboxReceiver {
+    control: mpsc::Receiver<String>,
+    bulk: mpsc::Receiver<(Instant, String)>,
+    /// Bulk messages taken off the queue by [`Self::expire_bulk`] and not
+    /// sent yet, served before the queue.
+    held: VecDeque<(Instant, String)>,
+    control_streak: usize,
+    bulk_queued_at: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl Outbox {
+    /// Creates both queues, each holding up to `capacity` messages.
+    pub fn channel(capacity: usize) -> (Outbox, OutboxReceiver) {
+        let (control_tx, control_rx) = mpsc::channel(capacity);
+        let (bulk_tx, bulk_rx) = mpsc::channel(capacity);
+        let bulk_queued_at = Arc::<Mutex<VecDeque<Instant>>>::default();
+        (
+            Outbox {
+                control: control_tx,
+                bulk: bulk_tx,
+                last_activity: Arc::default(),
+                bulk_queued_at: bulk_queued_at.clone(),
+            },
+            OutboxReceiver {
+                control: control_rx,
+                bulk: bulk_rx,
+                held: VecDeque::new(),
+                control_streak: 0,
+                bulk_queued_at,
+            },
+        )
+    }
+
+    /// Queues a control message (pong, reply to a server request).
+    pub async fn control(&self, msg: String) -> Result<(), SendError<String>> {
+        self.touch();
+        self.control.send(msg).await
+    }
+
+    /// Queues a heartbeat, as a control message that does not count as
+    /// activity.
+    pub async fn heartbeat(&self, msg: String) -> Result<(), SendError<String>> {
+        self.control.send(msg).await
+    }
+
+    /// Queues a bulk message (task result).
+    pub async fn bulk(&self, msg: String) -> Result<(), SendError<String>> {
+        self.bulk_at(msg, Instant::now()).await
+    }
+
+    /// Queues a bulk message stamped as queued at `queued_at`.
+    async fn bulk_at(&self, msg: String, queued_at: Instant) -> Result<(), SendError<String>> {
+        self.touch();
+        lock(&self.bulk_queued_at).push_back(queued_at);
+        let sent = self.bulk.send((queued_at, msg)).await;
+        if sent.is_err() {
+            lock(&self.bulk_queued_at).pop_back();
+        }
+        sent.map_err(|SendError((_, msg))| SendError(msg))
+    }
+
+    /// Queues a bulk message as if it had been waiting for `age`.
+    #[cfg(test)]
+    pub async fn bulk_aged(&self, msg: String, age: Duration) {
+        self.bulk_at(msg, Instant::now() - age).await.unwrap();
+    }
+
+    /// Queues a bulk message without waiting, for callers that must not
+    /// block the connection's read loop, which also drains the queue. When
+    /// the queue is full, a task waits for room and queues it then; fails
+    /// only when the queue is closed.
+    pub fn bulk_nowait(&self, msg: String) -> Result<(), SendError<String>> {
+        self.touch();
+        let queued_at = Instant::now();
+        lock(&self.bulk_queued_at).push_back(queued_at);
+        match self.bulk.try_send((queued_at, msg)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed((_, msg))) => {
+                lock(&self.bulk_queued_at).pop_back();
+                Err(SendError(msg))
+            }
+            Err(TrySendError::Full((_, msg))) => {
+                lock(&self.bulk_queued_at).pop_back();
+                let outbox = self.clone();
+                tokio::spawn(async move {
+                    let _ = outbox.bulk_at(msg, queued_at).await;
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Bulk messages queued and not yet taken by the write loop.
+    pub fn bulk_queued(&self) -> usize {
+        self.bulk.max_capacity() - self.bulk.capacity()
+    }
+
+    /// How long the oldest bulk message not yet taken by the write loop has
+    /// been waiting, including one blocked on a full queue.
+    pub fn oldest_bulk_age(&self) -> Option<Duration> {
+        lock(&self.bulk_queued_at).front().map(Instant::elapsed)
+    }
+
+    /// When a message other than a heartbeat was last queued, if ever.
+    pub fn last_activity(&self) -> Option<Instant> {
+        *lock(&self.last_activity)
+    }
+
+    fn touch(&self) {
+        *lock(&self.last_activity) = Some(Instant::now());
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl OutboxReceiver {
+    /// Waits for the next message to send, control first. Cancel-safe.
+    pub async fn recv(&mut self) -> Option<String> {
+        if self.control_streak >= CONTROL_BURST {
+            if let Some(msg) = self.try_bulk() {
+                self.control_streak = 0;
+                return Some(msg);
+            }
+        }
+        if !self.held.is_empty() {
+            if let Ok(msg) = self.control.try_recv() {
+                self.control_streak += 1;
+                return Some(msg);
+            }
+            self.control_streak = 0;
+            return self.try_bulk();
+        }
+        tokio::select! {
+            biased;
+            Some(msg) = self.control.recv() => {
+                self.control_streak += 1;
+                Some(msg)
+            }
+            Some((_, msg)) = self.bulk.recv() => {
+                self.control_streak = 0;
+                lock(&self.bulk_queued_at).pop_front();
+                Some(msg)
+            }
+            else => None,
+        }
+    }
+
+    /// Drops the bulk messages queued more than `ttl` ago and returns them,
+    /// oldest first.
+    pub fn expire_bulk(&mut self, ttl: Duration) -> Vec<String> {
+        while let Ok(entry) = self.bulk.try_recv() {
+            self.held.push_back(entry);
+        }
+        let (expired, fresh) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|(queued_at, _)| queued_at.elapsed() > ttl);
+        self.held = fresh;
+        let mut queued_at = lock(&self.bulk_queued_at);
+        expired
+            .into_iter()
+            .map(|(at, msg)| {
+                if let Some(index) = queued_at.iter().position(|&queued| queued == at) {
+                    queued_at.remove(index);
+                }
+                msg
+            })
+            .collect()
+    }
+
+    /// Takes the next bulk message without waiting.
+    fn try_bulk(&mut self) -> Option<String> {
+        let (_, msg) = self
+            .held
+            .pop_front()
+            .or_else(|| self.bulk.try_recv().ok())?;
+        lock(&self.bulk_queued_at).pop_front();
+        Some(msg)
+    }
+
+    /// Discards the queued control messages, which answer or report to a
+    /// connection that is gone, and returns how many there were.
+    pub fn drop_control(&mut self) -> usize {
+        let mut dropped = 0;
+        while self.control.try_recv().is_ok() {
+            dropped += 1;
+        }
+        self.control_streak = 0;
+        dropped
+    }
+
+    /// Takes the next message without waiting, control first.
+    #[cfg(test)]
+    pub fn try_recv(&mut self) -> Result<String, mpsc::error::TryRecvError> {
+        self.control
+            .try_recv()
+            .or_else(|_| self.try_bulk().ok_or(mpsc::error::TryRecvError::Empty))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_control_overtakes_queued_bulk() {
+        let (outbox, mut rx) = Outbox::channel(64);
+        for i in 0..20 {
+            outbox.bulk(format!("result-{}", i)).await.unwrap();
+        }
+        outbox.control("heartbeat".to_string()).await.unwrap();
+
+        assert_eq!(rx.recv().await.unwrap(), "heartbeat");
+        assert_eq!(rx.recv().await.unwrap(), "result-0");
+    }
+
+    #[tokio::test]
+    async fn test_bulk_progresses_under_control_load() {
+        let (outbox, mut rx) = Outbox::channel(64);
+        for i in 0..3 {
+            outbox.bulk(format!("result-{}", i)).await.unwrap();
+        }
+        for i in 0..20 {
+            outbox.control(format!("pong-{}", i)).await.unwrap();
+        }
+
+        let mut order = Vec::new();
+        while let Ok(Some(msg)) =
+            tokio::time::timeout(std::time::Duration::from_millis(50), rx.recv()).await
+        {
+            order.push(msg);
+        }
+
+        assert_eq!(order.len(), 23);
+        assert_eq!(order[CONTROL_BURST], "result-0");
+        assert_eq!(order[2 * CONTROL_BURST + 1], "result-1");
+        let pongs: Vec<&String> = order.iter().filter(|m| m.starts_with("pong")).collect();
+        assert_eq!(pongs[0], "pong-0");
+        assert_eq!(pongs[19], "pong-19");
+    }
+
+    #[tokio::test]
+    async fn test_bulk_nowait_does_not_wait_for_room() {
+        let (outbox, mut rx) = Outbox::channel(1);
+        outbox.bulk_nowait("result-0".to_string()).unwrap();
+        // Full: queued once the first one is taken
+        outbox.bulk_nowait("result-1".to_string()).unwrap();
+
+        assert_eq!(rx.recv().await.unwrap(), "result-0");
+        assert_eq!(rx.recv().await.unwrap(), "result-1");
+
+        drop(rx);
+        assert!(outbox.bulk_nowait("result-2".to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expire_bulk_keeps_fresh_messages_in_order() {
+        let (outbox, mut rx) = Outbox::channel(8);
+        outbox
+            .bulk_aged("old-0".to_string(), Duration::from_secs(120))
+            .await;
+        outbox.bulk("fresh-0".to_string()).await.unwrap();
+        outbox
+            .bulk_aged("old-1".to_string(), Duration::from_secs(90))
+            .await;
+        outbox.bulk("fresh-1".to_string()).await.unwrap();
+
+        assert_eq!(rx.expire_bulk(Duration::from_secs(60)), ["old-0", "old-1"]);
+        assert!(outbox.oldest_bulk_age().unwrap() < Duration::from_secs(60));
+        outbox.control("heartbeat".to_string()).await.unwrap();
+        outbox.bulk("fresh-2".to_string()).await.unwrap();
+
+        assert_eq!(rx.recv().await.unwrap(), "heartbeat");
+        assert_eq!(rx.recv().await.unwrap(), "fresh-0");
+        assert_eq!(rx.recv().await.unwrap(), "fresh-1");
+        assert_eq!(rx.recv().await.unwrap(), "fresh-2");
+        assert!(outbox.oldest_bulk_age().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_do_not_count_as_activity() {
+        let (outbox, _rx) = Outbox::channel(4);
+        outbox.heartbeat("heartbeat".to_string()).await.unwrap();
+        assert!(outbox.last_activity().is_none());
+
+        let before = Instant::now();
+        outbox.clone().bulk("result".to_string()).await.unwrap();
+        assert!(outbox.last_activity().unwrap() >= before);
+    }
+
+    #[tokio::test]
+    async fn test_oldest_bulk_age() {
+        let (outbox, mut rx) = Outbox::channel(4);
+        outbox.control("pong".to_string()).await.unwrap();
+        assert!(outbox.oldest_bulk_age().is_none());
+
+        outbox.bulk("result-0".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        outbox.bulk("result-1".to_string()).await.unwrap();
+        assert!(outbox.oldest_bulk_age().unwrap() >= Duration::from_millis(20));
+
+        assert_eq!(rx.recv().await.unwrap(), "pong");
+        assert!(outbox.oldest_bulk_age().unwrap() >= Duration::from_millis(20));
+        assert_eq!(rx.recv().await.unwrap(), "result-0");
+        assert!(outbox.oldest_bulk_age().unwrap() < Duration::from_millis(20));
+        assert_eq!(rx.recv().await.unwrap(), "result-1");
+        assert!(outbox.oldest_bulk_age().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_drop_control_keeps_bulk() {
+        let (outbox, mut rx) = Outbox::channel(4);
+        outbox.heartbeat("heartbeat".to_string()).await.unwrap();
+        outbox.bulk("result".to_string()).await.unwrap();
+        outbox.control("pong".to_string()).await.unwrap();
+
+        assert_eq!(rx.drop_control(), 2);
+        assert_eq!(rx.recv().await.unwrap(), "result");
+        assert!(outbox.oldest_bulk_age().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_recv_ends_when_outbox_dropped() {
+        let (outbox, mut rx) = Outbox::channel(4);
+        outbox.bulk("result".to_string()).await.unwrap();
+        drop(outbox);
+
+        assert_eq!(rx.recv().await.unwrap(), "result");
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,145 @@
+//! Compilation of patterns supplied by the server or the config. Every
+//! feature accepting a regex goes through here, so a hostile pattern is
+//! bounded in length, compiled size and compile time, and compiled once.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use regex::{Regex, RegexBuilder};
+
+/// Longest pattern accepted, in bytes.
+pub const MAX_PATTERN_LEN: usize = 4096;
+
+/// Largest compiled program accepted for a regex.
+const SIZE_LIMIT: usize = 1 << 20;
+
+/// Largest cache the lazy DFA may build for a regex.
+const DFA_SIZE_LIMIT: usize = 1 << 20;
+
+/// How long a regex may take to compile.
+const COMPILE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Compiled patterns kept before the cache is emptied.
+const MAX_CACHED: usize = 256;
+
+/// Compiled regexes keyed by a hash of their pattern.
+pub struct PatternCache {
+    entries: Mutex<HashMap<u64, (String, Regex)>>,
+    hits: AtomicU64,
+}
+
+impl PatternCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Compiles `pattern`, or returns the regex compiled by an earlier call.
+    pub async fn regex(&self, pattern: &str) -> Result<Regex> {
+        let key = hash(pattern);
+        if let Some((cached, regex)) = self.entries().get(&key) {
+            if cached == pattern {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(regex.clone());
+            }
+        }
+
+        let regex = compile(pattern).await?;
+        let mut entries = self.entries();
+        if entries.len() >= MAX_CACHED {
+            entries.clear();
+        }
+        entries.insert(key, (pattern.to_string(), regex.clone()));
+        Ok(regex)
+    }
+
+    /// Number of calls answered from the cache.
+    #[cfg(test)]
+    fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<u64, (String, Regex)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Compiles `pattern` through the agent-wide cache.
+pub async fn regex(pattern: &str) -> Result<Regex> {
+    static CACHE: OnceLock<PatternCache> = OnceLock::new();
+    CACHE.get_or_init(PatternCache::new).regex(pattern).await
+}
+
+fn hash(pattern: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    pattern.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Compiles `pattern` with the size limits on a blocking thread. A compile
+/// that outlives the timeout is abandoned: its thread finishes on its own,
+/// bounded by the size limits, but nobody waits for it.
+async fn compile(pattern: &str) -> Result<Regex> {
+    if pattern.len() > MAX_PATTERN_LEN {
+        bail!(
+            "pattern is {} bytes, more than the {} allowed",
+            pattern.len(),
+            MAX_PATTERN_LEN
+        );
+    }
+    let owned = pattern.to_string();
+    let build = tokio::task::spawn_blocking(move || {
+        RegexBuilder::new(&owned)
+            .size_limit(SIZE_LIMIT)
+            .dfa_size_limit(DFA_SIZE_LIMIT)
+            .build()
+    });
+    match tokio::time::timeout(COMPILE_TIMEOUT, build).await {
+        Ok(Ok(regex)) => Ok(regex?),
+        Ok(Err(e)) => Err(anyhow!("pattern compilation failed: {}", e)),
+        Err(_) => bail!(
+            "pattern took more than {}s to compile",
+            COMPILE_TIMEOUT.as_secs()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_catastrophic_pattern_rejected() {
+        // Nested counted repetitions expand into a huge program
+        let err = compile(r"(\w{1000}){1000}").await.unwrap_err();
+        assert!(err.to_string().contains("size limit"), "{}", err);
+
+        let err = compile(&"a".repeat(MAX_PATTERN_LEN + 1)).await.unwrap_err();
+        assert!(err.to_string().contains("allowed"), "{}", err);
+
+        assert!(compile("uid=(").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit() {
+        let cache = PatternCache::new();
+
+        let first = cache.regex(r"uid=\d+").await.unwrap();
+        assert_eq!(cache.hits(), 0);
+        let second = cache.regex(r"uid=\d+").await.unwrap();
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(first.as_str(), second.as_str());
+
+        cache.regex(r"gid=\d+").await.unwrap();
+        assert_eq!(cache.hits(), 1);
+        assert!(cache.regex("(").await.is_err());
+    }
+}
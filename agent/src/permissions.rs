@@ -0,0 +1,368 @@
+//! Startup checks that the files controlling the agent cannot be changed by
+//! other local users.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use tracing::warn;
+
+use crate::config::AgentConfig;
+
+/// A file the agent trusts, with the mode it is expected to have.
+pub struct TrustedPath {
+    /// Path to check; missing paths are skipped.
+    pub path: PathBuf,
+    /// Expected Unix mode, reported alongside an issue (e.g. `0o600`).
+    pub expected_mode: u32,
+}
+
+impl TrustedPath {
+    /// Describes a trusted path expected to have `expected_mode`.
+    pub fn new(path: impl Into<PathBuf>, expected_mode: u32) -> Self {
+        Self {
+            path: path.into(),
+            expected_mode,
+        }
+    }
+}
+
+/// A trusted path that another local user could modify.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PermissionIssue {
+    /// Offending path.
+    pub path: PathBuf,
+    /// What is wrong with it.
+    pub problem: String,
+    /// Mode the path should have.
+    pub expected_mode: u32,
+}
+
+impl fmt::Display for PermissionIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} (expected mode {:04o}, owned by the agent user or root)",
+            self.path.display(),
+            self.problem,
+            self.expected_mode
+        )
+    }
+}
+
+/// Lists the agent binary and its configuration file with their
+/// directories, where they could be swapped out, then the history file and
+/// the TLS files the configuration references, with the mode each should
+/// have.
+pub fn agent_paths(config_path: &str, config: &AgentConfig) -> Vec<TrustedPath> {
+    let mut paths = Vec::new();
+    let mut with_parent = |path: PathBuf, mode: u32| {
+        let parent = match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+            Some(parent) => parent,
+            None => Path::new("/"),
+        }
+        .to_path_buf();
+        paths.push(TrustedPath::new(path, mode));
+        if !paths.iter().any(|trusted| trusted.path == parent) {
+            paths.push(TrustedPath::new(parent, 0o755));
+        }
+    };
+    match std::env::current_exe() {
+        Ok(exe) => with_parent(exe, 0o755),
+        Err(e) => warn!("Cannot locate the agent binary: {}", e),
+    }
+    with_parent(PathBuf::from(config_path), 0o600);
+    if let Some(history) = &config.history.path {
+        paths.push(TrustedPath::new(history, 0o600));
+    }
+    if let Some(key_file) = &config.tls.key_file {
+        paths.push(TrustedPath::new(key_file, 0o600));
+    }
+    for file in [&config.tls.cert_file, &config.tls.ca_file]
+        .into_iter()
+        .flatten()
+    {
+        paths.push(TrustedPath::new(file, 0o644));
+    }
+    paths
+}
+
+/// Checks every existing trusted path and returns the issues found.
+pub fn check(paths: &[TrustedPath]) -> Vec<PermissionIssue> {
+    paths
+        .iter()
+        .filter(|trusted| trusted.path.exists())
+        .filter_map(|trusted| check_path(&trusted.path, trusted.expected_mode))
+        .collect()
+}
+
+/// Checks the trusted paths, failing on any issue when `strict` and logging
+/// a warning for each otherwise.
+pub fn enforce(paths: &[TrustedPath], strict: bool) -> Result<()> {
+    let issues = check(paths);
+    if issues.is_empty() {
+        return Ok(());
+    }
+    if strict {
+        let details: Vec<String> = issues.iter().map(ToString::to_string).collect();
+        bail!(
+            "refusing to start, other users can modify the agent: {}",
+            details.join("; ")
+        );
+    }
+    for issue in &issues {
+        warn!("Insecure permissions: {}", issue);
+    }
+    Ok(())
+}
+
+/// Flags a path writable by group/other or owned by another unprivileged
+/// user. A sticky directory such as `/tmp` may be world-writable: other
+/// users cannot replace the files in it they do not own.
+#[cfg(unix)]
+fn check_path(path: &Path, expected_mode: u32) -> Option<PermissionIssue> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            warn!("Cannot check permissions of {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let mode = metadata.mode() & 0o7777;
+    let euid = nix::unistd::geteuid().as_raw();
+
+    let sticky_dir = metadata.is_dir() && mode & 0o1000 != 0;
+    let problem = if mode & 0o022 != 0 && !sticky_dir {
+        format!("is writable by group/other (mode {:04o})", mode)
+    } else if metadata.uid() != euid && metadata.uid() != 0 {
+        format!(
+            "is owned by uid {}, not the agent user (uid {}) or root",
+            metadata.uid(),
+            euid
+        )
+    } else {
+        return None;
+    };
+
+    Some(PermissionIssue {
+        path: path.to_path_buf(),
+        problem,
+        expected_mode,
+    })
+}
+
+/// Flags a path whose DACL lets broad groups (Everyone, Users,
+/// Authenticated Users) write it. Ownership is not checked on Windows.
+#[cfg(windows)]
+fn check_path(path: &Path, expected_mode: u32) -> Option<PermissionIssue> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr::null_mut;
+    use winapi::shared::sddl::ConvertStringSidToSidW;
+    use winapi::shared::winerror::ERROR_SUCCESS;
+    use winapi::um::accctrl::{SE_FILE_OBJECT, TRUSTEE_W};
+    use winapi::um::aclapi::{
+        BuildTrusteeWithSidW, GetEffectiveRightsFromAclW, GetNamedSecurityInfoW,
+    };
+    use winapi::um::winbase::LocalFree;
+    use winapi::um::winnt::{
+        DACL_SECURITY_INFORMATION, FILE_APPEND_DATA, FILE_WRITE_DATA, PACL, PSECURITY_DESCRIPTOR,
+        PSID, WRITE_DAC, WRITE_OWNER,
+    };
+
+    /// Well-known groups that should never be able to modify agent files.
+    const BROAD_GROUPS: [(&str, &str); 3] = [
+        ("S-1-1-0", "Everyone"),
+        ("S-1-5-32-545", "Users"),
+        ("S-1-5-11", "Authenticated Users"),
+    ];
+    const WRITE_RIGHTS: u32 = FILE_WRITE_DATA | FILE_APPEND_DATA | WRITE_DAC | WRITE_OWNER;
+
+    let wide = |s: &std::ffi::OsStr| -> Vec<u16> { s.encode_wide().chain(Some(0)).collect() };
+    let path_w = wide(path.as_os_str());
+    let mut dacl: PACL = null_mut();
+    let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+    // SAFETY: `path_w` is NUL-terminated; the out pointers reference live
+    // locals, and `descriptor` is released with LocalFree below.
+    let status = unsafe {
+        GetNamedSecurityInfoW(
+            path_w.as_ptr() as *mut _,
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION,
+            null_mut(),
+            null_mut(),
+            &mut dacl,
+            null_mut(),
+            &mut descriptor,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        warn!(
+            "Cannot check permissions of {}: error {}",
+            path.display(),
+            status
+        );
+        return None;
+    }
+
+    let problem = if dacl.is_null() {
+        Some("has no access control list (writable by everyone)".to_string())
+    } else {
+        BROAD_GROUPS.iter().find_map(|(sid_string, name)| {
+            let sid_w = wide(std::ffi::OsStr::new(sid_string));
+            let mut sid: PSID = null_mut();
+            // SAFETY: `sid_w` is NUL-terminated; `sid` is freed with LocalFree.
+            if unsafe { ConvertStringSidToSidW(sid_w.as_ptr(), &mut sid) } == 0 {
+                return None;
+            }
+            let mut trustee: TRUSTEE_W = unsafe { std::mem::zeroed() };
+            let mut rights = 0;
+            // SAFETY: `trustee` borrows `sid`, which stays alive until LocalFree;
+            // `dacl` points into `descriptor`, still allocated here.
+            let status = unsafe {
+                BuildTrusteeWithSidW(&mut trustee, sid);
+                let status = GetEffectiveRightsFromAclW(dacl, &mut trustee, &mut rights);
+                LocalFree(sid as _);
+                status
+            };
+            (status == ERROR_SUCCESS && rights & WRITE_RIGHTS != 0)
+                .then(|| format!("is writable by {}", name))
+        })
+    };
+    // SAFETY: allocated by GetNamedSecurityInfoW and no longer referenced.
+    unsafe { LocalFree(descriptor as _) };
+
+    problem.map(|problem| PermissionIssue {
+        path: path.to_path_buf(),
+        problem,
+        expected_mode,
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn fixture(name: &str, mode: u32) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("autostrike_perm_{}_{}", name, std::process::id()));
+        std::fs::write(&path, "server_url: https://server:8443\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_check_accepts_private_file() {
+        let path = fixture("private", 0o600);
+
+        let issues = check(&[TrustedPath::new(&path, 0o600)]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn test_check_flags_world_writable_file() {
+        let path = fixture("world", 0o666);
+
+        let issues = check(&[TrustedPath::new(&path, 0o600)]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].problem.contains("writable by group/other"));
+        let message = issues[0].to_string();
+        assert!(message.contains(&path.display().to_string()));
+        assert!(message.contains("mode 0666"));
+        assert!(message.contains("expected mode 0600"));
+    }
+
+    #[test]
+    fn test_check_flags_group_writable_file() {
+        let path = fixture("group", 0o620);
+
+        let issues = check(&[TrustedPath::new(&path, 0o600)]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn test_check_skips_missing_path() {
+        let issues = check(&[TrustedPath::new("/nonexistent/agent.yaml", 0o600)]);
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn test_agent_paths_lists_config_and_tls_files() {
+        let mut config = AgentConfig::default();
+        config.tls.key_file = Some("/etc/agent/agent.key".to_string());
+        config.tls.ca_file = Some("/etc/agent/ca.crt".to_string());
+
+        let paths = agent_paths("agent.yaml", &config);
+        let find = |path: &str| paths.iter().find(|p| p.path == Path::new(path));
+
+        assert_eq!(find("agent.yaml").unwrap().expected_mode, 0o600);
+        assert_eq!(find("/etc/agent/agent.key").unwrap().expected_mode, 0o600);
+        assert_eq!(find("/etc/agent/ca.crt").unwrap().expected_mode, 0o644);
+        assert_eq!(paths[0].path, std::env::current_exe().unwrap());
+    }
+
+    #[test]
+    fn test_agent_paths_lists_directories_and_history() {
+        let mut config = AgentConfig::default();
+        config.history.path = Some(PathBuf::from("/var/lib/autostrike/history.json"));
+
+        let paths = agent_paths("/etc/agent/agent.yaml", &config);
+        let find = |path: &Path| paths.iter().find(|p| p.path == path);
+
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(find(exe.parent().unwrap()).unwrap().expected_mode, 0o755);
+        assert_eq!(find(Path::new("/etc/agent")).unwrap().expected_mode, 0o755);
+        let history = find(Path::new("/var/lib/autostrike/history.json")).unwrap();
+        assert_eq!(history.expected_mode, 0o600);
+
+        // A relative config file is checked along with the working directory
+        let paths = agent_paths("agent.yaml", &AgentConfig::default());
+        assert!(paths.iter().any(|p| p.path == Path::new(".")));
+    }
+
+    #[test]
+    fn test_check_flags_world_writable_directory_unless_sticky() {
+        let dir = std::env::temp_dir().join(format!("autostrike_perm_dir_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let open = check(&[TrustedPath::new(&dir, 0o755)]);
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o1777)).unwrap();
+        let sticky = check(&[TrustedPath::new(&dir, 0o755)]);
+        std::fs::remove_dir(&dir).unwrap();
+
+        assert_eq!(open.len(), 1);
+        assert!(sticky.is_empty());
+    }
+
+    #[test]
+    fn test_enforce_strict_refuses() {
+        let path = fixture("strict", 0o666);
+
+        let result = enforce(&[TrustedPath::new(&path, 0o600)], true);
+        std::fs::remove_file(&path).unwrap();
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("refusing to start"));
+        assert!(err.contains(&path.display().to_string()));
+    }
+
+    #[test]
+    fn test_enforce_warn_continues() {
+        let path = fixture("warn", 0o666);
+
+        let result = enforce(&[TrustedPath::new(&path, 0o600)], false);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_ok());
+    }
+}
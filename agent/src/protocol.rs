@@ -0,0 +1,1276 @@
+//! Messages exchanged with the AutoStrike server and their JSON Schemas.
+//!
+//! Every message shares the [`AgentMessage`] envelope; the payload shape
+//! depends on the message type. [`schemas`] describes each of them for
+//! integrators (`autostrike-agent schema`).
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use schemars::schema::RootSchema;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::debug;
+
+use crate::config::MessageLimits;
+use crate::disk::{DiskGuard, DiskSpaceError};
+use crate::eventlog::{EventCapture, EventLogReading};
+use crate::features::{self, BuildInfo};
+use crate::health::HealthIssue;
+use crate::registry::{RegistryCheck, RegistryReading};
+use crate::system::{FsInfo, SystemInfo};
+use crate::tls::TlsDiagnostics;
+
+/// Message structure for agent-server WebSocket communication.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct AgentMessage {
+    /// Message type (register, heartbeat, task, task_result, etc.)
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    /// Identifier set by the server on a request and echoed in the agent's
+    /// reply to it; absent from unsolicited messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Message payload as JSON value.
+    pub payload: serde_json::Value,
+}
+
+impl AgentMessage {
+    /// Builds a message of the given type from a typed payload.
+    pub fn new(msg_type: &str, payload: &impl Serialize) -> Result<Self> {
+        Ok(Self {
+            msg_type: msg_type.to_string(),
+            correlation_id: None,
+            payload: serde_json::to_value(payload)?,
+        })
+    }
+
+    /// Builds a reply of the given type to a request with `correlation_id`.
+    pub fn reply(
+        msg_type: &str,
+        payload: &impl Serialize,
+        correlation_id: Option<&str>,
+    ) -> Result<Self> {
+        Ok(Self {
+            correlation_id: correlation_id.map(str::to_string),
+            ..Self::new(msg_type, payload)?
+        })
+    }
+}
+
+/// Payload for agent registration with the server.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RegisterPayload {
+    /// Unique agent identifier.
+    pub paw: String,
+    /// Agent hostname.
+    pub hostname: String,
+    /// Current username.
+    pub username: String,
+    /// Operating system platform (linux, windows, darwin).
+    pub platform: String,
+    /// Available command executors (sh, bash, powershell, etc.).
+    pub executors: Vec<String>,
+    /// Mounted filesystems and their free space, for picking a scratch location.
+    #[serde(default)]
+    pub filesystems: Vec<FsInfo>,
+    /// 32-bit agent on 64-bit Windows, whose shells see WOW64 redirection.
+    #[serde(default)]
+    pub is_wow64: bool,
+    /// Process ID of the agent, to find it in host telemetry.
+    #[serde(default)]
+    pub pid: u32,
+    /// Process ID of the agent's parent, if known.
+    #[serde(default)]
+    pub ppid: Option<u32>,
+    /// Path of the agent executable, if known.
+    #[serde(default)]
+    pub exe_path: Option<String>,
+    /// How the agent was started: `systemd`, `launchd`, `windows_service`,
+    /// `service`, `daemon`, `interactive` or `unknown`.
+    #[serde(default)]
+    pub launch_context: String,
+    /// How the agent binary was built (version, target, cargo features).
+    #[serde(default)]
+    pub agent: BuildInfo,
+    /// Fields cut down to fit the configured message limits.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub truncated_fields: Vec<String>,
+    /// Lists were cut down further to fit `max_register_bytes`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub registration_truncated: bool,
+}
+
+impl RegisterPayload {
+    /// Builds the registration payload, capping collections to `limits`.
+    pub fn new(paw: &str, sys_info: &SystemInfo, limits: &MessageLimits) -> Self {
+        let mut truncated_fields = Vec::new();
+        let mut executors = sys_info.executors.clone();
+        if executors.len() > limits.max_executors {
+            executors.truncate(limits.max_executors);
+            truncated_fields.push("executors".to_string());
+        }
+        let mut filesystems = sys_info.filesystems.clone();
+        if filesystems.len() > limits.max_filesystems {
+            filesystems.truncate(limits.max_filesystems);
+            truncated_fields.push("filesystems".to_string());
+        }
+
+        let mut payload = Self {
+            paw: paw.to_string(),
+            hostname: sys_info.hostname.clone(),
+            username: sys_info.username.clone(),
+            platform: sys_info.platform.clone(),
+            executors,
+            filesystems,
+            is_wow64: sys_info.is_wow64,
+            pid: sys_info.agent_pid,
+            ppid: sys_info.agent_ppid,
+            exe_path: sys_info.agent_exe.clone(),
+            launch_context: sys_info.launch_context.clone(),
+            agent: features::build_info(),
+            truncated_fields,
+            registration_truncated: false,
+        };
+        payload.fit(limits.max_register_bytes);
+        payload
+    }
+
+    /// Halves the larger of the lists until the serialized payload fits in
+    /// `max_bytes`, or both lists are empty.
+    fn fit(&mut self, max_bytes: usize) {
+        while json_len(self) > max_bytes {
+            let executors = json_len(&self.executors);
+            let filesystems = json_len(&self.filesystems);
+            let field = if filesystems >= executors && !self.filesystems.is_empty() {
+                let keep = self.filesystems.len() / 2;
+                self.filesystems.truncate(keep);
+                "filesystems"
+            } else if !self.executors.is_empty() {
+                let keep = self.executors.len() / 2;
+                self.executors.truncate(keep);
+                "executors"
+            } else {
+                break;
+            };
+            if !self.truncated_fields.iter().any(|f| f == field) {
+                self.truncated_fields.push(field.to_string());
+            }
+            self.registration_truncated = true;
+        }
+    }
+}
+
+/// Length of `value` serialized as JSON.
+fn json_len<T: Serialize + ?Sized>(value: &T) -> usize {
+    serde_json::to_vec(value).map_or(0, |json| json.len())
+}
+
+/// Payload for task execution requests from the server.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(remote = "Self")]
+pub struct TaskPayload {
+    /// Unique task identifier.
+    pub id: String,
+    /// MITRE ATT&CK technique ID.
+    pub technique_id: String,
+    /// Command to execute. May also be given as the array of `steps`; one
+    /// of `command`, `steps` or `argv` is required.
+    #[serde(default)]
+    pub command: String,
+    /// Executor type (sh, bash, powershell, etc.).
+    pub executor: String,
+    /// Execution timeout in seconds. Also accepted as a float with no
+    /// fractional part (`300.0`) or a numeric string (`"300"`).
+    #[serde(default, deserialize_with = "lenient_timeout")]
+    pub timeout: Option<u64>,
+    /// RFC 3339 time, on the server's clock, by which the command must stop.
+    /// Caps `timeout` (or replaces it when unset); a task received after its
+    /// deadline is rejected without running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<String>,
+    /// Optional cleanup command to run after execution.
+    pub cleanup: Option<String>,
+    /// W3C trace context of the server-side operation that issued the task.
+    #[serde(default)]
+    pub traceparent: Option<String>,
+    /// Runs the command attached to a pseudo-terminal (Unix only).
+    #[serde(default)]
+    pub use_pty: bool,
+    /// Program and arguments run directly, without a shell. Takes precedence
+    /// over `command`, `steps`, `executor` and `use_pty`.
+    #[serde(default)]
+    pub argv: Option<Vec<String>>,
+    /// Commands run in order instead of `command`, each reported in the
+    /// result's `steps`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<TaskStep>,
+    /// Host facts the task needs; on a host without them the task is
+    /// answered as `skipped` without running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires: Option<TaskRequirements>,
+    /// Overrides the configured `force_utf8_console` for this task.
+    #[serde(default)]
+    pub force_utf8_console: Option<bool>,
+    /// Overrides the configured `login_shell` for this task.
+    #[serde(default)]
+    pub login_shell: Option<bool>,
+    /// Group (e.g. one operator's scenario) whose tasks run one at a time,
+    /// in arrival order; other groups and ungrouped tasks run alongside.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    /// URL the task's result is POSTed to instead of being sent to the
+    /// server; `https` unless `allow_insecure_result_endpoints` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_endpoint: Option<String>,
+    /// Sends only the first and last lines of the output, for chatty
+    /// commands where a sample is enough.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_sample: Option<OutputSample>,
+    /// Regex the output is expected to match, reported as `output_matched`.
+    #[serde(default)]
+    pub expect_output_regex: Option<String>,
+    /// Runs even while another task of the same technique is in progress.
+    #[serde(default)]
+    pub allow_concurrent: bool,
+    /// Registry values read back after the command (Windows only).
+    #[serde(default)]
+    pub verify_registry: Vec<RegistryCheck>,
+    /// Event log read back after the command, for the events the technique
+    /// triggered (Windows only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_events: Option<EventCapture>,
+    /// Leaves the task directory (`task_dirs`) in place after the task.
+    #[serde(default)]
+    pub keep_task_dir: bool,
+    /// Linux network namespace, as named by `ip netns`, to run the command
+    /// (and its cleanup) in. Requires `CAP_SYS_ADMIN`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub netns: Option<String>,
+    /// Server-assigned number, one more than the previous task's on the same
+    /// connection, so the agent can spot lost or reordered tasks. Accepted in
+    /// the same forms as `timeout`.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient_seq"
+    )]
+    pub seq: Option<u64>,
+}
+
+impl Serialize for TaskPayload {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TaskPayload::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TaskPayload {
+    /// Reads an array `command` as the `steps`, and rejects a task with
+    /// nothing to run.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let mut value = serde_json::Value::deserialize(deserializer)?;
+        if let Some(fields) = value.as_object_mut() {
+            if fields.get("command").is_some_and(|c| c.is_array()) {
+                if fields.contains_key("steps") {
+                    return Err(D::Error::custom("task has both steps and a command array"));
+                }
+                let steps = fields.remove("command").unwrap_or_default();
+                fields.insert("steps".to_string(), steps);
+            }
+        }
+        let task = TaskPayload::deserialize(value).map_err(D::Error::custom)?;
+        if task.command.is_empty() && task.steps.is_empty() && task.argv.is_none() {
+            return Err(D::Error::custom("task has no command, steps or argv"));
+        }
+        Ok(task)
+    }
+}
+
+/// Preconditions of a task, checked against the agent's system information.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TaskRequirements {
+    /// Platform, as reported at registration (`linux`, `windows`, `darwin`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// Lowest OS version, compared number by number (`5.0`, `10.0.17763`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_os_version: Option<String>,
+    /// Executor that must be available on the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executor: Option<String>,
+}
+
+/// Lines of a task's output kept by `output_sample`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OutputSample {
+    #[serde(default)]
+    pub head_lines: usize,
+    #[serde(default)]
+    pub tail_lines: usize,
+}
+
+/// One command of a multi-step task.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TaskStep {
+    /// Name of the step in the result.
+    pub name: String,
+    /// Command to execute.
+    pub command: String,
+    /// Executor of this step; the task's when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executor: Option<String>,
+    /// Timeout of this step in seconds, within what is left of the task's.
+    /// Accepted in the same forms as the task's `timeout`.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "lenient_timeout"
+    )]
+    pub timeout: Option<u64>,
+    /// Runs the next steps even if this one fails, without failing the task.
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
+/// Largest task `timeout` accepted, in seconds (a year).
+const MAX_TIMEOUT_SECS: u64 = 365 * 24 * 3600;
+
+/// Largest integer a JSON number holds exactly in most server languages (2^53 - 1).
+const MAX_EXACT_INTEGER: u64 = (1 << 53) - 1;
+
+fn lenient_timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    lenient_whole_number(deserializer, "timeout", MAX_TIMEOUT_SECS)
+}
+
+fn lenient_seq<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    lenient_whole_number(deserializer, "seq", MAX_EXACT_INTEGER)
+}
+
+/// Deserializes an optional whole number up to `max` sent as an integer, a
+/// float with no fractional part or a numeric string, as servers do not all
+/// encode numbers the same way.
+fn lenient_whole_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    field: &str,
+    max: u64,
+) -> Result<Option<u64>, D::Error> {
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(value) => whole_number(&value, max)
+            .map(Some)
+            .map_err(|e| serde::de::Error::custom(format!("invalid {}: {}", field, e))),
+    }
+}
+
+/// Reads `value` as a whole number from 0 to `max`.
+fn whole_number(value: &serde_json::Value, max: u64) -> std::result::Result<u64, String> {
+    let number = match value {
+        serde_json::Value::Number(number) => number.clone(),
+        serde_json::Value::String(text) => text
+            .trim()
+            .parse::<serde_json::Number>()
+            .map_err(|_| format!("{:?} is not a number", text))?,
+        other => return Err(format!("expected a number, got {}", other)),
+    };
+    let whole = match (number.as_u64(), number.as_f64()) {
+        (Some(whole), _) => whole,
+        (None, Some(float)) if float < 0.0 => return Err(format!("{} is negative", number)),
+        (None, Some(float)) if float.fract() != 0.0 => {
+            return Err(format!("{} is not a whole number", number))
+        }
+        (None, Some(float)) if float <= max as f64 => float as u64,
+        _ => return Err(format!("{} is larger than {}", number, max)),
+    };
+    if whole > max {
+        return Err(format!("{} is larger than {}", number, max));
+    }
+    Ok(whole)
+}
+
+/// Runtime metrics reported in each heartbeat.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct HeartbeatMetrics {
+    /// Free bytes on the agent's workspace volume.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_free_bytes: Option<u64>,
+    /// Whether free space on the workspace volume is below the configured reserve.
+    #[serde(default)]
+    pub disk_low: bool,
+    /// Server clock minus agent clock in milliseconds, once known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_skew_ms: Option<i64>,
+    /// Exited children the agent has not managed to reap yet.
+    #[serde(default)]
+    pub unreaped_children: usize,
+    /// Failed attempts to reap a child since startup.
+    #[serde(default)]
+    pub reap_failures: u64,
+    /// Commands run with a fallback executor because the requested one was unknown.
+    #[serde(default)]
+    pub executor_fallbacks: u64,
+    /// Bytes of command output held in memory, against `output_memory_budget_bytes`.
+    #[serde(default)]
+    pub output_bytes_in_use: usize,
+    /// Tasks running or waiting in each task group (`group_id`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub group_queue_depths: BTreeMap<String, usize>,
+    /// Close frames received from the server since the agent started, by
+    /// close code.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub server_close_codes: BTreeMap<u16, u64>,
+    /// Self-checks that failed at their last run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub health: Vec<HealthIssue>,
+}
+
+impl HeartbeatMetrics {
+    /// Collects current metrics for the given workspace directory.
+    pub fn collect(disk: &DiskGuard, workspace: &Path) -> Self {
+        let (disk_free_bytes, disk_low) = match disk.ensure_space(workspace, 0) {
+            Ok(available) => (Some(available), false),
+            Err(DiskSpaceError::InsufficientDisk { available, .. }) => (Some(available), true),
+            Err(e) => {
+                debug!("Heartbeat disk metrics unavailable: {}", e);
+                (None, false)
+            }
+        };
+
+        Self {
+            disk_free_bytes,
+            disk_low,
+            clock_skew_ms: None,
+            unreaped_children: 0,
+            reap_failures: 0,
+            executor_fallbacks: 0,
+            output_bytes_in_use: 0,
+            group_queue_depths: BTreeMap::new(),
+            server_close_codes: BTreeMap::new(),
+            health: Vec::new(),
+        }
+    }
+}
+
+/// Payload of the periodic `heartbeat` message.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct HeartbeatPayload {
+    /// Unique agent identifier.
+    pub paw: String,
+    /// Runtime metrics.
+    pub metrics: HeartbeatMetrics,
+    /// Task IDs whose results the server has not acknowledged yet, when enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_acks: Option<Vec<String>>,
+    /// Task processing is paused: new tasks wait for `resume` (or are rejected).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paused: bool,
+}
+
+/// Payload of the `task_result` message sent after a task's command ran.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct TaskResultPayload {
+    /// Task identifier from the task payload.
+    pub task_id: String,
+    /// MITRE ATT&CK technique ID.
+    pub technique_id: String,
+    /// Parent technique when `technique_id` is a sub-technique (`T1059` for
+    /// `T1059.001`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_technique: Option<String>,
+    /// Whether the command exited successfully.
+    pub success: bool,
+    /// Combined command output.
+    pub output: String,
+    /// Process exit code, if available.
+    pub exit_code: Option<i32>,
+    /// RFC 3339 host local time the command started (wall clock).
+    pub started_at: String,
+    /// RFC 3339 host local time the command finished (wall clock).
+    pub finished_at: String,
+    /// Time the command took, in milliseconds, from the monotonic clock, so a
+    /// clock step during the task does not skew it. Absent for cancelled tasks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Time the operating system took to start the command's process, in
+    /// milliseconds; part of `duration_ms`. Absent when nothing was spawned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawn_latency_ms: Option<u64>,
+    /// Program launched for the command (shell or `argv[0]`), as a full path
+    /// when it could be resolved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell_path: Option<String>,
+    /// Program and arguments the agent handed to the operating system, with
+    /// the agent secret redacted; only sent with `report_spawned_argv`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawned_argv: Option<Vec<String>>,
+    /// Executor the task asked for. Absent for `argv` and cancelled tasks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executor_requested: Option<String>,
+    /// Executor the command actually ran with, which differs from
+    /// `executor_requested` when that was unknown on this platform.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executor_used: Option<String>,
+    /// The shell ran as a login shell, with the user's profile sourced.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub login_shell: bool,
+    /// `PATH` the login shell started the command with, to explain a
+    /// "command not found". Only probed for login shells.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_path: Option<String>,
+    /// Time spent waiting for another run of the same technique, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub technique_wait_ms: Option<u64>,
+    /// State read back after the command, when the task asked for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+    /// Whether the output matched the task's `expect_output_regex`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_matched: Option<bool>,
+    /// Why `expect_output_regex` could not be evaluated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_match_error: Option<String>,
+    /// Results of the task's `steps`, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<StepResult>,
+    /// Fields cut down to fit the configured message limits.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub truncated_fields: Vec<String>,
+    /// Output was cut short because other tasks were using the agent-wide
+    /// `output_memory_budget_bytes`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub output_budget_limited: bool,
+    /// The task was stopped by a `kill_all` before it finished.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    /// Not run, as the host does not meet the task's `requires`; `output`
+    /// says why.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+    /// Identity the command ran under. Absent for cancelled tasks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<ExecutionContext>,
+}
+
+/// Optional `task_result` fields that `result_fields` can leave out; the
+/// others are always sent.
+pub const OPTIONAL_RESULT_FIELDS: &[&str] = &[
+    "parent_technique",
+    "duration_ms",
+    "spawn_latency_ms",
+    "shell_path",
+    "executor_requested",
+    "executor_used",
+    "effective_path",
+    "technique_wait_ms",
+    "verification",
+    "output_matched",
+    "output_match_error",
+    "context",
+];
+
+/// Checks that every name in `fields` is one of [`OPTIONAL_RESULT_FIELDS`].
+pub fn check_result_fields(fields: &[String]) -> Result<()> {
+    for field in fields {
+        if !OPTIONAL_RESULT_FIELDS.contains(&field.as_str()) {
+            bail!(
+                "unknown field {:?} in result_fields (expected one of {})",
+                field,
+                OPTIONAL_RESULT_FIELDS.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+impl TaskResultPayload {
+    /// Clears the optional fields not listed in `keep`.
+    pub fn retain_fields(&mut self, keep: &[String]) {
+        let kept = |name: &str| keep.iter().any(|field| field == name);
+        if !kept("parent_technique") {
+            self.parent_technique = None;
+        }
+        if !kept("duration_ms") {
+            self.duration_ms = None;
+        }
+        if !kept("spawn_latency_ms") {
+            self.spawn_latency_ms = None;
+        }
+        if !kept("shell_path") {
+            self.shell_path = None;
+        }
+        if !kept("executor_requested") {
+            self.executor_requested = None;
+        }
+        if !kept("executor_used") {
+            self.executor_used = None;
+        }
+        if !kept("effective_path") {
+            self.effective_path = None;
+        }
+        if !kept("technique_wait_ms") {
+            self.technique_wait_ms = None;
+        }
+        if !kept("verification") {
+            self.verification = None;
+        }
+        if !kept("output_matched") {
+            self.output_matched = None;
+        }
+        if !kept("output_match_error") {
+            self.output_match_error = None;
+        }
+        if !kept("context") {
+            self.context = None;
+        }
+    }
+}
+
+/// Identity a command ran under, as captured when it was spawned.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ExecutionContext {
+    /// User name (Unix) or `DOMAIN\user` of the process token (Windows).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Effective user ID (Unix).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub euid: Option<u32>,
+    /// Effective group ID (Unix).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egid: Option<u32>,
+    /// Supplementary group IDs (Unix).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<u32>,
+    /// File mode creation mask, in octal (Unix).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub umask: Option<String>,
+    /// Whether the process token is elevated (Windows).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevated: Option<bool>,
+}
+
+/// Result of one step of a multi-step task.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StepResult {
+    pub name: String,
+    pub success: bool,
+    /// Not run, as an earlier step failed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+    pub exit_code: Option<i32>,
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+/// Effects of a task read back by the agent after its command ran.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Verification {
+    /// Registry values requested in `verify_registry`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub registry: Vec<RegistryReading>,
+    /// Events logged while the task ran, requested in `capture_events`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<EventLogReading>,
+}
+
+/// Payload of the `connection_diagnostics` message reporting an earlier TLS failure.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ConnectionDiagnosticsPayload {
+    /// Unique agent identifier.
+    pub paw: String,
+    /// Analysis of the failed certificate verification.
+    pub tls_error: TlsDiagnostics,
+}
+
+/// Payload of the `result_ack` message acknowledging a task result.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ResultAckPayload {
+    /// Task whose result the server stored.
+    pub task_id: String,
+}
+
+/// Payload of the `register_ack` and `time_sync` messages.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct TimeSyncPayload {
+    /// Server's current time in RFC 3339 format.
+    #[serde(default)]
+    pub server_time: Option<String>,
+}
+
+/// Payload of the `resync_request` message sent when tasks were skipped.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ResyncRequestPayload {
+    /// Sequence number the agent expected next.
+    pub expected_seq: u64,
+    /// Sequence number it received instead.
+    pub received_seq: u64,
+}
+
+/// Payload of the `results_expired` message listing task results dropped
+/// because they waited longer than `result_ttl` to be sent.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ResultsExpiredPayload {
+    /// Tasks whose results were dropped.
+    pub task_ids: Vec<String>,
+}
+
+/// Payload of the `probe` message asking whether a binary is available.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ProbePayload {
+    /// Request identifier echoed back in the result.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Binary name looked up on the agent's `PATH`.
+    pub binary: String,
+}
+
+/// Payload of the `probe_result` message answering a `probe`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ProbeResultPayload {
+    /// Request identifier from the probe, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Binary name that was looked up.
+    pub binary: String,
+    /// Whether the binary was found.
+    pub found: bool,
+    /// Resolved path of the binary, when found.
+    pub path: Option<String>,
+    /// Why the probe was not run, when its payload is invalid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Payload of the `collect_bundle` message asking for a one-shot collection
+/// of host artifacts.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CollectBundlePayload {
+    /// Request identifier echoed back in the result.
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+/// What one collector of a `collect_bundle` gathered.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CollectorOutput {
+    /// Whether the collector ran.
+    pub success: bool,
+    /// Collected entries: objects where the agent parses the source, raw
+    /// lines of the system tool otherwise.
+    #[serde(default)]
+    pub items: Vec<serde_json::Value>,
+    /// Why the collector failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Payload of the `collect_bundle_result` message answering a `collect_bundle`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CollectBundleResultPayload {
+    /// Request identifier from the request, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Operating system the bundle comes from (`linux`, `windows`, `macos`).
+    pub platform: String,
+    /// RFC 3339 host local time of the collection.
+    pub collected_at: String,
+    /// Output of each collector: `kernel_modules`, `network_connections`,
+    /// `processes` and `scheduled_tasks`.
+    pub collectors: BTreeMap<String, CollectorOutput>,
+    /// Why the bundle was not sent, when it is too large for a message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Payload of the `read_file` message asking for the content of a host file.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ReadFilePayload {
+    /// Request identifier echoed back in the result.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Absolute path of the file.
+    pub path: String,
+}
+
+/// How the content of a `read_file_result` is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentEncoding {
+    /// The file is valid UTF-8 and sent as is.
+    Utf8,
+    /// The file is binary and sent as standard padded base64.
+    Base64,
+}
+
+/// Payload of the `read_file_result` message answering a `read_file`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ReadFileResultPayload {
+    /// Request identifier from the request, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Path that was requested.
+    pub path: String,
+    /// Whether the file was read.
+    pub success: bool,
+    /// File content, when read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Encoding of `content`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<ContentEncoding>,
+    /// File size in bytes, when read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Why the file was not read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Payload of the `update_available` message announcing a newer agent.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UpdateAvailablePayload {
+    /// Version offered, as a semantic version.
+    pub version: String,
+    /// Where to download the new binary for this agent's platform.
+    #[serde(default)]
+    pub download_url: Option<String>,
+    /// Hex-encoded SHA-256 of the binary, required to install it.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// Payload of the `kill_all_result` message answering a `kill_all`.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct KillAllResultPayload {
+    /// Number of tasks cancelled, running or waiting to run.
+    pub cancelled: usize,
+    /// IDs of the cancelled tasks.
+    pub task_ids: Vec<String>,
+}
+
+/// Payload of messages that carry no data (`ping`, `pong`, `kill_all`).
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct EmptyPayload {}
+
+/// JSON Schemas of the envelope and of every payload the agent sends or
+/// accepts, keyed by message type (`envelope` for the envelope itself).
+pub fn schemas() -> BTreeMap<&'static str, RootSchema> {
+    BTreeMap::from([
+        ("envelope", schema_for!(AgentMessage)),
+        // Sent by the agent
+        ("register", schema_for!(RegisterPayload)),
+        ("heartbeat", schema_for!(HeartbeatPayload)),
+        ("task_result", schema_for!(TaskResultPayload)),
+        (
+            "connection_diagnostics",
+            schema_for!(ConnectionDiagnosticsPayload),
+        ),
+        ("pong", schema_for!(EmptyPayload)),
+        ("probe_result", schema_for!(ProbeResultPayload)),
+        ("kill_all_result", schema_for!(KillAllResultPayload)),
+        ("read_file_result", schema_for!(ReadFileResultPayload)),
+        (
+            "collect_bundle_result",
+            schema_for!(CollectBundleResultPayload),
+        ),
+        ("resync_request", schema_for!(ResyncRequestPayload)),
+        ("results_expired", schema_for!(ResultsExpiredPayload)),
+        // Accepted from the server
+        ("task", schema_for!(TaskPayload)),
+        ("result_ack", schema_for!(ResultAckPayload)),
+        ("register_ack", schema_for!(TimeSyncPayload)),
+        ("time_sync", schema_for!(TimeSyncPayload)),
+        ("ping", schema_for!(EmptyPayload)),
+        ("probe", schema_for!(ProbePayload)),
+        ("kill_all", schema_for!(EmptyPayload)),
+        ("pause", schema_for!(EmptyPayload)),
+        ("resume", schema_for!(EmptyPayload)),
+        ("read_file", schema_for!(ReadFilePayload)),
+        ("collect_bundle", schema_for!(CollectBundlePayload)),
+        ("update_available", schema_for!(UpdateAvailablePayload)),
+    ])
+}
+
+/// Returns the schema of one message type, or every schema keyed by type.
+pub fn schema_document(message: Option<&str>) -> Result<serde_json::Value> {
+    let mut schemas = schemas();
+    let Some(name) = message else {
+        return Ok(serde_json::to_value(schemas)?);
+    };
+    match schemas.remove(name) {
+        Some(schema) => Ok(serde_json::to_value(schema)?),
+        None => bail!(
+            "unknown message type '{}' (available: {})",
+            name,
+            schemas.keys().copied().collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonschema::JSONSchema;
+    use serde_json::json;
+
+    fn validate(message: &str, instance: &serde_json::Value) {
+        let schema = schema_document(Some(message)).unwrap();
+        let compiled = JSONSchema::compile(&schema).unwrap();
+        let errors: Vec<String> = match compiled.validate(instance) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.map(|e| e.to_string()).collect(),
+        };
+        assert!(
+            errors.is_empty(),
+            "{} payload does not match its schema: {:?}",
+            message,
+            errors
+        );
+    }
+
+    #[test]
+    fn test_checked_in_schema_is_current() {
+        let checked_in: serde_json::Value =
+            serde_json::from_str(include_str!("../schema/protocol.schema.json")).unwrap();
+        assert_eq!(
+            checked_in,
+            schema_document(None).unwrap(),
+            "regenerate with `cargo run -- schema > schema/protocol.schema.json`"
+        );
+    }
+
+    #[test]
+    fn test_schema_document_unknown_message() {
+        let err = schema_document(Some("nope")).unwrap_err().to_string();
+        assert!(err.contains("unknown message type 'nope'"));
+        assert!(err.contains("task_result"));
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let msg = AgentMessage::new("pong", &EmptyPayload::default()).unwrap();
+        let value = serde_json::to_value(&msg).unwrap();
+        validate("envelope", &value);
+        validate("pong", &value["payload"]);
+    }
+
+    #[test]
+    fn test_register_round_trip() {
+        let sys_info = SystemInfo {
+            hostname: "host".to_string(),
+            username: "user".to_string(),
+            platform: "linux".to_string(),
+            executors: vec!["sh".to_string()],
+            os_version: "6.1".to_string(),
+            architecture: "x86_64".to_string(),
+            filesystems: vec![FsInfo {
+                mount_point: "/".to_string(),
+                fs_type: "ext4".to_string(),
+                total_bytes: 1 << 30,
+                free_bytes: 1 << 29,
+            }],
+            process_bits: 64,
+            os_bits: 64,
+            is_wow64: false,
+            agent_pid: 4242,
+            agent_ppid: Some(1),
+            agent_exe: Some("/opt/autostrike/autostrike-agent".to_string()),
+            launch_context: "systemd".to_string(),
+        };
+        let payload = RegisterPayload::new("paw", &sys_info, &MessageLimits::default());
+        assert_eq!(payload.pid, 4242);
+        assert_eq!(payload.ppid, Some(1));
+        assert_eq!(payload.launch_context, "systemd");
+        validate("register", &serde_json::to_value(payload).unwrap());
+    }
+
+    fn task_with(field: &str, value: serde_json::Value) -> serde_json::Result<TaskPayload> {
+        let mut task = json!({
+            "id": "task-1", "technique_id": "T1082", "executor": "sh", "command": "whoami"
+        });
+        task[field] = value;
+        serde_json::from_value(task)
+    }
+
+    #[test]
+    fn test_task_numbers_accept_lenient_forms() {
+        for value in [
+            json!(300),
+            json!(300.0),
+            json!("300"),
+            json!(" 300 "),
+            json!("3e2"),
+        ] {
+            let task = task_with("timeout", value.clone()).unwrap();
+            assert_eq!(task.timeout, Some(300), "{}", value);
+        }
+        assert_eq!(task_with("timeout", json!(null)).unwrap().timeout, None);
+        assert_eq!(task_with("seq", json!("7")).unwrap().seq, Some(7));
+        assert_eq!(task_with("seq", json!(7.0)).unwrap().seq, Some(7));
+    }
+
+    #[test]
+    fn test_task_numbers_reject_invalid_values() {
+        let cases = [
+            (json!(-5), "invalid timeout: -5 is negative"),
+            (json!("-5"), "invalid timeout: -5 is negative"),
+            (json!(1.5), "invalid timeout: 1.5 is not a whole number"),
+            (json!("soon"), "invalid timeout: \"soon\" is not a number"),
+            (json!(true), "invalid timeout: expected a number, got true"),
+            (json!(1e12), "is larger than 31536000"),
+            (json!(u64::MAX), "is larger than 31536000"),
+        ];
+        for (value, expected) in cases {
+            let err = task_with("timeout", value.clone()).unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", value, err);
+        }
+        let err = task_with("seq", json!(1e300)).unwrap_err().to_string();
+        assert!(err.contains("invalid seq"), "{}", err);
+    }
+
+    #[test]
+    fn test_heartbeat_round_trip() {
+        let payload = HeartbeatPayload {
+            paw: "paw".to_string(),
+            metrics: HeartbeatMetrics {
+                disk_free_bytes: Some(1024),
+                disk_low: false,
+                clock_skew_ms: Some(-5),
+                unreaped_children: 1,
+                reap_failures: 3,
+                executor_fallbacks: 2,
+                output_bytes_in_use: 4096,
+                group_queue_depths: BTreeMap::from([("scenario-a".to_string(), 3)]),
+                server_close_codes: BTreeMap::from([(1001, 2)]),
+                health: vec![HealthIssue {
+                    check: crate::health::HealthCheck::Heartbeat,
+                    detail: "heartbeat task stopped".to_string(),
+                    remediation: Some(crate::health::Remediation::RestartHeartbeat),
+                    remediations: 1,
+                }],
+            },
+            pending_acks: Some(vec!["task-1".to_string()]),
+            paused: true,
+        };
+        validate("heartbeat", &serde_json::to_value(payload).unwrap());
+    }
+
+    #[test]
+    fn test_task_result_round_trip() {
+        let payload = TaskResultPayload {
+            task_id: "task-1".to_string(),
+            technique_id: "T1059.004".to_string(),
+            parent_technique: Some("T1059".to_string()),
+            success: false,
+            output: "Command timed out".to_string(),
+            exit_code: None,
+            started_at: "2024-05-02T14:03:11.482+02:00".to_string(),
+            finished_at: "2024-05-02T14:03:12.917+02:00".to_string(),
+            duration_ms: Some(1435),
+            spawn_latency_ms: Some(2),
+            shell_path: Some("/bin/sh".to_string()),
+            spawned_argv: None,
+            executor_requested: Some("shh".to_string()),
+            executor_used: Some("sh".to_string()),
+            login_shell: false,
+            effective_path: None,
+            technique_wait_ms: Some(0),
+            verification: None,
+            output_matched: Some(false),
+            output_match_error: None,
+            steps: vec![StepResult {
+                name: "create file".to_string(),
+                success: false,
+                skipped: false,
+                exit_code: None,
+                output: "Command timed out".to_string(),
+                duration_ms: Some(1430),
+            }],
+            truncated_fields: vec!["output".to_string()],
+            output_budget_limited: false,
+            cancelled: false,
+            skipped: false,
+            context: Some(ExecutionContext {
+                user: Some("operator".to_string()),
+                euid: Some(1000),
+                egid: Some(1000),
+                groups: vec![1000, 27],
+                umask: Some("0022".to_string()),
+                elevated: None,
+            }),
+        };
+        let value = serde_json::to_value(payload).unwrap();
+        validate("task_result", &value);
+
+        let parsed: TaskResultPayload = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.truncated_fields, vec!["output"]);
+    }
+
+    #[test]
+    fn test_task_round_trip() {
+        let value = json!({
+            "id": "task-1",
+            "technique_id": "T1082",
+            "command": "whoami",
+            "executor": "sh",
+            "timeout": 60,
+            "cleanup": null,
+            "argv": ["/bin/echo", "hi"],
+            "use_pty": false
+        });
+        validate("task", &value);
+
+        let task: TaskPayload = serde_json::from_value(value).unwrap();
+        validate("task", &serde_json::to_value(task).unwrap());
+    }
+
+    #[test]
+    fn test_task_steps_round_trip() {
+        let value = json!({
+            "id": "task-1",
+            "technique_id": "T1053.003",
+            "executor": "sh",
+            "cleanup": null,
+            "steps": [
+                { "name": "create file", "command": "touch /tmp/job" },
+                { "name": "schedule", "command": "crontab /tmp/job", "executor": "bash", "timeout": 30, "continue_on_error": true }
+            ]
+        });
+        validate("task", &value);
+
+        let task: TaskPayload = serde_json::from_value(value).unwrap();
+        assert_eq!(task.command, "");
+        assert_eq!(task.steps.len(), 2);
+        assert_eq!(task.steps[1].executor.as_deref(), Some("bash"));
+        assert_eq!(task.steps[1].timeout, Some(30));
+        assert!(!task.steps[0].continue_on_error && task.steps[1].continue_on_error);
+        validate("task", &serde_json::to_value(task).unwrap());
+    }
+
+    #[test]
+    fn test_task_steps_as_command_array() {
+        let task: TaskPayload = serde_json::from_value(json!({
+            "id": "task-1",
+            "technique_id": "T1053.003",
+            "executor": "sh",
+            "command": [
+                { "name": "create file", "command": "touch /tmp/job" },
+                { "name": "schedule", "command": "crontab /tmp/job" }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(task.command, "");
+        assert_eq!(task.steps.len(), 2);
+        assert_eq!(task.steps[1].name, "schedule");
+
+        let err = serde_json::from_value::<TaskPayload>(json!({
+            "id": "task-1",
+            "technique_id": "T1053.003",
+            "executor": "sh",
+            "command": [{ "name": "a", "command": "true" }],
+            "steps": [{ "name": "b", "command": "true" }]
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("both steps"), "{}", err);
+    }
+
+    #[test]
+    fn test_task_without_command_rejected() {
+        let err = serde_json::from_value::<TaskPayload>(
+            json!({"id": "task-1", "technique_id": "T1082", "executor": "sh"}),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "task has no command, steps or argv");
+
+        let argv: TaskPayload = serde_json::from_value(json!({
+            "id": "task-1", "technique_id": "T1082", "executor": "sh", "argv": ["id"]
+        }))
+        .unwrap();
+        assert_eq!(argv.argv.unwrap(), ["id"]);
+    }
+
+    #[test]
+    fn test_task_schema_rejects_missing_id() {
+        let schema = schema_document(Some("task")).unwrap();
+        let compiled = JSONSchema::compile(&schema).unwrap();
+        assert!(!compiled.is_valid(&json!({"technique_id": "T1082", "executor": "sh"})));
+    }
+
+    #[test]
+    fn test_server_payloads_round_trip() {
+        validate("result_ack", &json!({"task_id": "task-1"}));
+        validate("time_sync", &json!({"server_time": "2024-05-02T12:00:00Z"}));
+        validate("register_ack", &json!({}));
+        validate("ping", &json!({}));
+        validate("probe", &json!({"id": "probe-1", "binary": "nmap"}));
+        validate("kill_all", &json!({}));
+        validate("pause", &json!({}));
+        validate("resume", &json!({}));
+        validate("read_file", &json!({"id": "read-1", "path": "/etc/hosts"}));
+        validate("collect_bundle", &json!({"id": "bundle-1"}));
+        validate(
+            "update_available",
+            &json!({
+                "version": "0.2.0",
+                "download_url": "https://server:8443/agents/linux-amd64",
+                "sha256": "ab".repeat(32)
+            }),
+        );
+    }
+
+    #[test]
+    fn test_probe_result_round_trip() {
+        let payload = ProbeResultPayload {
+            id: None,
+            binary: "nmap".to_string(),
+            found: false,
+            path: None,
+            error: None,
+        };
+        validate("probe_result", &serde_json::to_value(payload).unwrap());
+    }
+
+    #[test]
+    fn test_kill_all_result_round_trip() {
+        let payload = KillAllResultPayload {
+            cancelled: 2,
+            task_ids: vec!["task-1".to_string(), "task-2".to_string()],
+        };
+        validate("kill_all_result", &serde_json::to_value(payload).unwrap());
+    }
+
+    #[test]
+    fn test_resync_request_round_trip() {
+        let payload = ResyncRequestPayload {
+            expected_seq: 4,
+            received_seq: 7,
+        };
+        validate("resync_request", &serde_json::to_value(payload).unwrap());
+    }
+
+    #[test]
+    fn test_results_expired_round_trip() {
+        let payload = ResultsExpiredPayload {
+            task_ids: vec!["task-1".to_string()],
+        };
+        validate("results_expired", &serde_json::to_value(payload).unwrap());
+    }
+
+    #[test]
+    fn test_read_file_result_round_trip() {
+        let payload = ReadFileResultPayload {
+            id: Some("read-1".to_string()),
+            path: "/etc/hosts".to_string(),
+            success: true,
+            content: Some("/wD+QQ==".to_string()),
+            encoding: Some(ContentEncoding::Base64),
+            size: Some(4),
+            error: None,
+        };
+        let value = serde_json::to_value(payload).unwrap();
+        assert_eq!(value["encoding"], "base64");
+        validate("read_file_result", &value);
+    }
+}
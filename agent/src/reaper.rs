@@ -0,0 +1,257 @@
+//! Background reaping of child processes whose exit status could not be
+//! collected when their command ended, so they do not linger as zombies.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+/// How often unreaped children are retried.
+const REAP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A child process the reaper can retry.
+pub trait Reapable: Send {
+    /// Process ID, for logging.
+    fn pid(&self) -> Option<u32>;
+
+    /// Collects the exit status without blocking; `Ok(true)` once reaped.
+    fn try_reap(&mut self) -> std::io::Result<bool>;
+}
+
+impl Reapable for tokio::process::Child {
+    fn pid(&self) -> Option<u32> {
+        self.id()
+    }
+
+    fn try_reap(&mut self) -> std::io::Result<bool> {
+        Ok(self.try_wait()?.is_some())
+    }
+}
+
+#[cfg(unix)]
+impl Reapable for Box<dyn portable_pty::Child + Send + Sync> {
+    fn pid(&self) -> Option<u32> {
+        self.process_id()
+    }
+
+    fn try_reap(&mut self) -> std::io::Result<bool> {
+        Ok(self.try_wait()?.is_some())
+    }
+}
+
+/// Children that could not be waited for, retried in a background task.
+pub struct Reaper {
+    interval: Duration,
+    state: Mutex<ReaperState>,
+    /// Failed attempts to collect a child's exit status, initial waits included.
+    failures: AtomicU64,
+}
+
+impl Default for Reaper {
+    fn default() -> Self {
+        Self::new(REAP_INTERVAL)
+    }
+}
+
+#[derive(Default)]
+struct ReaperState {
+    children: Vec<Box<dyn Reapable>>,
+    /// Whether the background task is running.
+    retrying: bool,
+}
+
+impl Reaper {
+    /// Creates a reaper retrying every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::default(),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes over a child that could not be waited for and retries it until
+    /// it is reaped.
+    pub fn adopt(self: &Arc<Self>, child: Box<dyn Reapable>) {
+        warn!(
+            "Child process {:?} could not be reaped, retrying in the background",
+            child.pid()
+        );
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.state();
+        state.children.push(child);
+        if !state.retrying {
+            state.retrying = true;
+            let reaper = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(reaper.interval).await;
+                    if reaper.reap_once() == 0 {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+
+    /// Number of children still waiting to be reaped.
+    pub fn pending(&self) -> usize {
+        self.state().children.len()
+    }
+
+    /// Failed attempts to reap a child since startup, a host accumulating
+    /// zombies showing a growing count.
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Retries every pending child once and returns how many remain. Stops
+    /// the background task when none do; a child reaped elsewhere is dropped.
+    pub fn reap_once(&self) -> usize {
+        let mut state = self.state();
+        state.children.retain_mut(|child| match child.try_reap() {
+            Ok(true) => {
+                info!("Reaped child process {:?}", child.pid());
+                false
+            }
+            Ok(false) => {
+                debug!("Child process {:?} has not exited yet", child.pid());
+                true
+            }
+            Err(e) if already_reaped(&e) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "Child process {:?} was already reaped elsewhere: {}",
+                    child.pid(),
+                    e
+                );
+                false
+            }
+            Err(e) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                warn!("Failed to reap child process {:?}: {}", child.pid(), e);
+                true
+            }
+        });
+        let remaining = state.children.len();
+        if remaining == 0 {
+            state.retrying = false;
+        }
+        remaining
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ReaperState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether waiting failed because the child no longer exists to be waited
+/// for (`ECHILD`), so retrying can never succeed.
+#[cfg(unix)]
+fn already_reaped(error: &std::io::Error) -> bool {
+    error.raw_os_error() == Some(nix::libc::ECHILD)
+}
+
+#[cfg(not(unix))]
+fn already_reaped(_error: &std::io::Error) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Child whose wait fails a set number of times before it is reaped.
+    struct FailingChild {
+        failures_left: usize,
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl Reapable for FailingChild {
+        fn pid(&self) -> Option<u32> {
+            Some(4242)
+        }
+
+        fn try_reap(&mut self) -> std::io::Result<bool> {
+            self.attempts.fetch_add(1, Ordering::Relaxed);
+            if self.failures_left > 0 {
+                self.failures_left -= 1;
+                return Err(std::io::Error::other("wait failed"));
+            }
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reaper_retries_until_reaped() {
+        let reaper = Arc::new(Reaper::default());
+        let attempts = Arc::new(AtomicUsize::new(0));
+        reaper.adopt(Box::new(FailingChild {
+            failures_left: 2,
+            attempts: attempts.clone(),
+        }));
+        assert_eq!(reaper.pending(), 1);
+
+        assert_eq!(reaper.reap_once(), 1);
+        assert_eq!(reaper.reap_once(), 1);
+        assert_eq!(reaper.reap_once(), 0);
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+        assert_eq!(reaper.pending(), 0);
+        // The initial wait and both retries
+        assert_eq!(reaper.failures(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reaper_background_task_retries() {
+        let reaper = Arc::new(Reaper::new(Duration::from_millis(10)));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        reaper.adopt(Box::new(FailingChild {
+            failures_left: 1,
+            attempts: attempts.clone(),
+        }));
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+        assert_eq!(reaper.pending(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reaper_reaps_real_child() {
+        let reaper = Arc::new(Reaper::default());
+        let child = tokio::process::Command::new("true").spawn().unwrap();
+        reaper.adopt(Box::new(child));
+
+        for _ in 0..50 {
+            if reaper.reap_once() == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("child was never reaped");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reaper_detects_child_reaped_elsewhere() {
+        use nix::sys::wait::waitpid;
+        use nix::unistd::Pid;
+
+        let reaper = Arc::new(Reaper::default());
+        let mut child = tokio::process::Command::new("true").spawn().unwrap();
+        // Reap it behind tokio's back, as another waiter on the host could
+        let pid = Pid::from_raw(child.id().unwrap() as i32);
+        waitpid(pid, None).unwrap();
+        let e = child.try_wait().unwrap_err();
+        assert!(already_reaped(&e), "{}", e);
+
+        reaper.adopt(Box::new(child));
+        assert_eq!(reaper.failures(), 1);
+        // Dropped rather than retried forever
+        assert_eq!(reaper.reap_once(), 0);
+        assert_eq!(reaper.failures(), 2);
+    }
+}
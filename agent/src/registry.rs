@@ -0,0 +1,313 @@
+//! Native Windows registry read-back used to verify what a technique changed.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A registry value to read back after a task's command ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RegistryCheck {
+    /// Root key: `HKLM`, `HKCU`, `HKCR`, `HKU` or `HKCC` (long names accepted).
+    pub hive: String,
+    /// Subkey path under the hive, e.g. `Software\Microsoft\Windows\CurrentVersion\Run`.
+    pub key: String,
+    /// Value name; empty for the key's default value.
+    #[serde(default)]
+    pub value_name: String,
+}
+
+/// Outcome of reading one registry value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistryStatus {
+    /// The value exists; `value_type` and `data` are set.
+    Present,
+    /// The key or value does not exist.
+    Absent,
+    /// The value could not be read; `error` says why.
+    Error,
+    /// Registry verification is only available on Windows.
+    Unsupported,
+}
+
+/// A registry value as read back by the agent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RegistryReading {
+    /// Root key as requested.
+    pub hive: String,
+    /// Subkey path as requested.
+    pub key: String,
+    /// Value name as requested.
+    pub value_name: String,
+    /// Whether the value exists, or why it could not be read.
+    pub status: RegistryStatus,
+    /// Registry type name (`REG_SZ`, `REG_DWORD`, ...), when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_type: Option<String>,
+    /// Value data: strings as-is, numbers in decimal, multi-strings one per
+    /// line, anything else as hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// Read failure, when `status` is `error`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RegistryReading {
+    fn new(check: &RegistryCheck, status: RegistryStatus) -> Self {
+        Self {
+            hive: check.hive.clone(),
+            key: check.key.clone(),
+            value_name: check.value_name.clone(),
+            status,
+            value_type: None,
+            data: None,
+            error: None,
+        }
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    fn error(check: &RegistryCheck, error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..Self::new(check, RegistryStatus::Error)
+        }
+    }
+}
+
+/// Reads every requested value; failures are reported per entry.
+pub fn read_values(checks: &[RegistryCheck]) -> Vec<RegistryReading> {
+    checks.iter().map(read_value).collect()
+}
+
+#[cfg(not(windows))]
+fn read_value(check: &RegistryCheck) -> RegistryReading {
+    RegistryReading::new(check, RegistryStatus::Unsupported)
+}
+
+#[cfg(windows)]
+fn read_value(check: &RegistryCheck) -> RegistryReading {
+    use std::ptr::null_mut;
+    use winapi::shared::winerror::{ERROR_FILE_NOT_FOUND, ERROR_MORE_DATA, ERROR_SUCCESS};
+    use winapi::um::winreg::{RegGetValueW, RRF_NOEXPAND, RRF_RT_ANY};
+
+    let Some(hive) = hive_handle(&check.hive) else {
+        return RegistryReading::error(check, format!("unknown hive {:?}", check.hive));
+    };
+    let wide = |s: &str| -> Vec<u16> { s.encode_utf16().chain(Some(0)).collect() };
+    let key = wide(&check.key);
+    let value_name = wide(&check.value_name);
+
+    let mut data: Vec<u8> = Vec::new();
+    loop {
+        let mut value_type = 0;
+        let mut size = data.len() as u32;
+        // SAFETY: the strings are NUL-terminated and `size` is the capacity
+        // of `data` (a null buffer when empty, which only queries the size).
+        let status = unsafe {
+            RegGetValueW(
+                hive,
+                key.as_ptr(),
+                value_name.as_ptr(),
+                RRF_RT_ANY | RRF_NOEXPAND,
+                &mut value_type,
+                if data.is_empty() {
+                    null_mut()
+                } else {
+                    data.as_mut_ptr().cast()
+                },
+                &mut size,
+            )
+        } as u32;
+        match status {
+            ERROR_SUCCESS if data.is_empty() && size > 0 => data.resize(size as usize, 0),
+            ERROR_MORE_DATA => data.resize(size as usize, 0),
+            ERROR_SUCCESS => {
+                data.truncate(size as usize);
+                return RegistryReading {
+                    value_type: Some(type_name(value_type).to_string()),
+                    data: Some(format_data(value_type, &data)),
+                    ..RegistryReading::new(check, RegistryStatus::Present)
+                };
+            }
+            ERROR_FILE_NOT_FOUND => return RegistryReading::new(check, RegistryStatus::Absent),
+            code => {
+                return RegistryReading::error(
+                    check,
+                    std::io::Error::from_raw_os_error(code as i32).to_string(),
+                )
+            }
+        }
+    }
+}
+
+/// Maps a hive name to its predefined root key.
+#[cfg(windows)]
+fn hive_handle(hive: &str) -> Option<winapi::shared::minwindef::HKEY> {
+    use winapi::um::winreg::{
+        HKEY_CLASSES_ROOT, HKEY_CURRENT_CONFIG, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS,
+    };
+
+    match hive.to_ascii_uppercase().as_str() {
+        "HKLM" | "HKEY_LOCAL_MACHINE" => Some(HKEY_LOCAL_MACHINE),
+        "HKCU" | "HKEY_CURRENT_USER" => Some(HKEY_CURRENT_USER),
+        "HKCR" | "HKEY_CLASSES_ROOT" => Some(HKEY_CLASSES_ROOT),
+        "HKU" | "HKEY_USERS" => Some(HKEY_USERS),
+        "HKCC" | "HKEY_CURRENT_CONFIG" => Some(HKEY_CURRENT_CONFIG),
+        _ => None,
+    }
+}
+
+// Registry value types (winnt.h)
+const REG_SZ: u32 = 1;
+const REG_EXPAND_SZ: u32 = 2;
+const REG_BINARY: u32 = 3;
+const REG_DWORD: u32 = 4;
+const REG_DWORD_BIG_ENDIAN: u32 = 5;
+const REG_MULTI_SZ: u32 = 7;
+const REG_QWORD: u32 = 11;
+
+/// Name of a registry value type.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn type_name(value_type: u32) -> &'static str {
+    match value_type {
+        0 => "REG_NONE",
+        REG_SZ => "REG_SZ",
+        REG_EXPAND_SZ => "REG_EXPAND_SZ",
+        REG_BINARY => "REG_BINARY",
+        REG_DWORD => "REG_DWORD",
+        REG_DWORD_BIG_ENDIAN => "REG_DWORD_BIG_ENDIAN",
+        6 => "REG_LINK",
+        REG_MULTI_SZ => "REG_MULTI_SZ",
+        REG_QWORD => "REG_QWORD",
+        _ => "REG_UNKNOWN",
+    }
+}
+
+/// Renders raw value data according to its type.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn format_data(value_type: u32, data: &[u8]) -> String {
+    let utf16 = || -> Vec<u16> {
+        data.chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect()
+    };
+    match (value_type, data.len()) {
+        (REG_SZ | REG_EXPAND_SZ, _) => {
+            let units = utf16();
+            let end = units.iter().position(|&u| u == 0).unwrap_or(units.len());
+            String::from_utf16_lossy(&units[..end])
+        }
+        (REG_MULTI_SZ, _) => utf16()
+            .split(|&u| u == 0)
+            .filter(|s| !s.is_empty())
+            .map(String::from_utf16_lossy)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (REG_DWORD, 4) => u32::from_le_bytes(data.try_into().unwrap()).to_string(),
+        (REG_DWORD_BIG_ENDIAN, 4) => u32::from_be_bytes(data.try_into().unwrap()).to_string(),
+        (REG_QWORD, 8) => u64::from_le_bytes(data.try_into().unwrap()).to_string(),
+        _ => hex::encode(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16_bytes(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn check(value_name: &str) -> RegistryCheck {
+        RegistryCheck {
+            hive: "HKCU".to_string(),
+            key: r"Software\AutoStrikeTest".to_string(),
+            value_name: value_name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_format_data() {
+        assert_eq!(format_data(REG_SZ, &utf16_bytes("calc.exe\0")), "calc.exe");
+        assert_eq!(
+            format_data(REG_EXPAND_SZ, &utf16_bytes("%TEMP%\0")),
+            "%TEMP%"
+        );
+        assert_eq!(format_data(REG_MULTI_SZ, &utf16_bytes("a\0b\0\0")), "a\nb");
+        assert_eq!(format_data(REG_DWORD, &1u32.to_le_bytes()), "1");
+        assert_eq!(format_data(REG_DWORD_BIG_ENDIAN, &[0, 0, 1, 0]), "256");
+        assert_eq!(
+            format_data(REG_QWORD, &u64::MAX.to_le_bytes()),
+            u64::MAX.to_string()
+        );
+        assert_eq!(format_data(REG_BINARY, &[0xde, 0xad]), "dead");
+        assert_eq!(format_data(REG_DWORD, &[1, 2]), "0102");
+        assert_eq!(type_name(REG_QWORD), "REG_QWORD");
+    }
+
+    #[test]
+    fn test_reading_serialization() {
+        let json =
+            serde_json::to_value(RegistryReading::new(&check("Run"), RegistryStatus::Absent))
+                .unwrap();
+        assert_eq!(json["status"], "absent");
+        assert_eq!(json["value_name"], "Run");
+        assert!(json.get("data").is_none());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_read_values_unsupported() {
+        let readings = read_values(&[check("a"), check("b")]);
+
+        assert_eq!(readings.len(), 2);
+        assert!(readings
+            .iter()
+            .all(|r| r.status == RegistryStatus::Unsupported));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_read_values_hkcu() {
+        use winapi::um::winreg::{RegDeleteTreeW, RegSetKeyValueW, HKEY_CURRENT_USER};
+
+        let key = format!(r"Software\AutoStrikeTest\{}", std::process::id());
+        let key_w: Vec<u16> = key.encode_utf16().chain(Some(0)).collect();
+        let name_w: Vec<u16> = "Payload".encode_utf16().chain(Some(0)).collect();
+        let data = utf16_bytes("calc.exe\0");
+        // SAFETY: NUL-terminated strings and a buffer of the stated length.
+        let status = unsafe {
+            RegSetKeyValueW(
+                HKEY_CURRENT_USER,
+                key_w.as_ptr(),
+                name_w.as_ptr(),
+                REG_SZ,
+                data.as_ptr().cast(),
+                data.len() as u32,
+            )
+        };
+        assert_eq!(status, 0);
+
+        let present = RegistryCheck {
+            key: key.clone(),
+            ..check("Payload")
+        };
+        let absent = RegistryCheck {
+            key: key.clone(),
+            ..check("Missing")
+        };
+        let bad_hive = RegistryCheck {
+            hive: "HKXX".to_string(),
+            ..check("Payload")
+        };
+        let readings = read_values(&[present, absent, bad_hive]);
+        // SAFETY: NUL-terminated key path under HKCU created above.
+        unsafe { RegDeleteTreeW(HKEY_CURRENT_USER, key_w.as_ptr()) };
+
+        assert_eq!(readings[0].status, RegistryStatus::Present);
+        assert_eq!(readings[0].value_type.as_deref(), Some("REG_SZ"));
+        assert_eq!(readings[0].data.as_deref(), Some("calc.exe"));
+        assert_eq!(readings[1].status, RegistryStatus::Absent);
+        assert_eq!(readings[2].status, RegistryStatus::Error);
+    }
+}
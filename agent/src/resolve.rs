@@ -0,0 +1,148 @@
+//! Resolution of the server's host name, with static overrides for labs
+//! where the server has no DNS record. Only the TCP connection uses the
+//! overridden address; TLS still sends and verifies the host name.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::net::TcpStream;
+use tracing::{debug, info};
+
+use crate::config::{NetworkConfig, Resolver};
+
+/// Where the addresses of a host came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The host is an IP address.
+    Literal,
+    /// `network.host_overrides`.
+    Override,
+    /// The system resolver.
+    System,
+}
+
+/// Addresses of `host` on `port`, from the overrides first. With the
+/// `static-only` resolver, a name missing from the overrides fails.
+pub async fn resolve(
+    host: &str,
+    port: u16,
+    network: &NetworkConfig,
+) -> io::Result<(Vec<SocketAddr>, Source)> {
+    // URI hosts keep the brackets around IPv6 addresses
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok((vec![SocketAddr::new(ip, port)], Source::Literal));
+    }
+    if let Some(ip) = lookup_override(host, network) {
+        return Ok((vec![SocketAddr::new(ip, port)], Source::Override));
+    }
+    if network.resolver == Resolver::StaticOnly {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "{} is not in network.host_overrides and the resolver is static-only",
+                host
+            ),
+        ));
+    }
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+    Ok((addrs, Source::System))
+}
+
+/// Address `network` overrides `host` with; names are matched ignoring case.
+fn lookup_override(host: &str, network: &NetworkConfig) -> Option<IpAddr> {
+    network
+        .host_overrides
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        .map(|(_, ip)| *ip)
+}
+
+/// Opens a TCP connection to `host` on `port`, trying each of its addresses
+/// in turn.
+pub async fn connect(host: &str, port: u16, network: &NetworkConfig) -> io::Result<TcpStream> {
+    let (addrs, source) = resolve(host, port, network).await?;
+    match source {
+        Source::Override => info!(
+            "Resolved {} to {} through network.host_overrides",
+            host,
+            addrs[0].ip()
+        ),
+        _ => debug!("Resolved {} to {:?} ({:?})", host, addrs, source),
+    }
+
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                debug!("Failed to connect to {}: {}", addr, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} resolved to no address", host),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(resolver: Resolver) -> NetworkConfig {
+        NetworkConfig {
+            host_overrides: [("AutoStrike.lab".to_string(), IpAddr::from([127, 0, 0, 1]))]
+                .into_iter()
+                .collect(),
+            resolver,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_override_before_system_dns() {
+        let network = overrides(Resolver::System);
+
+        let (addrs, source) = resolve("autostrike.lab", 8443, &network).await.unwrap();
+        assert_eq!(source, Source::Override);
+        assert_eq!(addrs, [SocketAddr::from(([127, 0, 0, 1], 8443))]);
+
+        let (addrs, source) = resolve("[::1]", 443, &network).await.unwrap();
+        assert_eq!(source, Source::Literal);
+        assert_eq!(
+            addrs,
+            [SocketAddr::from(([0u16, 0, 0, 0, 0, 0, 0, 1], 443))]
+        );
+
+        let (_, source) = resolve("localhost", 443, &network).await.unwrap();
+        assert_eq!(source, Source::System);
+    }
+
+    #[tokio::test]
+    async fn test_static_only_forbids_fallback() {
+        let network = overrides(Resolver::StaticOnly);
+
+        let err = resolve("localhost", 443, &network).await.unwrap_err();
+        assert!(err.to_string().contains("static-only"), "{}", err);
+        assert!(resolve("autostrike.lab", 443, &network).await.is_ok());
+        assert!(resolve("127.0.0.1", 443, &network).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_override() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let network = overrides(Resolver::StaticOnly);
+
+        let (connected, accepted) =
+            tokio::join!(connect("autostrike.lab", port, &network), listener.accept());
+        assert_eq!(
+            connected.unwrap().peer_addr().unwrap(),
+            SocketAddr::from(([127, 0, 0, 1], port))
+        );
+        accepted.unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+//! Delivery of a task's result to another collector than the server, when
+//! the task names a `result_endpoint` (e.g. a dedicated artifact sink).
+
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use reqwest::Url;
+
+use crate::config::TlsConfig;
+use crate::secret::SecretString;
+
+/// How long a result endpoint may take to accept a result.
+const POST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a task's result goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// The server, over the WebSocket connection.
+    WebSocket,
+    /// An HTTP POST to the task's `result_endpoint`.
+    Http(Url),
+}
+
+/// Route of the result of a task with `endpoint`, checked with [`check`].
+pub fn route(endpoint: Option<&str>, allow_insecure: bool) -> Result<Route> {
+    match endpoint {
+        Some(url) => Ok(Route::Http(check(url, allow_insecure)?)),
+        None => Ok(Route::WebSocket),
+    }
+}
+
+/// Parses a `result_endpoint`, which must be an `https` URL; plain `http`
+/// needs `allow_insecure_result_endpoints`, as results can hold secrets.
+pub fn check(url: &str, allow_insecure: bool) -> Result<Url> {
+    let parsed = Url::parse(url).with_context(|| format!("invalid result_endpoint {:?}", url))?;
+    match parsed.scheme() {
+        "https" => {}
+        "http" if allow_insecure => {}
+        "http" => bail!(
+            "result_endpoint {} uses http; use https or set allow_insecure_result_endpoints",
+            url
+        ),
+        scheme => bail!(
+            "result_endpoint {} has unsupported scheme {:?} (expected https)",
+            url,
+            scheme
+        ),
+    }
+    if parsed.host_str().is_none() {
+        bail!("result_endpoint {} has no host", url);
+    }
+    Ok(parsed)
+}
+
+/// Whether `url` has the host and port of the server at `server_url`
+/// (`ws`/`wss` counting as `http`/`https`), the only endpoint trusted with
+/// the agent secret.
+pub fn same_origin(url: &Url, server_url: &str) -> bool {
+    let Ok(server) = Url::parse(server_url) else {
+        return false;
+    };
+    let host = |url: &Url| url.host_str().map(str::to_ascii_lowercase);
+    host(url).is_some()
+        && host(url) == host(&server)
+        && url.port_or_known_default() == server.port_or_known_default()
+}
+
+/// POSTs `message`, an encoded `task_result`, to `url`, authenticated with
+/// `secret` (the agent secret, when `url` is the server's) like the
+/// WebSocket connection.
+pub async fn post(
+    url: &Url,
+    message: String,
+    tls: &TlsConfig,
+    secret: Option<&SecretString>,
+) -> Result<()> {
+    let client = crate::tls::http_client(tls, POST_TIMEOUT)?;
+    let mut request = client
+        .post(url.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .body(message);
+    if let Some(secret) = secret {
+        let mut value =
+            HeaderValue::from_str(secret.expose()).context("Invalid agent secret value")?;
+        value.set_sensitive(true);
+        request = request.header("x-agent-key", value);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route(None, false).unwrap(), Route::WebSocket);
+        assert_eq!(
+            route(Some("https://sink.lab:8443/results"), false).unwrap(),
+            Route::Http(Url::parse("https://sink.lab:8443/results").unwrap())
+        );
+        assert!(route(Some("http://sink.lab/results"), false).is_err());
+    }
+
+    #[test]
+    fn test_same_origin() {
+        let url = Url::parse("https://c2.lab:8443/results").unwrap();
+        assert!(same_origin(&url, "wss://c2.lab:8443/ws/agent"));
+        assert!(same_origin(&url, "https://C2.lab:8443"));
+        assert!(!same_origin(&url, "wss://c2.lab/ws/agent"));
+        assert!(!same_origin(&url, "wss://sink.lab:8443"));
+        assert!(!same_origin(&url, "not a url"));
+
+        let default_port = Url::parse("https://c2.lab/results").unwrap();
+        assert!(same_origin(&default_port, "wss://c2.lab"));
+    }
+
+    #[test]
+    fn test_check_scheme_policy() {
+        assert!(check("https://sink.lab/results", false).is_ok());
+        let err = check("http://sink.lab/results", false).unwrap_err();
+        assert!(
+            err.to_string().contains("allow_insecure_result_endpoints"),
+            "{}",
+            err
+        );
+        assert!(check("http://sink.lab/results", true).is_ok());
+
+        for url in [
+            "ftp://sink.lab/results",
+            "file:///tmp/results",
+            "sink.lab/results",
+        ] {
+            assert!(check(url, true).is_err(), "{}", url);
+        }
+    }
+}
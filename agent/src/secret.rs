@@ -0,0 +1,97 @@
+//! Secret values that are wiped from memory and never printed.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroizing;
+
+/// Placeholder emitted wherever a secret would otherwise be displayed.
+const REDACTED: &str = "[REDACTED]";
+
+/// A string secret zeroized on drop.
+///
+/// `Debug` and `Serialize` always emit `[REDACTED]`; the value is only
+/// reachable through [`SecretString::expose`] at the point of use.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(Zeroizing<String>);
+
+impl SecretString {
+    /// Wraps a secret value.
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(Zeroizing::new(value.into()))
+    }
+
+    /// Returns the secret value. Call only where the real value is required.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expose_returns_value() {
+        let secret = SecretString::new("hunter2");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn test_debug_redacts_value() {
+        let secret = SecretString::from("hunter2");
+        let debug_str = format!("{:?}", secret);
+
+        assert_eq!(debug_str, "[REDACTED]");
+        assert!(!debug_str.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_serialize_redacts_value() {
+        let secret = SecretString::from("hunter2".to_string());
+        let json = serde_json::to_string(&secret).unwrap();
+
+        assert_eq!(json, "\"[REDACTED]\"");
+    }
+
+    #[test]
+    fn test_deserialize_produces_wrapper() {
+        let secret: SecretString = serde_json::from_str("\"hunter2\"").unwrap();
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn test_clone_and_eq() {
+        let secret = SecretString::from("hunter2");
+        assert_eq!(secret.clone(), secret);
+        assert_ne!(secret, SecretString::from("other"));
+    }
+}
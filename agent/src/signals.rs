@@ -0,0 +1,138 @@
+//! Unix signal handling: SIGUSR1 logs a status snapshot and SIGUSR2
+//! toggles debug logging on a running agent.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::protocol::HeartbeatMetrics;
+
+/// Switches the active log filter between its normal and debug versions.
+pub struct DebugToggle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    normal_filter: String,
+    debug_filter: String,
+    debug: AtomicBool,
+}
+
+impl DebugToggle {
+    /// Wraps the reload handle of a filter currently set to `debug_filter`
+    /// if `debug`, to `normal_filter` otherwise.
+    pub fn new(
+        handle: reload::Handle<EnvFilter, Registry>,
+        normal_filter: String,
+        debug_filter: String,
+        debug: bool,
+    ) -> Self {
+        Self {
+            handle,
+            normal_filter,
+            debug_filter,
+            debug: AtomicBool::new(debug),
+        }
+    }
+
+    /// Flips the filter and returns whether debug logging is now on.
+    pub fn toggle(&self) -> Result<bool> {
+        let debug = !self.debug.fetch_xor(true, Ordering::Relaxed);
+        let filter = if debug {
+            &self.debug_filter
+        } else {
+            &self.normal_filter
+        };
+        self.handle.reload(EnvFilter::new(filter))?;
+        Ok(debug)
+    }
+}
+
+/// Toggles debug logging each time the process receives SIGUSR2.
+pub fn spawn_debug_toggle(toggle: DebugToggle) -> Result<()> {
+    let mut usr2 = signal(SignalKind::user_defined2())?;
+    tokio::spawn(async move {
+        while usr2.recv().await.is_some() {
+            match toggle.toggle() {
+                Ok(enabled) => info!(
+                    "SIGUSR2 received, debug logging {}",
+                    if enabled { "enabled" } else { "disabled" }
+                ),
+                Err(e) => warn!("Failed to switch the log level: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Logs the metrics returned by `snapshot`, as sent in the heartbeat, each
+/// time the process receives SIGUSR1.
+pub fn spawn_status_dump(snapshot: impl Fn() -> HeartbeatMetrics + Send + 'static) -> Result<()> {
+    let mut usr1 = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            match serde_json::to_string(&snapshot()) {
+                Ok(status) => info!("SIGUSR1 received, status: {}", status),
+                Err(e) => warn!("Failed to serialize the status: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[tokio::test]
+    async fn test_sigusr2_toggles_debug_logging() {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        // Kept alive, not installed: the handle only needs the layer to exist
+        let _subscriber = Registry::default().with(layer);
+        let current = || handle.with_current(|filter| filter.to_string()).unwrap();
+        let toggle = DebugToggle::new(handle.clone(), "info".into(), "debug".into(), false);
+        spawn_debug_toggle(toggle).unwrap();
+
+        nix::sys::signal::raise(nix::sys::signal::Signal::SIGUSR2).unwrap();
+        for _ in 0..50 {
+            if current() == "debug" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(current(), "debug");
+
+        nix::sys::signal::raise(nix::sys::signal::Signal::SIGUSR2).unwrap();
+        for _ in 0..50 {
+            if current() == "info" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(current(), "info");
+    }
+
+    #[tokio::test]
+    async fn test_sigusr1_takes_a_status_snapshot() {
+        let snapshots = Arc::new(AtomicUsize::new(0));
+        let counted = snapshots.clone();
+        spawn_status_dump(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            HeartbeatMetrics::default()
+        })
+        .unwrap();
+
+        nix::sys::signal::raise(nix::sys::signal::Signal::SIGUSR1).unwrap();
+        for _ in 0..50 {
+            if snapshots.load(Ordering::SeqCst) == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(snapshots.load(Ordering::SeqCst), 1);
+    }
+}
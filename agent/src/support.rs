@@ -0,0 +1,255 @@
+//! Support bundles: a tarball describing the agent's configuration and host
+//! for troubleshooting, built without secrets or task contents.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config::AgentConfig;
+use crate::executor::{CommandExecutor, ExecOptions};
+use crate::features;
+use crate::system::SystemInfo;
+
+/// How long an executor may take to report its version.
+const VERSION_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Version reported by one detected executor.
+#[derive(Debug, Serialize)]
+struct ExecutorVersion {
+    executor: String,
+    /// First line of the version output, when the executor supports a query.
+    version: Option<String>,
+}
+
+/// Writes a gzip-compressed tarball with an `index.json` and one JSON file
+/// per section.
+///
+/// Only typed, already-sanitized data goes in: the configuration is written
+/// through its `Serialize` impl, which emits `[REDACTED]` for every secret.
+pub async fn write_bundle(
+    output: &Path,
+    config: &AgentConfig,
+    sys_info: &SystemInfo,
+) -> Result<()> {
+    let executors = executor_versions(sys_info).await;
+    let info = features::build_info();
+    let build = json!({
+        "version": info.version,
+        "target": info.target,
+        "target_os": std::env::consts::OS,
+        "target_arch": std::env::consts::ARCH,
+        "debug_build": cfg!(debug_assertions),
+        "features": info.features,
+        "static_linkage": info.static_linkage,
+        "tls_backend": info.tls_backend,
+    });
+
+    let sections: [(&str, serde_json::Value); 4] = [
+        ("config.json", serde_json::to_value(config)?),
+        ("system.json", serde_json::to_value(sys_info)?),
+        ("executors.json", serde_json::to_value(executors)?),
+        ("build.json", build),
+    ];
+    let index = json!({
+        "generated_at": Local::now().to_rfc3339(),
+        "agent_version": env!("CARGO_PKG_VERSION"),
+        "files": sections.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+        "omitted": {
+            "agent_log": "the agent logs to stdout/stderr only, no log file to tail",
+            "connection_statistics": "kept in memory by the running agent only",
+            "spool": "the agent keeps no spool directory",
+        },
+    });
+
+    let file = std::fs::File::create(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::new(config.gzip_level)));
+    for (name, value) in std::iter::once(("index.json", index)).chain(sections) {
+        let data = serde_json::to_vec_pretty(&value)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(Local::now().timestamp().max(0) as u64);
+        header.set_cksum();
+        archive.append_data(&mut header, name, data.as_slice())?;
+    }
+    archive.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+/// Queries each detected executor for its version without a shell.
+async fn executor_versions(sys_info: &SystemInfo) -> Vec<ExecutorVersion> {
+    let executor = CommandExecutor::new();
+    let mut versions = Vec::new();
+    for name in &sys_info.executors {
+        let version = match version_argv(name) {
+            Some(argv) => {
+                let result = executor
+                    .execute_argv(&argv, VERSION_PROBE_TIMEOUT, &ExecOptions::default())
+                    .await;
+                result
+                    .success
+                    .then(|| result.output.lines().next().map(str::to_string))
+                    .flatten()
+            }
+            None => None,
+        };
+        versions.push(ExecutorVersion {
+            executor: name.clone(),
+            version,
+        });
+    }
+    versions
+}
+
+/// Command printing an executor's version; `sh` has no portable version flag.
+fn version_argv(executor: &str) -> Option<Vec<String>> {
+    let argv: &[&str] = match executor {
+        "bash" | "zsh" | "python3" | "python" | "pwsh" => &["--version"],
+        "powershell" => &[
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "$PSVersionTable.PSVersion.ToString()",
+        ],
+        "cmd" => &["/C", "ver"],
+        _ => return None,
+    };
+    Some(
+        std::iter::once(executor)
+            .chain(argv.iter().copied())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::SecretString;
+    use std::io::Read;
+
+    /// Reads every entry of a bundle as (name, contents).
+    fn read_bundle(path: &Path) -> Vec<(String, String)> {
+        let file = std::fs::File::open(path).unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                (name, contents)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_bundle_excludes_secrets() {
+        let mut config = AgentConfig {
+            agent_secret: Some(SecretString::from("planted-agent-secret")),
+            ..AgentConfig::default()
+        };
+        config
+            .otel
+            .headers
+            .insert("authorization".to_string(), "planted-otel-token".into());
+        let output =
+            std::env::temp_dir().join(format!("autostrike_bundle_{}.tar.gz", std::process::id()));
+
+        write_bundle(&output, &config, &SystemInfo::gather())
+            .await
+            .unwrap();
+        let entries = read_bundle(&output);
+        std::fs::remove_file(&output).unwrap();
+
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "index.json",
+                "config.json",
+                "system.json",
+                "executors.json",
+                "build.json"
+            ]
+        );
+        let everything: String = entries
+            .iter()
+            .map(|(_, contents)| contents.as_str())
+            .collect();
+        assert!(!everything.contains("planted-agent-secret"));
+        assert!(!everything.contains("planted-otel-token"));
+        assert!(everything.contains("[REDACTED]"));
+    }
+
+    #[tokio::test]
+    async fn test_bundle_index_lists_sections() {
+        let output = std::env::temp_dir().join(format!(
+            "autostrike_bundle_index_{}.tar.gz",
+            std::process::id()
+        ));
+
+        write_bundle(&output, &AgentConfig::default(), &SystemInfo::gather())
+            .await
+            .unwrap();
+        let entries = read_bundle(&output);
+        std::fs::remove_file(&output).unwrap();
+
+        let index: serde_json::Value = serde_json::from_str(&entries[0].1).unwrap();
+        assert_eq!(index["agent_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(index["files"].as_array().unwrap().len(), 4);
+        assert!(index["omitted"]["agent_log"].is_string());
+
+        let build: serde_json::Value = serde_json::from_str(&entries[4].1).unwrap();
+        assert_eq!(
+            build["features"],
+            serde_json::json!(features::build_info().features)
+        );
+        assert_eq!(build["tls_backend"], "rustls");
+    }
+
+    #[tokio::test]
+    async fn test_bundle_gzip_levels() {
+        let sys_info = SystemInfo::gather();
+        let mut sizes = Vec::new();
+        for level in [0, 1, 9] {
+            let config = AgentConfig {
+                gzip_level: level,
+                ..AgentConfig::default()
+            };
+            let output = std::env::temp_dir().join(format!(
+                "autostrike_bundle_level{}_{}.tar.gz",
+                level,
+                std::process::id()
+            ));
+
+            write_bundle(&output, &config, &sys_info).await.unwrap();
+            sizes.push(std::fs::metadata(&output).unwrap().len());
+            let entries = read_bundle(&output);
+            std::fs::remove_file(&output).unwrap();
+            assert_eq!(entries.len(), 5);
+        }
+        // Level 0 only stores the data
+        assert!(sizes[0] > sizes[2], "{:?}", sizes);
+    }
+
+    #[test]
+    fn test_version_argv() {
+        assert_eq!(
+            version_argv("bash"),
+            Some(vec!["bash".to_string(), "--version".to_string()])
+        );
+        assert_eq!(version_argv("cmd").unwrap()[1..], ["/C", "ver"]);
+        assert!(version_argv("sh").is_none());
+    }
+}
@@ -0,0 +1,166 @@
+//! Task result summaries written to the local syslog (Unix) or the
+//! Application event log (Windows), so they flow into a SOC's existing
+//! collection. Independent of the tracing logger and its level.
+
+/// Name the agent logs under, NUL-terminated for the system calls.
+#[cfg_attr(not(any(unix, windows)), allow(dead_code))]
+const IDENT: &str = "autostrike-agent\0";
+
+/// Outcome of a task, as summarized in the system log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskRecord<'a> {
+    pub task_id: &'a str,
+    pub technique_id: &'a str,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+}
+
+/// Severity of a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The task succeeded.
+    Info,
+    /// The task failed.
+    Warning,
+}
+
+/// Severity and message logged for `record`, as `key=value` pairs with the
+/// strings quoted.
+pub fn format(record: &TaskRecord) -> (Severity, String) {
+    let severity = if record.success {
+        Severity::Info
+    } else {
+        Severity::Warning
+    };
+    let mut message = format!(
+        "task_result task_id={:?} technique_id={:?} success={}",
+        record.task_id, record.technique_id, record.success
+    );
+    if let Some(code) = record.exit_code {
+        message.push_str(&format!(" exit_code={}", code));
+    }
+    (severity, message)
+}
+
+/// Writes `record` to the system log. Best effort: failures are ignored.
+pub fn report(record: &TaskRecord) {
+    let (severity, message) = format(record);
+    send(severity, &message);
+}
+
+#[cfg(unix)]
+fn send(severity: Severity, message: &str) {
+    use nix::libc;
+    use std::ffi::CString;
+    use std::sync::Once;
+
+    static OPEN: Once = Once::new();
+
+    let Ok(message) = CString::new(message.replace('\0', "")) else {
+        return;
+    };
+    let priority = match severity {
+        Severity::Info => libc::LOG_INFO,
+        Severity::Warning => libc::LOG_WARNING,
+    };
+    // SAFETY: openlog keeps the identity, which is static and NUL-terminated;
+    // the message goes through a "%s" format, so it is never interpreted.
+    unsafe {
+        OPEN.call_once(|| {
+            libc::openlog(
+                IDENT.as_ptr().cast(),
+                libc::LOG_PID | libc::LOG_NDELAY,
+                libc::LOG_DAEMON,
+            )
+        });
+        libc::syslog(priority, c"%s".as_ptr(), message.as_ptr());
+    }
+}
+
+#[cfg(windows)]
+fn send(severity: Severity, message: &str) {
+    use std::ptr::null_mut;
+    use winapi::um::winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW};
+    use winapi::um::winnt::{EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE};
+
+    let source: Vec<u16> = IDENT.encode_utf16().collect();
+    let message: Vec<u16> = message.encode_utf16().chain(Some(0)).collect();
+    let kind = match severity {
+        Severity::Info => EVENTLOG_INFORMATION_TYPE,
+        Severity::Warning => EVENTLOG_WARNING_TYPE,
+    };
+    // SAFETY: the strings are NUL-terminated and outlive the calls; the
+    // handle is deregistered once.
+    unsafe {
+        let handle = RegisterEventSourceW(null_mut(), source.as_ptr());
+        if handle.is_null() {
+            return;
+        }
+        let mut strings = [message.as_ptr()];
+        ReportEventW(
+            handle,
+            kind,
+            0,
+            1,
+            null_mut(),
+            1,
+            0,
+            strings.as_mut_ptr(),
+            null_mut(),
+        );
+        DeregisterEventSource(handle);
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn send(_severity: Severity, _message: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        let record = TaskRecord {
+            task_id: "task-1",
+            technique_id: "T1082",
+            success: true,
+            exit_code: Some(0),
+        };
+        assert_eq!(
+            format(&record),
+            (
+                Severity::Info,
+                r#"task_result task_id="task-1" technique_id="T1082" success=true exit_code=0"#
+                    .to_string()
+            )
+        );
+
+        // Quotes keep a hostile task ID from forging fields
+        let record = TaskRecord {
+            task_id: "x\" success=true",
+            success: false,
+            exit_code: None,
+            ..record
+        };
+        assert_eq!(
+            format(&record),
+            (
+                Severity::Warning,
+                r#"task_result task_id="x\" success=true" technique_id="T1082" success=false"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_report_does_not_fail_without_syslog_daemon() {
+        report(&TaskRecord {
+            task_id: "task-1",
+            technique_id: "T1082",
+            success: false,
+            exit_code: Some(1),
+        });
+    }
+}
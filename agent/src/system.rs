@@ -1,9 +1,12 @@
 //! System information gathering for agent registration.
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sysinfo::{System, SystemExt};
+use sysinfo::{DiskExt, Pid, PidExt, ProcessExt, System, SystemExt};
 use which::which;
 
+use crate::protocol::TaskRequirements;
+
 /// System information collected from the host machine.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemInfo {
@@ -19,6 +22,48 @@ pub struct SystemInfo {
     pub os_version: String,
     /// CPU architecture (x86_64, aarch64, etc.).
     pub architecture: String,
+    /// Mounted filesystems with their free space (best-effort).
+    #[serde(default)]
+    pub filesystems: Vec<FsInfo>,
+    /// Pointer width of the agent process, in bits.
+    #[serde(default)]
+    pub process_bits: u32,
+    /// Pointer width of the operating system, in bits (taken to match the
+    /// process outside Windows).
+    #[serde(default)]
+    pub os_bits: u32,
+    /// 32-bit agent on 64-bit Windows: the shells it spawns are 32-bit too
+    /// and see SysWOW64 filesystem and registry redirection.
+    #[serde(default)]
+    pub is_wow64: bool,
+    /// Process ID of the agent.
+    #[serde(default)]
+    pub agent_pid: u32,
+    /// Process ID of the agent's parent (service manager, shell), if known.
+    #[serde(default)]
+    pub agent_ppid: Option<u32>,
+    /// Path of the agent executable, if known.
+    #[serde(default)]
+    pub agent_exe: Option<String>,
+    /// How the agent was started (see [`detect_launch_context`]).
+    #[serde(default)]
+    pub launch_context: String,
+}
+
+/// Set only in WOW64 processes, to the native processor architecture.
+const WOW64_NATIVE_ARCH_VAR: &str = "PROCESSOR_ARCHITEW6432";
+
+/// A mounted filesystem and its capacity.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FsInfo {
+    /// Mount point (drive root on Windows).
+    pub mount_point: String,
+    /// Filesystem type (ext4, ntfs, apfs, etc.).
+    pub fs_type: String,
+    /// Total size in bytes.
+    pub total_bytes: u64,
+    /// Bytes available to the agent.
+    pub free_bytes: u64,
 }
 
 impl SystemInfo {
@@ -38,7 +83,30 @@ impl SystemInfo {
         // Detect available executors
         let executors = Self::detect_executors();
 
-        let sys = System::new();
+        let mut sys = System::new();
+        sys.refresh_disks_list();
+
+        let agent_pid = std::process::id();
+        sys.refresh_process(Pid::from_u32(agent_pid));
+        let agent_ppid = sys
+            .process(Pid::from_u32(agent_pid))
+            .and_then(|process| process.parent())
+            .map(|parent| parent.as_u32());
+        let parent_name = agent_ppid.and_then(|ppid| {
+            let ppid = Pid::from_u32(ppid);
+            sys.refresh_process(ppid);
+            sys.process(ppid).map(|parent| parent.name().to_string())
+        });
+        let clues = LaunchClues {
+            ppid: agent_ppid,
+            parent_name: parent_name.as_deref(),
+            session_id: session_id(),
+        };
+        let launch_context = detect_launch_context(&clues, |name| std::env::var(name).ok());
+
+        let process_bits = usize::BITS;
+        let (os_bits, is_wow64) =
+            detect_os_bits(process_bits, cfg!(windows), |name| std::env::var(name).ok());
 
         SystemInfo {
             hostname: sys.host_name().unwrap_or_else(|| "unknown".to_string()),
@@ -47,9 +115,32 @@ impl SystemInfo {
             executors,
             os_version: sys.os_version().unwrap_or_else(|| "unknown".to_string()),
             architecture: std::env::consts::ARCH.to_string(),
+            filesystems: Self::list_filesystems(&sys),
+            process_bits,
+            os_bits,
+            is_wow64,
+            agent_pid,
+            agent_ppid,
+            agent_exe: std::env::current_exe()
+                .ok()
+                .map(|path| path.to_string_lossy().into_owned()),
+            launch_context: launch_context.to_string(),
         }
     }
 
+    /// Lists the filesystems sysinfo found; empty when the platform offers none.
+    fn list_filesystems(sys: &System) -> Vec<FsInfo> {
+        sys.disks()
+            .iter()
+            .map(|disk| FsInfo {
+                mount_point: disk.mount_point().to_string_lossy().into_owned(),
+                fs_type: String::from_utf8_lossy(disk.file_system()).into_owned(),
+                total_bytes: disk.total_space(),
+                free_bytes: disk.available_space(),
+            })
+            .collect()
+    }
+
     fn detect_executors() -> Vec<String> {
         let mut executors = Vec::new();
 
@@ -78,6 +169,134 @@ impl SystemInfo {
 
         executors
     }
+
+    /// Why this host does not meet `requires`, if it does not.
+    pub fn unmet(&self, requires: &TaskRequirements) -> Option<String> {
+        if let Some(platform) = &requires.platform {
+            if !platform.eq_ignore_ascii_case(&self.platform) {
+                return Some(format!(
+                    "requires platform {}, host is {}",
+                    platform, self.platform
+                ));
+            }
+        }
+        if let Some(min) = &requires.min_os_version {
+            let older = match (version_numbers(&self.os_version), version_numbers(min)) {
+                (Some(host), Some(min)) => compare_versions(&host, &min).is_lt(),
+                _ => true,
+            };
+            if older {
+                return Some(format!(
+                    "requires OS version {} or later, host has {}",
+                    min, self.os_version
+                ));
+            }
+        }
+        if let Some(executor) = &requires.executor {
+            if !self.executors.contains(executor) {
+                return Some(format!(
+                    "requires executor {}, not available on this host",
+                    executor
+                ));
+            }
+        }
+        None
+    }
+}
+
+/// Numbers of a dotted version, up to the first part not starting with a
+/// digit (`5.15.0-91-generic` gives 5, 15, 0). `None` without any.
+fn version_numbers(version: &str) -> Option<Vec<u64>> {
+    let numbers: Vec<u64> = version
+        .trim()
+        .split('.')
+        .map_while(|part| {
+            let digits = part.len() - part.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            part[..digits].parse().ok()
+        })
+        .collect();
+    (!numbers.is_empty()).then_some(numbers)
+}
+
+/// Compares versions part by part, missing parts counting as 0.
+fn compare_versions(a: &[u64], b: &[u64]) -> std::cmp::Ordering {
+    (0..a.len().max(b.len()))
+        .map(|i| {
+            let part = |v: &[u64]| v.get(i).copied().unwrap_or(0);
+            part(a).cmp(&part(b))
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// Returns the OS pointer width and whether a process of `process_bits`
+/// runs under WOW64, using `env` to read environment variables.
+fn detect_os_bits(
+    process_bits: u32,
+    windows: bool,
+    env: impl Fn(&str) -> Option<String>,
+) -> (u32, bool) {
+    let is_wow64 = windows && process_bits == 32 && env(WOW64_NATIVE_ARCH_VAR).is_some();
+    (if is_wow64 { 64 } else { process_bits }, is_wow64)
+}
+
+/// Parent and session of the agent process, the clues to how it was started.
+#[derive(Debug, Default)]
+struct LaunchClues<'a> {
+    ppid: Option<u32>,
+    parent_name: Option<&'a str>,
+    /// Windows session; services run in session 0.
+    session_id: Option<u32>,
+}
+
+/// Tells how the agent was started, best effort, using `env` to read
+/// environment variables: `systemd`, `launchd`, `windows_service`, `service`
+/// (`SERVICE` is set), `daemon` (parent is init), `interactive`, or
+/// `unknown` when the parent is unknown.
+fn detect_launch_context(
+    clues: &LaunchClues,
+    env: impl Fn(&str) -> Option<String>,
+) -> &'static str {
+    let parent = clues.parent_name.unwrap_or_default().to_ascii_lowercase();
+    // systemd sets INVOCATION_ID for its units, but shells may inherit it
+    if parent == "systemd" || (clues.ppid == Some(1) && env("INVOCATION_ID").is_some()) {
+        "systemd"
+    } else if parent == "launchd" {
+        "launchd"
+    } else if clues.session_id == Some(0) || parent == "services.exe" {
+        "windows_service"
+    } else if env("SERVICE").is_some_and(|value| !value.is_empty()) {
+        "service"
+    } else if clues.ppid == Some(1) {
+        "daemon"
+    } else if clues.ppid.is_some() {
+        "interactive"
+    } else {
+        "unknown"
+    }
+}
+
+/// Session of the agent process; only Windows has them.
+#[cfg(windows)]
+fn session_id() -> Option<u32> {
+    use winapi::um::processthreadsapi::{GetCurrentProcessId, ProcessIdToSessionId};
+
+    let mut session = 0;
+    // SAFETY: writes one DWORD to a local.
+    let ok = unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session) };
+    (ok != 0).then_some(session)
+}
+
+#[cfg(not(windows))]
+fn session_id() -> Option<u32> {
+    None
+}
+
+/// Resolves `binary` on the `PATH` like `which`/`where`, without running it.
+pub fn find_binary(binary: &str) -> Option<String> {
+    which(binary)
+        .ok()
+        .map(|path| path.to_string_lossy().into_owned())
 }
 
 #[cfg(test)]
@@ -181,6 +400,182 @@ mod tests {
         assert_eq!(info.executors, vec!["sh", "bash"]);
         assert_eq!(info.os_version, "5.0");
         assert_eq!(info.architecture, "x86_64");
+        assert!(info.filesystems.is_empty());
+        assert!(!info.is_wow64);
+    }
+
+    #[test]
+    fn test_system_info_serializes_bitness() {
+        let info = SystemInfo {
+            process_bits: 32,
+            os_bits: 64,
+            is_wow64: true,
+            ..SystemInfo::gather()
+        };
+        let json = serde_json::to_value(&info).unwrap();
+
+        assert_eq!(json["process_bits"], 32);
+        assert_eq!(json["os_bits"], 64);
+        assert_eq!(json["is_wow64"], true);
+        let parsed: SystemInfo = serde_json::from_value(json).unwrap();
+        assert!(parsed.is_wow64);
+    }
+
+    #[test]
+    fn test_gather_reports_own_bitness() {
+        let info = SystemInfo::gather();
+
+        assert_eq!(info.process_bits, usize::BITS);
+        assert!(info.os_bits >= info.process_bits);
+    }
+
+    #[test]
+    fn test_gather_reports_agent_process() {
+        let info = SystemInfo::gather();
+
+        assert_eq!(info.agent_pid, std::process::id());
+        #[cfg(unix)]
+        assert_eq!(info.agent_ppid, Some(std::os::unix::process::parent_id()));
+        #[cfg(not(unix))]
+        assert!(info.agent_ppid.is_some_and(|ppid| ppid != info.agent_pid));
+        assert_ne!(info.launch_context, "unknown");
+        let exe = info.agent_exe.unwrap();
+        assert!(std::path::Path::new(&exe).is_absolute());
+        assert_eq!(
+            std::path::Path::new(&exe),
+            std::env::current_exe().unwrap().as_path()
+        );
+    }
+
+    #[test]
+    fn test_detect_os_bits() {
+        let wow64 = |name: &str| (name == WOW64_NATIVE_ARCH_VAR).then(|| "AMD64".to_string());
+        let native = |_: &str| None;
+
+        assert_eq!(detect_os_bits(32, true, wow64), (64, true));
+        assert_eq!(detect_os_bits(32, true, native), (32, false));
+        assert_eq!(detect_os_bits(64, true, wow64), (64, false));
+        assert_eq!(detect_os_bits(32, false, wow64), (32, false));
+    }
+
+    #[test]
+    fn test_detect_launch_context() {
+        let no_env = |_: &str| None;
+        let clues = |ppid, parent_name, session_id| LaunchClues {
+            ppid,
+            parent_name,
+            session_id,
+        };
+
+        let systemd = clues(Some(1), Some("systemd"), None);
+        assert_eq!(detect_launch_context(&systemd, no_env), "systemd");
+        let user_unit = clues(Some(1830), Some("systemd"), None);
+        assert_eq!(detect_launch_context(&user_unit, no_env), "systemd");
+        let invocation = |name: &str| (name == "INVOCATION_ID").then(|| "4f1c".to_string());
+        let init = clues(Some(1), Some("init"), None);
+        assert_eq!(detect_launch_context(&init, invocation), "systemd");
+        assert_eq!(detect_launch_context(&init, no_env), "daemon");
+
+        let launchd = clues(Some(1), Some("launchd"), None);
+        assert_eq!(detect_launch_context(&launchd, no_env), "launchd");
+
+        let session0 = clues(Some(712), Some("services.exe"), Some(0));
+        assert_eq!(detect_launch_context(&session0, no_env), "windows_service");
+        let scm = clues(Some(712), Some("SERVICES.EXE"), None);
+        assert_eq!(detect_launch_context(&scm, no_env), "windows_service");
+        let console = clues(Some(5120), Some("powershell.exe"), Some(1));
+        assert_eq!(detect_launch_context(&console, no_env), "interactive");
+
+        // A shell inheriting INVOCATION_ID is still interactive
+        let shell = clues(Some(2211), Some("bash"), None);
+        assert_eq!(detect_launch_context(&shell, invocation), "interactive");
+        let service = |name: &str| (name == "SERVICE").then(|| "autostrike".to_string());
+        assert_eq!(detect_launch_context(&shell, service), "service");
+
+        assert_eq!(
+            detect_launch_context(&LaunchClues::default(), no_env),
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn test_gather_reports_filesystems() {
+        let info = SystemInfo::gather();
+
+        assert!(info
+            .filesystems
+            .iter()
+            .any(|fs| fs.total_bytes > 0 && !fs.mount_point.is_empty()));
+        for fs in &info.filesystems {
+            assert!(fs.free_bytes <= fs.total_bytes);
+        }
+    }
+
+    #[test]
+    fn test_find_binary_present() {
+        #[cfg(unix)]
+        let path = find_binary("sh").unwrap();
+        #[cfg(windows)]
+        let path = find_binary("cmd").unwrap();
+
+        assert!(std::path::Path::new(&path).is_absolute());
+    }
+
+    #[test]
+    fn test_unmet_requirements() {
+        let info = SystemInfo {
+            platform: "linux".to_string(),
+            os_version: "5.15.0-91-generic".to_string(),
+            executors: vec!["sh".to_string(), "bash".to_string()],
+            ..SystemInfo::gather()
+        };
+        let requires =
+            |platform: Option<&str>, min: Option<&str>, executor: Option<&str>| TaskRequirements {
+                platform: platform.map(str::to_string),
+                min_os_version: min.map(str::to_string),
+                executor: executor.map(str::to_string),
+            };
+
+        for met in [
+            requires(None, None, None),
+            requires(Some("linux"), Some("5.0"), Some("bash")),
+            requires(Some("Linux"), Some("5.15"), None),
+            requires(None, Some("5.15.0"), None),
+        ] {
+            assert_eq!(info.unmet(&met), None, "{:?}", met);
+        }
+
+        assert_eq!(
+            info.unmet(&requires(Some("windows"), None, None)).unwrap(),
+            "requires platform windows, host is linux"
+        );
+        assert_eq!(
+            info.unmet(&requires(None, Some("6.1"), None)).unwrap(),
+            "requires OS version 6.1 or later, host has 5.15.0-91-generic"
+        );
+        assert_eq!(
+            info.unmet(&requires(None, None, Some("zsh"))).unwrap(),
+            "requires executor zsh, not available on this host"
+        );
+        let unknown = SystemInfo {
+            os_version: "unknown".to_string(),
+            ..info
+        };
+        assert!(unknown.unmet(&requires(None, Some("5.0"), None)).is_some());
+    }
+
+    #[test]
+    fn test_version_numbers() {
+        assert_eq!(version_numbers("10.0.19045"), Some(vec![10, 0, 19045]));
+        assert_eq!(version_numbers("22.04 LTS"), Some(vec![22, 4]));
+        assert_eq!(version_numbers("unknown"), None);
+        assert!(compare_versions(&[5, 15], &[5, 15, 0]).is_eq());
+        assert!(compare_versions(&[5, 9], &[5, 10]).is_lt());
+    }
+
+    #[test]
+    fn test_find_binary_absent() {
+        assert!(find_binary("autostrike-no-such-binary").is_none());
     }
 
     #[test]
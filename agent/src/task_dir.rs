@@ -0,0 +1,91 @@
+//! Per-task temporary directories, so each task's artifacts stay apart and
+//! are torn down with it.
+
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, warn};
+
+/// A task's private directory, removed when dropped unless kept.
+#[derive(Debug)]
+pub struct TaskDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TaskDir {
+    /// Creates a fresh directory under `root`, named after `task_id`. With
+    /// `keep`, it is left in place for inspection once the task is done.
+    pub fn create(root: &Path, task_id: &str, keep: bool) -> std::io::Result<Self> {
+        let name: String = task_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        // The suffix keeps a re-sent task from reusing a previous run's leftovers
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let path = root.join(format!("autostrike-{}-{}", name, &suffix[..8]));
+
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut std::fs::DirBuilder::new(), 0o700)
+            .create(&path)?;
+        #[cfg(not(unix))]
+        std::fs::create_dir(&path)?;
+        debug!("Created task directory {}", path.display());
+        Ok(Self { path, keep })
+    }
+
+    /// Path of the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TaskDir {
+    fn drop(&mut self) {
+        if self.keep {
+            debug!("Keeping task directory {}", self.path.display());
+            return;
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            warn!(
+                "Failed to remove task directory {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_dir_removed_on_drop() {
+        let dir = TaskDir::create(&std::env::temp_dir(), "task/../1", false).unwrap();
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("loot.txt"), "loot").unwrap();
+
+        assert_eq!(path.parent(), Some(std::env::temp_dir().as_path()));
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("autostrike-task____1-"), "{}", name);
+
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_kept_task_dir_survives_drop() {
+        let dir = TaskDir::create(&std::env::temp_dir(), "kept", true).unwrap();
+        let path = dir.path().to_path_buf();
+
+        drop(dir);
+        assert!(path.is_dir());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
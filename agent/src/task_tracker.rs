@@ -0,0 +1,219 @@
+//! Tracking of in-flight tasks, so the server can cancel them and the number
+//! running at once stays bounded.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{oneshot, watch, OwnedSemaphorePermit, Semaphore};
+
+/// A tracked task, running or waiting for a slot.
+struct Entry {
+    task_id: String,
+    /// Whether its `task_result` was already sent (it may be in cleanup).
+    reported: bool,
+    cancel: oneshot::Sender<Cancelled>,
+}
+
+/// Sent to a tracked task when it is cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled {
+    /// The task had already sent its result, so none is owed.
+    pub reported: bool,
+}
+
+/// Registry of tasks accepted from the server and not yet finished.
+pub struct TaskTracker {
+    next_key: AtomicU64,
+    tasks: Mutex<HashMap<u64, Entry>>,
+    slots: Arc<Semaphore>,
+    /// Line of tasks waiting for a slot; only its head waits on the pause and
+    /// the slots, so tasks start in arrival order.
+    turn: Semaphore,
+    /// Set while the server has paused task processing.
+    paused: watch::Sender<bool>,
+}
+
+impl TaskTracker {
+    /// Creates a tracker letting `max_concurrent` tasks run at once (at least one).
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            next_key: AtomicU64::new(0),
+            tasks: Mutex::new(HashMap::new()),
+            slots: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            turn: Semaphore::new(1),
+            paused: watch::channel(false).0,
+        }
+    }
+
+    /// Starts tracking a task. Returns its key, to pass to [`Self::finish`],
+    /// and a receiver that resolves if the task is cancelled.
+    pub fn track(&self, task_id: &str) -> (u64, oneshot::Receiver<Cancelled>) {
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+        let (cancel, cancelled) = oneshot::channel();
+        self.map().insert(
+            key,
+            Entry {
+                task_id: task_id.to_string(),
+                reported: false,
+                cancel,
+            },
+        );
+        (key, cancelled)
+    }
+
+    /// Waits for processing not to be paused, then for a free slot, held
+    /// until the returned permit is dropped. Tasks waiting out a pause hold
+    /// no slot, so they do not make the agent look saturated; they still
+    /// start in arrival order.
+    pub async fn slot(&self) -> OwnedSemaphorePermit {
+        let _turn = self
+            .turn
+            .acquire()
+            .await
+            .expect("task line is never closed");
+        let mut paused = self.paused.subscribe();
+        loop {
+            let _ = paused.wait_for(|paused| !paused).await;
+            let permit = self
+                .slots
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("task slots are never closed");
+            // Paused again while waiting for the slot
+            if !self.is_paused() {
+                return permit;
+            }
+        }
+    }
+
+    /// Whether every slot is taken, so a new task would have to wait.
+    pub fn is_saturated(&self) -> bool {
+        self.slots.available_permits() == 0
+    }
+
+    /// Pauses or resumes task processing; tasks already running carry on.
+    /// Returns whether it was paused before.
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.paused.send_replace(paused)
+    }
+
+    /// Whether task processing is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Notes that `task_id` sent its result; cancelling it later must not
+    /// produce a second one.
+    pub fn mark_reported(&self, task_id: &str) {
+        for entry in self.map().values_mut() {
+            if entry.task_id == task_id {
+                entry.reported = true;
+            }
+        }
+    }
+
+    /// Stops tracking a finished task.
+    pub fn finish(&self, key: u64) {
+        self.map().remove(&key);
+    }
+
+    /// Cancels every tracked task and returns their IDs.
+    pub fn cancel_all(&self) -> Vec<String> {
+        let mut entries: Vec<(u64, Entry)> = self.map().drain().collect();
+        entries.sort_by_key(|(key, _)| *key);
+        entries
+            .into_iter()
+            .map(|(_, entry)| {
+                let _ = entry.cancel.send(Cancelled {
+                    reported: entry.reported,
+                });
+                entry.task_id
+            })
+            .collect()
+    }
+
+    /// Whether no task is running or waiting for a slot.
+    pub fn is_empty(&self) -> bool {
+        self.map().is_empty()
+    }
+
+    /// Number of tasks running or waiting for a slot.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.map().len()
+    }
+
+    fn map(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancel_all_signals_every_task() {
+        let tracker = TaskTracker::new(1);
+        let (_, first) = tracker.track("task-1");
+        let (_, second) = tracker.track("task-2");
+        tracker.mark_reported("task-2");
+
+        assert_eq!(tracker.cancel_all(), ["task-1", "task-2"]);
+        assert_eq!(first.await.unwrap(), Cancelled { reported: false });
+        assert_eq!(second.await.unwrap(), Cancelled { reported: true });
+        assert_eq!(tracker.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_finished_task_is_not_cancelled() {
+        let tracker = TaskTracker::new(1);
+        let (key, cancelled) = tracker.track("task-1");
+        tracker.finish(key);
+
+        assert!(tracker.is_empty());
+        assert!(tracker.cancel_all().is_empty());
+        assert!(cancelled.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_paused_tracker_holds_slots() {
+        let tracker = Arc::new(TaskTracker::new(2));
+        assert!(!tracker.set_paused(true));
+        assert!(tracker.is_paused());
+
+        let waiting = tokio::spawn({
+            let tracker = tracker.clone();
+            async move { drop(tracker.slot().await) }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        // Waiting out the pause takes no slot
+        assert!(!tracker.is_saturated());
+        let held = tracker.slots.clone().try_acquire_many_owned(2).unwrap();
+        drop(held);
+
+        assert!(tracker.set_paused(false));
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_slots_bound_concurrency() {
+        let tracker = TaskTracker::new(2);
+        let _first = tracker.slot().await;
+        let second = tracker.slot().await;
+
+        let third = tokio::time::timeout(Duration::from_millis(20), tracker.slot()).await;
+        assert!(third.is_err());
+
+        drop(second);
+        let third = tokio::time::timeout(Duration::from_millis(100), tracker.slot()).await;
+        assert!(third.is_ok());
+    }
+}
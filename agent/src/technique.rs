@@ -0,0 +1,33 @@
+//! MITRE ATT&CK technique identifiers.
+
+/// Parent of a sub-technique ID such as `T1059.001` (`T1059`); `None` for a
+/// base technique or an ID not in the `T<4 digits>.<3 digits>` form.
+pub fn parent(technique_id: &str) -> Option<&str> {
+    let (base, sub) = technique_id.split_once('.')?;
+    let digits = base.strip_prefix('T')?;
+    let numeric =
+        |part: &str, len: usize| part.len() == len && part.bytes().all(|b| b.is_ascii_digit());
+    (numeric(digits, 4) && numeric(sub, 3)).then_some(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parent_of_sub_technique() {
+        assert_eq!(parent("T1059.001"), Some("T1059"));
+        assert_eq!(parent("T1003.008"), Some("T1003"));
+    }
+
+    #[test]
+    fn test_no_parent() {
+        assert_eq!(parent("T1059"), None);
+        assert_eq!(parent("T1059."), None);
+        assert_eq!(parent("T1059.1"), None);
+        assert_eq!(parent("t1059.001"), None);
+        assert_eq!(parent("TA0002.001"), None);
+        assert_eq!(parent("T1059.001.002"), None);
+        assert_eq!(parent(""), None);
+    }
+}
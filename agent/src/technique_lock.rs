@@ -0,0 +1,118 @@
+//! Per-technique locks that keep two runs of the same technique from
+//! overlapping on one agent.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Map of technique IDs to the lock held by the run in progress.
+///
+/// Entries exist only while a run holds or awaits the lock, so completed
+/// techniques leave nothing behind.
+#[derive(Debug, Default)]
+pub struct TechniqueLocks {
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl TechniqueLocks {
+    /// Waits until no other run of `technique_id` holds the lock, then takes it
+    /// until the returned guard is dropped.
+    pub async fn acquire(self: &Arc<Self>, technique_id: &str) -> TechniqueGuard {
+        let lock = self
+            .map()
+            .entry(technique_id.to_string())
+            .or_default()
+            .clone();
+        let guard = lock.clone().lock_owned().await;
+
+        TechniqueGuard {
+            locks: self.clone(),
+            technique_id: technique_id.to_string(),
+            lock,
+            guard: Some(guard),
+        }
+    }
+
+    /// Number of techniques currently running or awaited.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.map().len()
+    }
+
+    fn map(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<AsyncMutex<()>>>> {
+        self.locks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Holds a technique's lock; releases it and drops the map entry once no
+/// other run is waiting.
+pub struct TechniqueGuard {
+    locks: Arc<TechniqueLocks>,
+    technique_id: String,
+    lock: Arc<AsyncMutex<()>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for TechniqueGuard {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        let mut map = self.locks.map();
+        // Waiters hold their own reference; only the map and this guard remain
+        // when nobody else wants the technique.
+        if Arc::strong_count(&self.lock) == 2 {
+            map.remove(&self.technique_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_same_technique_waits() {
+        let locks = Arc::new(TechniqueLocks::default());
+        let first = locks.acquire("T1082").await;
+
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move { locks.acquire("T1082").await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        drop(first);
+        let second = tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(locks.len(), 1);
+        drop(second);
+        assert_eq!(locks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_different_techniques_do_not_wait() {
+        let locks = Arc::new(TechniqueLocks::default());
+        let _first = locks.acquire("T1082").await;
+
+        let second = tokio::time::timeout(Duration::from_millis(100), locks.acquire("T1003")).await;
+        assert!(second.is_ok());
+        assert_eq!(locks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_does_not_leak() {
+        let locks = Arc::new(TechniqueLocks::default());
+        let first = locks.acquire("T1082").await;
+
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(20), locks.acquire("T1082")).await;
+        assert!(cancelled.is_err());
+
+        drop(first);
+        assert_eq!(locks.len(), 0);
+    }
+}
@@ -0,0 +1,102 @@
+//! OpenTelemetry trace export.
+//!
+//! Spans are always emitted through `tracing`; with the `otel` feature they
+//! are also exported over OTLP/HTTP so task execution shows up in the same
+//! tracing backend as the server.
+
+use tracing::Span;
+
+#[cfg(feature = "otel")]
+use {
+    crate::config::OtelConfig,
+    anyhow::Result,
+    opentelemetry::propagation::TextMapPropagator,
+    opentelemetry::trace::TracerProvider as _,
+    opentelemetry_otlp::{WithExportConfig, WithHttpConfig},
+    opentelemetry_sdk::propagation::TraceContextPropagator,
+    opentelemetry_sdk::trace::{Sampler, SdkTracer, SdkTracerProvider},
+    opentelemetry_sdk::Resource,
+    std::collections::HashMap,
+    tracing::Subscriber,
+    tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt},
+    tracing_subscriber::registry::LookupSpan,
+};
+
+/// Name under which the agent reports its spans.
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "autostrike-agent";
+
+/// Flushes pending spans and shuts the exporter down when dropped.
+#[cfg(feature = "otel")]
+pub struct TracerGuard(SdkTracerProvider);
+
+#[cfg(feature = "otel")]
+impl Drop for TracerGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            eprintln!("Failed to flush trace exporter: {}", e);
+        }
+    }
+}
+
+/// Builds the OTLP exporting layer, or `None` when no endpoint is configured.
+#[cfg(feature = "otel")]
+pub fn layer<S>(
+    config: &OtelConfig,
+) -> Result<Option<(OpenTelemetryLayer<S, SdkTracer>, TracerGuard)>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Some(endpoint) = &config.endpoint else {
+        return Ok(None);
+    };
+
+    let headers = config
+        .headers
+        .iter()
+        .map(|(name, value)| (name.clone(), value.expose().to_string()))
+        .collect();
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .with_headers(headers)
+        .build()?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+    let tracer = provider.tracer(SERVICE_NAME);
+
+    Ok(Some((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        TracerGuard(provider),
+    )))
+}
+
+/// Continues the server's trace when a task carries a W3C `traceparent`.
+pub fn set_parent(span: &Span, traceparent: Option<&str>) {
+    #[cfg(feature = "otel")]
+    if let Some(traceparent) = traceparent {
+        let carrier = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+        let context = TraceContextPropagator::new().extract(&carrier);
+        let _ = span.set_parent(context);
+    }
+
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, traceparent);
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_disabled_without_endpoint() {
+        let layer = layer::<tracing_subscriber::Registry>(&OtelConfig::default()).unwrap();
+        assert!(layer.is_none());
+    }
+}
@@ -0,0 +1,375 @@
+//! TLS connection setup and interception diagnostics.
+//!
+//! Corporate proxies that re-sign TLS traffic make certificate verification
+//! fail on every reconnect. The verifier installed here records the chain the
+//! server presented whenever verification fails, so the failure can be
+//! analyzed and reported instead of only retried.
+
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Known TLS inspection products, matched case-insensitively against
+/// certificate issuer and subject names.
+const KNOWN_INSPECTION_CAS: &[(&str, &str)] = &[
+    ("zscaler", "Zscaler"),
+    ("blue coat", "Blue Coat"),
+    ("bluecoat", "Blue Coat"),
+    ("palo alto", "Palo Alto Networks"),
+];
+
+/// Summary of a certificate presented by the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CertSummary {
+    /// Certificate subject distinguished name.
+    pub subject: String,
+    /// Certificate issuer distinguished name.
+    pub issuer: String,
+    /// Hex-encoded SHA-256 of the subject public key info.
+    pub spki_sha256: String,
+    /// Start of the validity period.
+    pub not_before: String,
+    /// End of the validity period.
+    pub not_after: String,
+}
+
+impl CertSummary {
+    /// Parses a DER-encoded certificate into a summary.
+    /// Returns `None` if the certificate cannot be parsed.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let spki = Sha256::digest(cert.tbs_certificate.subject_pki.raw);
+
+        Some(Self {
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            spki_sha256: hex::encode(spki),
+            not_before: cert.validity().not_before.to_string(),
+            not_after: cert.validity().not_after.to_string(),
+        })
+    }
+}
+
+/// Analysis of a failed certificate verification.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TlsDiagnostics {
+    /// Verification error reported by rustls.
+    pub error: String,
+    /// Presented chain, end-entity certificate first.
+    pub chain: Vec<CertSummary>,
+    /// Recognized TLS inspection product, if any.
+    pub inspection_product: Option<String>,
+    /// Operator hint when the failure has a known cause.
+    pub hint: Option<String>,
+}
+
+impl TlsDiagnostics {
+    /// Analyzes a presented chain for signs of TLS inspection.
+    pub fn analyze(error: String, chain: Vec<CertSummary>) -> Self {
+        let inspection_product = detect_inspection_product(&chain);
+        let hint = inspection_product.as_ref().map(|product| {
+            format!(
+                "The server certificate was re-signed by {} TLS inspection; \
+                 ask the network team to exempt the AutoStrike server from inspection",
+                product
+            )
+        });
+
+        Self {
+            error,
+            chain,
+            inspection_product,
+            hint,
+        }
+    }
+
+    /// Emits the analysis as a structured `tls_error` log event.
+    pub fn log(&self) {
+        let leaf = self.chain.first();
+        warn!(
+            target: "tls_error",
+            error = %self.error,
+            subject = leaf.map(|c| c.subject.as_str()).unwrap_or(""),
+            issuer = leaf.map(|c| c.issuer.as_str()).unwrap_or(""),
+            spki_sha256 = leaf.map(|c| c.spki_sha256.as_str()).unwrap_or(""),
+            chain_length = self.chain.len(),
+            inspection_product = self.inspection_product.as_deref().unwrap_or(""),
+            "TLS certificate verification failed{}",
+            self.hint
+                .as_ref()
+                .map(|h| format!(": {}", h))
+                .unwrap_or_default()
+        );
+    }
+}
+
+/// Returns the inspection product whose name appears in any certificate of the chain.
+fn detect_inspection_product(chain: &[CertSummary]) -> Option<String> {
+    chain.iter().find_map(|cert| {
+        let names = format!("{} {}", cert.issuer, cert.subject).to_lowercase();
+        KNOWN_INSPECTION_CAS
+            .iter()
+            .find(|(pattern, _)| names.contains(pattern))
+            .map(|(_, product)| product.to_string())
+    })
+}
+
+/// Records the chain presented during a failed verification.
+#[derive(Default)]
+pub struct ChainRecorder {
+    failure: Mutex<Option<(String, Vec<Vec<u8>>)>>,
+}
+
+impl ChainRecorder {
+    fn record(&self, error: String, end_entity: &Certificate, intermediates: &[Certificate]) {
+        let chain = std::iter::once(end_entity)
+            .chain(intermediates)
+            .map(|c| c.0.clone())
+            .collect();
+        if let Ok(mut failure) = self.failure.lock() {
+            *failure = Some((error, chain));
+        }
+    }
+
+    /// Takes the recorded failure, if any, and analyzes it.
+    pub fn take_diagnostics(&self) -> Option<TlsDiagnostics> {
+        let (error, chain) = self.failure.lock().ok()?.take()?;
+        let chain = chain
+            .iter()
+            .filter_map(|der| CertSummary::from_der(der))
+            .collect();
+        Some(TlsDiagnostics::analyze(error, chain))
+    }
+}
+
+/// Certificate verifier that delegates to webpki and records failed chains.
+struct RecordingVerifier {
+    inner: WebPkiVerifier,
+    recorder: Arc<ChainRecorder>,
+}
+
+impl RecordingVerifier {
+    fn new(recorder: Arc<ChainRecorder>) -> Self {
+        let mut roots = RootCertStore::empty();
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+
+        Self {
+            inner: WebPkiVerifier::new(roots, None),
+            recorder,
+        }
+    }
+}
+
+impl ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let result = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        );
+        if let Err(ref e) = result {
+            self.recorder
+                .record(e.to_string(), end_entity, intermediates);
+        }
+        result
+    }
+}
+
+/// Builds a rustls client configuration trusting the webpki roots, with
+/// failed verifications recorded into `recorder`.
+pub fn client_config(recorder: Arc<ChainRecorder>) -> Arc<ClientConfig> {
+    Arc::new(
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(RecordingVerifier::new(recorder)))
+            .with_no_client_auth(),
+    )
+}
+
+/// Builds an HTTP client for side requests to the server's infrastructure
+/// (update downloads, result endpoints), trusting the configured CA and
+/// honouring `verify` like the WebSocket connection.
+pub fn http_client(
+    tls: &crate::config::TlsConfig,
+    timeout: std::time::Duration,
+) -> anyhow::Result<reqwest::Client> {
+    use anyhow::Context;
+
+    let mut client = reqwest::Client::builder().timeout(timeout);
+    if let Some(ca_file) = &tls.ca_file {
+        let pem = std::fs::read(ca_file).with_context(|| format!("cannot read {}", ca_file))?;
+        client = client.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+    if !tls.verify {
+        client = client.danger_accept_invalid_certs(true);
+    }
+    Ok(client.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cert(subject: &str, issuer: &str) -> CertSummary {
+        CertSummary {
+            subject: subject.to_string(),
+            issuer: issuer.to_string(),
+            spki_sha256: "00".repeat(32),
+            not_before: "Jan  1 00:00:00 2024 +00:00".to_string(),
+            not_after: "Jan  1 00:00:00 2034 +00:00".to_string(),
+        }
+    }
+
+    fn self_signed(common_name: &str) -> Vec<u8> {
+        let mut params = rcgen::CertificateParams::new(vec!["test.server".to_string()]);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_analyze_zscaler_chain() {
+        let chain = vec![
+            cert(
+                "CN=test.server",
+                "CN=Zscaler Intermediate Root CA (zscaler.net)",
+            ),
+            cert(
+                "CN=Zscaler Intermediate Root CA (zscaler.net)",
+                "CN=Zscaler Root CA, O=Zscaler Inc.",
+            ),
+        ];
+
+        let diag = TlsDiagnostics::analyze("UnknownIssuer".to_string(), chain);
+        assert_eq!(diag.inspection_product.as_deref(), Some("Zscaler"));
+        assert!(diag.hint.unwrap().contains("Zscaler"));
+    }
+
+    #[test]
+    fn test_analyze_bluecoat_chain() {
+        let chain = vec![cert(
+            "CN=test.server",
+            "CN=BlueCoat ProxySG CA, O=Blue Coat Systems",
+        )];
+
+        let diag = TlsDiagnostics::analyze("UnknownIssuer".to_string(), chain);
+        assert_eq!(diag.inspection_product.as_deref(), Some("Blue Coat"));
+    }
+
+    #[test]
+    fn test_analyze_palo_alto_chain() {
+        let chain = vec![
+            cert(
+                "CN=test.server",
+                "CN=Forward-Trust-CA, O=Palo Alto Networks",
+            ),
+            cert("CN=Forward-Trust-CA, O=Palo Alto Networks", "CN=Corp Root"),
+        ];
+
+        let diag = TlsDiagnostics::analyze("UnknownIssuer".to_string(), chain);
+        assert_eq!(
+            diag.inspection_product.as_deref(),
+            Some("Palo Alto Networks")
+        );
+    }
+
+    #[test]
+    fn test_analyze_unrecognized_chain() {
+        let chain = vec![
+            cert("CN=test.server", "CN=R3, O=Let's Encrypt"),
+            cert("CN=R3, O=Let's Encrypt", "CN=ISRG Root X1"),
+        ];
+
+        let diag = TlsDiagnostics::analyze("CertExpired".to_string(), chain);
+        assert!(diag.inspection_product.is_none());
+        assert!(diag.hint.is_none());
+        assert_eq!(diag.chain.len(), 2);
+    }
+
+    #[test]
+    fn test_analyze_empty_chain() {
+        let diag = TlsDiagnostics::analyze("UnknownIssuer".to_string(), vec![]);
+        assert!(diag.inspection_product.is_none());
+        assert!(diag.chain.is_empty());
+    }
+
+    #[test]
+    fn test_cert_summary_from_der() {
+        let der = self_signed("Zscaler Root CA");
+        let summary = CertSummary::from_der(&der).unwrap();
+
+        assert!(summary.subject.contains("Zscaler Root CA"));
+        assert_eq!(summary.subject, summary.issuer);
+        assert_eq!(summary.spki_sha256.len(), 64);
+        assert!(!summary.not_before.is_empty());
+        assert!(!summary.not_after.is_empty());
+    }
+
+    #[test]
+    fn test_cert_summary_from_invalid_der() {
+        assert!(CertSummary::from_der(b"not a certificate").is_none());
+    }
+
+    #[test]
+    fn test_recording_verifier_captures_rejected_chain() {
+        let recorder = Arc::new(ChainRecorder::default());
+        let verifier = RecordingVerifier::new(recorder.clone());
+        let der = self_signed("Zscaler Root CA");
+
+        let result = verifier.verify_server_cert(
+            &Certificate(der),
+            &[],
+            &ServerName::try_from("test.server").unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            SystemTime::now(),
+        );
+        assert!(result.is_err());
+
+        let diag = recorder.take_diagnostics().unwrap();
+        assert_eq!(diag.chain.len(), 1);
+        assert_eq!(diag.inspection_product.as_deref(), Some("Zscaler"));
+        assert!(recorder.take_diagnostics().is_none());
+    }
+
+    #[test]
+    fn test_diagnostics_serialization() {
+        let diag = TlsDiagnostics::analyze(
+            "UnknownIssuer".to_string(),
+            vec![cert("CN=test.server", "CN=Zscaler Root CA")],
+        );
+
+        let json = serde_json::to_value(&diag).unwrap();
+        assert_eq!(json["inspection_product"], "Zscaler");
+        assert_eq!(json["chain"][0]["issuer"], "CN=Zscaler Root CA");
+        assert!(json["chain"][0]["spki_sha256"].is_string());
+    }
+}
@@ -0,0 +1,208 @@
+//! Agent self-update: compares the version the server offers with the
+//! running one and, when allowed, replaces the binary and restarts.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::config::TlsConfig;
+use crate::protocol::UpdateAvailablePayload;
+
+/// Version of the running agent.
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How long the new binary may take to download.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Largest binary accepted, in bytes.
+const MAX_BINARY_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Whether `offered` is a later version than `current`. Both are semantic
+/// versions, optionally prefixed with `v`.
+pub fn is_newer(current: &str, offered: &str) -> Result<bool> {
+    let parse = |version: &str| {
+        semver::Version::parse(version.trim().trim_start_matches('v'))
+            .with_context(|| format!("invalid version {:?}", version))
+    };
+    Ok(parse(offered)? > parse(current)?)
+}
+
+/// Checks that `bytes` hash to the hex-encoded SHA-256 `expected`.
+pub fn verify_sha256(bytes: &[u8], expected: &str) -> Result<()> {
+    let expected = hex::decode(expected.trim()).context("sha256 is not valid hex")?;
+    if expected.len() != 32 {
+        bail!("sha256 must be 32 bytes, got {}", expected.len());
+    }
+    let actual = Sha256::digest(bytes);
+    if actual.as_slice() != expected.as_slice() {
+        bail!(
+            "sha256 mismatch: expected {}, got {}",
+            hex::encode(expected),
+            hex::encode(actual)
+        );
+    }
+    Ok(())
+}
+
+/// Downloads the offered binary, verifies it, waits for `idle` so no task
+/// is cut short, then puts it in place of the running one and restarts the
+/// agent. Only returns on failure.
+pub async fn install(
+    update: UpdateAvailablePayload,
+    tls: &TlsConfig,
+    idle: impl Future<Output = ()>,
+) -> Result<()> {
+    let (Some(url), Some(sha256)) = (&update.download_url, &update.sha256) else {
+        bail!("update {} has no download_url and sha256", update.version);
+    };
+    let exe = std::env::current_exe().context("cannot locate the agent binary")?;
+
+    info!("Downloading agent {} from {}", update.version, url);
+    let bytes = download(url, tls).await?;
+    let staged = stage(&bytes, sha256, &exe)?;
+    idle.await;
+    swap(&staged, &exe)?;
+    info!("Agent updated to {}, restarting", update.version);
+    restart(&exe)
+}
+
+/// Fetches `url`, trusting the server's CA and honouring `verify`.
+async fn download(url: &str, tls: &TlsConfig) -> Result<Vec<u8>> {
+    let client = crate::tls::http_client(tls, DOWNLOAD_TIMEOUT)?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    if response.content_length().unwrap_or(0) > MAX_BINARY_BYTES {
+        bail!("update is larger than {} bytes", MAX_BINARY_BYTES);
+    }
+    let bytes = response.bytes().await?;
+    if bytes.len() as u64 > MAX_BINARY_BYTES {
+        bail!("update is larger than {} bytes", MAX_BINARY_BYTES);
+    }
+    Ok(bytes.to_vec())
+}
+
+/// Verifies `bytes` and writes them, executable, next to `exe` so the
+/// final rename stays on one filesystem. Returns the staged path.
+fn stage(bytes: &[u8], sha256: &str, exe: &Path) -> Result<PathBuf> {
+    verify_sha256(bytes, sha256)?;
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(".update");
+    let staged = exe.with_file_name(name);
+    std::fs::write(&staged, bytes).with_context(|| format!("cannot write {}", staged.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(staged)
+}
+
+/// Puts `staged` in place of `exe`, removing it when that fails so no
+/// stale `<exe>.update` is left behind.
+fn swap(staged: &Path, exe: &Path) -> Result<()> {
+    let replaced = replace(staged, exe);
+    if replaced.is_err() {
+        let _ = std::fs::remove_file(staged);
+    }
+    replaced
+}
+
+/// Puts `staged` in place of `exe`. Windows cannot overwrite a running
+/// binary, so the old one is first moved aside as `<exe>.old`.
+fn replace(staged: &Path, exe: &Path) -> Result<()> {
+    #[cfg(windows)]
+    {
+        let mut name = exe.file_name().unwrap_or_default().to_os_string();
+        name.push(".old");
+        let old = exe.with_file_name(name);
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old).context("cannot move the running binary aside")?;
+    }
+    std::fs::rename(staged, exe).context("cannot replace the agent binary")
+}
+
+/// Starts the new binary with the same arguments: in place of this process
+/// on Unix, as a new process followed by exiting on Windows.
+fn restart(exe: &Path) -> Result<()> {
+    let mut cmd = std::process::Command::new(exe);
+    cmd.args(std::env::args_os().skip(1));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Err(cmd.exec()).context("cannot restart the agent")
+    }
+    #[cfg(not(unix))]
+    {
+        cmd.spawn().context("cannot restart the agent")?;
+        std::process::exit(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer_compares_semantic_versions() {
+        assert!(is_newer("0.1.0", "0.2.0").unwrap());
+        assert!(is_newer("0.9.0", "0.10.0").unwrap());
+        assert!(is_newer("0.1.0", "v0.1.1").unwrap());
+        assert!(!is_newer("0.2.0", "0.1.9").unwrap());
+        assert!(!is_newer("0.1.0", "0.1.0").unwrap());
+        // A pre-release comes before its release
+        assert!(is_newer("1.0.0-rc.1", "1.0.0").unwrap());
+        assert!(!is_newer("1.0.0", "1.0.0-rc.1").unwrap());
+        assert!(is_newer("0.1.0", "latest").is_err());
+    }
+
+    #[test]
+    fn test_verify_sha256() {
+        let digest = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(verify_sha256(b"hello", digest).is_ok());
+        assert!(verify_sha256(b"hello", &digest.to_uppercase()).is_ok());
+
+        let err = verify_sha256(b"hellO", digest).unwrap_err();
+        assert!(err.to_string().contains("mismatch"));
+        assert!(verify_sha256(b"hello", "not-hex").is_err());
+        assert!(verify_sha256(b"hello", "2cf24dba").is_err());
+    }
+
+    #[test]
+    fn test_stage_refuses_unverified_binary() {
+        let dir = std::env::temp_dir().join(format!("autostrike_update_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("autostrike-agent");
+
+        assert!(stage(b"binary", &"00".repeat(32), &exe).is_err());
+        assert!(!dir.join("autostrike-agent.update").exists());
+
+        let digest = hex::encode(Sha256::digest(b"binary"));
+        let staged = stage(b"binary", &digest, &exe).unwrap();
+        assert_eq!(std::fs::read(&staged).unwrap(), b"binary");
+        replace(&staged, &exe).unwrap();
+        assert_eq!(std::fs::read(&exe).unwrap(), b"binary");
+        assert!(!staged.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_swap_removes_staged_binary() {
+        let dir =
+            std::env::temp_dir().join(format!("autostrike_update_swap_{}", std::process::id()));
+        // A non-empty directory cannot be replaced by a file
+        let exe = dir.join("autostrike-agent");
+        std::fs::create_dir_all(exe.join("busy")).unwrap();
+
+        let digest = hex::encode(Sha256::digest(b"binary"));
+        let staged = stage(b"binary", &digest, &exe).unwrap();
+        assert!(swap(&staged, &exe).is_err());
+        assert!(!staged.exists());
+        assert!(exe.is_dir());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}